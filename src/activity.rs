@@ -0,0 +1,61 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Deadline of the activity timeout, pushed back by every HTTP request and
+/// network command, so that WiFi Connect exits only once nobody has used the
+/// portal for the whole timeout
+#[derive(Clone)]
+pub struct ActivityTimer {
+    timeout: u64,
+    deadline: Arc<Mutex<Instant>>,
+}
+
+impl ActivityTimer {
+    /// A zero timeout disables the timer
+    pub fn new(timeout: u64) -> Self {
+        ActivityTimer {
+            timeout: timeout,
+            deadline: Arc::new(Mutex::new(Instant::now() + Duration::from_secs(timeout))),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.timeout != 0
+    }
+
+    pub fn timeout(&self) -> u64 {
+        self.timeout
+    }
+
+    pub fn kick(&self) {
+        if self.is_enabled() {
+            *self.deadline.lock().unwrap() = Instant::now() + Duration::from_secs(self.timeout);
+        }
+    }
+
+    /// Time left until the deadline, `None` if the timer is disabled
+    pub fn remaining(&self) -> Option<Duration> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let deadline = *self.deadline.lock().unwrap();
+        let now = Instant::now();
+
+        Some(if deadline > now {
+            deadline - now
+        } else {
+            Duration::from_secs(0)
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Some(Duration::from_secs(0))
+    }
+
+    /// Wall clock time of the deadline, for clients which only know the
+    /// current time
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.remaining().map(|remaining| SystemTime::now() + remaining)
+    }
+}
@@ -0,0 +1,366 @@
+use std::thread;
+use std::f64::consts::PI;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::Sender;
+
+use errors::*;
+use network::{validate_passphrase, validate_ssid, ApSelection, NetworkCommand};
+
+/// Sample rate of the capture, the usual one of DTMF (Hz)
+const SAMPLE_RATE: u32 = 8000;
+
+/// Samples per block the tones are detected in, 25.6 ms, which resolves the
+/// DTMF frequencies apart
+const BLOCK_SIZE: usize = 205;
+
+const ROW_FREQUENCIES: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+const COLUMN_FREQUENCIES: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+
+const SYMBOLS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+/// Mean square of the samples below which a block is taken as silence,
+/// about -40 dBFS
+const MIN_POWER: f64 = 100_000.0;
+
+/// Share of the energy of a block each of the two tones of a symbol must
+/// have, so that speech and music are not taken for symbols
+const MIN_TONE_SHARE: f64 = 0.15;
+
+/// Consecutive blocks a symbol must be detected in
+const MIN_BLOCKS: usize = 2;
+
+/// Decodes WiFi credentials played as DTMF tones by a phone app, from the
+/// ALSA capture device, e.g. a line in or a microphone. The capture runs
+/// while the `arecord` child process is alive.
+///
+/// A message starts with `*` and ends with `#`. In between, every byte is
+/// three decimal digits: the UTF-8 SSID, a zero byte, the passphrase and the
+/// sum of the previous bytes modulo 256. Tones of at least 80 ms separated
+/// by 80 ms of silence are decoded reliably.
+pub fn start_audio_capture(device: &str, network_tx: Sender<NetworkCommand>) -> Result<Child> {
+    let rate = SAMPLE_RATE.to_string();
+
+    let mut child = Command::new("arecord")
+        .args(&["-q", "-D", device, "-f", "S16_LE", "-r", &rate, "-c", "1", "-t", "raw"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .chain_err(|| ErrorKind::AudioCapture)?;
+
+    let mut stdout = child.stdout.take().unwrap();
+
+    info!("Listening for WiFi credentials on audio device {}...", device);
+
+    thread::spawn(move || {
+        let mut detector = SymbolDetector::default();
+        let mut digits: Option<String> = None;
+        let mut buffer = vec![0; BLOCK_SIZE * 2];
+
+        loop {
+            if let Err(e) = stdout.read_exact(&mut buffer) {
+                warn!("Audio capture stopped: {}", e);
+                return;
+            }
+
+            let samples = buffer
+                .chunks(2)
+                .map(|bytes| f64::from((u16::from(bytes[0]) | u16::from(bytes[1]) << 8) as i16))
+                .collect::<Vec<_>>();
+
+            let symbol = match detector.push(detect_symbol(&samples)) {
+                Some(symbol) => symbol,
+                None => continue,
+            };
+
+            match symbol {
+                '*' => digits = Some(String::new()),
+                '#' => {
+                    let message = match digits.take() {
+                        Some(message) => message,
+                        None => continue,
+                    };
+
+                    let (ssid, passphrase) = match decode_message(&message) {
+                        Ok(credentials) => credentials,
+                        Err(reason) => {
+                            warn!("Ignoring invalid audio message: {}", reason);
+                            continue;
+                        },
+                    };
+
+                    info!("Connecting to '{}' from audio message", ssid);
+
+                    let command = NetworkCommand::Connect {
+                        ssid: ssid,
+                        passphrase: passphrase,
+                        dns_servers: None,
+                        reuse: false,
+                        selection: ApSelection::default(),
+                        checkpoint: None,
+                    };
+
+                    if network_tx.send(command).is_err() {
+                        return;
+                    }
+                },
+                digit => if let Some(ref mut message) = digits {
+                    message.push(digit);
+                },
+            }
+        }
+    });
+
+    Ok(child)
+}
+
+/// Reports a symbol once it was detected in enough consecutive blocks. The
+/// same symbol is reported again only after a block without it, so that
+/// repeated digits need a pause in between.
+#[derive(Default)]
+struct SymbolDetector {
+    candidate: Option<char>,
+    blocks: usize,
+}
+
+impl SymbolDetector {
+    fn push(&mut self, symbol: Option<char>) -> Option<char> {
+        if symbol != self.candidate {
+            self.candidate = symbol;
+            self.blocks = 0;
+        }
+
+        self.blocks += 1;
+
+        if self.blocks == MIN_BLOCKS {
+            self.candidate
+        } else {
+            None
+        }
+    }
+}
+
+/// Finds the DTMF symbol of a block: the strongest row and column tones
+/// must carry most of its energy, and be well above the other tones of
+/// their group
+fn detect_symbol(samples: &[f64]) -> Option<char> {
+    let energy = samples.iter().map(|sample| sample * sample).sum::<f64>();
+
+    if energy / (samples.len() as f64) < MIN_POWER {
+        return None;
+    }
+
+    let (row, row_share) = get_strongest(samples, &ROW_FREQUENCIES, energy)?;
+    let (column, column_share) = get_strongest(samples, &COLUMN_FREQUENCIES, energy)?;
+
+    if row_share < MIN_TONE_SHARE || column_share < MIN_TONE_SHARE {
+        return None;
+    }
+
+    Some(SYMBOLS[row][column])
+}
+
+/// The index of the strongest frequency and its share of the energy, unless
+/// another frequency is within 6 dB of it
+fn get_strongest(samples: &[f64], frequencies: &[f64], energy: f64) -> Option<(usize, f64)> {
+    let mut shares = frequencies
+        .iter()
+        .map(|frequency| goertzel(samples, *frequency) / (energy * samples.len() as f64 / 2.0))
+        .enumerate()
+        .collect::<Vec<_>>();
+
+    shares.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(::std::cmp::Ordering::Equal));
+
+    if shares[1].1 * 4.0 > shares[0].1 {
+        return None;
+    }
+
+    Some(shares[0])
+}
+
+/// Power of a single frequency in a block
+fn goertzel(samples: &[f64], frequency: f64) -> f64 {
+    let coefficient = 2.0 * (2.0 * PI * frequency / f64::from(SAMPLE_RATE)).cos();
+
+    let (mut s1, mut s2) = (0.0, 0.0);
+
+    for sample in samples {
+        let s0 = sample + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+
+    s1 * s1 + s2 * s2 - coefficient * s1 * s2
+}
+
+/// Decodes the digits between `*` and `#` into the SSID and passphrase
+fn decode_message(digits: &str) -> ::std::result::Result<(String, String), String> {
+    if digits.is_empty() || digits.len() % 3 != 0 {
+        return Err("not a multiple of three digits".to_string());
+    }
+
+    let mut bytes = Vec::new();
+
+    for i in 0..digits.len() / 3 {
+        let byte = digits[i * 3..i * 3 + 3]
+            .parse::<u8>()
+            .map_err(|_| "byte out of range")?;
+
+        bytes.push(byte);
+    }
+
+    let checksum = bytes.pop().unwrap();
+
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+
+    if sum != checksum {
+        return Err("checksum mismatch".to_string());
+    }
+
+    let separator = bytes
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or("no passphrase separator")?;
+
+    let ssid = String::from_utf8(bytes[..separator].to_vec()).map_err(|_| "SSID is not UTF-8")?;
+
+    let passphrase = String::from_utf8(bytes[separator + 1..].to_vec())
+        .map_err(|_| "passphrase is not UTF-8")?;
+
+    validate_ssid(&ssid)?;
+    validate_passphrase(&passphrase)?;
+
+    Ok((ssid, passphrase))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A block of the two tones of a symbol at -12 dBFS each
+    fn tones(row: f64, column: f64, offset: usize) -> Vec<f64> {
+        (offset..offset + BLOCK_SIZE)
+            .map(|i| {
+                let t = i as f64 / f64::from(SAMPLE_RATE);
+                8192.0 * ((2.0 * PI * row * t).sin() + (2.0 * PI * column * t).sin())
+            })
+            .collect()
+    }
+
+    fn symbol_tones(symbol: char, offset: usize) -> Vec<f64> {
+        for (row, symbols) in SYMBOLS.iter().enumerate() {
+            if let Some(column) = symbols.iter().position(|s| *s == symbol) {
+                return tones(ROW_FREQUENCIES[row], COLUMN_FREQUENCIES[column], offset);
+            }
+        }
+
+        panic!("no DTMF symbol {}", symbol);
+    }
+
+    #[test]
+    fn every_symbol_is_detected() {
+        for symbols in &SYMBOLS {
+            for &symbol in symbols {
+                assert_eq!(detect_symbol(&symbol_tones(symbol, 0)), Some(symbol));
+                assert_eq!(detect_symbol(&symbol_tones(symbol, 1000)), Some(symbol));
+            }
+        }
+    }
+
+    #[test]
+    fn silence_single_tones_and_noise_are_not_symbols() {
+        assert_eq!(detect_symbol(&[0.0; BLOCK_SIZE]), None);
+
+        // Too quiet
+        let quiet = symbol_tones('5', 0)
+            .iter()
+            .map(|sample| sample / 100.0)
+            .collect::<Vec<_>>();
+        assert_eq!(detect_symbol(&quiet), None);
+
+        let row_only = tones(770.0, 0.0, 0);
+        assert_eq!(detect_symbol(&row_only), None);
+
+        let mut seed = 1u32;
+        let noise = (0..BLOCK_SIZE)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                f64::from((seed >> 16) as i16)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(detect_symbol(&noise), None);
+    }
+
+    #[test]
+    fn symbols_need_consecutive_blocks() {
+        let mut detector = SymbolDetector::default();
+
+        let blocks = [
+            Some('1'), Some('1'), Some('1'), None, Some('1'), Some('2'), Some('1'), Some('1'),
+        ];
+
+        let reported = blocks
+            .iter()
+            .map(|symbol| detector.push(*symbol))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            reported,
+            [None, Some('1'), None, None, None, None, None, Some('1')]
+        );
+    }
+
+    #[test]
+    fn message_is_decoded() {
+        let digits = "072111109101000115101099114101116049050051165";
+
+        assert_eq!(
+            decode_message(digits),
+            Ok(("Home".to_string(), "secret123".to_string()))
+        );
+    }
+
+    #[test]
+    fn played_message_is_decoded() {
+        let message = "*072111109101000115101099114101116049050051165#";
+
+        let mut detector = SymbolDetector::default();
+        let mut symbols = String::new();
+        let mut offset = 0;
+
+        // Three blocks of each tone, 77 ms, and a block of silence
+        for symbol in message.chars() {
+            for _ in 0..3 {
+                if let Some(symbol) = detector.push(detect_symbol(&symbol_tones(symbol, offset))) {
+                    symbols.push(symbol);
+                }
+
+                offset += BLOCK_SIZE;
+            }
+
+            assert_eq!(detector.push(detect_symbol(&[0.0; BLOCK_SIZE])), None);
+            offset += BLOCK_SIZE;
+        }
+
+        assert_eq!(symbols, message);
+        assert!(decode_message(&symbols[1..symbols.len() - 1]).is_ok());
+    }
+
+    #[test]
+    fn invalid_messages() {
+        assert!(decode_message("").is_err());
+        assert!(decode_message("0720").is_err());
+        assert!(decode_message("256256").is_err());
+
+        // Checksum off by one
+        assert!(decode_message("072111109101000115101099114101116049050051166").is_err());
+
+        // No separator
+        assert!(decode_message("072111109101183").is_err());
+    }
+}
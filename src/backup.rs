@@ -0,0 +1,175 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde_json::{self, Value};
+
+use errors::*;
+use nmcli::{get_wifi_profiles, WifiProfile};
+
+const BACKUP_VERSION: u32 = 1;
+
+/// Key management values that can be recreated from the SSID, passphrase
+/// and IPv4 settings alone
+pub const SUPPORTED_KEY_MANAGEMENT: &[&str] = &["", "wpa-psk", "sae", "owe"];
+
+#[derive(Serialize, Deserialize)]
+struct Backup {
+    version: u32,
+    encrypted: bool,
+    profiles: Vec<BackupProfile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BackupProfile {
+    ssid: String,
+    #[serde(default)]
+    security: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    passphrase: Option<String>,
+    #[serde(default)]
+    ipv4: BackupIpv4,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct BackupIpv4 {
+    method: String,
+    #[serde(default)]
+    addresses: Vec<String>,
+    #[serde(default)]
+    gateway: Option<String>,
+    #[serde(default)]
+    dns: Vec<String>,
+}
+
+/// Serializes the saved WiFi profiles. Passphrases are included only when an
+/// encryption key is given, encrypted with it.
+pub fn export_profiles(key: Option<&str>) -> Result<Value> {
+    let mut profiles = Vec::new();
+
+    for profile in get_wifi_profiles()? {
+        if !SUPPORTED_KEY_MANAGEMENT.contains(&profile.key_management.as_str()) {
+            warn!(
+                "Not exporting '{}' with unsupported key management {}",
+                profile.ssid, profile.key_management
+            );
+            continue;
+        }
+
+        let passphrase = match (key, profile.passphrase) {
+            (Some(key), Some(ref passphrase)) => Some(encrypt(passphrase, key)?),
+            _ => None,
+        };
+
+        profiles.push(BackupProfile {
+            ssid: profile.ssid,
+            security: profile.key_management,
+            passphrase: passphrase,
+            ipv4: BackupIpv4 {
+                method: profile.ipv4_method,
+                addresses: profile.ipv4_addresses,
+                gateway: profile.ipv4_gateway,
+                dns: profile.ipv4_dns,
+            },
+        });
+    }
+
+    let backup = Backup {
+        version: BACKUP_VERSION,
+        encrypted: key.is_some(),
+        profiles: profiles,
+    };
+
+    Ok(serde_json::to_value(backup)?)
+}
+
+/// Parses an exported backup into profiles, decrypting the passphrases
+pub fn parse_backup(backup: Value, key: Option<&str>) -> Result<Vec<WifiProfile>> {
+    let backup: Backup = serde_json::from_value(backup)
+        .map_err(|e| Error::from(ErrorKind::InvalidBackup(e.to_string())))?;
+
+    if backup.version != BACKUP_VERSION {
+        bail!(ErrorKind::InvalidBackup(format!(
+            "unsupported version {}",
+            backup.version
+        )));
+    }
+
+    let mut profiles = Vec::new();
+
+    for profile in backup.profiles {
+        if !SUPPORTED_KEY_MANAGEMENT.contains(&profile.security.as_str()) {
+            bail!(ErrorKind::InvalidBackup(format!(
+                "unsupported key management {} of '{}'",
+                profile.security, profile.ssid
+            )));
+        }
+
+        let passphrase = match profile.passphrase {
+            Some(ref passphrase) => match key {
+                Some(key) => Some(decrypt(passphrase, key)?),
+                None => bail!(ErrorKind::InvalidBackup(
+                    "an encryption key is required for the passphrases".into()
+                )),
+            },
+            None => None,
+        };
+
+        let method = if profile.ipv4.method.is_empty() {
+            "auto".to_string()
+        } else {
+            profile.ipv4.method
+        };
+
+        profiles.push(WifiProfile {
+            ssid: profile.ssid,
+            key_management: profile.security,
+            passphrase: passphrase,
+            ipv4_method: method,
+            ipv4_addresses: profile.ipv4.addresses,
+            ipv4_gateway: profile.ipv4.gateway,
+            ipv4_dns: profile.ipv4.dns,
+        });
+    }
+
+    Ok(profiles)
+}
+
+fn encrypt(plaintext: &str, key: &str) -> Result<String> {
+    openssl_enc(&[], plaintext, key)
+}
+
+fn decrypt(ciphertext: &str, key: &str) -> Result<String> {
+    openssl_enc(&["-d"], ciphertext, key)
+        .chain_err(|| ErrorKind::InvalidBackup("decrypting a passphrase failed".into()))
+}
+
+/// Runs `openssl enc` with AES-256 and a PBKDF2 derived key. The key is
+/// passed through the environment, so that it does not show up in the
+/// process list.
+fn openssl_enc(args: &[&str], input: &str, key: &str) -> Result<String> {
+    let mut child = Command::new("openssl")
+        .args(&["enc", "-aes-256-cbc", "-pbkdf2", "-salt", "-a", "-A"])
+        .args(args)
+        .args(&["-pass", "env:WIFI_CONNECT_BACKUP_KEY"])
+        .env("WIFI_CONNECT_BACKUP_KEY", key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .chain_err(|| ErrorKind::Openssl)?;
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())?;
+
+    let output = child.wait_with_output().chain_err(|| ErrorKind::Openssl)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        bail!(ErrorKind::OpensslStatus(stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,77 @@
+use std::fs::File;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use config::Config;
+use server::{get_portal_url, API_VERSION};
+use subnet;
+
+const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+
+/// Announces the captive portal to native companion apps with a small JSON
+/// datagram every beacon interval, so that they find the device without
+/// knowing the gateway address, e.g.
+/// `{"service": "wifi-connect", "portal_url": "http://192.168.42.1/", ...}`.
+/// The datagrams are sent from the gateway address, so none are sent while
+/// the portal is closed.
+pub fn spawn_beacon(config: &Config) {
+    if config.beacon_port == 0 {
+        return;
+    }
+
+    let gateway = config.gateway;
+    let interval = Duration::from_secs(config.beacon_interval);
+
+    let destination = SocketAddr::new(
+        config
+            .beacon_address
+            .unwrap_or_else(|| subnet::broadcast(gateway, config.portal_prefix))
+            .into(),
+        config.beacon_port,
+    );
+
+    let beacon = json!({
+        "service": "wifi-connect",
+        "portal_url": get_portal_url(config),
+        "device_id": get_hostname(),
+        "api_version": API_VERSION,
+        "version": env!("CARGO_PKG_VERSION"),
+    }).to_string();
+
+    info!("Announcing the captive portal to {}", destination);
+
+    thread::spawn(move || loop {
+        if let Err(e) = send_beacon(gateway, destination, &beacon) {
+            debug!("Sending discovery beacon failed: {}", e);
+        }
+
+        thread::sleep(interval);
+    });
+}
+
+fn send_beacon(gateway: Ipv4Addr, destination: SocketAddr, beacon: &str) -> ::std::io::Result<()> {
+    // Binding fails while the portal network is down
+    let socket = UdpSocket::bind(SocketAddr::new(gateway.into(), 0))?;
+
+    if destination.ip().is_multicast() {
+        socket.set_multicast_ttl_v4(1)?;
+    } else {
+        socket.set_broadcast(true)?;
+    }
+
+    socket.send_to(beacon.as_bytes(), destination)?;
+
+    Ok(())
+}
+
+fn get_hostname() -> Option<String> {
+    let mut hostname = String::new();
+
+    File::open(HOSTNAME_PATH)
+        .and_then(|mut file| file.read_to_string(&mut hostname))
+        .ok()?;
+
+    Some(hostname.trim().to_string())
+}
@@ -0,0 +1,290 @@
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+use network_manager::NetworkManager;
+use serde_json;
+
+use errors::*;
+use config::{validate_dhcp_range, Config, Scheme};
+use dhcp::is_supported_option;
+use network::{find_device, find_ethernet_device, is_network_manager_reachable};
+use tls::check_certificate;
+
+/// Outcome of a single check. Only errors fail the check.
+enum Status {
+    Ok,
+    Warning,
+    Error,
+    Skipped,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+            Status::Skipped => "skipped",
+        }
+    }
+}
+
+struct Check {
+    name: &'static str,
+    status: Status,
+    message: String,
+}
+
+/// Validates the configuration as far as possible without starting the
+/// captive portal, e.g. in the CI of device images, and prints a report as
+/// JSON on stdout. Values which cannot be parsed at all are already refused
+/// while reading the configuration.
+pub fn check_config(config: &Config) -> Result<()> {
+    let checks = [
+        check_dhcp_range(config),
+        check_dhcp_options(config),
+        check_ui_directory(config),
+        check_tls(config),
+        check_state_directory(config),
+        check_interface(config),
+    ];
+
+    let errors = checks
+        .iter()
+        .filter(|check| match check.status {
+            Status::Error => true,
+            _ => false,
+        })
+        .count();
+
+    let checks_json = checks
+        .iter()
+        .map(|check| {
+            json!({
+                "name": check.name,
+                "status": check.status.as_str(),
+                "message": check.message,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let report = json!({
+        "valid": errors == 0,
+        "errors": errors,
+        "checks": checks_json,
+    });
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if errors != 0 {
+        bail!(ErrorKind::InvalidConfig(errors));
+    }
+
+    Ok(())
+}
+
+fn check(name: &'static str, status: Status, message: String) -> Check {
+    Check {
+        name: name,
+        status: status,
+        message: message,
+    }
+}
+
+fn check_dhcp_range(config: &Config) -> Check {
+    let validation = validate_dhcp_range(&config.dhcp_range, config.gateway, config.portal_prefix);
+
+    if let Err(message) = validation {
+        return check("dhcp_range", Status::Error, message);
+    }
+
+    let addresses = config
+        .dhcp_range
+        .split(',')
+        .map(|address| Ipv4Addr::from_str(address.trim()).map(u32::from))
+        .collect::<::std::result::Result<Vec<_>, _>>();
+
+    match addresses {
+        Ok(ref addresses) if addresses.len() >= 2 => {
+            let gateway = u32::from(config.gateway);
+
+            if gateway >= addresses[0] && gateway <= addresses[1] {
+                check(
+                    "dhcp_range",
+                    Status::Error,
+                    format!(
+                        "Gateway {} is inside of DHCP range {}",
+                        config.gateway, config.dhcp_range
+                    ),
+                )
+            } else {
+                check(
+                    "dhcp_range",
+                    Status::Ok,
+                    format!("{} with gateway {}", config.dhcp_range, config.gateway),
+                )
+            }
+        },
+        _ => check(
+            "dhcp_range",
+            Status::Error,
+            format!("Invalid DHCP range {}", config.dhcp_range),
+        ),
+    }
+}
+
+fn check_dhcp_options(config: &Config) -> Check {
+    if config.dnsmasq {
+        return check(
+            "dhcp_options",
+            Status::Skipped,
+            "Passed to dnsmasq as they are".to_string(),
+        );
+    }
+
+    let unsupported = config
+        .dhcp_options
+        .iter()
+        .filter(|option| !is_supported_option(option))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    if unsupported.is_empty() {
+        check(
+            "dhcp_options",
+            Status::Ok,
+            format!("{} options", config.dhcp_options.len()),
+        )
+    } else {
+        check(
+            "dhcp_options",
+            Status::Warning,
+            format!(
+                "Ignored by the built-in DHCP server: {}",
+                unsupported.join("; ")
+            ),
+        )
+    }
+}
+
+fn check_ui_directory(config: &Config) -> Check {
+    let directory = match config.ui_directory {
+        Some(ref directory) => directory,
+        None => {
+            return check(
+                "ui_directory",
+                Status::Ok,
+                "The embedded UI is served".to_string(),
+            )
+        },
+    };
+
+    if !directory.is_dir() {
+        check(
+            "ui_directory",
+            Status::Error,
+            format!("{} is not a directory", directory.display()),
+        )
+    } else if !directory.join("index.html").is_file() {
+        check(
+            "ui_directory",
+            Status::Error,
+            format!("{} has no index.html", directory.display()),
+        )
+    } else {
+        check(
+            "ui_directory",
+            Status::Ok,
+            directory.display().to_string(),
+        )
+    }
+}
+
+fn check_tls(config: &Config) -> Check {
+    if config.listening_scheme != Scheme::Https {
+        return check("tls", Status::Skipped, "HTTPS is disabled".to_string());
+    }
+
+    match (&config.tls_certificate, &config.tls_key) {
+        (&Some(ref certificate), &Some(ref key)) => match check_certificate(certificate, key) {
+            Ok(()) => check(
+                "tls",
+                Status::Ok,
+                format!("{} is valid", certificate.display()),
+            ),
+            Err(e) => check(
+                "tls",
+                Status::Error,
+                format!(
+                    "{} or {} is invalid or expired: {}",
+                    certificate.display(),
+                    key.display(),
+                    e
+                ),
+            ),
+        },
+        (&None, &None) => check(
+            "tls",
+            Status::Ok,
+            "A self-signed certificate is generated on the first run".to_string(),
+        ),
+        _ => check(
+            "tls",
+            Status::Error,
+            "Both --tls-certificate and --tls-key are required".to_string(),
+        ),
+    }
+}
+
+fn check_state_directory(config: &Config) -> Check {
+    let directory = &config.state_directory;
+
+    if directory.is_dir() {
+        check(
+            "state_directory",
+            Status::Ok,
+            directory.display().to_string(),
+        )
+    } else if directory.exists() {
+        check(
+            "state_directory",
+            Status::Error,
+            format!("{} is not a directory", directory.display()),
+        )
+    } else {
+        check(
+            "state_directory",
+            Status::Warning,
+            format!("{} is created on the first run", directory.display()),
+        )
+    }
+}
+
+fn check_interface(config: &Config) -> Check {
+    if !is_network_manager_reachable() {
+        return check(
+            "interface",
+            Status::Skipped,
+            "NetworkManager is not reachable".to_string(),
+        );
+    }
+
+    let manager = NetworkManager::new();
+
+    match find_device(&manager, &config.interface) {
+        Ok(device) => check("interface", Status::Ok, device.interface().to_string()),
+        Err(e) => {
+            if config.ethernet_portal {
+                if let Ok(device) = find_ethernet_device(&manager, &config.ethernet_interface) {
+                    return check(
+                        "interface",
+                        Status::Warning,
+                        format!("{} - the portal is served on {}", e, device.interface()),
+                    );
+                }
+            }
+
+            check("interface", Status::Error, e.to_string())
+        },
+    }
+}
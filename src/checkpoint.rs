@@ -0,0 +1,71 @@
+use dbus::{BusType, Connection, Message, Path};
+
+use errors::*;
+
+const NM_DBUS_NAME: &str = "org.freedesktop.NetworkManager";
+const NM_DBUS_PATH: &str = "/org/freedesktop/NetworkManager";
+const NM_DBUS_INTERFACE: &str = "org.freedesktop.NetworkManager";
+
+/// Time to wait for NetworkManager to answer (milliseconds)
+const CALL_TIMEOUT: i32 = 30_000;
+
+/// `NM_CHECKPOINT_CREATE_FLAG_DELETE_NEW_CONNECTIONS`: profiles created
+/// after the checkpoint, e.g. for the network which stranded the device, are
+/// deleted on rollback
+const CREATE_FLAGS: u32 = 0x02;
+
+/// A NetworkManager checkpoint of a device, which restores its connection
+/// and profiles on rollback. NetworkManager rolls back on its own once the
+/// rollback timeout expires, even if WiFi Connect is not around anymore.
+pub struct Checkpoint {
+    path: Path<'static>,
+}
+
+impl Checkpoint {
+    pub fn create(interface: &str, rollback_timeout: u64) -> Result<Self> {
+        let device = call("GetDeviceByIpIface", |message| message.append1(interface))?
+            .get1::<Path>()
+            .ok_or_else(|| ErrorKind::Checkpoint("GetDeviceByIpIface".to_string()))?;
+
+        let reply = call("CheckpointCreate", |message| {
+            message.append3(vec![device], rollback_timeout as u32, CREATE_FLAGS)
+        })?;
+
+        let path = reply
+            .get1::<Path>()
+            .ok_or_else(|| ErrorKind::Checkpoint("create".to_string()))?;
+
+        Ok(Checkpoint { path: path })
+    }
+
+    /// Resets the time left until NetworkManager rolls back on its own
+    pub fn adjust(&self, rollback_timeout: u64) -> Result<()> {
+        call("CheckpointAdjustRollbackTimeout", |message| {
+            message.append2(self.path.clone(), rollback_timeout as u32)
+        }).map(|_| ())
+    }
+
+    /// Keeps the changes made since the checkpoint
+    pub fn destroy(self) -> Result<()> {
+        call("CheckpointDestroy", |message| message.append1(self.path.clone())).map(|_| ())
+    }
+
+    pub fn roll_back(self) -> Result<()> {
+        call("CheckpointRollback", |message| message.append1(self.path.clone())).map(|_| ())
+    }
+}
+
+fn call<F>(method: &str, append: F) -> Result<Message>
+where
+    F: FnOnce(Message) -> Message,
+{
+    let connection = Connection::get_private(BusType::System)
+        .chain_err(|| ErrorKind::Checkpoint(method.to_string()))?;
+
+    let message = Message::new_method_call(NM_DBUS_NAME, NM_DBUS_PATH, NM_DBUS_INTERFACE, method)
+        .map_err(|e| Error::from(ErrorKind::Checkpoint(format!("{}: {}", method, e))))?;
+
+    connection
+        .send_with_reply_and_block(append(message), CALL_TIMEOUT)
+        .chain_err(|| ErrorKind::Checkpoint(method.to_string()))
+}
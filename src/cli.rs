@@ -0,0 +1,58 @@
+use serde_json::{self, Value};
+
+use errors::*;
+use config::{CliCommand, Config};
+use check::check_config;
+use network::{connect_network, forget_network, get_network_status, scan_networks};
+
+/// Runs a subcommand and prints its result as JSON on stdout, so that
+/// scripts and installers can provision a device without the captive portal
+pub fn run(config: &Config, command: &CliCommand) -> Result<()> {
+    match *command {
+        CliCommand::Scan => {
+            let access_points = scan_networks(config)?
+                .iter()
+                .map(|ap| {
+                    json!({
+                        "ssid": ap.ssid,
+                        "strength": ap.strength,
+                        "security": ap.key_management.as_str(),
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            print_json(&Value::Array(access_points))
+        },
+        CliCommand::Connect {
+            ref ssid,
+            ref passphrase,
+            reuse,
+        } => match connect_network(config, ssid, passphrase, reuse) {
+            Ok(connectivity) => print_json(&json!({
+                "ssid": ssid,
+                "connected": true,
+                "connectivity": connectivity,
+            })),
+            Err(e) => {
+                print_json(&json!({
+                    "ssid": ssid,
+                    "connected": false,
+                    "error": e.to_string(),
+                }))?;
+
+                Err(e)
+            },
+        },
+        CliCommand::Status => print_json(&get_network_status(config)?),
+        CliCommand::Forget { ref ssid } => print_json(&json!({
+            "ssid": ssid,
+            "deleted": forget_network(ssid),
+        })),
+        CliCommand::CheckConfig => check_config(config),
+    }
+}
+
+fn print_json(value: &Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
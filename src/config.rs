@@ -1,16 +1,143 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use toml;
+use serde_json::Value;
 
 use std::env;
-use std::net::Ipv4Addr;
+use std::net::{AddrParseError, IpAddr, Ipv4Addr, SocketAddr};
 use std::str::FromStr;
 use std::path::PathBuf;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::mem;
+use std::fs::{self, File};
+use std::io::Read;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use log::LogLevelFilter;
+
+use redact::REDACTED;
+use subnet;
+use internet::Probe;
+use network::validate_ssid;
+use pairing;
+use nmcli::Band;
+use locale::Locale;
+use serializer::JsonCase;
+use dns::DnsRule;
+use logger::LogFormat;
+
+/// Options whose values may contain commas, so their environment variables
+/// take semicolon separated lists
+const SEMICOLON_SEPARATED: &[&str] = &["portal-dhcp-option", "portal-dns-rule"];
+
+/// Options of `--print-config` whose command line options and environment
+/// variable are not named after them
+const OPTION_NAMES: &[(&str, &[&str], &str)] = &[
+    ("portal_prefix", &["portal-gateway"], "PORTAL_GATEWAY"),
+    ("portal_dhcp_options", &["portal-dhcp-option"], "PORTAL_DHCP_OPTION"),
+    ("portal_dns_rules", &["portal-dns-rule"], "PORTAL_DNS_RULE"),
+    ("cors_origins", &["cors-origin"], "CORS_ORIGIN"),
+    ("webhooks", &["webhook"], "WEBHOOK"),
+    ("startup_policy", &["strict", "lenient"], "STARTUP_POLICY"),
+    ("listening_address", &["listening-address"], "PORTAL_LISTENING_ADDRESS"),
+];
+
+/// Options which a reload of the configuration applies to the running
+/// process, named like in `--print-config`. The others take effect on the
+/// next start.
+pub const RELOADABLE_OPTIONS: &[&str] = &[
+    "log_level",
+    "connect_attempts",
+    "connect_backoff",
+    "connect_attempt_timeout",
+    "connect_any_timeout",
+    "confirm_timeout",
+    "rollback_timeout",
+    "checkpoint_timeout",
+    "scan_retries",
+    "scan_interval",
+    "internet_check",
+    "internet_check_timeout",
+    "refuse_unknown_bssids",
+    "portal_dns_rules",
+    "on_connect",
+    "on_disconnect",
+    "webhooks",
+    "webhook_secret",
+    "hook_timeout",
+];
 
 const DEFAULT_GATEWAY: &str = "192.168.42.1";
-const DEFAULT_DHCP_RANGE: &str = "192.168.42.2,192.168.42.254";
 const DEFAULT_SSID: &str = "WiFi Connect";
 const DEFAULT_ACTIVITY_TIMEOUT: &str = "0";
-const DEFAULT_UI_DIRECTORY: &str = "ui";
+const DEFAULT_STATE_DIRECTORY: &str = "/var/lib/wifi-connect";
+const DEFAULT_MDNS_SERVICE_NAME: &str = "WiFi Connect";
+const DEFAULT_LISTENING_SCHEME: &str = "http";
+const DEFAULT_MONITOR_INTERVAL: &str = "15";
+const DEFAULT_MONITOR_GRACE_PERIOD: &str = "60";
+const DEFAULT_SCAN_RETRIES: &str = "10";
+const DEFAULT_SCAN_INTERVAL: &str = "1";
+const DEFAULT_ROLLBACK_TIMEOUT: &str = "30";
+const DEFAULT_CONFIRM_TIMEOUT: &str = "60";
+const DEFAULT_CONFIG_FILE: &str = "/etc/wifi-connect/wifi-connect.toml";
+const DEFAULT_MQTT_PORT: &str = "1883";
+const DEFAULT_MQTT_TOPIC_PREFIX: &str = "wifi-connect";
+const DEFAULT_PORTAL_SLEEP_TIMEOUT: &str = "0";
+const DEFAULT_INTERNET_CHECK_TIMEOUT: &str = "5";
+const DEFAULT_CONNECT_ATTEMPTS: &str = "1";
+const DEFAULT_CONNECT_BACKOFF: &str = "5";
+const DEFAULT_CONNECT_ATTEMPT_TIMEOUT: &str = "20";
+const DEFAULT_PORTAL_MAX_CLIENTS: &str = "4";
+const DEFAULT_MAX_RUNTIME: &str = "0";
+const DEFAULT_LOCALE: &str = "en";
+const DEFAULT_RATE_LIMIT: &str = "10";
+const DEFAULT_CORS_METHODS: &str = "GET, POST, PUT, DELETE";
+const DEFAULT_CORS_HEADERS: &str = "Content-Type, Authorization, X-Pairing-Token";
+const DEFAULT_SCAN_WATCH_INTERVAL: &str = "0";
+const DEFAULT_DBUS_WAIT_TIMEOUT: &str = "60";
+const DEFAULT_LOCK_FILE: &str = "/run/wifi-connect.lock";
+const DEFAULT_HOOK_TIMEOUT: &str = "30";
+const DEFAULT_BEACON_PORT: &str = "0";
+const DEFAULT_BEACON_INTERVAL: &str = "5";
+const DEFAULT_LOG_FILE_MAX_SIZE: &str = "1024";
+const DEFAULT_CONNECT_ANY_TIMEOUT: &str = "15";
+const DEFAULT_CHECKPOINT_TIMEOUT: &str = "0";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl Scheme {
+    pub fn default_port(&self) -> u16 {
+        match *self {
+            Scheme::Http => 80,
+            Scheme::Https => 443,
+        }
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "http" => Ok(Scheme::Http),
+            "https" => Ok(Scheme::Https),
+            _ => Err(format!("Unknown scheme: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Scheme::Http => write!(f, "http"),
+            Scheme::Https => write!(f, "https"),
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct Config {
@@ -18,13 +145,412 @@ pub struct Config {
     pub ssid: String,
     pub passphrase: Option<String>,
     pub gateway: Ipv4Addr,
+    pub portal_prefix: u8,
     pub dhcp_range: String,
     pub activity_timeout: u64,
-    pub ui_directory: PathBuf,
+    pub ui_directory: Option<PathBuf>,
+    pub state_directory: PathBuf,
+    pub mdns_service_name: String,
+    pub refuse_unknown_bssids: bool,
+    pub listening_scheme: Scheme,
+    pub listening_port: u16,
+    pub tls_certificate: Option<PathBuf>,
+    pub tls_key: Option<PathBuf>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub auth_username: Option<String>,
+    pub auth_password: Option<String>,
+    pub auth_token: Option<String>,
+    pub listening_address: IpAddr,
+    pub disable_ipv4_listener: bool,
+    pub disable_ipv6_listener: bool,
+    pub monitor: bool,
+    pub monitor_interval: u64,
+    pub monitor_grace_period: u64,
+    pub bind_interface: bool,
+    pub hidden_ssids: Vec<String>,
+    pub scan_retries: u32,
+    pub scan_interval: u64,
+    pub manage_ethernet: bool,
+    pub ethernet_interface: Option<String>,
+    pub change_network: bool,
+    pub rollback_timeout: u64,
+    pub confirm_timeout: u64,
+    pub command: Option<CliCommand>,
+    pub profile: Option<String>,
+    pub dbus: bool,
+    pub print_config: bool,
+    pub mqtt_host: Option<String>,
+    pub mqtt_port: u16,
+    pub mqtt_topic_prefix: String,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<String>,
+    pub mqtt_ca_file: Option<PathBuf>,
+    pub preserve_existing_profiles: bool,
+    pub portal_tx_power: Option<u32>,
+    pub portal_power_save: bool,
+    pub dhcp_lease_time: Option<String>,
+    pub dhcp_options: Vec<String>,
+    pub portal_sleep_timeout: u64,
+    pub portal_beacon_interval: Option<u16>,
+    pub portal_dtim_period: Option<u8>,
+    pub dnsmasq: bool,
+    pub failure_report_file: Option<String>,
+    pub internet_checks: Vec<Probe>,
+    pub internet_check_timeout: u64,
+    pub pairing_token: bool,
+    pub connect_attempts: u32,
+    pub connect_backoff: u64,
+    pub connect_attempt_timeout: u64,
+    pub portal_max_clients: u32,
+    pub lenient: bool,
+    pub max_runtime: u64,
+    pub portal_channel: Option<u32>,
+    pub portal_band: Option<Band>,
+    pub ethernet_portal: bool,
+    pub locale: Locale,
+    pub rate_limit: u32,
+    pub cors_origins: Vec<String>,
+    pub cors_methods: String,
+    pub cors_headers: String,
+    pub json_case: JsonCase,
+    pub json_envelope: bool,
+    pub scan_watch_interval: u64,
+    pub dbus_wait_timeout: u64,
+    pub lock_file: PathBuf,
+    pub on_connect: Option<String>,
+    pub on_disconnect: Option<String>,
+    pub webhooks: Vec<String>,
+    pub hook_timeout: u64,
+    pub status_file: Option<PathBuf>,
+    pub dns_rules: Vec<DnsRule>,
+    pub dns_upstream: Option<Ipv4Addr>,
+    pub beacon_port: u16,
+    pub beacon_interval: u64,
+    pub beacon_address: Option<Ipv4Addr>,
+    pub log_format: LogFormat,
+    pub log_file: Option<PathBuf>,
+    pub log_file_max_size: u64,
+    pub import: Option<PathBuf>,
+    pub ssdp: bool,
+    pub connect_any_timeout: u64,
+    pub enrollment_token: Option<String>,
+    pub checkpoint_timeout: u64,
+    pub webhook_secret: Option<String>,
+    pub virtual_ap: Option<String>,
+    pub log_level: LogLevelFilter,
+    pub wifi_p2p: bool,
+    pub nfc_reader: Option<String>,
+    pub audio_capture: Option<String>,
+    /// Where the value of every option of `to_json` comes from
+    sources: BTreeMap<String, ConfigSource>,
+}
+
+impl Config {
+    /// The fully resolved configuration with the secrets redacted
+    pub fn to_json(&self) -> Value {
+        let path = |path: &Option<PathBuf>| path.as_ref().map(|path| path.display().to_string());
+        let secret = |secret: &Option<String>| secret.as_ref().map(|_| REDACTED);
+
+        json!({
+            "profile": self.profile,
+            "portal_interface": self.interface,
+            "portal_ssid": self.ssid,
+            "portal_passphrase": secret(&self.passphrase),
+            "portal_gateway": self.gateway.to_string(),
+            "portal_prefix": self.portal_prefix,
+            "portal_channel": self.portal_channel,
+            "portal_band": self.portal_band.map(|band| band.as_str()),
+            "locale": self.locale.to_string(),
+            "rate_limit": self.rate_limit,
+            "cors_origins": self.cors_origins,
+            "cors_methods": self.cors_methods,
+            "cors_headers": self.cors_headers,
+            "json_case": self.json_case.to_string(),
+            "json_envelope": self.json_envelope,
+            "scan_watch_interval": self.scan_watch_interval,
+            "dbus_wait_timeout": self.dbus_wait_timeout,
+            "lock_file": self.lock_file.display().to_string(),
+            "on_connect": self.on_connect,
+            "on_disconnect": self.on_disconnect,
+            "webhooks": self.webhooks,
+            "hook_timeout": self.hook_timeout,
+            "status_file": self.status_file
+                .as_ref()
+                .map(|path| path.display().to_string()),
+            "portal_dns_rules": self.dns_rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect::<Vec<_>>(),
+            "portal_dns_upstream": self.dns_upstream.map(|upstream| upstream.to_string()),
+            "beacon_port": self.beacon_port,
+            "beacon_interval": self.beacon_interval,
+            "beacon_address": self.beacon_address.map(|address| address.to_string()),
+            "log_format": self.log_format.to_string(),
+            "log_file": self.log_file
+                .as_ref()
+                .map(|path| path.display().to_string()),
+            "log_file_max_size": self.log_file_max_size,
+            "import": self.import
+                .as_ref()
+                .map(|path| path.display().to_string()),
+            "ssdp": self.ssdp,
+            "connect_any_timeout": self.connect_any_timeout,
+            "enrollment_token": secret(&self.enrollment_token),
+            "checkpoint_timeout": self.checkpoint_timeout,
+            "webhook_secret": secret(&self.webhook_secret),
+            "virtual_ap": self.virtual_ap,
+            "log_level": self.log_level.to_string().to_lowercase(),
+            "wifi_p2p": self.wifi_p2p,
+            "nfc_reader": self.nfc_reader,
+            "audio_capture": self.audio_capture,
+            "portal_dhcp_range": self.dhcp_range,
+            "portal_dhcp_lease_time": self.dhcp_lease_time,
+            "portal_dhcp_options": self.dhcp_options,
+            "portal_max_clients": self.portal_max_clients,
+            "ethernet_portal": self.ethernet_portal,
+            "activity_timeout": self.activity_timeout,
+            "ui_directory": self.ui_directory
+                .as_ref()
+                .map(|directory| directory.display().to_string()),
+            "state_directory": self.state_directory.display().to_string(),
+            "mdns_service_name": self.mdns_service_name,
+            "refuse_unknown_bssids": self.refuse_unknown_bssids,
+            "listening_scheme": self.listening_scheme.to_string(),
+            "listening_address": self.listening_address.to_string(),
+            "listening_port": self.listening_port,
+            "tls_certificate": path(&self.tls_certificate),
+            "tls_key": path(&self.tls_key),
+            "dns_servers": self.dns_servers
+                .iter()
+                .map(|server| server.to_string())
+                .collect::<Vec<_>>(),
+            "auth_username": self.auth_username,
+            "auth_password": secret(&self.auth_password),
+            "auth_token": secret(&self.auth_token),
+            "disable_ipv4_listener": self.disable_ipv4_listener,
+            "disable_ipv6_listener": self.disable_ipv6_listener,
+            "monitor": self.monitor,
+            "monitor_interval": self.monitor_interval,
+            "monitor_grace_period": self.monitor_grace_period,
+            "bind_interface": self.bind_interface,
+            "hidden_ssids": self.hidden_ssids,
+            "scan_retries": self.scan_retries,
+            "scan_interval": self.scan_interval,
+            "manage_ethernet": self.manage_ethernet,
+            "ethernet_interface": self.ethernet_interface,
+            "change_network": self.change_network,
+            "rollback_timeout": self.rollback_timeout,
+            "confirm_timeout": self.confirm_timeout,
+            "preserve_existing_profiles": self.preserve_existing_profiles,
+            "portal_tx_power": self.portal_tx_power,
+            "portal_power_save": self.portal_power_save,
+            "dnsmasq": self.dnsmasq,
+            "portal_sleep_timeout": self.portal_sleep_timeout,
+            "portal_beacon_interval": self.portal_beacon_interval,
+            "portal_dtim_period": self.portal_dtim_period,
+            "failure_report_file": self.failure_report_file,
+            "internet_check": self
+                .internet_checks
+                .iter()
+                .map(|probe| probe.to_string())
+                .collect::<Vec<_>>(),
+            "internet_check_timeout": self.internet_check_timeout,
+            "pairing_token": self.pairing_token,
+            "connect_attempts": self.connect_attempts,
+            "connect_backoff": self.connect_backoff,
+            "connect_attempt_timeout": self.connect_attempt_timeout,
+            "startup_policy": if self.lenient { "lenient" } else { "strict" },
+            "max_runtime": self.max_runtime,
+            "dbus": self.dbus,
+            "mqtt_host": self.mqtt_host,
+            "mqtt_port": self.mqtt_port,
+            "mqtt_topic_prefix": self.mqtt_topic_prefix,
+            "mqtt_username": self.mqtt_username,
+            "mqtt_password": secret(&self.mqtt_password),
+            "mqtt_ca_file": path(&self.mqtt_ca_file),
+        })
+    }
+
+    /// The resolved options like `to_json`, each with the source of its
+    /// value, e.g. `{"portal_ssid": {"value": "Factory", "source": "config_file"}}`
+    pub fn to_json_with_sources(&self) -> Value {
+        let mut options = self.to_json();
+
+        if let Value::Object(ref mut options) = options {
+            for (name, value) in options.iter_mut() {
+                let source = self.sources
+                    .get(name)
+                    .map_or(ConfigSource::Default, |source| *source);
+
+                *value = json!({
+                    "value": mem::replace(value, Value::Null),
+                    "source": source.as_str(),
+                });
+            }
+        }
+
+        options
+    }
+
+    /// Summary of the profile, backend, transports and enabled features, to
+    /// be logged as a single line on startup
+    pub fn summary(&self) -> Value {
+        let mut transports = vec![self.listening_scheme.to_string()];
+
+        if self.dbus {
+            transports.push("dbus".to_string());
+        }
+
+        if self.mqtt_host.is_some() {
+            transports.push("mqtt".to_string());
+        }
+
+        let features = [
+            ("monitor", self.monitor),
+            ("change-network", self.change_network),
+            ("manage-ethernet", self.manage_ethernet),
+            ("refuse-unknown-bssids", self.refuse_unknown_bssids),
+            ("bind-interface", self.bind_interface),
+            ("auth", self.auth_password.is_some() || self.auth_token.is_some()),
+            ("enrollment-token", self.enrollment_token.is_some()),
+            ("virtual-ap", self.virtual_ap.is_some()),
+            ("wifi-p2p", self.wifi_p2p),
+            ("nfc", self.nfc_reader.is_some()),
+            ("audio", self.audio_capture.is_some()),
+        ].iter()
+            .filter(|&&(_, enabled)| enabled)
+            .map(|&(feature, _)| feature)
+            .collect::<Vec<_>>();
+
+        json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "profile": self.profile,
+            "backend": "networkmanager",
+            "transports": transports,
+            "features": features,
+        })
+    }
+
+    /// Takes over the reloadable options of a reloaded configuration
+    pub fn apply_reload(&mut self, reloaded: &Config) -> ConfigReload {
+        let current = self.to_json();
+        let next = reloaded.to_json();
+
+        let mut applied = Vec::new();
+        let mut restart_required = Vec::new();
+
+        if let (Some(current), Some(next)) = (current.as_object(), next.as_object()) {
+            for (name, value) in next {
+                if current.get(name) == Some(value) {
+                    continue;
+                }
+
+                if RELOADABLE_OPTIONS.contains(&(name as &str)) {
+                    applied.push(name.clone());
+                } else {
+                    restart_required.push(name.clone());
+                }
+            }
+        }
+
+        self.log_level = reloaded.log_level;
+        self.connect_attempts = reloaded.connect_attempts;
+        self.connect_backoff = reloaded.connect_backoff;
+        self.connect_attempt_timeout = reloaded.connect_attempt_timeout;
+        self.connect_any_timeout = reloaded.connect_any_timeout;
+        self.confirm_timeout = reloaded.confirm_timeout;
+        self.rollback_timeout = reloaded.rollback_timeout;
+        self.checkpoint_timeout = reloaded.checkpoint_timeout;
+        self.scan_retries = reloaded.scan_retries;
+        self.scan_interval = reloaded.scan_interval;
+        self.internet_checks = reloaded.internet_checks.clone();
+        self.internet_check_timeout = reloaded.internet_check_timeout;
+        self.refuse_unknown_bssids = reloaded.refuse_unknown_bssids;
+        self.dns_rules = reloaded.dns_rules.clone();
+        self.on_connect = reloaded.on_connect.clone();
+        self.on_disconnect = reloaded.on_disconnect.clone();
+        self.webhooks = reloaded.webhooks.clone();
+        self.webhook_secret = reloaded.webhook_secret.clone();
+        self.hook_timeout = reloaded.hook_timeout;
+
+        for name in &applied {
+            if let Some(source) = reloaded.sources.get(name) {
+                self.sources.insert(name.clone(), *source);
+            }
+        }
+
+        ConfigReload {
+            applied: applied,
+            restart_required: restart_required,
+        }
+    }
+}
+
+/// Where the value of an option comes from, in the order of precedence
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConfigSource {
+    CommandLine,
+    Environment,
+    ConfigFile,
+    Default,
+}
+
+impl ConfigSource {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            ConfigSource::CommandLine => "command_line",
+            ConfigSource::Environment => "environment",
+            ConfigSource::ConfigFile => "config_file",
+            ConfigSource::Default => "default",
+        }
+    }
+}
+
+/// The configuration as last reloaded, for the threads which apply the
+/// reloadable options
+pub type SharedConfig = Arc<Mutex<Config>>;
+
+/// Options of a reloaded configuration which differ from the running one
+pub struct ConfigReload {
+    /// Reloadable options, which apply right away
+    pub applied: Vec<String>,
+    /// Other options, which need a restart
+    pub restart_required: Vec<String>,
+}
+
+/// Operations run directly instead of opening the captive portal
+#[derive(Clone)]
+pub enum CliCommand {
+    Scan,
+    Connect {
+        ssid: String,
+        passphrase: String,
+        reuse: bool,
+    },
+    Status,
+    Forget { ssid: String },
+    CheckConfig,
+}
+
+/// Reads the configuration on startup. Invalid command line arguments print
+/// the usage and exit, like with any clap application.
+pub fn get_config() -> Result<Config, String> {
+    let matches = get_matches().unwrap_or_else(|e| e.exit());
+
+    parse_config(matches)
+}
+
+/// Reads the configuration again like on startup, with the current settings
+/// of the config file. Nothing is printed and the process keeps running if
+/// the configuration is invalid.
+pub fn reload_config() -> Result<Config, String> {
+    let matches = get_matches().map_err(|e| e.message)?;
+
+    parse_config(matches)
 }
 
-pub fn get_config() -> Config {
-    let matches = App::new(env!("CARGO_PKG_NAME"))
+fn get_matches() -> ::std::result::Result<ArgMatches<'static>, ::clap::Error> {
+    App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
@@ -61,7 +587,9 @@ pub fn get_config() -> Config {
                 .long("portal-gateway")
                 .value_name("gateway")
                 .help(&format!(
-                    "Gateway of the captive portal WiFi network (default: {})",
+                    "Gateway of the captive portal WiFi network, optionally with the prefix \
+                     length, e.g. 10.0.0.1/16, or `auto` to derive it from the MAC address \
+                     (default: {})",
                     DEFAULT_GATEWAY
                 ))
                 .takes_value(true),
@@ -71,18 +599,34 @@ pub fn get_config() -> Config {
                 .short("d")
                 .long("portal-dhcp-range")
                 .value_name("dhcp_range")
-                .help(&format!(
-                    "DHCP range of the WiFi network (default: {})",
-                    DEFAULT_DHCP_RANGE
-                ))
+                .help(
+                    "DHCP range of the WiFi network (default: the addresses of the gateway \
+                     network after the gateway)",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-dhcp-lease-time")
+                .long("portal-dhcp-lease-time")
+                .value_name("lease_time")
+                .help("DHCP lease time of the WiFi network, e.g. 10m (default: 1h)")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("portal-dhcp-option")
+                .long("portal-dhcp-option")
+                .value_name("dhcp_option")
+                .help("Additional DHCP option of the WiFi network, e.g. 114,http://192.168.42.1/")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             Arg::with_name("activity-timeout")
                 .short("a")
                 .long("activity-timeout")
                 .value_name("activity_timeout")
-                .help("Exit if no activity for the specified time (seconds) (default: none)")
+                .help("Exit after no activity for the specified time (seconds) (default: none)")
                 .takes_value(true),
         )
         .arg(
@@ -90,71 +634,1810 @@ pub fn get_config() -> Config {
                 .short("u")
                 .long("ui-directory")
                 .value_name("ui_directory")
+                .help("Web UI directory location (default: the embedded UI)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("state-directory")
+                .long("state-directory")
+                .value_name("state_directory")
                 .help(&format!(
-                    "Web UI directory location (default: {})",
-                    DEFAULT_UI_DIRECTORY
+                    "Directory for state persisted across restarts (default: {})",
+                    DEFAULT_STATE_DIRECTORY
                 ))
                 .takes_value(true),
         )
-        .get_matches();
-
-    let interface: Option<String> = matches.value_of("portal-interface").map_or_else(
-        || env::var("PORTAL_INTERFACE").ok(),
-        |v| Some(v.to_string()),
-    );
-
-    let ssid: String = matches.value_of("portal-ssid").map_or_else(
-        || env::var("PORTAL_SSID").unwrap_or_else(|_| DEFAULT_SSID.to_string()),
-        String::from,
-    );
-
-    let passphrase: Option<String> = matches.value_of("portal-passphrase").map_or_else(
-        || env::var("PORTAL_PASSPHRASE").ok(),
-        |v| Some(v.to_string()),
-    );
-
-    let gateway = Ipv4Addr::from_str(&matches.value_of("portal-gateway").map_or_else(
-        || env::var("PORTAL_GATEWAY").unwrap_or_else(|_| DEFAULT_GATEWAY.to_string()),
-        String::from,
-    )).expect("Cannot parse gateway address");
-
-    let dhcp_range = matches.value_of("portal-dhcp-range").map_or_else(
-        || env::var("PORTAL_DHCP_RANGE").unwrap_or_else(|_| DEFAULT_DHCP_RANGE.to_string()),
-        String::from,
-    );
-
-    let activity_timeout = u64::from_str(&matches.value_of("activity-timeout").map_or_else(
-        || env::var("ACTIVITY_TIMEOUT").unwrap_or_else(|_| DEFAULT_ACTIVITY_TIMEOUT.to_string()),
-        String::from,
-    )).expect("Cannot parse activity timeout");
-
-    let ui_directory = get_ui_directory(matches.value_of("ui-directory"));
-
-    Config {
-        interface: interface,
-        ssid: ssid,
-        passphrase: passphrase,
-        gateway: gateway,
-        dhcp_range: dhcp_range,
-        activity_timeout: activity_timeout,
-        ui_directory: ui_directory,
-    }
-}
-
-fn get_ui_directory(cmd_ui_directory: Option<&str>) -> PathBuf {
-    if let Some(ui_directory) = cmd_ui_directory {
-        return PathBuf::from(ui_directory);
-    }
-
-    if let Ok(ui_directory) = env::var("UI_DIRECTORY") {
-        return PathBuf::from(ui_directory);
+        .arg(
+            Arg::with_name("mdns-service-name")
+                .long("mdns-service-name")
+                .value_name("mdns_service_name")
+                .help(&format!(
+                    "Service name of the portal advertised over mDNS (default: {})",
+                    DEFAULT_MDNS_SERVICE_NAME
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("refuse-unknown-bssids")
+                .long("refuse-unknown-bssids")
+                .help("Refuse connecting to saved SSIDs visible only from unknown BSSIDs"),
+        )
+        .arg(
+            Arg::with_name("listening-scheme")
+                .long("listening-scheme")
+                .value_name("scheme")
+                .possible_values(&["http", "https"])
+                .help(&format!(
+                    "Scheme of the HTTP server (default: {})",
+                    DEFAULT_LISTENING_SCHEME
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listening-port")
+                .long("listening-port")
+                .value_name("port")
+                .help("Port of the HTTP server (default: 80 for http, 443 for https)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-certificate")
+                .long("tls-certificate")
+                .value_name("certificate")
+                .help("PEM certificate for HTTPS (default: self-signed)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .value_name("key")
+                .help("PEM private key for HTTPS (default: self-signed)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dns-servers")
+                .long("dns-servers")
+                .value_name("dns_servers")
+                .help("Comma separated DNS servers forced on the provisioned connection")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("auth-username")
+                .long("auth-username")
+                .value_name("username")
+                .help("Username for HTTP basic authentication of the API")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("auth-password")
+                .long("auth-password")
+                .value_name("password")
+                .help("Password for HTTP basic authentication of the API")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("auth-token")
+                .long("auth-token")
+                .value_name("token")
+                .help("Bearer token for authentication of the API")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listening-address")
+                .long("listening-address")
+                .value_name("address")
+                .help("Address (and optionally port) the HTTP server binds to (default: gateway)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("disable-ipv4-listener")
+                .long("disable-ipv4-listener")
+                .help("Do not listen on the listening address"),
+        )
+        .arg(
+            Arg::with_name("disable-ipv6-listener")
+                .long("disable-ipv6-listener")
+                .help("Do not listen on the link-local IPv6 address of the portal interface"),
+        )
+        .arg(
+            Arg::with_name("monitor")
+                .long("monitor")
+                .help("Keep running after connecting and monitor the connectivity"),
+        )
+        .arg(
+            Arg::with_name("monitor-interval")
+                .long("monitor-interval")
+                .value_name("monitor_interval")
+                .help(&format!(
+                    "Connectivity check interval in monitor mode (seconds) (default: {})",
+                    DEFAULT_MONITOR_INTERVAL
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("monitor-grace-period")
+                .long("monitor-grace-period")
+                .value_name("monitor_grace_period")
+                .help(&format!(
+                    "Time without connectivity before reconnecting (seconds) (default: {})",
+                    DEFAULT_MONITOR_GRACE_PERIOD
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bind-interface")
+                .long("bind-interface")
+                .help("Bind the HTTP server to the address of the portal interface"),
+        )
+        .arg(
+            Arg::with_name("hidden-ssids")
+                .long("hidden-ssids")
+                .value_name("hidden_ssids")
+                .help("Comma-separated hidden SSIDs to probe for with active scans")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scan-retries")
+                .long("scan-retries")
+                .value_name("scan_retries")
+                .help(&format!(
+                    "Number of scans while waiting for access points (default: {})",
+                    DEFAULT_SCAN_RETRIES
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("scan-interval")
+                .long("scan-interval")
+                .value_name("scan_interval")
+                .help(&format!(
+                    "Interval between scans for access points (seconds) (default: {})",
+                    DEFAULT_SCAN_INTERVAL
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("manage-ethernet")
+                .long("manage-ethernet")
+                .help("Report and configure the wired device through the portal"),
+        )
+        .arg(
+            Arg::with_name("ethernet-interface")
+                .long("ethernet-interface")
+                .value_name("ethernet_interface")
+                .help("Wired network interface to be managed by WiFi Connect")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("change-network")
+                .long("change-network")
+                .help("Allow switching networks while online, rolling back on failure"),
+        )
+        .arg(
+            Arg::with_name("rollback-timeout")
+                .long("rollback-timeout")
+                .value_name("rollback_timeout")
+                .help(&format!(
+                    "Time for a new network to get connectivity before rolling back (seconds) \
+                     (default: {})",
+                    DEFAULT_ROLLBACK_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("confirm-timeout")
+                .long("confirm-timeout")
+                .value_name("confirm_timeout")
+                .help(&format!(
+                    "Time to confirm advanced network changes before rolling back (seconds) \
+                     (default: {})",
+                    DEFAULT_CONFIRM_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("config-file")
+                .long("config-file")
+                .value_name("config_file")
+                .help(&format!(
+                    "TOML file with the config profiles (default: {})",
+                    DEFAULT_CONFIG_FILE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .value_name("profile")
+                .help("Config profile of the TOML file to apply")
+                .takes_value(true),
+        )
+        .subcommand(SubCommand::with_name("scan").about("List the visible access points as JSON"))
+        .subcommand(
+            SubCommand::with_name("connect")
+                .about("Connect to an access point and print the result as JSON")
+                .arg(
+                    Arg::with_name("ssid")
+                        .long("ssid")
+                        .value_name("ssid")
+                        .help("SSID of the access point")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("passphrase")
+                        .long("passphrase")
+                        .alias("pass")
+                        .value_name("passphrase")
+                        .help("Passphrase of the access point")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("reuse")
+                        .long("reuse")
+                        .help("Activate the saved profile of the SSID, if there is one"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("status").about("Print the connection status as JSON"))
+        .subcommand(
+            SubCommand::with_name("check-config")
+                .about("Validate the configuration and print a report as JSON"),
+        )
+        .subcommand(
+            SubCommand::with_name("forget")
+                .about("Delete the saved connection profiles of an SSID")
+                .arg(
+                    Arg::with_name("ssid")
+                        .long("ssid")
+                        .value_name("ssid")
+                        .help("SSID of the connection profiles")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .arg(
+            Arg::with_name("dbus")
+                .long("dbus")
+                .help("Export the provisioning state and methods on the system D-Bus"),
+        )
+        .arg(
+            Arg::with_name("print-config")
+                .long("print-config")
+                .help("Print the resolved configuration as JSON and exit"),
+        )
+        .arg(
+            Arg::with_name("mqtt-host")
+                .long("mqtt-host")
+                .value_name("mqtt_host")
+                .help("MQTT broker to publish the provisioning status to")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-port")
+                .long("mqtt-port")
+                .value_name("mqtt_port")
+                .help(&format!("Port of the MQTT broker (default: {})", DEFAULT_MQTT_PORT))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-topic-prefix")
+                .long("mqtt-topic-prefix")
+                .value_name("mqtt_topic_prefix")
+                .help(&format!(
+                    "Prefix of the MQTT topics (default: {})",
+                    DEFAULT_MQTT_TOPIC_PREFIX
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-username")
+                .long("mqtt-username")
+                .value_name("username")
+                .help("Username for the MQTT broker")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-password")
+                .long("mqtt-password")
+                .value_name("password")
+                .help("Password for the MQTT broker")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("mqtt-ca-file")
+                .long("mqtt-ca-file")
+                .value_name("ca_file")
+                .help("PEM CA certificates to connect to the MQTT broker with TLS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("preserve-existing-profiles")
+                .long("preserve-existing-profiles")
+                .help("Update and reactivate saved profiles instead of recreating them"),
+        )
+        .arg(
+            Arg::with_name("portal-tx-power")
+                .long("portal-tx-power")
+                .value_name("tx_power")
+                .help("Transmit power of the captive portal WiFi network (dBm)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-power-save")
+                .long("portal-power-save")
+                .help("Enable WiFi power save while the captive portal is open"),
+        )
+        .arg(
+            Arg::with_name("portal-sleep-timeout")
+                .long("portal-sleep-timeout")
+                .value_name("sleep_timeout")
+                .help("Time without clients before the portal sleeps (default: 0 - never)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-beacon-interval")
+                .long("portal-beacon-interval")
+                .value_name("beacon_interval")
+                .help("Beacon interval of the captive portal WiFi network (TU)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-dtim-period")
+                .long("portal-dtim-period")
+                .value_name("dtim_period")
+                .help("DTIM period of the captive portal WiFi network (beacons)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dnsmasq")
+                .long("dnsmasq")
+                .help("Serve DHCP and DNS with dnsmasq instead of the built-in servers"),
+        )
+        .arg(
+            Arg::with_name("failure-report-file")
+                .long("failure-report-file")
+                .value_name("failure_report_file")
+                .help("File to write a JSON failure report to when exiting with an error")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("internet-check")
+                .long("internet-check")
+                .value_name("internet_check")
+                .help("Comma separated Internet check probes, e.g. tcp:1.1.1.1:53")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("internet-check-timeout")
+                .long("internet-check-timeout")
+                .value_name("internet_check_timeout")
+                .help("Timeout of each Internet check probe (secs)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pairing-token")
+                .long("pairing-token")
+                .help("Append a pairing token to the portal SSID, required by mutating requests"),
+        )
+        .arg(
+            Arg::with_name("connect-attempts")
+                .long("connect-attempts")
+                .value_name("connect_attempts")
+                .help("Attempts to connect to the selected network before reopening the portal")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("connect-backoff")
+                .long("connect-backoff")
+                .value_name("connect_backoff")
+                .help("Delay before the first connection retry, then doubled (secs)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("connect-attempt-timeout")
+                .long("connect-attempt-timeout")
+                .value_name("connect_attempt_timeout")
+                .help("Time to wait for Internet connectivity in each connection attempt (secs)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-max-clients")
+                .long("portal-max-clients")
+                .value_name("max_clients")
+                .help("Maximum number of clients served by the captive portal, 0 for no limit")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strict")
+                .long("strict")
+                .conflicts_with("lenient")
+                .help("Abort on any startup failure (default)"),
+        )
+        .arg(
+            Arg::with_name("lenient")
+                .long("lenient")
+                .help("Continue in a degraded mode after non-fatal startup failures"),
+        )
+        .arg(
+            Arg::with_name("max-runtime")
+                .long("max-runtime")
+                .value_name("max_runtime")
+                .help("Exit after this time whatever the state, 0 for no limit (secs)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-channel")
+                .long("portal-channel")
+                .value_name("channel")
+                .help("Channel of the captive portal WiFi network (default: automatic)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-band")
+                .long("portal-band")
+                .value_name("band")
+                .help("Band of the captive portal network: 2.4ghz or 5ghz (default: automatic)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ethernet-portal")
+                .long("ethernet-portal")
+                .help("Serve the captive portal over Ethernet if there is no WiFi device"),
+        )
+        .arg(
+            Arg::with_name("locale")
+                .long("locale")
+                .value_name("locale")
+                .help(&format!(
+                    "Language of the user-facing milestone messages: en, de, es or fr \
+                     (default: {})",
+                    DEFAULT_LOCALE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rate-limit")
+                .long("rate-limit")
+                .value_name("rate_limit")
+                .help(&format!(
+                    "Mutating requests allowed per client and minute, 0 for no limit \
+                     (default: {})",
+                    DEFAULT_RATE_LIMIT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cors-origin")
+                .long("cors-origin")
+                .value_name("cors_origin")
+                .help("Comma separated origins allowed to call the API, or * (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cors-methods")
+                .long("cors-methods")
+                .value_name("cors_methods")
+                .help(&format!(
+                    "Comma separated methods allowed for other origins (default: {})",
+                    DEFAULT_CORS_METHODS
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cors-headers")
+                .long("cors-headers")
+                .value_name("cors_headers")
+                .help(&format!(
+                    "Comma separated request headers allowed for other origins (default: {})",
+                    DEFAULT_CORS_HEADERS
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json-case")
+                .long("json-case")
+                .value_name("json_case")
+                .help("Casing of the JSON field names of the API: snake or camel (default: snake)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("json-envelope")
+                .long("json-envelope")
+                .help("Wrap the JSON responses of the API in a {success, data, error} envelope"),
+        )
+        .arg(
+            Arg::with_name("scan-watch-interval")
+                .long("scan-watch-interval")
+                .value_name("interval")
+                .help("Rescan in the background and report access points coming into and \
+                       out of range, 0 to disable (secs) (default: 0)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dbus-wait-timeout")
+                .long("dbus-wait-timeout")
+                .value_name("dbus_wait_timeout")
+                .help(&format!(
+                    "Time to wait for the system D-Bus at startup (secs) (default: {})",
+                    DEFAULT_DBUS_WAIT_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("lock-file")
+                .long("lock-file")
+                .value_name("lock_file")
+                .help(&format!(
+                    "Lock file guarding against concurrent instances (default: {})",
+                    DEFAULT_LOCK_FILE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("on-connect")
+                .long("on-connect")
+                .value_name("on_connect")
+                .help("Shell command run after connecting to a network (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("on-disconnect")
+                .long("on-disconnect")
+                .value_name("on_disconnect")
+                .help("Shell command run after disconnecting from a network (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("webhook")
+                .long("webhook")
+                .value_name("webhook")
+                .help("Comma separated URLs notified of connects and disconnects (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("hook-timeout")
+                .long("hook-timeout")
+                .value_name("hook_timeout")
+                .help(&format!(
+                    "Time a hook command or webhook may take (secs) (default: {})",
+                    DEFAULT_HOOK_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("status-file")
+                .long("status-file")
+                .value_name("status_file")
+                .help("JSON file kept up to date with the current state (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("portal-dns-rule")
+                .long("portal-dns-rule")
+                .value_name("dns_rule")
+                .help("Captive portal DNS rule, e.g. forward:api.example.com,*.example.net")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("portal-dns-upstream")
+                .long("portal-dns-upstream")
+                .value_name("dns_upstream")
+                .help("DNS server of the forwarded queries (default: the system name server)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("beacon-port")
+                .long("beacon-port")
+                .value_name("beacon_port")
+                .help("UDP port of the discovery beacon, 0 to disable (default: 0)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("beacon-interval")
+                .long("beacon-interval")
+                .value_name("beacon_interval")
+                .help(&format!(
+                    "Interval of the discovery beacon (secs) (default: {})",
+                    DEFAULT_BEACON_INTERVAL
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("beacon-address")
+                .long("beacon-address")
+                .value_name("beacon_address")
+                .help("Destination of the discovery beacon (default: portal subnet broadcast)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-format")
+                .long("log-format")
+                .value_name("log_format")
+                .help("Format of the logs: text or json (default: text)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-file")
+                .long("log-file")
+                .value_name("log_file")
+                .help("File the logs are written to as well (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-file-max-size")
+                .long("log-file-max-size")
+                .value_name("log_file_max_size")
+                .help(&format!(
+                    "Size of the log file at which it is rotated (KiB) (default: {})",
+                    DEFAULT_LOG_FILE_MAX_SIZE
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import")
+                .long("import")
+                .value_name("import")
+                .help("Networks file or directory of profiles to create at startup (default: none)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ssdp")
+                .long("ssdp")
+                .help("Announce the captive portal over SSDP for the network view of Windows"),
+        )
+        .arg(
+            Arg::with_name("connect-any-timeout")
+                .long("connect-any-timeout")
+                .value_name("connect_any_timeout")
+                .help(&format!(
+                    "Time to wait for Internet connectivity of each saved network tried by \
+                     /connect/any (secs) (default: {})",
+                    DEFAULT_CONNECT_ANY_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("enrollment-token")
+                .long("enrollment-token")
+                .value_name("token")
+                .help("Token from the label of the device which connect requests must include")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("checkpoint-timeout")
+                .long("checkpoint-timeout")
+                .value_name("checkpoint_timeout")
+                .help(&format!(
+                    "Time to confirm connectivity after connecting or disconnecting before the \
+                     previous network state is restored, 0 to disable (secs) (default: {})",
+                    DEFAULT_CHECKPOINT_TIMEOUT
+                ))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("webhook-secret")
+                .long("webhook-secret")
+                .value_name("secret")
+                .help("Shared secret the webhook payloads are signed with (HMAC-SHA256)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("virtual-ap")
+                .long("virtual-ap")
+                .value_name("interface")
+                .help("Virtual interface to run the captive portal on next to the client one")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .value_name("log_level")
+                .help("Level of the logs: off, error, warn, info, debug or trace (default: info)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("wifi-p2p")
+                .long("wifi-p2p")
+                .help("Offer the captive portal over Wi-Fi Direct as well"),
+        )
+        .arg(
+            Arg::with_name("nfc-reader")
+                .long("nfc-reader")
+                .value_name("command")
+                .help("Command printing the NDEF messages of the NFC tags read, in hex")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("audio-capture")
+                .long("audio-capture")
+                .value_name("device")
+                .help("ALSA device to decode WiFi credentials played as DTMF tones from")
+                .takes_value(true),
+        )
+        .get_matches_safe()
+}
+
+/// Resolves the options from the command line, the environment and the
+/// config file, in that order of precedence
+fn parse_config(matches: ArgMatches) -> Result<Config, String> {
+    let profile: Option<String> = matches.value_of("profile").map_or_else(
+        || env::var("PROFILE").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let config_file: Option<PathBuf> = matches.value_of("config-file").map_or_else(
+        || env::var("CONFIG_FILE").ok().map(PathBuf::from),
+        |v| Some(PathBuf::from(v)),
+    );
+
+    let config_file =
+        read_config_file(config_file, profile.as_ref().map(|profile| profile as &str))?;
+
+    let vars = Variables {
+        config_file: config_file,
+    };
+
+    let interface: Option<String> = matches.value_of("portal-interface").map_or_else(
+        || vars.var("PORTAL_INTERFACE").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    // A non UTF-8 environment variable is an error rather than a fallback to
+    // the default SSID
+    let ssid: String = matches.value_of("portal-ssid").map_or_else(
+        || {
+            vars.var_os("PORTAL_SSID").map_or_else(
+                || Ok(DEFAULT_SSID.to_string()),
+                |ssid| {
+                    ssid.into_string()
+                        .map_err(|_| "Portal SSID is not valid UTF-8".to_string())
+                },
+            )
+        },
+        |v| Ok(v.to_string()),
+    )?;
+
+    validate_ssid(&ssid).map_err(|e| format!("Invalid portal SSID: {}", e))?;
+
+    let passphrase: Option<String> = matches.value_of("portal-passphrase").map_or_else(
+        || vars.var("PORTAL_PASSPHRASE").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let gateway = matches.value_of("portal-gateway").map_or_else(
+        || vars.var("PORTAL_GATEWAY").unwrap_or_else(|_| DEFAULT_GATEWAY.to_string()),
+        String::from,
+    );
+
+    let (gateway, portal_prefix) = if gateway == "auto" {
+        let mac = get_mac_address(interface.as_ref().map(|interface| interface as &str))
+            .ok_or_else(|| "Cannot derive gateway address from the MAC address".to_string())?;
+
+        (subnet::derive_gateway(mac), subnet::DEFAULT_PREFIX)
+    } else {
+        subnet::parse_gateway(&gateway).map_err(|e| format!("Cannot parse gateway address: {}", e))?
+    };
+
+    let dhcp_range = matches.value_of("portal-dhcp-range").map_or_else(
+        || {
+            vars.var("PORTAL_DHCP_RANGE")
+                .unwrap_or_else(|_| subnet::default_dhcp_range(gateway, portal_prefix))
+        },
+        String::from,
+    );
+
+    validate_dhcp_range(&dhcp_range, gateway, portal_prefix)
+        .map_err(|e| format!("Invalid DHCP range: {}", e))?;
+
+    let activity_timeout = u64::from_str(&matches.value_of("activity-timeout").map_or_else(
+        || vars.var("ACTIVITY_TIMEOUT").unwrap_or_else(|_| DEFAULT_ACTIVITY_TIMEOUT.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse activity timeout: {}", e))?;
+
+    let ui_directory = get_ui_directory(matches.value_of("ui-directory"), &vars);
+
+    let state_directory = PathBuf::from(matches.value_of("state-directory").map_or_else(
+        || vars.var("STATE_DIRECTORY").unwrap_or_else(|_| DEFAULT_STATE_DIRECTORY.to_string()),
+        String::from,
+    ));
+
+    let mdns_service_name = matches.value_of("mdns-service-name").map_or_else(
+        || vars.var("MDNS_SERVICE_NAME").unwrap_or_else(|_| DEFAULT_MDNS_SERVICE_NAME.to_string()),
+        String::from,
+    );
+
+    let refuse_unknown_bssids =
+        get_flag(&matches, &vars, "refuse-unknown-bssids", "REFUSE_UNKNOWN_BSSIDS");
+
+    let listening_scheme = Scheme::from_str(&matches.value_of("listening-scheme").map_or_else(
+        || vars.var("LISTENING_SCHEME").unwrap_or_else(|_| DEFAULT_LISTENING_SCHEME.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse listening scheme: {}", e))?;
+
+    let listening_port = matches
+        .value_of("listening-port")
+        .map(String::from)
+        .or_else(|| vars.var("LISTENING_PORT").ok())
+        .map_or_else(
+            || Ok(listening_scheme.default_port()),
+            |port| {
+                u16::from_str(&port).map_err(|e| format!("Cannot parse listening port: {}", e))
+            },
+        )?;
+
+    let tls_certificate: Option<PathBuf> = matches.value_of("tls-certificate").map_or_else(
+        || vars.var("TLS_CERTIFICATE").ok().map(PathBuf::from),
+        |v| Some(PathBuf::from(v)),
+    );
+
+    let tls_key: Option<PathBuf> = matches.value_of("tls-key").map_or_else(
+        || vars.var("TLS_KEY").ok().map(PathBuf::from),
+        |v| Some(PathBuf::from(v)),
+    );
+
+    let dns_servers = matches
+        .value_of("dns-servers")
+        .map(String::from)
+        .or_else(|| vars.var("DNS_SERVERS").ok())
+        .map_or_else(
+            || Ok(Vec::new()),
+            |servers| {
+                parse_ip_list(&servers).map_err(|e| format!("Cannot parse DNS servers: {}", e))
+            },
+        )?;
+
+    let auth_username: Option<String> = matches.value_of("auth-username").map_or_else(
+        || vars.var("AUTH_USERNAME").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let auth_password: Option<String> = matches.value_of("auth-password").map_or_else(
+        || vars.var("AUTH_PASSWORD").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let auth_token: Option<String> = matches.value_of("auth-token").map_or_else(
+        || vars.var("AUTH_TOKEN").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let (listening_address, listening_port) = get_listening_address(
+        matches.value_of("listening-address"),
+        &vars,
+        IpAddr::V4(gateway),
+        listening_port,
+    )?;
+
+    let disable_ipv4_listener =
+        get_flag(&matches, &vars, "disable-ipv4-listener", "DISABLE_IPV4_LISTENER");
+
+    let disable_ipv6_listener =
+        get_flag(&matches, &vars, "disable-ipv6-listener", "DISABLE_IPV6_LISTENER");
+
+    let monitor = get_flag(&matches, &vars, "monitor", "MONITOR");
+
+    let monitor_interval = u64::from_str(&matches.value_of("monitor-interval").map_or_else(
+        || vars.var("MONITOR_INTERVAL").unwrap_or_else(|_| DEFAULT_MONITOR_INTERVAL.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse monitor interval: {}", e))?;
+
+    let monitor_grace_period = u64::from_str(
+        &matches.value_of("monitor-grace-period").map_or_else(
+            || {
+                vars.var("MONITOR_GRACE_PERIOD")
+                    .unwrap_or_else(|_| DEFAULT_MONITOR_GRACE_PERIOD.to_string())
+            },
+            String::from,
+        ),
+    ).map_err(|e| format!("Cannot parse monitor grace period: {}", e))?;
+
+    let bind_interface = get_flag(&matches, &vars, "bind-interface", "BIND_INTERFACE");
+
+    let hidden_ssids = matches
+        .value_of("hidden-ssids")
+        .map(String::from)
+        .or_else(|| vars.var("HIDDEN_SSIDS").ok())
+        .map_or_else(Vec::new, |ssids| {
+            ssids
+                .split(',')
+                .map(|ssid| ssid.trim().to_string())
+                .filter(|ssid| !ssid.is_empty())
+                .collect()
+        });
+
+    let scan_retries = u32::from_str(&matches.value_of("scan-retries").map_or_else(
+        || vars.var("SCAN_RETRIES").unwrap_or_else(|_| DEFAULT_SCAN_RETRIES.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse scan retries: {}", e))?;
+
+    let scan_interval = u64::from_str(&matches.value_of("scan-interval").map_or_else(
+        || vars.var("SCAN_INTERVAL").unwrap_or_else(|_| DEFAULT_SCAN_INTERVAL.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse scan interval: {}", e))?;
+
+    let manage_ethernet = get_flag(&matches, &vars, "manage-ethernet", "MANAGE_ETHERNET");
+
+    let ethernet_interface: Option<String> = matches.value_of("ethernet-interface").map_or_else(
+        || vars.var("ETHERNET_INTERFACE").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let change_network = get_flag(&matches, &vars, "change-network", "CHANGE_NETWORK");
+
+    let rollback_timeout = u64::from_str(&matches.value_of("rollback-timeout").map_or_else(
+        || vars.var("ROLLBACK_TIMEOUT").unwrap_or_else(|_| DEFAULT_ROLLBACK_TIMEOUT.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse rollback timeout: {}", e))?;
+
+    let confirm_timeout = u64::from_str(&matches.value_of("confirm-timeout").map_or_else(
+        || vars.var("CONFIRM_TIMEOUT").unwrap_or_else(|_| DEFAULT_CONFIRM_TIMEOUT.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse confirm timeout: {}", e))?;
+
+    let command = match matches.subcommand() {
+        ("scan", _) => Some(CliCommand::Scan),
+        ("connect", Some(matches)) => Some(CliCommand::Connect {
+            ssid: matches.value_of("ssid").unwrap().to_string(),
+            passphrase: matches.value_of("passphrase").unwrap_or("").to_string(),
+            reuse: matches.is_present("reuse"),
+        }),
+        ("status", _) => Some(CliCommand::Status),
+        ("check-config", _) => Some(CliCommand::CheckConfig),
+        ("forget", Some(matches)) => Some(CliCommand::Forget {
+            ssid: matches.value_of("ssid").unwrap().to_string(),
+        }),
+        _ => None,
+    };
+
+    let dbus = get_flag(&matches, &vars, "dbus", "DBUS");
+
+    let print_config = matches.is_present("print-config");
+
+    let mqtt_host: Option<String> = matches.value_of("mqtt-host").map_or_else(
+        || vars.var("MQTT_HOST").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let mqtt_port = u16::from_str(&matches.value_of("mqtt-port").map_or_else(
+        || vars.var("MQTT_PORT").unwrap_or_else(|_| DEFAULT_MQTT_PORT.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse MQTT port: {}", e))?;
+
+    let mqtt_topic_prefix = matches.value_of("mqtt-topic-prefix").map_or_else(
+        || vars.var("MQTT_TOPIC_PREFIX").unwrap_or_else(|_| DEFAULT_MQTT_TOPIC_PREFIX.to_string()),
+        String::from,
+    );
+
+    let mqtt_username: Option<String> = matches.value_of("mqtt-username").map_or_else(
+        || vars.var("MQTT_USERNAME").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let mqtt_password: Option<String> = matches.value_of("mqtt-password").map_or_else(
+        || vars.var("MQTT_PASSWORD").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let mqtt_ca_file: Option<PathBuf> = matches.value_of("mqtt-ca-file").map_or_else(
+        || vars.var("MQTT_CA_FILE").ok().map(PathBuf::from),
+        |v| Some(PathBuf::from(v)),
+    );
+
+    let preserve_existing_profiles =
+        get_flag(&matches, &vars, "preserve-existing-profiles", "PRESERVE_EXISTING_PROFILES");
+
+    let portal_tx_power = matches
+        .value_of("portal-tx-power")
+        .map(String::from)
+        .or_else(|| vars.var("PORTAL_TX_POWER").ok())
+        .map(|tx_power| {
+            u32::from_str(&tx_power).map_err(|e| format!("Cannot parse portal TX power: {}", e))
+        });
+    let portal_tx_power = transpose(portal_tx_power)?;
+
+    let portal_power_save = get_flag(&matches, &vars, "portal-power-save", "PORTAL_POWER_SAVE");
+
+    let dhcp_lease_time: Option<String> = matches.value_of("portal-dhcp-lease-time").map_or_else(
+        || vars.var("PORTAL_DHCP_LEASE_TIME").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    if let Some(ref lease_time) = dhcp_lease_time {
+        validate_lease_time(lease_time).map_err(|e| format!("Invalid DHCP lease time: {}", e))?;
+    }
+
+    let dhcp_options = match matches.values_of("portal-dhcp-option") {
+        Some(options) => options.map(String::from).collect(),
+        None => vars.var("PORTAL_DHCP_OPTION").ok().map_or_else(Vec::new, |options| {
+            options
+                .split(';')
+                .map(|option| option.trim().to_string())
+                .filter(|option| !option.is_empty())
+                .collect()
+        }),
+    };
+
+    let portal_sleep_timeout = u64::from_str(&matches.value_of("portal-sleep-timeout").map_or_else(
+        || {
+            vars.var("PORTAL_SLEEP_TIMEOUT")
+                .unwrap_or_else(|_| DEFAULT_PORTAL_SLEEP_TIMEOUT.to_string())
+        },
+        String::from,
+    )).map_err(|e| format!("Cannot parse portal sleep timeout: {}", e))?;
+
+    let portal_beacon_interval = matches
+        .value_of("portal-beacon-interval")
+        .map(String::from)
+        .or_else(|| vars.var("PORTAL_BEACON_INTERVAL").ok())
+        .map(|interval| {
+            match u16::from_str(&interval) {
+                Ok(interval) if interval >= 15 => Ok(interval),
+                _ => Err(format!("Cannot parse portal beacon interval: {}", interval)),
+            }
+        });
+    let portal_beacon_interval = transpose(portal_beacon_interval)?;
+
+    let portal_dtim_period = matches
+        .value_of("portal-dtim-period")
+        .map(String::from)
+        .or_else(|| vars.var("PORTAL_DTIM_PERIOD").ok())
+        .map(|period| {
+            match u8::from_str(&period) {
+                Ok(period) if period >= 1 => Ok(period),
+                _ => Err(format!("Cannot parse portal DTIM period: {}", period)),
+            }
+        });
+    let portal_dtim_period = transpose(portal_dtim_period)?;
+
+    let dnsmasq = get_flag(&matches, &vars, "dnsmasq", "DNSMASQ");
+
+    let failure_report_file: Option<String> = matches.value_of("failure-report-file").map_or_else(
+        || vars.var("FAILURE_REPORT_FILE").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let internet_checks = matches
+        .value_of("internet-check")
+        .map(String::from)
+        .or_else(|| vars.var("INTERNET_CHECK").ok())
+        .map_or_else(
+            || Ok(Vec::new()),
+            |probes| {
+                probes
+                    .split(',')
+                    .filter(|probe| !probe.trim().is_empty())
+                    .map(|probe| {
+                        Probe::from_str(probe)
+                            .map_err(|e| format!("Cannot parse Internet check: {}", e))
+                    })
+                    .collect()
+            },
+        )?;
+
+    let internet_check_timeout =
+        u64::from_str(&matches.value_of("internet-check-timeout").map_or_else(
+            || {
+                vars.var("INTERNET_CHECK_TIMEOUT")
+                    .unwrap_or_else(|_| DEFAULT_INTERNET_CHECK_TIMEOUT.to_string())
+            },
+            String::from,
+        )).map_err(|e| format!("Cannot parse Internet check timeout: {}", e))?;
+
+    let pairing_token = get_flag(&matches, &vars, "pairing-token", "PAIRING_TOKEN");
+
+    if pairing_token && ssid.len() + pairing::SSID_SUFFIX_LENGTH > 32 {
+        return Err("Portal SSID is too long for the pairing token suffix".to_string());
+    }
+
+    let connect_attempts = u32::from_str(&matches.value_of("connect-attempts").map_or_else(
+        || vars.var("CONNECT_ATTEMPTS").unwrap_or_else(|_| DEFAULT_CONNECT_ATTEMPTS.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse connect attempts: {}", e))?;
+
+    let connect_backoff = u64::from_str(&matches.value_of("connect-backoff").map_or_else(
+        || vars.var("CONNECT_BACKOFF").unwrap_or_else(|_| DEFAULT_CONNECT_BACKOFF.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse connect backoff: {}", e))?;
+
+    let connect_attempt_timeout =
+        u64::from_str(&matches.value_of("connect-attempt-timeout").map_or_else(
+            || {
+                vars.var("CONNECT_ATTEMPT_TIMEOUT")
+                    .unwrap_or_else(|_| DEFAULT_CONNECT_ATTEMPT_TIMEOUT.to_string())
+            },
+            String::from,
+        )).map_err(|e| format!("Cannot parse connect attempt timeout: {}", e))?;
+
+    let portal_max_clients = u32::from_str(&matches.value_of("portal-max-clients").map_or_else(
+        || {
+            vars.var("PORTAL_MAX_CLIENTS")
+                .unwrap_or_else(|_| DEFAULT_PORTAL_MAX_CLIENTS.to_string())
+        },
+        String::from,
+    )).map_err(|e| format!("Cannot parse portal max clients: {}", e))?;
+
+    let lenient = if matches.is_present("strict") {
+        false
+    } else if matches.is_present("lenient") {
+        true
+    } else {
+        match vars.var("STARTUP_POLICY") {
+            Ok(ref policy) if policy == "lenient" => true,
+            Ok(ref policy) if policy == "strict" => false,
+            Ok(policy) => return Err(format!("Cannot parse startup policy: {}", policy)),
+            Err(_) => false,
+        }
+    };
+
+    let max_runtime = u64::from_str(&matches.value_of("max-runtime").map_or_else(
+        || vars.var("MAX_RUNTIME").unwrap_or_else(|_| DEFAULT_MAX_RUNTIME.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse max runtime: {}", e))?;
+
+    let portal_channel = matches
+        .value_of("portal-channel")
+        .map_or_else(|| vars.var("PORTAL_CHANNEL").ok(), |v| Some(v.to_string()))
+        .map(|channel| {
+            u32::from_str(&channel).map_err(|e| format!("Cannot parse portal channel: {}", e))
+        });
+    let portal_channel = transpose(portal_channel)?;
+
+    let portal_band = matches
+        .value_of("portal-band")
+        .map_or_else(|| vars.var("PORTAL_BAND").ok(), |v| Some(v.to_string()))
+        .map(|band| Band::from_str(&band).map_err(|e| format!("Invalid portal band: {}", e)));
+    let portal_band = transpose(portal_band)?;
+
+    // NetworkManager fixes a channel only together with its band
+    let portal_band = match (portal_band, portal_channel) {
+        (None, Some(channel)) => Some(if channel <= 14 { Band::Bg } else { Band::A }),
+        (band, _) => band,
+    };
+
+    if let (Some(band), Some(channel)) = (portal_band, portal_channel) {
+        validate_portal_channel(band, channel)
+            .map_err(|e| format!("Invalid portal channel: {}", e))?;
+    }
+
+    let ethernet_portal = get_flag(&matches, &vars, "ethernet-portal", "ETHERNET_PORTAL");
+
+    let locale = matches
+        .value_of("locale")
+        .map_or_else(|| vars.var("LOCALE").ok(), |v| Some(v.to_string()))
+        .unwrap_or_else(|| DEFAULT_LOCALE.to_string());
+    let locale = Locale::from_str(&locale).map_err(|e| format!("Invalid locale: {}", e))?;
+
+    let rate_limit = u32::from_str(&matches.value_of("rate-limit").map_or_else(
+        || vars.var("RATE_LIMIT").unwrap_or_else(|_| DEFAULT_RATE_LIMIT.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse rate limit: {}", e))?;
+
+    let cors_origins = matches
+        .value_of("cors-origin")
+        .map(String::from)
+        .or_else(|| vars.var("CORS_ORIGIN").ok())
+        .map_or_else(Vec::new, |origins| {
+            origins
+                .split(',')
+                .map(|origin| origin.trim().trim_right_matches('/').to_string())
+                .filter(|origin| !origin.is_empty())
+                .collect()
+        });
+
+    let cors_methods = matches.value_of("cors-methods").map_or_else(
+        || vars.var("CORS_METHODS").unwrap_or_else(|_| DEFAULT_CORS_METHODS.to_string()),
+        String::from,
+    );
+
+    let cors_headers = matches.value_of("cors-headers").map_or_else(
+        || vars.var("CORS_HEADERS").unwrap_or_else(|_| DEFAULT_CORS_HEADERS.to_string()),
+        String::from,
+    );
+
+    let json_case = matches
+        .value_of("json-case")
+        .map_or_else(|| vars.var("JSON_CASE").ok(), |v| Some(v.to_string()))
+        .map_or(Ok(JsonCase::Snake), |case| {
+            JsonCase::from_str(&case).map_err(|e| format!("Invalid JSON case: {}", e))
+        })?;
+
+    let json_envelope = get_flag(&matches, &vars, "json-envelope", "JSON_ENVELOPE");
+
+    let scan_watch_interval = u64::from_str(&matches.value_of("scan-watch-interval").map_or_else(
+        || {
+            vars.var("SCAN_WATCH_INTERVAL")
+                .unwrap_or_else(|_| DEFAULT_SCAN_WATCH_INTERVAL.to_string())
+        },
+        String::from,
+    )).map_err(|e| format!("Cannot parse scan watch interval: {}", e))?;
+
+    let dbus_wait_timeout = u64::from_str(&matches.value_of("dbus-wait-timeout").map_or_else(
+        || {
+            vars.var("DBUS_WAIT_TIMEOUT")
+                .unwrap_or_else(|_| DEFAULT_DBUS_WAIT_TIMEOUT.to_string())
+        },
+        String::from,
+    )).map_err(|e| format!("Cannot parse D-Bus wait timeout: {}", e))?;
+
+    let lock_file = PathBuf::from(matches.value_of("lock-file").map_or_else(
+        || vars.var("LOCK_FILE").unwrap_or_else(|_| DEFAULT_LOCK_FILE.to_string()),
+        String::from,
+    ));
+
+    let on_connect: Option<String> = matches.value_of("on-connect").map_or_else(
+        || vars.var("ON_CONNECT").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let on_disconnect: Option<String> = matches.value_of("on-disconnect").map_or_else(
+        || vars.var("ON_DISCONNECT").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let webhooks = matches
+        .value_of("webhook")
+        .map(String::from)
+        .or_else(|| vars.var("WEBHOOK").ok())
+        .map_or_else(Vec::new, |urls| {
+            urls.split(',')
+                .map(|url| url.trim().to_string())
+                .filter(|url| !url.is_empty())
+                .collect()
+        });
+
+    let hook_timeout = u64::from_str(&matches.value_of("hook-timeout").map_or_else(
+        || vars.var("HOOK_TIMEOUT").unwrap_or_else(|_| DEFAULT_HOOK_TIMEOUT.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse hook timeout: {}", e))?;
+
+    let status_file = matches
+        .value_of("status-file")
+        .map(String::from)
+        .or_else(|| vars.var("STATUS_FILE").ok())
+        .map(PathBuf::from);
+
+    let dns_rules: Vec<String> = match matches.values_of("portal-dns-rule") {
+        Some(rules) => rules.map(String::from).collect(),
+        None => vars.var("PORTAL_DNS_RULE").ok().map_or_else(Vec::new, |rules| {
+            rules
+                .split(';')
+                .map(|rule| rule.trim().to_string())
+                .filter(|rule| !rule.is_empty())
+                .collect()
+        }),
+    };
+
+    let dns_rules = dns_rules
+        .iter()
+        .map(|rule| DnsRule::from_str(rule).map_err(|e| format!("Invalid portal DNS rule: {}", e)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let dns_upstream = matches
+        .value_of("portal-dns-upstream")
+        .map(String::from)
+        .or_else(|| vars.var("PORTAL_DNS_UPSTREAM").ok())
+        .map(|upstream| {
+            Ipv4Addr::from_str(&upstream)
+                .map_err(|e| format!("Cannot parse portal DNS upstream address: {}", e))
+        });
+    let dns_upstream = transpose(dns_upstream)?;
+
+    let beacon_port = u16::from_str(&matches.value_of("beacon-port").map_or_else(
+        || vars.var("BEACON_PORT").unwrap_or_else(|_| DEFAULT_BEACON_PORT.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse beacon port: {}", e))?;
+
+    let beacon_interval = u64::from_str(&matches.value_of("beacon-interval").map_or_else(
+        || vars.var("BEACON_INTERVAL").unwrap_or_else(|_| DEFAULT_BEACON_INTERVAL.to_string()),
+        String::from,
+    )).map_err(|e| format!("Cannot parse beacon interval: {}", e))?;
+
+    if beacon_interval == 0 {
+        return Err("Beacon interval must be at least 1 second".to_string());
+    }
+
+    let beacon_address = matches
+        .value_of("beacon-address")
+        .map(String::from)
+        .or_else(|| vars.var("BEACON_ADDRESS").ok())
+        .map(|address| {
+            Ipv4Addr::from_str(&address).map_err(|e| format!("Cannot parse beacon address: {}", e))
+        });
+    let beacon_address = transpose(beacon_address)?;
+
+    let log_format = matches
+        .value_of("log-format")
+        .map_or_else(|| vars.var("LOG_FORMAT").ok(), |v| Some(v.to_string()))
+        .map_or(Ok(LogFormat::Text), |format| {
+            LogFormat::from_str(&format).map_err(|e| format!("Invalid log format: {}", e))
+        })?;
+
+    let log_file = matches
+        .value_of("log-file")
+        .map(String::from)
+        .or_else(|| vars.var("LOG_FILE").ok())
+        .map(PathBuf::from);
+
+    let log_file_max_size = u64::from_str(&matches.value_of("log-file-max-size").map_or_else(
+        || {
+            vars.var("LOG_FILE_MAX_SIZE")
+                .unwrap_or_else(|_| DEFAULT_LOG_FILE_MAX_SIZE.to_string())
+        },
+        String::from,
+    )).map_err(|e| format!("Cannot parse log file max size: {}", e))?;
+
+    let import = matches
+        .value_of("import")
+        .map(String::from)
+        .or_else(|| vars.var("IMPORT").ok())
+        .map(PathBuf::from);
+
+    let ssdp = get_flag(&matches, &vars, "ssdp", "SSDP");
+
+    let connect_any_timeout =
+        u64::from_str(&matches.value_of("connect-any-timeout").map_or_else(
+            || {
+                vars.var("CONNECT_ANY_TIMEOUT")
+                    .unwrap_or_else(|_| DEFAULT_CONNECT_ANY_TIMEOUT.to_string())
+            },
+            String::from,
+        )).map_err(|e| format!("Cannot parse connect any timeout: {}", e))?;
+
+    let enrollment_token: Option<String> = matches.value_of("enrollment-token").map_or_else(
+        || vars.var("ENROLLMENT_TOKEN").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let checkpoint_timeout =
+        u64::from_str(&matches.value_of("checkpoint-timeout").map_or_else(
+            || {
+                vars.var("CHECKPOINT_TIMEOUT")
+                    .unwrap_or_else(|_| DEFAULT_CHECKPOINT_TIMEOUT.to_string())
+            },
+            String::from,
+        )).map_err(|e| format!("Cannot parse checkpoint timeout: {}", e))?;
+
+    let webhook_secret: Option<String> = matches.value_of("webhook-secret").map_or_else(
+        || vars.var("WEBHOOK_SECRET").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let virtual_ap: Option<String> = matches.value_of("virtual-ap").map_or_else(
+        || vars.var("VIRTUAL_AP").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let log_level = matches
+        .value_of("log-level")
+        .map_or_else(|| vars.var("LOG_LEVEL").ok(), |v| Some(v.to_string()))
+        .map_or(Ok(LogLevelFilter::Info), |level| {
+            LogLevelFilter::from_str(&level).map_err(|_| format!("Invalid log level: {}", level))
+        })?;
+
+    let wifi_p2p = get_flag(&matches, &vars, "wifi-p2p", "WIFI_P2P");
+
+    let nfc_reader: Option<String> = matches.value_of("nfc-reader").map_or_else(
+        || vars.var("NFC_READER").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let audio_capture: Option<String> = matches.value_of("audio-capture").map_or_else(
+        || vars.var("AUDIO_CAPTURE").ok(),
+        |v| Some(v.to_string()),
+    );
+
+    let mut config = Config {
+        interface: interface,
+        ssid: ssid,
+        passphrase: passphrase,
+        gateway: gateway,
+        portal_prefix: portal_prefix,
+        dhcp_range: dhcp_range,
+        activity_timeout: activity_timeout,
+        ui_directory: ui_directory,
+        state_directory: state_directory,
+        mdns_service_name: mdns_service_name,
+        refuse_unknown_bssids: refuse_unknown_bssids,
+        listening_scheme: listening_scheme,
+        listening_port: listening_port,
+        tls_certificate: tls_certificate,
+        tls_key: tls_key,
+        dns_servers: dns_servers,
+        auth_username: auth_username,
+        auth_password: auth_password,
+        auth_token: auth_token,
+        listening_address: listening_address,
+        disable_ipv4_listener: disable_ipv4_listener,
+        disable_ipv6_listener: disable_ipv6_listener,
+        monitor: monitor,
+        monitor_interval: monitor_interval,
+        monitor_grace_period: monitor_grace_period,
+        bind_interface: bind_interface,
+        hidden_ssids: hidden_ssids,
+        scan_retries: scan_retries,
+        scan_interval: scan_interval,
+        manage_ethernet: manage_ethernet,
+        ethernet_interface: ethernet_interface,
+        change_network: change_network,
+        rollback_timeout: rollback_timeout,
+        confirm_timeout: confirm_timeout,
+        command: command,
+        profile: profile,
+        dbus: dbus,
+        print_config: print_config,
+        mqtt_host: mqtt_host,
+        mqtt_port: mqtt_port,
+        mqtt_topic_prefix: mqtt_topic_prefix,
+        mqtt_username: mqtt_username,
+        mqtt_password: mqtt_password,
+        mqtt_ca_file: mqtt_ca_file,
+        preserve_existing_profiles: preserve_existing_profiles,
+        portal_tx_power: portal_tx_power,
+        portal_power_save: portal_power_save,
+        dhcp_lease_time: dhcp_lease_time,
+        dhcp_options: dhcp_options,
+        portal_sleep_timeout: portal_sleep_timeout,
+        portal_beacon_interval: portal_beacon_interval,
+        portal_dtim_period: portal_dtim_period,
+        dnsmasq: dnsmasq,
+        failure_report_file: failure_report_file,
+        internet_checks: internet_checks,
+        internet_check_timeout: internet_check_timeout,
+        pairing_token: pairing_token,
+        connect_attempts: connect_attempts,
+        connect_backoff: connect_backoff,
+        connect_attempt_timeout: connect_attempt_timeout,
+        portal_max_clients: portal_max_clients,
+        lenient: lenient,
+        max_runtime: max_runtime,
+        portal_channel: portal_channel,
+        portal_band: portal_band,
+        ethernet_portal: ethernet_portal,
+        locale: locale,
+        rate_limit: rate_limit,
+        cors_origins: cors_origins,
+        cors_methods: cors_methods,
+        cors_headers: cors_headers,
+        json_case: json_case,
+        json_envelope: json_envelope,
+        scan_watch_interval: scan_watch_interval,
+        dbus_wait_timeout: dbus_wait_timeout,
+        lock_file: lock_file,
+        on_connect: on_connect,
+        on_disconnect: on_disconnect,
+        webhooks: webhooks,
+        hook_timeout: hook_timeout,
+        status_file: status_file,
+        dns_rules: dns_rules,
+        dns_upstream: dns_upstream,
+        beacon_port: beacon_port,
+        beacon_interval: beacon_interval,
+        beacon_address: beacon_address,
+        log_format: log_format,
+        log_file: log_file,
+        log_file_max_size: log_file_max_size,
+        import: import,
+        ssdp: ssdp,
+        connect_any_timeout: connect_any_timeout,
+        enrollment_token: enrollment_token,
+        checkpoint_timeout: checkpoint_timeout,
+        webhook_secret: webhook_secret,
+        virtual_ap: virtual_ap,
+        log_level: log_level,
+        wifi_p2p: wifi_p2p,
+        nfc_reader: nfc_reader,
+        audio_capture: audio_capture,
+        sources: BTreeMap::new(),
+    };
+
+    config.sources = get_sources(&matches, &vars, &config);
+
+    Ok(config)
+}
+
+/// Finds the source of every option of `to_json`. Derived values, such as the
+/// default DHCP range of a gateway, count as defaults.
+fn get_sources(
+    matches: &ArgMatches,
+    vars: &Variables,
+    config: &Config,
+) -> BTreeMap<String, ConfigSource> {
+    let mut sources = BTreeMap::new();
+
+    if let Value::Object(ref options) = config.to_json() {
+        for name in options.keys() {
+            let names = OPTION_NAMES.iter().find(|&&(option, _, _)| option == name);
+
+            let (args, env_var) = match names {
+                Some(&(_, args, env_var)) => (
+                    args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>(),
+                    env_var.to_string(),
+                ),
+                None => (vec![name.replace('_', "-")], name.to_uppercase()),
+            };
+
+            let source = if args.iter().any(|arg| matches.occurrences_of(arg) > 0) {
+                ConfigSource::CommandLine
+            } else if env::var_os(&env_var).is_some() {
+                ConfigSource::Environment
+            } else if vars.config_file.contains_key(&env_var) {
+                ConfigSource::ConfigFile
+            } else {
+                ConfigSource::Default
+            };
+
+            sources.insert(name.clone(), source);
+        }
+    }
+
+    sources
+}
+
+/// The listening address is either an IP address or an `address:port` pair,
+/// in which case the port overrides the one set with `--listening-port`
+fn get_listening_address(
+    cmd_listening_address: Option<&str>,
+    vars: &Variables,
+    default_address: IpAddr,
+    default_port: u16,
+) -> Result<(IpAddr, u16), String> {
+    let listening_address = match cmd_listening_address {
+        Some(listening_address) => listening_address.to_string(),
+        None => match vars.var("PORTAL_LISTENING_ADDRESS") {
+            Ok(listening_address) => listening_address,
+            Err(_) => return Ok((default_address, default_port)),
+        },
+    };
+
+    if let Ok(socket_address) = SocketAddr::from_str(&listening_address) {
+        return Ok((socket_address.ip(), socket_address.port()));
+    }
+
+    let address = IpAddr::from_str(&listening_address)
+        .map_err(|e| format!("Cannot parse listening address: {}", e))?;
+
+    Ok((address, default_port))
+}
+
+/// Like `Option::transpose` of newer compilers, for the optional values which
+/// are parsed on the way
+fn transpose<T>(value: Option<Result<T, String>>) -> Result<Option<T>, String> {
+    match value {
+        Some(Ok(value)) => Ok(Some(value)),
+        Some(Err(e)) => Err(e),
+        None => Ok(None),
+    }
+}
+
+/// Looks up the environment variables of the options, which fall back to the
+/// settings of the config file. The settings never go through the process
+/// environment, so that reading the configuration has no side effects.
+struct Variables {
+    config_file: BTreeMap<String, String>,
+}
+
+impl Variables {
+    fn var(&self, name: &str) -> Result<String, env::VarError> {
+        match env::var(name) {
+            Err(env::VarError::NotPresent) => self.config_file
+                .get(name)
+                .cloned()
+                .ok_or(env::VarError::NotPresent),
+            result => result,
+        }
+    }
+
+    fn var_os(&self, name: &str) -> Option<OsString> {
+        env::var_os(name).or_else(|| self.config_file.get(name).map(OsString::from))
+    }
+}
+
+/// Parses a comma separated list of IPv4 addresses
+pub fn parse_ip_list(list: &str) -> Result<Vec<Ipv4Addr>, AddrParseError> {
+    list.split(',')
+        .map(|address| Ipv4Addr::from_str(address.trim()))
+        .collect()
+}
+
+/// Reads the settings of the TOML config file. Top-level settings apply to
+/// all profiles, while those of the `[profiles.<name>]` table of the selected
+/// profile override them. Settings are named after the long command line
+/// options and take effect as if set through their environment variables,
+/// so both command line options and environment variables take precedence.
+/// Returns the values by the names of those environment variables.
+fn read_config_file(
+    config_file: Option<PathBuf>,
+    profile: Option<&str>,
+) -> Result<BTreeMap<String, String>, String> {
+    let explicit = config_file.is_some() || profile.is_some();
+
+    let path = config_file.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+
+    if !explicit && !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut contents = String::new();
+
+    File::open(&path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|e| format!("Cannot read config file: {}", e))?;
+
+    let table = toml::from_str(&contents).map_err(|e| format!("Cannot parse config file: {}", e))?;
+
+    let mut table = match table {
+        toml::Value::Table(table) => table,
+        _ => return Err("Cannot parse config file".to_string()),
+    };
+
+    let profiles = table.remove("profiles");
+
+    let mut settings = table;
+
+    if let Some(profile) = profile {
+        let profile_settings = profiles
+            .as_ref()
+            .and_then(|profiles| profiles.get(profile))
+            .and_then(|profile| profile.as_table())
+            .ok_or_else(|| format!("Cannot find config profile '{}'", profile))?;
+
+        for (name, value) in profile_settings.iter() {
+            settings.insert(name.clone(), value.clone());
+        }
+    }
+
+    let mut values = BTreeMap::new();
+
+    for (name, value) in settings {
+        let env_var = name.to_uppercase().replace('-', "_");
+
+        values.insert(env_var, get_config_file_value(&name, &value)?);
+    }
+
+    Ok(values)
+}
+
+fn get_config_file_value(name: &str, value: &toml::Value) -> Result<String, String> {
+    match *value {
+        toml::Value::String(ref value) => Ok(value.clone()),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        toml::Value::Array(ref values) => Ok(values
+            .iter()
+            .map(|value| get_config_file_value(name, value))
+            .collect::<Result<Vec<_>, _>>()?
+            .join(if SEMICOLON_SEPARATED.contains(&name) {
+                ";"
+            } else {
+                ","
+            })),
+        _ => Err(format!("Cannot parse config file setting '{}'", name)),
+    }
+}
+
+fn validate_portal_channel(band: Band, channel: u32) -> Result<(), String> {
+    let valid = match band {
+        Band::Bg => channel >= 1 && channel <= 14,
+        Band::A => channel >= 32 && channel <= 177,
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(format!("{} is not a channel of band {}", channel, band.as_str()))
+    }
+}
+
+/// Checks that a `start,end` DHCP range lies within the portal network of
+/// the gateway
+pub fn validate_dhcp_range(dhcp_range: &str, gateway: Ipv4Addr, prefix: u8) -> Result<(), String> {
+    let mut parts = dhcp_range.split(',').map(str::trim);
+
+    let start = parts.next().unwrap_or("");
+    let end = parts.next().ok_or_else(|| format!("{} has no end address", dhcp_range))?;
+
+    let start = Ipv4Addr::from_str(start).map_err(|e| format!("{}: {}", start, e))?;
+    let end = Ipv4Addr::from_str(end).map_err(|e| format!("{}: {}", end, e))?;
+
+    for address in &[start, end] {
+        if !subnet::contains(gateway, prefix, *address) {
+            return Err(format!("{} is outside of {}/{}", address, gateway, prefix));
+        }
+    }
+
+    if u32::from(start) > u32::from(end) {
+        return Err(format!("{} is after {}", start, end));
+    }
+
+    Ok(())
+}
+
+/// Checks a dnsmasq lease time: a number of seconds, optionally with a
+/// minutes, hours, days or weeks suffix, or `infinite`
+fn validate_lease_time(lease_time: &str) -> Result<(), String> {
+    if lease_time == "infinite" {
+        return Ok(());
+    }
+
+    let digits = lease_time.trim_right_matches(|c| "smhdw".contains(c));
+
+    if digits.is_empty() || lease_time.len() - digits.len() > 1
+        || !digits.chars().all(|c| c.is_digit(10))
+    {
+        return Err(format!("{} is not a lease time", lease_time));
+    }
+
+    Ok(())
+}
+
+/// Flags are set either on the command line or with an environment variable
+/// with value `true` or `1`
+fn get_flag(matches: &ArgMatches, vars: &Variables, name: &str, env_var: &str) -> bool {
+    if matches.is_present(name) {
+        return true;
+    }
+
+    match vars.var(env_var) {
+        Ok(value) => value == "true" || value == "1",
+        Err(_) => false,
+    }
+}
+
+/// Reads the MAC address of the portal interface, or of the first wireless
+/// interface when none is given, as NetworkManager is not queried yet
+fn get_mac_address(interface: Option<&str>) -> Option<[u8; 6]> {
+    let interface = match interface {
+        Some(interface) => interface.to_string(),
+        None => {
+            let mut interfaces = fs::read_dir("/sys/class/net")
+                .ok()?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().join("wireless").exists())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect::<Vec<_>>();
+
+            interfaces.sort();
+            interfaces.into_iter().next()?
+        },
+    };
+
+    let mut address = String::new();
+
+    File::open(format!("/sys/class/net/{}/address", interface))
+        .and_then(|mut file| file.read_to_string(&mut address))
+        .ok()?;
+
+    let octets = address
+        .trim()
+        .split(':')
+        .map(|octet| u8::from_str_radix(octet, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    if octets.len() != 6 {
+        return None;
+    }
+
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&octets);
+
+    Some(mac)
+}
+
+/// Returns the UI directory on disk, if any, which overrides the UI embedded
+/// in the binary
+fn get_ui_directory(cmd_ui_directory: Option<&str>, vars: &Variables) -> Option<PathBuf> {
+    if let Some(ui_directory) = cmd_ui_directory {
+        return Some(PathBuf::from(ui_directory));
     }
 
-    if let Some(install_ui_directory) = get_install_ui_directory() {
-        return install_ui_directory;
+    if let Ok(ui_directory) = vars.var("UI_DIRECTORY") {
+        return Some(PathBuf::from(ui_directory));
     }
 
-    PathBuf::from(DEFAULT_UI_DIRECTORY)
+    get_install_ui_directory()
 }
 
 /// Checks whether `WiFi Connect` is running from install path and whether the
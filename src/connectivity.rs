@@ -0,0 +1,43 @@
+use dbus::{BusType, Connection, Message};
+use network_manager::Connectivity;
+
+use errors::*;
+
+const NM_DBUS_NAME: &str = "org.freedesktop.NetworkManager";
+const NM_DBUS_PATH: &str = "/org/freedesktop/NetworkManager";
+const NM_DBUS_INTERFACE: &str = "org.freedesktop.NetworkManager";
+
+/// Time to wait for NetworkManager to probe its connectivity check URI
+/// (milliseconds)
+const CALL_TIMEOUT: i32 = 30_000;
+
+/// Has NetworkManager probe the connectivity right away and returns the
+/// result, while its `Connectivity` property is only updated by the periodic
+/// check, which runs every few minutes
+pub fn check_connectivity() -> Result<Connectivity> {
+    let connection =
+        Connection::get_private(BusType::System).chain_err(|| ErrorKind::CheckConnectivity)?;
+
+    let message = Message::new_method_call(
+        NM_DBUS_NAME,
+        NM_DBUS_PATH,
+        NM_DBUS_INTERFACE,
+        "CheckConnectivity",
+    ).map_err(|e| Error::with_chain(Error::from(e), ErrorKind::CheckConnectivity))?;
+
+    let reply = connection
+        .send_with_reply_and_block(message, CALL_TIMEOUT)
+        .chain_err(|| ErrorKind::CheckConnectivity)?;
+
+    let connectivity = reply
+        .get1::<u32>()
+        .ok_or_else(|| Error::from(ErrorKind::CheckConnectivity))?;
+
+    Ok(match connectivity {
+        1 => Connectivity::None,
+        2 => Connectivity::Portal,
+        3 => Connectivity::Limited,
+        4 => Connectivity::Full,
+        _ => Connectivity::Unknown,
+    })
+}
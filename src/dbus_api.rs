@@ -0,0 +1,164 @@
+use std::thread;
+use std::rc::Rc;
+use std::cell::Cell;
+use std::sync::mpsc::{Receiver, Sender};
+
+use dbus::{BusType, Connection, ConnectionItem, Message, NameFlag};
+use dbus::tree::{Factory, MTFn, MethodErr, MethodInfo, MethodResult};
+
+use errors::*;
+use network::{request_network_command, ApSelection, NetworkCommand, NetworkCommandResponse,
+              ProvisioningState};
+
+const DBUS_NAME: &str = "io.resin.WifiConnect";
+const DBUS_PATH: &str = "/io/resin/WifiConnect";
+const DBUS_INTERFACE: &str = "io.resin.WifiConnect";
+
+/// Exports the provisioning state machine on the system bus, so that other
+/// services on the device may follow and drive it. Method calls are turned
+/// into network commands and state changes into `StateChanged` signals.
+pub fn start_dbus_api(network_tx: Sender<NetworkCommand>, state_rx: Receiver<ProvisioningState>) {
+    thread::spawn(move || {
+        if let Err(e) = run_dbus_api(network_tx, &state_rx) {
+            warn!("D-Bus API disabled: {}", e);
+        }
+    });
+}
+
+fn run_dbus_api(
+    network_tx: Sender<NetworkCommand>,
+    state_rx: &Receiver<ProvisioningState>,
+) -> Result<()> {
+    let connection = Connection::get_private(BusType::System).chain_err(|| ErrorKind::DbusApi)?;
+
+    connection
+        .register_name(DBUS_NAME, NameFlag::ReplaceExisting as u32)
+        .chain_err(|| ErrorKind::DbusApi)?;
+
+    let state = Rc::new(Cell::new(ProvisioningState::Provisioning));
+
+    let f = Factory::new_fn::<()>();
+
+    let scan_tx = network_tx.clone();
+    let connect_tx = network_tx.clone();
+    let disconnect_tx = network_tx;
+    let get_state = state.clone();
+
+    let tree = f.tree(()).add(
+        f.object_path(DBUS_PATH, ()).introspectable().add(
+            f.interface(DBUS_INTERFACE, ())
+                .add_m(
+                    f.method("Scan", (), move |m| scan(m, &scan_tx))
+                        .out_arg(("access_points", "a(sus)")),
+                )
+                .add_m(
+                    f.method("Connect", (), move |m| connect(m, &connect_tx))
+                        .in_arg(("ssid", "s"))
+                        .in_arg(("passphrase", "s")),
+                )
+                .add_m(
+                    f.method("Disconnect", (), move |m| disconnect(m, &disconnect_tx))
+                        .out_arg(("disconnected", "b")),
+                )
+                .add_m(
+                    f.method("GetState", (), move |m| {
+                        Ok(vec![m.msg.method_return().append1(get_state.get().as_str())])
+                    }).out_arg(("state", "s")),
+                )
+                .add_s(f.signal("StateChanged", ()).arg(("state", "s"))),
+        ),
+    );
+
+    tree.set_registered(&connection, true)
+        .chain_err(|| ErrorKind::DbusApi)?;
+
+    info!("D-Bus API exported as {}", DBUS_NAME);
+
+    for item in tree.run(&connection, connection.iter(1000)) {
+        if let ConnectionItem::Nothing = item {
+            while let Ok(new_state) = state_rx.try_recv() {
+                if new_state != state.get() {
+                    state.set(new_state);
+                    emit_state_changed(&connection, new_state);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn emit_state_changed(connection: &Connection, state: ProvisioningState) {
+    let signal = match Message::new_signal(DBUS_PATH, DBUS_INTERFACE, "StateChanged") {
+        Ok(signal) => signal.append1(state.as_str()),
+        Err(e) => {
+            error!("Creating StateChanged signal failed: {}", e);
+            return;
+        },
+    };
+
+    if connection.send(signal).is_err() {
+        error!("Sending StateChanged signal failed");
+    }
+}
+
+fn scan(m: &MethodInfo<MTFn, ()>, network_tx: &Sender<NetworkCommand>) -> MethodResult {
+    let response = request_network_command(network_tx, |response_tx| {
+        NetworkCommand::Activate {
+            response_tx: response_tx,
+        }
+    }).map_err(|e| MethodErr::failed(&e))?;
+
+    let access_points = match response {
+        NetworkCommandResponse::AccessPoints(access_points) => access_points,
+        _ => return Err(MethodErr::failed(&"Unexpected network command response")),
+    };
+
+    let access_points = access_points
+        .into_iter()
+        .map(|ap| {
+            (
+                ap.ssid,
+                ap.strength,
+                ap.key_management.as_str().to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    Ok(vec![m.msg.method_return().append1(access_points)])
+}
+
+fn connect(m: &MethodInfo<MTFn, ()>, network_tx: &Sender<NetworkCommand>) -> MethodResult {
+    let (ssid, passphrase) = m.msg.get2::<&str, &str>();
+
+    let command = NetworkCommand::Connect {
+        ssid: ssid.ok_or_else(MethodErr::no_arg)?.to_string(),
+        passphrase: passphrase.unwrap_or("").to_string(),
+        dns_servers: None,
+        reuse: false,
+        selection: ApSelection::default(),
+        checkpoint: None,
+    };
+
+    network_tx
+        .send(command)
+        .map_err(|e| MethodErr::failed(&e))?;
+
+    Ok(vec![m.msg.method_return()])
+}
+
+fn disconnect(m: &MethodInfo<MTFn, ()>, network_tx: &Sender<NetworkCommand>) -> MethodResult {
+    let response = request_network_command(network_tx, |response_tx| {
+        NetworkCommand::Disconnect {
+            checkpoint: None,
+            response_tx: response_tx,
+        }
+    }).map_err(|e| MethodErr::failed(&e))?;
+
+    let disconnected = match response {
+        NetworkCommandResponse::Disconnected(disconnected) => disconnected,
+        _ => return Err(MethodErr::failed(&"Unexpected network command response")),
+    };
+
+    Ok(vec![m.msg.method_return().append1(disconnected)])
+}
@@ -0,0 +1,697 @@
+use std::thread;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::os::unix::io::AsRawFd;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use nix::libc;
+
+use errors::*;
+use config::Config;
+use dns::is_timeout;
+use subnet;
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+const DEFAULT_LEASE_TIME: u32 = 3600;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// Offset of the options, after the fixed fields and the magic cookie
+const OPTIONS_OFFSET: usize = 240;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS_SERVERS: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_END: u8 = 255;
+
+const DHCPDISCOVER: u8 = 1;
+const DHCPOFFER: u8 = 2;
+const DHCPREQUEST: u8 = 3;
+const DHCPDECLINE: u8 = 4;
+const DHCPACK: u8 = 5;
+const DHCPNAK: u8 = 6;
+const DHCPRELEASE: u8 = 7;
+
+/// Hands out the addresses of the DHCP range to the clients of the captive
+/// portal, with the gateway as their router and DNS server
+pub struct DhcpServer {
+    stop: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+struct Pool {
+    gateway: Ipv4Addr,
+    prefix: u8,
+    start: u32,
+    end: u32,
+    lease_time: u32,
+    max_clients: usize,
+    options: Vec<(u8, Vec<u8>)>,
+    leases: HashMap<[u8; 6], (Ipv4Addr, Instant)>,
+}
+
+impl DhcpServer {
+    pub fn start(config: &Config, interface: &str) -> Result<Self> {
+        let pool = Pool::new(config)?;
+
+        let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), SERVER_PORT))
+            .chain_err(|| ErrorKind::BuiltinDhcp)?;
+
+        bind_to_device(&socket, interface).chain_err(|| ErrorKind::BuiltinDhcp)?;
+
+        socket
+            .set_broadcast(true)
+            .chain_err(|| ErrorKind::BuiltinDhcp)?;
+
+        // Wakes the thread up periodically to check whether to stop
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .chain_err(|| ErrorKind::BuiltinDhcp)?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let stop = stop.clone();
+            let running = running.clone();
+
+            thread::spawn(move || {
+                serve(&socket, pool, &stop);
+                running.store(false, Ordering::SeqCst);
+            })
+        };
+
+        info!("DHCP server listening on {}", interface);
+
+        Ok(DhcpServer {
+            stop: stop,
+            running: running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Receives only the broadcasts of the portal interface, so that clients of
+/// other networks are never answered
+fn bind_to_device(socket: &UdpSocket, interface: &str) -> Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+
+    if result != 0 {
+        return Err(::std::io::Error::last_os_error().into());
+    }
+
+    Ok(())
+}
+
+fn serve(socket: &UdpSocket, mut pool: Pool, stop: &AtomicBool) {
+    let mut buffer = [0; 1500];
+
+    while !stop.load(Ordering::SeqCst) {
+        let len = match socket.recv_from(&mut buffer) {
+            Ok((len, _)) => len,
+            Err(ref e) if is_timeout(e) => continue,
+            Err(e) => {
+                error!("Receiving DHCP message failed: {}", e);
+                return;
+            },
+        };
+
+        if let Some(reply) = pool.handle(&buffer[..len]) {
+            let destination = Ipv4Addr::new(255, 255, 255, 255);
+
+            if let Err(e) = socket.send_to(&reply, (destination, CLIENT_PORT)) {
+                warn!("Sending DHCP reply failed: {}", e);
+            }
+        }
+    }
+}
+
+impl Pool {
+    fn new(config: &Config) -> Result<Self> {
+        let mut range = config.dhcp_range.split(',').map(str::trim);
+
+        let start = parse_address(range.next())?;
+        let end = parse_address(range.next())?;
+
+        let lease_time = match config.dhcp_lease_time {
+            Some(ref lease_time) => parse_lease_time(lease_time)?,
+            None => DEFAULT_LEASE_TIME,
+        };
+
+        let mut options = Vec::new();
+
+        for option in &config.dhcp_options {
+            match parse_option(option) {
+                Some(option) => options.push(option),
+                None => warn!("DHCP option not supported by the built-in server: {}", option),
+            }
+        }
+
+        Ok(Pool {
+            gateway: config.gateway,
+            prefix: config.portal_prefix,
+            start: u32::from(start),
+            end: u32::from(end),
+            lease_time: lease_time,
+            max_clients: config.portal_max_clients as usize,
+            options: options,
+            leases: HashMap::new(),
+        })
+    }
+
+    fn handle(&mut self, message: &[u8]) -> Option<Vec<u8>> {
+        if message.len() < OPTIONS_OFFSET || message[0] != BOOTREQUEST
+            || message[236..240] != MAGIC_COOKIE
+        {
+            return None;
+        }
+
+        // Only Ethernet hardware addresses are handed leases
+        if message[1] != 1 || message[2] != 6 {
+            return None;
+        }
+
+        let mut mac = [0; 6];
+        mac.copy_from_slice(&message[28..34]);
+
+        let options = parse_options(&message[OPTIONS_OFFSET..]);
+
+        let message_type = *options.get(&OPTION_MESSAGE_TYPE)?.first()?;
+
+        let requested = options
+            .get(&OPTION_REQUESTED_ADDRESS)
+            .and_then(|address| to_address(address))
+            .or_else(|| to_address(&message[12..16]))
+            .and_then(|address| {
+                if address == Ipv4Addr::new(0, 0, 0, 0) {
+                    None
+                } else {
+                    Some(address)
+                }
+            });
+
+        // Expired leases are dropped before allocating, so that the leases are
+        // bounded by the addresses of the range
+        self.remove_expired_leases();
+
+        match message_type {
+            DHCPDISCOVER => {
+                let address = self.allocate(mac, requested)?;
+                Some(self.reply(message, DHCPOFFER, address))
+            },
+            DHCPREQUEST => {
+                // The client selected the offer of another server
+                if let Some(server_id) = options.get(&OPTION_SERVER_ID) {
+                    if to_address(server_id) != Some(self.gateway) {
+                        return None;
+                    }
+                }
+
+                let is_new_client = !self.leases.contains_key(&mac);
+
+                match requested {
+                    Some(address)
+                        if self.is_available(mac, address) && !(is_new_client && self.is_full()) =>
+                    {
+                        self.lease(mac, address);
+                        info!("DHCP lease of {} to {}", address, format_mac(&mac));
+                        Some(self.reply(message, DHCPACK, address))
+                    },
+                    _ => Some(self.reply(message, DHCPNAK, Ipv4Addr::new(0, 0, 0, 0))),
+                }
+            },
+            DHCPDECLINE | DHCPRELEASE => {
+                self.leases.remove(&mac);
+                None
+            },
+            _ => None,
+        }
+    }
+
+    /// Picks the address already leased to the client, the requested one or
+    /// the first free one. New clients get none once the maximum number of
+    /// clients, or all addresses of the range, hold leases.
+    fn allocate(&self, mac: [u8; 6], requested: Option<Ipv4Addr>) -> Option<Ipv4Addr> {
+        if let Some(&(address, _)) = self.leases.get(&mac) {
+            return Some(address);
+        }
+
+        if self.is_full() {
+            warn!(
+                "Not serving {}: the captive portal has {} clients already",
+                format_mac(&mac),
+                self.leases.len()
+            );
+            return None;
+        }
+
+        if let Some(address) = requested {
+            if self.is_available(mac, address) {
+                return Some(address);
+            }
+        }
+
+        (self.start..self.end + 1)
+            .map(Ipv4Addr::from)
+            .find(|address| self.is_available(mac, *address))
+    }
+
+    fn is_available(&self, mac: [u8; 6], address: Ipv4Addr) -> bool {
+        let value = u32::from(address);
+
+        if value < self.start || value > self.end || address == self.gateway {
+            return false;
+        }
+
+        let now = Instant::now();
+
+        self.leases.iter().all(|(lease_mac, &(lease_address, expires))| {
+            *lease_mac == mac || lease_address != address || expires < now
+        })
+    }
+
+    /// Whether a new client can be leased an address, which assumes that the
+    /// expired leases are removed
+    fn is_full(&self) -> bool {
+        self.leases.len() >= self.size()
+            || (self.max_clients != 0 && self.leases.len() >= self.max_clients)
+    }
+
+    /// Number of addresses of the range, without the gateway
+    fn size(&self) -> usize {
+        let gateway = u32::from(self.gateway);
+        let size = (self.end - self.start + 1) as usize;
+
+        if gateway >= self.start && gateway <= self.end {
+            size - 1
+        } else {
+            size
+        }
+    }
+
+    fn remove_expired_leases(&mut self) {
+        let now = Instant::now();
+
+        self.leases.retain(|_, &mut (_, expires)| expires >= now);
+    }
+
+    fn lease(&mut self, mac: [u8; 6], address: Ipv4Addr) {
+        let expires = Instant::now() + Duration::from_secs(u64::from(self.lease_time));
+        self.leases.insert(mac, (address, expires));
+    }
+
+    fn reply(&self, request: &[u8], message_type: u8, address: Ipv4Addr) -> Vec<u8> {
+        let mut reply = vec![0; OPTIONS_OFFSET];
+
+        reply[0] = BOOTREPLY;
+        // Hardware type and address length, transaction ID, seconds and flags
+        reply[1..12].copy_from_slice(&request[1..12]);
+        reply[3] = 0;
+        reply[16..20].copy_from_slice(&address.octets());
+        reply[20..24].copy_from_slice(&self.gateway.octets());
+        // Relay agent and client hardware address
+        reply[24..44].copy_from_slice(&request[24..44]);
+        reply[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let gateway = self.gateway.octets().to_vec();
+
+        push_option(&mut reply, OPTION_MESSAGE_TYPE, &[message_type]);
+        push_option(&mut reply, OPTION_SERVER_ID, &gateway);
+
+        if message_type != DHCPNAK {
+            let mask = subnet::netmask(self.prefix);
+
+            push_option(&mut reply, OPTION_LEASE_TIME, &to_bytes(self.lease_time));
+            push_option(&mut reply, OPTION_SUBNET_MASK, &mask.octets());
+            push_option(&mut reply, OPTION_ROUTER, &gateway);
+            push_option(&mut reply, OPTION_DNS_SERVERS, &gateway);
+
+            for &(code, ref value) in &self.options {
+                push_option(&mut reply, code, value);
+            }
+        }
+
+        reply.push(OPTION_END);
+
+        reply
+    }
+}
+
+fn parse_options(mut options: &[u8]) -> HashMap<u8, Vec<u8>> {
+    let mut parsed = HashMap::new();
+
+    while let Some(&code) = options.first() {
+        if code == OPTION_END {
+            break;
+        }
+
+        if code == OPTION_PAD {
+            options = &options[1..];
+            continue;
+        }
+
+        let len = match options.get(1) {
+            Some(&len) => len as usize,
+            None => break,
+        };
+
+        if options.len() < 2 + len {
+            break;
+        }
+
+        parsed.insert(code, options[2..2 + len].to_vec());
+        options = &options[2 + len..];
+    }
+
+    parsed
+}
+
+fn push_option(reply: &mut Vec<u8>, code: u8, value: &[u8]) {
+    reply.push(code);
+    reply.push(value.len() as u8);
+    reply.extend_from_slice(value);
+}
+
+fn to_address(bytes: &[u8]) -> Option<Ipv4Addr> {
+    if bytes.len() != 4 {
+        return None;
+    }
+
+    Some(Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3]))
+}
+
+fn to_bytes(value: u32) -> [u8; 4] {
+    [
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]
+}
+
+fn format_mac(mac: &[u8; 6]) -> String {
+    mac.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn parse_address(address: Option<&str>) -> Result<Ipv4Addr> {
+    let address = address.unwrap_or("");
+
+    Ipv4Addr::from_str(address)
+        .map_err(|_| ErrorKind::InvalidDhcpConfig(format!("invalid address '{}'", address)).into())
+}
+
+/// Parses a dnsmasq lease time, e.g. `3600`, `10m`, `12h` or `infinite`
+fn parse_lease_time(lease_time: &str) -> Result<u32> {
+    if lease_time == "infinite" {
+        return Ok(!0);
+    }
+
+    let (digits, multiplier) = match lease_time.chars().last() {
+        Some('m') => (&lease_time[..lease_time.len() - 1], 60),
+        Some('h') => (&lease_time[..lease_time.len() - 1], 60 * 60),
+        Some('d') => (&lease_time[..lease_time.len() - 1], 24 * 60 * 60),
+        Some('w') => (&lease_time[..lease_time.len() - 1], 7 * 24 * 60 * 60),
+        Some('s') => (&lease_time[..lease_time.len() - 1], 1),
+        _ => (lease_time, 1),
+    };
+
+    u32::from_str(digits)
+        .map(|value| value.saturating_mul(multiplier))
+        .map_err(|_| {
+            ErrorKind::InvalidDhcpConfig(format!("invalid lease time '{}'", lease_time)).into()
+        })
+}
+
+/// Whether the built-in server can send an option, which it ignores otherwise
+pub fn is_supported_option(option: &str) -> bool {
+    parse_option(option).is_some()
+}
+
+/// Parses an option given in the dnsmasq `<code>,<value>` format. A value of
+/// comma separated IPv4 addresses is sent as such, any other as a string.
+/// Named options like `option:router` are not supported.
+fn parse_option(option: &str) -> Option<(u8, Vec<u8>)> {
+    let mut parts = option.splitn(2, ',');
+
+    let code = u8::from_str(parts.next()?.trim()).ok()?;
+    let value = parts.next()?.trim();
+
+    let addresses = value
+        .split(',')
+        .map(|address| Ipv4Addr::from_str(address.trim()))
+        .collect::<::std::result::Result<Vec<_>, _>>();
+
+    let bytes = match addresses {
+        Ok(addresses) => addresses
+            .iter()
+            .flat_map(|address| address.octets().to_vec())
+            .collect(),
+        Err(_) => value.as_bytes().to_vec(),
+    };
+
+    if bytes.len() > 255 {
+        return None;
+    }
+
+    Some((code, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x01];
+    const OTHER_CLIENT: [u8; 6] = [0x02, 0x00, 0x00, 0x00, 0x00, 0x02];
+
+    fn pool(max_clients: usize) -> Pool {
+        Pool {
+            gateway: Ipv4Addr::new(192, 168, 42, 1),
+            prefix: 24,
+            start: u32::from(Ipv4Addr::new(192, 168, 42, 2)),
+            end: u32::from(Ipv4Addr::new(192, 168, 42, 254)),
+            lease_time: DEFAULT_LEASE_TIME,
+            max_clients: max_clients,
+            options: vec![(15, b"portal".to_vec())],
+            leases: HashMap::new(),
+        }
+    }
+
+    fn request(mac: [u8; 6], options: &[u8]) -> Vec<u8> {
+        let mut message = vec![0; OPTIONS_OFFSET];
+
+        message[0] = BOOTREQUEST;
+        message[1] = 1;
+        message[2] = 6;
+        message[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        message[28..34].copy_from_slice(&mac);
+        message[236..240].copy_from_slice(&MAGIC_COOKIE);
+        message.extend_from_slice(options);
+        message.push(OPTION_END);
+
+        message
+    }
+
+    #[test]
+    fn discover_is_offered_the_first_free_address() {
+        let reply = pool(0)
+            .handle(&request(CLIENT, &[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER]))
+            .unwrap();
+
+        assert_eq!(reply[0], BOOTREPLY);
+        assert_eq!(reply[4..8], [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(reply[16..20], [192, 168, 42, 2]);
+        assert_eq!(reply[20..24], [192, 168, 42, 1]);
+        assert_eq!(reply[28..34], CLIENT);
+        assert_eq!(reply[236..240], MAGIC_COOKIE);
+
+        let options = parse_options(&reply[OPTIONS_OFFSET..]);
+
+        assert_eq!(options[&OPTION_MESSAGE_TYPE], [DHCPOFFER]);
+        assert_eq!(options[&OPTION_SERVER_ID], [192, 168, 42, 1]);
+        assert_eq!(options[&OPTION_LEASE_TIME], [0, 0, 0x0e, 0x10]);
+        assert_eq!(options[&OPTION_SUBNET_MASK], [255, 255, 255, 0]);
+        assert_eq!(options[&OPTION_ROUTER], [192, 168, 42, 1]);
+        assert_eq!(options[&OPTION_DNS_SERVERS], [192, 168, 42, 1]);
+        assert_eq!(options[&15], b"portal");
+    }
+
+    #[test]
+    fn request_is_acknowledged_once() {
+        let mut pool = pool(0);
+
+        let options = [
+            OPTION_MESSAGE_TYPE, 1, DHCPREQUEST,
+            OPTION_REQUESTED_ADDRESS, 4, 192, 168, 42, 10,
+        ];
+
+        let reply = pool.handle(&request(CLIENT, &options)).unwrap();
+        assert_eq!(reply[16..20], [192, 168, 42, 10]);
+        assert_eq!(parse_options(&reply[OPTIONS_OFFSET..])[&OPTION_MESSAGE_TYPE], [DHCPACK]);
+
+        // The address is leased to the first client
+        let reply = pool.handle(&request(OTHER_CLIENT, &options)).unwrap();
+        assert_eq!(reply[16..20], [0, 0, 0, 0]);
+        assert_eq!(parse_options(&reply[OPTIONS_OFFSET..])[&OPTION_MESSAGE_TYPE], [DHCPNAK]);
+
+        // Renewing keeps the lease
+        let discover = [OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER];
+        let reply = pool.handle(&request(CLIENT, &discover)).unwrap();
+        assert_eq!(reply[16..20], [192, 168, 42, 10]);
+    }
+
+    #[test]
+    fn request_for_another_server_is_ignored() {
+        let options = [
+            OPTION_MESSAGE_TYPE, 1, DHCPREQUEST,
+            OPTION_SERVER_ID, 4, 192, 168, 1, 1,
+            OPTION_REQUESTED_ADDRESS, 4, 192, 168, 42, 10,
+        ];
+
+        assert!(pool(0).handle(&request(CLIENT, &options)).is_none());
+    }
+
+    #[test]
+    fn new_clients_are_refused_when_full() {
+        let mut pool = pool(1);
+
+        let options = [
+            OPTION_MESSAGE_TYPE, 1, DHCPREQUEST,
+            OPTION_REQUESTED_ADDRESS, 4, 192, 168, 42, 10,
+        ];
+        pool.handle(&request(CLIENT, &options)).unwrap();
+
+        let discover = [OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER];
+        assert!(pool.handle(&request(OTHER_CLIENT, &discover)).is_none());
+        assert!(pool.handle(&request(CLIENT, &discover)).is_some());
+    }
+
+    #[test]
+    fn expired_leases_are_removed() {
+        let mut pool = pool(0);
+
+        let expired = Instant::now() - Duration::from_secs(1);
+        pool.leases.insert(OTHER_CLIENT, (Ipv4Addr::new(192, 168, 42, 2), expired));
+
+        let discover = [OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER];
+        let reply = pool.handle(&request(CLIENT, &discover)).unwrap();
+
+        assert_eq!(reply[16..20], [192, 168, 42, 2]);
+        assert!(pool.leases.is_empty());
+    }
+
+    #[test]
+    fn leases_are_capped_at_the_range() {
+        let mut pool = pool(0);
+        pool.end = u32::from(Ipv4Addr::new(192, 168, 42, 3));
+
+        for &(mac, last_octet) in &[(CLIENT, 2), (OTHER_CLIENT, 3)] {
+            let options = [
+                OPTION_MESSAGE_TYPE, 1, DHCPREQUEST,
+                OPTION_REQUESTED_ADDRESS, 4, 192, 168, 42, last_octet,
+            ];
+            pool.handle(&request(mac, &options)).unwrap();
+        }
+
+        let discover = [OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER];
+        let third_client = [0x02, 0x00, 0x00, 0x00, 0x00, 0x03];
+
+        assert!(pool.handle(&request(third_client, &discover)).is_none());
+        assert_eq!(pool.leases.len(), 2);
+    }
+
+    #[test]
+    fn malformed_messages_are_dropped() {
+        let mut pool = pool(0);
+        let discover = request(CLIENT, &[OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER]);
+
+        assert!(pool.handle(&discover[..OPTIONS_OFFSET - 1]).is_none());
+
+        let mut reply = discover.clone();
+        reply[0] = BOOTREPLY;
+        assert!(pool.handle(&reply).is_none());
+
+        let mut no_cookie = discover.clone();
+        no_cookie[236] = 0;
+        assert!(pool.handle(&no_cookie).is_none());
+
+        assert!(pool.handle(&request(CLIENT, &[])).is_none());
+    }
+
+    #[test]
+    fn options_skip_padding_and_stop_at_truncation() {
+        let options = parse_options(&[
+            OPTION_PAD, OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER, OPTION_PAD,
+            OPTION_ROUTER, 4, 10, 0, 0, 1,
+            OPTION_LEASE_TIME, 4, 0, 0,
+        ]);
+
+        assert_eq!(options.len(), 2);
+        assert_eq!(options[&OPTION_MESSAGE_TYPE], [DHCPDISCOVER]);
+        assert_eq!(options[&OPTION_ROUTER], [10, 0, 0, 1]);
+
+        let options = parse_options(&[OPTION_END, OPTION_MESSAGE_TYPE, 1, DHCPDISCOVER]);
+        assert!(options.is_empty());
+    }
+
+    #[test]
+    fn lease_times() {
+        assert_eq!(parse_lease_time("3600").unwrap(), 3600);
+        assert_eq!(parse_lease_time("45s").unwrap(), 45);
+        assert_eq!(parse_lease_time("10m").unwrap(), 600);
+        assert_eq!(parse_lease_time("12h").unwrap(), 43_200);
+        assert_eq!(parse_lease_time("1d").unwrap(), 86_400);
+        assert_eq!(parse_lease_time("2w").unwrap(), 1_209_600);
+        assert_eq!(parse_lease_time("infinite").unwrap(), !0);
+        assert!(parse_lease_time("1y").is_err());
+        assert!(parse_lease_time("").is_err());
+    }
+
+    #[test]
+    fn dnsmasq_style_options() {
+        assert_eq!(
+            parse_option("6,10.0.0.1, 10.0.0.2"),
+            Some((6, vec![10, 0, 0, 1, 10, 0, 0, 2]))
+        );
+        assert_eq!(parse_option("15,portal.lan"), Some((15, b"portal.lan".to_vec())));
+        assert_eq!(parse_option("option:router,10.0.0.1"), None);
+        assert_eq!(parse_option("15"), None);
+        assert!(parse_option(&format!("15,{}", "a".repeat(256))).is_none());
+    }
+}
@@ -0,0 +1,614 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::thread;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use errors::*;
+
+const DNS_PORT: u16 = 53;
+
+/// Time to wait for the upstream server to answer a forwarded query (secs)
+const FORWARD_TIMEOUT: u64 = 2;
+
+/// Threads relaying the forwarded queries, so that a slow upstream does not
+/// hold up the other clients
+const FORWARD_THREADS: usize = 4;
+
+/// Forwarded queries waiting for a thread. Further queries are dropped and
+/// left to the retries of the clients, which bounds what a flood of queries
+/// costs.
+const FORWARD_QUEUE: usize = 32;
+
+const RCODE_NXDOMAIN: u16 = 3;
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+
+/// What the portal DNS does with the queries for a host name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DnsAction {
+    /// Answers with the gateway address, leading to the captive portal
+    Hijack,
+    /// Answers that the name does not exist
+    Nxdomain,
+    /// Passes the query on to the upstream server, if there is an uplink
+    Forward,
+}
+
+impl DnsAction {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DnsAction::Hijack => "hijack",
+            DnsAction::Nxdomain => "nxdomain",
+            DnsAction::Forward => "forward",
+        }
+    }
+}
+
+/// Host name patterns sharing an action, e.g. `forward:api.example.com` or
+/// `nxdomain:*.apple.com,*.gstatic.com`. A pattern is a host name, `*.` and
+/// a domain for its subdomains, or `*` for any name.
+#[derive(Debug, Clone)]
+pub struct DnsRule {
+    pub action: DnsAction,
+    pub patterns: Vec<String>,
+}
+
+impl DnsRule {
+    fn matches(&self, name: &str) -> bool {
+        self.patterns.iter().any(|pattern| {
+            if pattern == "*" {
+                true
+            } else if pattern.starts_with("*.") {
+                name.ends_with(&pattern[1..])
+            } else {
+                name == pattern
+            }
+        })
+    }
+}
+
+impl FromStr for DnsRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+
+        let action = match parts.next().map(str::trim) {
+            Some("hijack") => DnsAction::Hijack,
+            Some("nxdomain") => DnsAction::Nxdomain,
+            Some("forward") => DnsAction::Forward,
+            _ => {
+                return Err(format!(
+                    "Invalid DNS rule: {} (expected hijack, nxdomain or forward)",
+                    s
+                ))
+            },
+        };
+
+        let patterns = parts
+            .next()
+            .unwrap_or("")
+            .split(',')
+            .map(|pattern| normalize_name(pattern.trim()))
+            .filter(|pattern| !pattern.is_empty())
+            .collect::<Vec<_>>();
+
+        if patterns.is_empty() {
+            return Err(format!("Invalid DNS rule: {} (no host name patterns)", s));
+        }
+
+        for pattern in &patterns {
+            if pattern != "*" && pattern[..].trim_left_matches("*.").contains('*') {
+                return Err(format!("Invalid DNS rule pattern: {}", pattern));
+            }
+        }
+
+        Ok(DnsRule {
+            action: action,
+            patterns: patterns,
+        })
+    }
+}
+
+impl fmt::Display for DnsRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.action.as_str(), self.patterns.join(","))
+    }
+}
+
+/// The action of the first rule matching the name. Names no rule matches
+/// are hijacked.
+pub fn get_dns_action(rules: &[DnsRule], name: &str) -> DnsAction {
+    rules
+        .iter()
+        .find(|rule| rule.matches(name))
+        .map_or(DnsAction::Hijack, |rule| rule.action)
+}
+
+/// The first IPv4 name server of the system resolver which is not on the
+/// device itself, i.e. the upstream server of an uplink, if there is one
+pub fn get_system_nameserver() -> Option<Ipv4Addr> {
+    let mut contents = String::new();
+
+    File::open(RESOLV_CONF)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .ok()?;
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+
+            match (fields.next(), fields.next()) {
+                (Some("nameserver"), Some(address)) => Ipv4Addr::from_str(address).ok(),
+                _ => None,
+            }
+        })
+        .find(|address| !address.is_loopback())
+}
+
+/// Host names compare in lower case and without the trailing dot of the
+/// root
+fn normalize_name(name: &str) -> String {
+    name.trim_right_matches('.').to_lowercase()
+}
+
+/// Answers every A query with the gateway address, like dnsmasq with
+/// `--address=/#/<gateway>`, so that any page a client opens leads to the
+/// captive portal. The DNS rules may exempt host names from this.
+pub struct DnsServer {
+    resolver: Arc<RwLock<Resolver>>,
+    stop: Arc<AtomicBool>,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl DnsServer {
+    pub fn start(
+        gateway: Ipv4Addr,
+        rules: Vec<DnsRule>,
+        upstream: Option<Ipv4Addr>,
+    ) -> Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(gateway.into(), DNS_PORT))
+            .chain_err(|| ErrorKind::BuiltinDns)?;
+
+        // Wakes the thread up periodically to check whether to stop
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .chain_err(|| ErrorKind::BuiltinDns)?;
+
+        let forward_tx = start_forwarders(&socket, gateway).chain_err(|| ErrorKind::BuiltinDns)?;
+
+        let resolver = Arc::new(RwLock::new(Resolver {
+            rules: rules,
+            upstream: upstream,
+        }));
+        let stop = Arc::new(AtomicBool::new(false));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let resolver = resolver.clone();
+            let stop = stop.clone();
+            let running = running.clone();
+
+            thread::spawn(move || {
+                serve(&socket, gateway, &resolver, &forward_tx, &stop);
+                running.store(false, Ordering::SeqCst);
+            })
+        };
+
+        info!("DNS server listening on {}:{}", gateway, DNS_PORT);
+
+        Ok(DnsServer {
+            resolver: resolver,
+            stop: stop,
+            running: running,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    /// Applies from the next query on
+    pub fn set_rules(&self, rules: Vec<DnsRule>, upstream: Option<Ipv4Addr>) {
+        let mut resolver = self.resolver.write().unwrap();
+
+        resolver.rules = rules;
+        resolver.upstream = upstream;
+    }
+
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Rules of the server and the upstream server of the names to forward,
+/// which a reload of the configuration replaces
+struct Resolver {
+    rules: Vec<DnsRule>,
+    upstream: Option<Ipv4Addr>,
+}
+
+fn serve(
+    socket: &UdpSocket,
+    gateway: Ipv4Addr,
+    resolver: &RwLock<Resolver>,
+    forward_tx: &SyncSender<ForwardedQuery>,
+    stop: &AtomicBool,
+) {
+    let mut buffer = [0; 512];
+
+    while !stop.load(Ordering::SeqCst) {
+        let (len, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(ref e) if is_timeout(e) => continue,
+            Err(e) => {
+                error!("Receiving DNS query failed: {}", e);
+                return;
+            },
+        };
+
+        let query = &buffer[..len];
+
+        let question = match parse_query(query) {
+            Some(question) => question,
+            None => continue,
+        };
+
+        let (action, upstream) = {
+            let resolver = resolver.read().unwrap();
+            (get_dns_action(&resolver.rules, &question.name), resolver.upstream)
+        };
+
+        let action = match (action, upstream) {
+            (DnsAction::Forward, Some(upstream)) => {
+                let name = question.name.clone();
+
+                let forwarded = ForwardedQuery {
+                    query: query.to_vec(),
+                    question: question,
+                    source: source,
+                    upstream: upstream,
+                };
+
+                if forward_tx.try_send(forwarded).is_err() {
+                    debug!("Too many forwarded DNS queries - dropping the query for {}", name);
+                }
+
+                continue;
+            },
+            // Without an uplink the portal is the best answer there is
+            (DnsAction::Forward, None) => DnsAction::Hijack,
+            (action, _) => action,
+        };
+
+        let response = get_response(query, &question, gateway, action);
+
+        if let Err(e) = socket.send_to(&response, source) {
+            warn!("Sending DNS response to {} failed: {}", source, e);
+        }
+    }
+}
+
+/// A query to relay to the upstream server and the client to answer
+struct ForwardedQuery {
+    query: Vec<u8>,
+    question: Question,
+    source: SocketAddr,
+    upstream: Ipv4Addr,
+}
+
+/// Starts the threads relaying forwarded queries. They stop once the server
+/// drops the sender.
+fn start_forwarders(
+    socket: &UdpSocket,
+    gateway: Ipv4Addr,
+) -> ::std::io::Result<SyncSender<ForwardedQuery>> {
+    let (forward_tx, forward_rx) = sync_channel(FORWARD_QUEUE);
+    let forward_rx = Arc::new(Mutex::new(forward_rx));
+
+    for _ in 0..FORWARD_THREADS {
+        let socket = socket.try_clone()?;
+        let forward_rx = forward_rx.clone();
+
+        thread::spawn(move || forward(&socket, gateway, &forward_rx));
+    }
+
+    Ok(forward_tx)
+}
+
+/// Relays queries to the upstream server. A query is hijacked if the
+/// upstream server does not answer.
+fn forward(socket: &UdpSocket, gateway: Ipv4Addr, forward_rx: &Mutex<Receiver<ForwardedQuery>>) {
+    loop {
+        let forwarded = match forward_rx.lock().unwrap().recv() {
+            Ok(forwarded) => forwarded,
+            Err(_) => return,
+        };
+
+        let response = match forward_query(&forwarded.query, forwarded.upstream) {
+            Ok(response) => response,
+            Err(e) => {
+                debug!("Forwarding DNS query for {} failed: {}", forwarded.question.name, e);
+                get_response(&forwarded.query, &forwarded.question, gateway, DnsAction::Hijack)
+            },
+        };
+
+        if let Err(e) = socket.send_to(&response, forwarded.source) {
+            warn!("Sending DNS response to {} failed: {}", forwarded.source, e);
+        }
+    }
+}
+
+fn forward_query(query: &[u8], upstream: Ipv4Addr) -> ::std::io::Result<Vec<u8>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_secs(FORWARD_TIMEOUT)))?;
+    socket.connect(SocketAddr::new(upstream.into(), DNS_PORT))?;
+    socket.send(query)?;
+
+    let mut buffer = [0; 512];
+
+    // Answers to other queries are not expected on the fresh socket
+    let len = socket.recv(&mut buffer)?;
+
+    Ok(buffer[..len].to_vec())
+}
+
+pub fn is_timeout(e: &::std::io::Error) -> bool {
+    e.kind() == ::std::io::ErrorKind::WouldBlock || e.kind() == ::std::io::ErrorKind::TimedOut
+}
+
+#[derive(Clone)]
+struct Question {
+    name: String,
+    question_type: u16,
+    question_class: u16,
+    /// Offset of the end of the question within the query
+    end: usize,
+}
+
+/// Parses a standard query with a single question. Other messages are
+/// dropped.
+fn parse_query(query: &[u8]) -> Option<Question> {
+    if query.len() < 12 {
+        return None;
+    }
+
+    let flags = read_u16(query, 2);
+    let is_response = flags & 0x8000 != 0;
+    let opcode = (flags >> 11) & 0xf;
+    let questions = read_u16(query, 4);
+
+    if is_response || opcode != 0 || questions != 1 {
+        return None;
+    }
+
+    // The question name is a sequence of length prefixed labels
+    let mut position = 12;
+    let mut labels = Vec::new();
+
+    loop {
+        let len = *query.get(position)? as usize;
+
+        if len == 0 {
+            position += 1;
+            break;
+        }
+
+        if len & 0xc0 != 0 {
+            return None;
+        }
+
+        labels.push(String::from_utf8_lossy(query.get(position + 1..position + 1 + len)?));
+
+        position += 1 + len;
+    }
+
+    if query.len() < position + 4 {
+        return None;
+    }
+
+    Some(Question {
+        name: normalize_name(&labels.join(".")),
+        question_type: read_u16(query, position),
+        question_class: read_u16(query, position + 2),
+        end: position + 4,
+    })
+}
+
+/// Builds the response to a parsed query, either the gateway address or
+/// that the name does not exist
+fn get_response(
+    query: &[u8],
+    question: &Question,
+    gateway: Ipv4Addr,
+    action: DnsAction,
+) -> Vec<u8> {
+    let flags = read_u16(query, 2);
+    let rcode = if action == DnsAction::Nxdomain {
+        RCODE_NXDOMAIN
+    } else {
+        0
+    };
+
+    let answer = action == DnsAction::Hijack && question.question_class == CLASS_IN
+        && (question.question_type == TYPE_A || question.question_type == TYPE_ANY);
+
+    let mut response = Vec::with_capacity(question.end + 16);
+
+    // Same ID, response bit, opcode and recursion desired bit, authoritative
+    // and recursion available
+    response.extend_from_slice(&query[0..2]);
+    push_u16(&mut response, 0x8000 | (flags & 0x0100) | 0x0400 | 0x0080 | rcode);
+    push_u16(&mut response, 1);
+    push_u16(&mut response, if answer { 1 } else { 0 });
+    push_u16(&mut response, 0);
+    push_u16(&mut response, 0);
+
+    response.extend_from_slice(&query[12..question.end]);
+
+    if answer {
+        // Pointer to the question name
+        push_u16(&mut response, 0xc00c);
+        push_u16(&mut response, TYPE_A);
+        push_u16(&mut response, CLASS_IN);
+        response.extend_from_slice(&[0, 0, 0, 0]);
+        push_u16(&mut response, 4);
+        response.extend_from_slice(&gateway.octets());
+    }
+
+    response
+}
+
+fn read_u16(buffer: &[u8], position: usize) -> u16 {
+    (u16::from(buffer[position]) << 8) | u16::from(buffer[position + 1])
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.push((value >> 8) as u8);
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Query for the A record of `Example.COM.` with recursion desired
+    const QUERY: [u8; 29] = [
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        7, b'E', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'C', b'O', b'M', 0,
+        0x00, 0x01, 0x00, 0x01,
+    ];
+
+    fn rule(rule: &str) -> DnsRule {
+        rule.parse().unwrap()
+    }
+
+    #[test]
+    fn query_is_parsed() {
+        let question = parse_query(&QUERY).unwrap();
+
+        assert_eq!(question.name, "example.com");
+        assert_eq!(question.question_type, TYPE_A);
+        assert_eq!(question.question_class, CLASS_IN);
+        assert_eq!(question.end, QUERY.len());
+    }
+
+    #[test]
+    fn other_messages_are_dropped() {
+        assert!(parse_query(&QUERY[..11]).is_none());
+        assert!(parse_query(&QUERY[..QUERY.len() - 1]).is_none());
+
+        let mut response = QUERY;
+        response[2] |= 0x80;
+        assert!(parse_query(&response).is_none());
+
+        let mut status = QUERY;
+        status[2] |= 2 << 3;
+        assert!(parse_query(&status).is_none());
+
+        let mut two_questions = QUERY;
+        two_questions[5] = 2;
+        assert!(parse_query(&two_questions).is_none());
+
+        // Compression pointers have no place in a question
+        let mut pointer = QUERY;
+        pointer[12] = 0xc0;
+        assert!(parse_query(&pointer).is_none());
+    }
+
+    #[test]
+    fn hijacked_query_is_answered_with_the_gateway() {
+        let question = parse_query(&QUERY).unwrap();
+        let response = get_response(
+            &QUERY,
+            &question,
+            Ipv4Addr::new(192, 168, 42, 1),
+            DnsAction::Hijack,
+        );
+
+        let mut expected = vec![
+            0x12, 0x34, 0x85, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+        ];
+        expected.extend_from_slice(&QUERY[12..]);
+        expected.extend_from_slice(&[
+            0xc0, 0x0c, 0x00, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0x00, 0x04, 192, 168, 42, 1,
+        ]);
+
+        assert_eq!(response, expected);
+    }
+
+    #[test]
+    fn nxdomain_query_has_no_answer() {
+        let question = parse_query(&QUERY).unwrap();
+        let response = get_response(
+            &QUERY,
+            &question,
+            Ipv4Addr::new(192, 168, 42, 1),
+            DnsAction::Nxdomain,
+        );
+
+        assert_eq!(response[..12], [0x12, 0x34, 0x85, 0x83, 0, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(response[12..], QUERY[12..]);
+    }
+
+    #[test]
+    fn aaaa_query_is_hijacked_without_answer() {
+        let mut query = QUERY;
+        query[26] = 28;
+
+        let question = parse_query(&query).unwrap();
+        let response = get_response(
+            &query,
+            &question,
+            Ipv4Addr::new(192, 168, 42, 1),
+            DnsAction::Hijack,
+        );
+
+        assert_eq!(response[2..8], [0x85, 0x80, 0, 1, 0, 0]);
+        assert_eq!(response.len(), query.len());
+    }
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let rules = [
+            rule("forward:api.example.com"),
+            rule("nxdomain:*.example.com,*.apple.com"),
+        ];
+
+        assert_eq!(get_dns_action(&rules, "api.example.com"), DnsAction::Forward);
+        assert_eq!(get_dns_action(&rules, "www.example.com"), DnsAction::Nxdomain);
+        assert_eq!(get_dns_action(&rules, "example.com"), DnsAction::Hijack);
+        assert_eq!(get_dns_action(&rules, "captive.apple.com"), DnsAction::Nxdomain);
+        assert_eq!(get_dns_action(&[rule("forward:*")], "example.org"), DnsAction::Forward);
+    }
+
+    #[test]
+    fn rules_are_parsed() {
+        let parsed = rule(" nxdomain : *.Apple.com., gstatic.com ");
+
+        assert_eq!(parsed.action, DnsAction::Nxdomain);
+        assert_eq!(parsed.patterns, ["*.apple.com", "gstatic.com"]);
+        assert_eq!(parsed.to_string(), "nxdomain:*.apple.com,gstatic.com");
+
+        assert!("drop:example.com".parse::<DnsRule>().is_err());
+        assert!("forward:".parse::<DnsRule>().is_err());
+        assert!("forward:api.*.com".parse::<DnsRule>().is_err());
+    }
+}
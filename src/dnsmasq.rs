@@ -1,14 +1,124 @@
+use std::net::Ipv4Addr;
 use std::process::{Child, Command};
 
 use network_manager::Device;
 
 use errors::*;
 use config::Config;
+use dhcp::DhcpServer;
+use dns::{get_system_nameserver, DnsAction, DnsServer};
+use subnet;
+
+/// DHCP and DNS service of the captive portal network: the built-in servers,
+/// or dnsmasq if requested with `--dnsmasq`
+pub enum DhcpDns {
+    Builtin {
+        dhcp: DhcpServer,
+        dns: Option<DnsServer>,
+    },
+    Dnsmasq(Child),
+}
+
+impl DhcpDns {
+    pub fn start(config: &Config, device: &Device) -> Result<Self> {
+        if config.dnsmasq {
+            return Ok(DhcpDns::Dnsmasq(start_dnsmasq(config, device)?));
+        }
+
+        let dhcp = DhcpServer::start(config, device.interface())?;
+
+        // Without DNS hijacking clients have to open the portal URL manually,
+        // which the lenient startup policy accepts
+        let upstream = get_dns_upstream(config);
+
+        let dns = match DnsServer::start(config.gateway, config.dns_rules.clone(), upstream) {
+            Ok(dns) => Some(dns),
+            Err(e) => if config.lenient {
+                warn!("Captive portal DNS disabled: {}", e);
+                None
+            } else {
+                let mut dhcp = dhcp;
+                dhcp.stop();
+                return Err(e);
+            },
+        };
+
+        Ok(DhcpDns::Builtin { dhcp: dhcp, dns: dns })
+    }
+
+    /// Applies the DNS rules of a reloaded configuration to the built-in
+    /// server, while dnsmasq only picks them up the next time it is started
+    pub fn set_dns_rules(&self, config: &Config) -> bool {
+        match *self {
+            DhcpDns::Builtin { ref dns, .. } => {
+                if let Some(ref dns) = *dns {
+                    dns.set_rules(config.dns_rules.clone(), get_dns_upstream(config));
+                }
+
+                true
+            },
+            DhcpDns::Dnsmasq(_) => false,
+        }
+    }
+
+    /// A DNS server disabled by the lenient startup policy does not count as
+    /// stopped, since the portal was accepted without it
+    pub fn is_running(&mut self) -> bool {
+        match *self {
+            DhcpDns::Builtin {
+                ref dhcp,
+                ref dns,
+            } => dhcp.is_running() && dns.as_ref().map_or(true, |dns| dns.is_running()),
+            DhcpDns::Dnsmasq(ref mut dnsmasq) => match dnsmasq.try_wait() {
+                Ok(None) => true,
+                Ok(Some(status)) => {
+                    error!("dnsmasq exited: {}", status);
+                    false
+                },
+                Err(e) => {
+                    error!("Checking dnsmasq failed: {}", e);
+                    false
+                },
+            },
+        }
+    }
+
+    pub fn stop(&mut self) {
+        match *self {
+            DhcpDns::Builtin {
+                ref mut dhcp,
+                ref mut dns,
+            } => {
+                dhcp.stop();
+
+                if let Some(ref mut dns) = *dns {
+                    dns.stop();
+                }
+            },
+            DhcpDns::Dnsmasq(ref mut dnsmasq) => {
+                let _ = dnsmasq.kill();
+                let _ = dnsmasq.wait();
+            },
+        }
+    }
+}
+
+fn start_dnsmasq(config: &Config, device: &Device) -> Result<Child> {
+    // The netmask is given rather than taken from the interface, which may
+    // not have its address yet
+    let dhcp_range = format!(
+        "{},{}",
+        config.dhcp_range,
+        subnet::netmask(config.portal_prefix)
+    );
+
+    let dhcp_range = match config.dhcp_lease_time {
+        Some(ref lease_time) => format!("{},{}", dhcp_range, lease_time),
+        None => dhcp_range,
+    };
 
-pub fn start_dnsmasq(config: &Config, device: &Device) -> Result<Child> {
     let args = [
-        &format!("--address=/#/{}", config.gateway),
-        &format!("--dhcp-range={}", config.dhcp_range),
+        &format!("--dhcp-range={}", dhcp_range),
         &format!("--dhcp-option=option:router,{}", config.gateway),
         &format!("--interface={}", device.interface()),
         "--keep-in-foreground",
@@ -18,8 +128,75 @@ pub fn start_dnsmasq(config: &Config, device: &Device) -> Result<Child> {
         "--no-hosts",
     ];
 
+    let mut dhcp_options = config
+        .dhcp_options
+        .iter()
+        .map(|option| format!("--dhcp-option={}", option))
+        .collect::<Vec<_>>();
+
+    if config.portal_max_clients != 0 {
+        dhcp_options.push(format!("--dhcp-lease-max={}", config.portal_max_clients));
+    }
+
     Command::new("dnsmasq")
         .args(&args)
+        .args(&get_dnsmasq_dns_args(config))
+        .args(&dhcp_options)
         .spawn()
         .chain_err(|| ErrorKind::Dnsmasq)
 }
+
+fn get_dns_upstream(config: &Config) -> Option<Ipv4Addr> {
+    if !config.dns_rules
+        .iter()
+        .any(|rule| rule.action == DnsAction::Forward)
+    {
+        return None;
+    }
+
+    let upstream = config.dns_upstream.or_else(get_system_nameserver);
+
+    match upstream {
+        Some(upstream) => info!("Forwarding DNS queries to {}", upstream),
+        None => warn!("No upstream DNS server - hijacking the queries to forward"),
+    }
+
+    upstream
+}
+
+/// Translates the DNS rules to dnsmasq options. Unlike the built-in server,
+/// dnsmasq picks the rule with the most specific domain instead of the first
+/// matching one, and matches the subdomains of host names as well.
+fn get_dnsmasq_dns_args(config: &Config) -> Vec<String> {
+    let upstream = get_dns_upstream(config);
+
+    let mut args = Vec::new();
+    let mut catch_all = false;
+
+    for rule in &config.dns_rules {
+        for pattern in &rule.patterns {
+            let domain = if pattern == "*" {
+                catch_all = true;
+                "#"
+            } else {
+                pattern.trim_left_matches("*.")
+            };
+
+            match (rule.action, upstream) {
+                (DnsAction::Nxdomain, _) => args.push(format!("--address=/{}/", domain)),
+                (DnsAction::Forward, Some(upstream)) => {
+                    args.push(format!("--server=/{}/{}", domain, upstream))
+                },
+                (DnsAction::Hijack, _) | (DnsAction::Forward, None) => {
+                    args.push(format!("--address=/{}/{}", domain, config.gateway))
+                },
+            }
+        }
+    }
+
+    if !catch_all {
+        args.push(format!("--address=/#/{}", config.gateway));
+    }
+
+    args
+}
@@ -8,6 +8,9 @@ error_chain! {
         Recv(::std::sync::mpsc::RecvError);
         SendNetworkCommand(::std::sync::mpsc::SendError<network::NetworkCommand>);
         Nix(::nix::Error);
+        Json(::serde_json::Error);
+        Cbor(::serde_cbor::error::Error);
+        MessagePack(::rmp_serde::encode::Error);
     }
 
     links {
@@ -97,6 +100,418 @@ error_chain! {
         TrapExitSignals {
             description("Trapping exit signals failed")
         }
+
+        SendNetworkCommandStatus {
+            description("Sending NetworkCommand::Status failed")
+        }
+
+        RecvStatus {
+            description("Receiving status failed")
+        }
+
+        SendStatus {
+            description("Sending status failed")
+        }
+
+        UnexpectedNetworkCommandResponse {
+            description("Unexpected network command response")
+        }
+
+        Avahi(method: String) {
+            description("Avahi D-Bus call failed")
+            display("Avahi D-Bus call {} failed", method)
+        }
+
+        Nmcli(command: String) {
+            description("Spawning nmcli failed")
+            display("Spawning 'nmcli {}' failed", command)
+        }
+
+        NmcliStatus(command: String, stderr: String) {
+            description("nmcli exited with an error")
+            display("'nmcli {}' exited with an error: {}", command, stderr)
+        }
+
+        SendNetworkCommandBlacklistBssid {
+            description("Sending NetworkCommand::BlacklistBssid failed")
+        }
+
+        RecvBssidBlacklist {
+            description("Receiving BSSID blacklist failed")
+        }
+
+        SendBssidBlacklist {
+            description("Sending BSSID blacklist failed")
+        }
+
+        BssidsBlacklisted(ssid: String) {
+            description("All BSSIDs of the access point are blacklisted")
+            display("All BSSIDs of access point '{}' are blacklisted", ssid)
+        }
+
+        NoActiveConnection(ssid: String) {
+            description("Cannot find active connection")
+            display("Cannot find active connection to access point '{}'", ssid)
+        }
+
+        SaveState(name: String) {
+            description("Saving state document failed")
+            display("Saving state document '{}' failed", name)
+        }
+
+        TlsIdentity {
+            description("Preparing the TLS identity failed")
+        }
+
+        TlsKeyPair {
+            description("Only one of --tls-certificate and --tls-key given")
+        }
+
+        Openssl {
+            description("Spawning openssl failed")
+        }
+
+        OpensslStatus(stderr: String) {
+            description("openssl exited with an error")
+            display("openssl exited with an error: {}", stderr)
+        }
+
+        SendNetworkCommandConnection {
+            description("Sending NetworkCommand::Connection failed")
+        }
+
+        RecvConnection {
+            description("Receiving connection details failed")
+        }
+
+        SendConnection {
+            description("Sending connection details failed")
+        }
+
+        NotAnEthernetDevice(interface: String) {
+            description("Not an Ethernet device")
+            display("Not an Ethernet device: {}", interface)
+        }
+
+        NoEthernetDevice {
+            description("Cannot find an Ethernet device")
+        }
+
+        SendNetworkCommandEthernet {
+            description("Sending NetworkCommand::Ethernet failed")
+        }
+
+        RecvEthernet {
+            description("Receiving Ethernet status failed")
+        }
+
+        SendEthernet {
+            description("Sending Ethernet status failed")
+        }
+
+        InvalidBackup(reason: String) {
+            description("Invalid backup")
+            display("Invalid backup: {}", reason)
+        }
+
+        SendNetworkCommandBackup {
+            description("Sending backup NetworkCommand failed")
+        }
+
+        RecvBackup {
+            description("Receiving backup result failed")
+        }
+
+        SendBackup {
+            description("Sending backup result failed")
+        }
+
+        SerializeResponse(media_type: String) {
+            description("Serializing response failed")
+            display("Serializing {} response failed", media_type)
+        }
+
+        SendNetworkCommandAdvanced {
+            description("Sending advanced settings NetworkCommand failed")
+        }
+
+        RecvAdvanced {
+            description("Receiving advanced settings result failed")
+        }
+
+        SendAdvanced {
+            description("Sending advanced settings result failed")
+        }
+
+        InvalidSsid(reason: String) {
+            description("Invalid SSID")
+            display("Invalid SSID: {}", reason)
+        }
+
+        AccessPointNotFound(ssid: String) {
+            description("Access point not found")
+            display("Access point '{}' not found", ssid)
+        }
+
+        ConnectionNotActivated(ssid: String) {
+            description("Connection not activated")
+            display("Connection to access point '{}' not activated", ssid)
+        }
+
+        DbusApi {
+            description("Exporting the D-Bus API failed")
+        }
+
+        SendDisconnected {
+            description("Sending disconnect result failed")
+        }
+
+        MosquittoSub {
+            description("Spawning mosquitto_sub failed")
+        }
+
+        SendCleared {
+            description("Sending cleared profiles count failed")
+        }
+
+        Iw {
+            description("Spawning iw failed")
+        }
+
+        IwStatus(stderr: String) {
+            description("iw failed")
+            display("iw failed: {}", stderr)
+        }
+
+        BuiltinDhcp {
+            description("Starting the built-in DHCP server failed")
+        }
+
+        BuiltinDns {
+            description("Starting the built-in DNS server failed")
+        }
+
+        InvalidDhcpConfig(reason: String) {
+            description("Invalid DHCP configuration")
+            display("Invalid DHCP configuration: {}", reason)
+        }
+
+        SendNetworkCommandForget {
+            description("Sending NetworkCommand::Forget failed")
+        }
+
+        RecvForgotten {
+            description("Receiving forgotten network failed")
+        }
+
+        SendForgotten {
+            description("Sending forgotten network failed")
+        }
+
+        SendNetworkCommandListSaved {
+            description("Sending NetworkCommand::ListSaved failed")
+        }
+
+        RecvSaved {
+            description("Receiving saved networks failed")
+        }
+
+        SendSaved {
+            description("Sending saved networks failed")
+        }
+
+        SendNetworkCommandSignal {
+            description("Sending NetworkCommand::Signal failed")
+        }
+
+        RecvSignal {
+            description("Receiving signal failed")
+        }
+
+        SendSignal {
+            description("Sending signal failed")
+        }
+
+        MaxRuntime(seconds: u64) {
+            description("Max runtime reached")
+            display("Max runtime of {} seconds reached", seconds)
+        }
+
+        SendNetworkCommandInterfaces {
+            description("Sending NetworkCommand::Interfaces failed")
+        }
+
+        RecvInterfaces {
+            description("Receiving interfaces failed")
+        }
+
+        SendInterfaces {
+            description("Sending interfaces failed")
+        }
+
+        SendNetworkCommandHotspot {
+            description("Sending NetworkCommand::Hotspot failed")
+        }
+
+        RecvHotspot {
+            description("Receiving hotspot failed")
+        }
+
+        SendHotspot {
+            description("Sending hotspot failed")
+        }
+
+        Configuration(reason: String) {
+            description("Invalid configuration")
+            display("Invalid configuration: {}", reason)
+        }
+
+        InvalidConfig(errors: usize) {
+            description("Invalid configuration")
+            display("Invalid configuration: {} problems found", errors)
+        }
+
+        SystemBusUnavailable(seconds: u64) {
+            description("System D-Bus not available")
+            display("System D-Bus not available after waiting {} seconds", seconds)
+        }
+
+        AlreadyRunning(path: String, owner: String) {
+            description("Another instance is running")
+            display("Another instance is running: {} holds the lock file {}", owner, path)
+        }
+
+        ProcessLock(path: String) {
+            description("Acquiring the process lock failed")
+            display("Acquiring the process lock {} failed", path)
+        }
+
+        ImportNetworks(path: String) {
+            description("Importing networks failed")
+            display("Importing networks from {} failed", path)
+        }
+
+        InvalidNetworksFile(reason: String) {
+            description("Invalid networks file")
+            display("Invalid networks file: {}", reason)
+        }
+
+        SendNetworkCommandConnectAny {
+            description("Sending NetworkCommand::ConnectAny failed")
+        }
+
+        RecvConnectedAny {
+            description("Receiving connect any result failed")
+        }
+
+        SendConnectedAny {
+            description("Sending connect any result failed")
+        }
+
+        Checkpoint(method: String) {
+            description("NetworkManager checkpoint failed")
+            display("NetworkManager checkpoint call {} failed", method)
+        }
+
+        StopNetworkManager {
+            description("Stopping the NetworkManager service failed")
+        }
+
+        SendNetworkCommandRestartNetworkManager {
+            description("Sending NetworkCommand::RestartNetworkManager failed")
+        }
+
+        RecvNetworkManagerRestarted {
+            description("Receiving NetworkManager restart result failed")
+        }
+
+        SendNetworkManagerRestarted {
+            description("Sending NetworkManager restart result failed")
+        }
+
+        SendNetworkCommandScan {
+            description("Sending NetworkCommand::Scan failed")
+        }
+
+        RecvScanResults {
+            description("Receiving scan results failed")
+        }
+
+        SendScanResults {
+            description("Sending scan results failed")
+        }
+
+        CheckConnectivity {
+            description("Checking NetworkManager connectivity failed")
+        }
+
+        VirtualApUnsupported(interface: String) {
+            description("Access point and client concurrency not supported")
+            display("The radio of {} cannot run an access point next to a client", interface)
+        }
+
+        SendNetworkCommandVerify {
+            description("Sending NetworkCommand::Verify failed")
+        }
+
+        RecvVerified {
+            description("Receiving verification result failed")
+        }
+
+        SendVerified {
+            description("Sending verification result failed")
+        }
+
+        SendNetworkCommandRestartPortal {
+            description("Sending NetworkCommand::RestartPortal failed")
+        }
+
+        RecvPortalRestarted {
+            description("Receiving portal restart result failed")
+        }
+
+        SendPortalRestarted {
+            description("Sending portal restart result failed")
+        }
+
+        SendNetworkCommandReloadConfig {
+            description("Sending NetworkCommand::ReloadConfig failed")
+        }
+
+        RecvConfigReloaded {
+            description("Receiving config reload result failed")
+        }
+
+        SendConfigReloaded {
+            description("Sending config reload result failed")
+        }
+
+        P2p(operation: String) {
+            description("Wi-Fi Direct failed")
+            display("Wi-Fi Direct {} failed", operation)
+        }
+
+        NfcReader {
+            description("Spawning the NFC reader failed")
+        }
+
+        AudioCapture {
+            description("Spawning arecord failed")
+        }
+
+        AudioUnsupported {
+            description("Built without the audio feature")
+        }
+
+        ConnectionUpdate(method: String) {
+            description("Updating a connection profile failed")
+            display("NetworkManager call {} updating a connection profile failed", method)
+        }
+
+        NmcliOutput(command: String) {
+            description("Unexpected nmcli output")
+            display("Unexpected output of 'nmcli {}'", command)
+        }
     }
 }
 
@@ -122,6 +537,117 @@ pub fn exit_code(e: &Error) -> i32 {
         ErrorKind::NetworkManagerServiceState => 20,
         ErrorKind::BlockExitSignals => 21,
         ErrorKind::TrapExitSignals => 22,
+        ErrorKind::SendNetworkCommandStatus => 23,
+        ErrorKind::RecvStatus => 24,
+        ErrorKind::SendStatus => 25,
+        ErrorKind::UnexpectedNetworkCommandResponse => 26,
+        ErrorKind::SaveState(_) => 27,
+        ErrorKind::Avahi(_) => 28,
+        ErrorKind::Nmcli(_) => 29,
+        ErrorKind::NmcliStatus(_, _) => 30,
+        ErrorKind::SendNetworkCommandBlacklistBssid => 31,
+        ErrorKind::RecvBssidBlacklist => 32,
+        ErrorKind::SendBssidBlacklist => 33,
+        ErrorKind::BssidsBlacklisted(_) => 34,
+        ErrorKind::NoActiveConnection(_) => 35,
+        ErrorKind::TlsIdentity => 36,
+        ErrorKind::Openssl => 37,
+        ErrorKind::OpensslStatus(_) => 38,
+        ErrorKind::SendNetworkCommandConnection => 39,
+        ErrorKind::RecvConnection => 40,
+        ErrorKind::SendConnection => 41,
+        ErrorKind::NotAnEthernetDevice(_) => 42,
+        ErrorKind::NoEthernetDevice => 43,
+        ErrorKind::SendNetworkCommandEthernet => 44,
+        ErrorKind::RecvEthernet => 45,
+        ErrorKind::SendEthernet => 46,
+        ErrorKind::InvalidBackup(_) => 47,
+        ErrorKind::SendNetworkCommandBackup => 48,
+        ErrorKind::RecvBackup => 49,
+        ErrorKind::SendBackup => 50,
+        ErrorKind::SerializeResponse(_) => 51,
+        ErrorKind::SendNetworkCommandAdvanced => 52,
+        ErrorKind::RecvAdvanced => 53,
+        ErrorKind::SendAdvanced => 54,
+        ErrorKind::InvalidSsid(_) => 55,
+        ErrorKind::AccessPointNotFound(_) => 56,
+        ErrorKind::ConnectionNotActivated(_) => 57,
+        ErrorKind::DbusApi => 58,
+        ErrorKind::SendDisconnected => 59,
+        ErrorKind::MosquittoSub => 60,
+        ErrorKind::SendCleared => 61,
+        ErrorKind::Iw => 63,
+        ErrorKind::IwStatus(_) => 64,
+        ErrorKind::BuiltinDhcp => 65,
+        ErrorKind::BuiltinDns => 66,
+        ErrorKind::InvalidDhcpConfig(_) => 67,
+        ErrorKind::SendNetworkCommandForget => 68,
+        ErrorKind::RecvForgotten => 69,
+        ErrorKind::SendForgotten => 70,
+        ErrorKind::SendNetworkCommandListSaved => 71,
+        ErrorKind::RecvSaved => 72,
+        ErrorKind::SendSaved => 73,
+        ErrorKind::SendNetworkCommandSignal => 74,
+        ErrorKind::RecvSignal => 75,
+        ErrorKind::SendSignal => 76,
+        ErrorKind::MaxRuntime(_) => 77,
+        ErrorKind::SendNetworkCommandInterfaces => 78,
+        ErrorKind::RecvInterfaces => 79,
+        ErrorKind::SendInterfaces => 80,
+        ErrorKind::SendNetworkCommandHotspot => 81,
+        ErrorKind::RecvHotspot => 82,
+        ErrorKind::SendHotspot => 83,
+        ErrorKind::InvalidConfig(_) => 84,
+        ErrorKind::SystemBusUnavailable(_) => 85,
+        ErrorKind::AlreadyRunning(_, _) => 86,
+        ErrorKind::ProcessLock(_) => 87,
+        ErrorKind::ImportNetworks(_) => 88,
+        ErrorKind::InvalidNetworksFile(_) => 89,
+        ErrorKind::SendNetworkCommandConnectAny => 90,
+        ErrorKind::RecvConnectedAny => 91,
+        ErrorKind::SendConnectedAny => 92,
+        ErrorKind::Checkpoint(_) => 93,
+        ErrorKind::StopNetworkManager => 94,
+        ErrorKind::SendNetworkCommandRestartNetworkManager => 95,
+        ErrorKind::RecvNetworkManagerRestarted => 96,
+        ErrorKind::SendNetworkManagerRestarted => 97,
+        ErrorKind::SendNetworkCommandScan => 98,
+        ErrorKind::RecvScanResults => 99,
+        ErrorKind::SendScanResults => 100,
+        ErrorKind::CheckConnectivity => 101,
+        ErrorKind::VirtualApUnsupported(_) => 102,
+        ErrorKind::SendNetworkCommandVerify => 103,
+        ErrorKind::RecvVerified => 104,
+        ErrorKind::SendVerified => 105,
+        ErrorKind::SendNetworkCommandRestartPortal => 106,
+        ErrorKind::RecvPortalRestarted => 107,
+        ErrorKind::SendPortalRestarted => 108,
+        ErrorKind::SendNetworkCommandReloadConfig => 109,
+        ErrorKind::RecvConfigReloaded => 110,
+        ErrorKind::SendConfigReloaded => 111,
+        ErrorKind::P2p(_) => 112,
+        ErrorKind::NfcReader => 113,
+        ErrorKind::AudioCapture => 114,
+        ErrorKind::AudioUnsupported => 115,
+        ErrorKind::ConnectionUpdate(_) => 116,
+        ErrorKind::NmcliOutput(_) => 117,
+        ErrorKind::TlsKeyPair => 118,
+        ErrorKind::Configuration(_) => 119,
         _ => 1,
     }
 }
+
+/// Stable code of the error for API clients, which unlike the descriptions
+/// never changes between releases
+pub fn api_error_code(e_kind: &ErrorKind) -> &'static str {
+    match *e_kind {
+        ErrorKind::AccessPointNotFound(_) => "AP_NOT_FOUND",
+        ErrorKind::ConnectionNotActivated(_) => "CONNECTION_NOT_ACTIVATED",
+        ErrorKind::InvalidSsid(_) => "INVALID_SSID",
+        ErrorKind::NetworkManager(_)
+        | ErrorKind::StartNetworkManager
+        | ErrorKind::StartActiveNetworkManager
+        | ErrorKind::NetworkManagerServiceState => "NM_UNAVAILABLE",
+        _ => "INTERNAL_ERROR",
+    }
+}
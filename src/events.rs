@@ -0,0 +1,298 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll, Stream};
+use futures::sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use serde_json::{Map, Value};
+use tokio::timer::{self, Delay, Interval};
+
+/// Interval of the full snapshots in the event streams (seconds), which let
+/// clients recover from missed deltas
+const SNAPSHOT_INTERVAL: u64 = 30;
+
+/// Every event stream and long poll keeps a connection to the HTTP server open
+const MAX_SUBSCRIBERS: usize = 4;
+
+/// Events kept for the long polls, which clients falling further behind
+/// recover from with a snapshot
+const RECENT_EVENTS: usize = 64;
+
+struct EventBusState {
+    seq: u64,
+    fields: Map<String, Value>,
+    subscribers: Vec<UnboundedSender<Value>>,
+    active: usize,
+    recent: VecDeque<Value>,
+}
+
+/// Keeps the current provisioning state as a set of fields and sends only
+/// the changed ones to the event streams, which keeps the streams light for
+/// microcontrollers listening over the access point link
+#[derive(Clone)]
+pub struct EventBus {
+    state: Arc<Mutex<EventBusState>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus {
+            state: Arc::new(Mutex::new(EventBusState {
+                seq: 0,
+                fields: Map::new(),
+                subscribers: Vec::new(),
+                active: 0,
+                recent: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Merges the fields into the current state. Subscribers receive a delta
+    /// event with the fields which actually changed, if any did.
+    pub fn publish(&self, fields: Value) {
+        let mut state = self.state.lock().unwrap();
+
+        let changed = match fields {
+            Value::Object(fields) => fields
+                .into_iter()
+                .filter(|&(ref key, ref value)| state.fields.get(key) != Some(value))
+                .collect::<Map<_, _>>(),
+            _ => return,
+        };
+
+        if changed.is_empty() {
+            return;
+        }
+
+        for (key, value) in &changed {
+            state.fields.insert(key.clone(), value.clone());
+        }
+
+        state.seq += 1;
+
+        let event = json!({
+            "type": "delta",
+            "seq": state.seq,
+            "state": state.fields.get("state"),
+            "changed": changed,
+        });
+
+        broadcast(&mut state, &event);
+    }
+
+    /// Sends a one-off event, which unlike the fields is not part of the
+    /// snapshots
+    pub fn emit(&self, kind: &str, data: Value) {
+        let mut state = self.state.lock().unwrap();
+
+        state.seq += 1;
+
+        let event = json!({
+            "type": kind,
+            "seq": state.seq,
+            "state": state.fields.get("state"),
+            "data": data,
+        });
+
+        broadcast(&mut state, &event);
+    }
+
+    /// Opens an event stream starting with a snapshot, unless too many are
+    /// open already
+    pub fn subscribe(&self) -> Option<EventStream> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.active >= MAX_SUBSCRIBERS {
+            return None;
+        }
+
+        let (event_tx, event_rx) = unbounded();
+
+        state.subscribers.push(event_tx);
+        state.active += 1;
+
+        let interval = Duration::from_secs(SNAPSHOT_INTERVAL);
+
+        Some(EventStream {
+            bus: self.clone(),
+            event_rx: event_rx,
+            snapshot: Some(get_snapshot(&state)),
+            snapshots: Interval::new(Instant::now() + interval, interval),
+        })
+    }
+
+    /// Returns the events after the sequence number, once there are some or
+    /// the timeout passed. Clients polling for the first time with 0, or
+    /// which missed events no longer kept, get a snapshot instead. None if
+    /// too many clients wait already.
+    pub fn poll(&self, since: u64, timeout: Duration) -> Option<EventPoll> {
+        let mut state = self.state.lock().unwrap();
+
+        let events = get_events_since(&state, since);
+
+        if !events.is_empty() {
+            return Some(EventPoll {
+                bus: self.clone(),
+                events: events,
+                waiting: None,
+            });
+        }
+
+        if state.active >= MAX_SUBSCRIBERS {
+            return None;
+        }
+
+        let (event_tx, event_rx) = unbounded();
+
+        state.subscribers.push(event_tx);
+        state.active += 1;
+
+        Some(EventPoll {
+            bus: self.clone(),
+            events: Vec::new(),
+            waiting: Some((event_rx, Delay::new(Instant::now() + timeout))),
+        })
+    }
+
+    /// The current value of a field
+    pub fn get(&self, key: &str) -> Option<Value> {
+        self.state.lock().unwrap().fields.get(key).cloned()
+    }
+
+    fn snapshot(&self) -> Value {
+        get_snapshot(&self.state.lock().unwrap())
+    }
+}
+
+/// Sends the event to every subscriber and forgets the ones gone
+fn broadcast(state: &mut EventBusState, event: &Value) {
+    state
+        .subscribers
+        .retain(|event_tx| event_tx.unbounded_send(event.clone()).is_ok());
+
+    state.recent.push_back(event.clone());
+
+    if state.recent.len() > RECENT_EVENTS {
+        state.recent.pop_front();
+    }
+}
+
+/// The recent events after the sequence number, or a snapshot if the
+/// client missed some or knows a sequence number of a previous run
+fn get_events_since(state: &EventBusState, since: u64) -> Vec<Value> {
+    if since == state.seq {
+        return Vec::new();
+    }
+
+    let oldest = state
+        .recent
+        .front()
+        .and_then(|event| event["seq"].as_u64())
+        .unwrap_or(0);
+
+    if since == 0 || since > state.seq || since + 1 < oldest {
+        return vec![get_snapshot(state)];
+    }
+
+    state
+        .recent
+        .iter()
+        .filter(|event| event["seq"].as_u64().map_or(false, |seq| seq > since))
+        .cloned()
+        .collect()
+}
+
+fn get_snapshot(state: &EventBusState) -> Value {
+    json!({
+        "type": "snapshot",
+        "seq": state.seq,
+        "state": state.fields.get("state"),
+        "fields": state.fields,
+    })
+}
+
+/// Body of a `text/event-stream` response, streamed until the client goes
+/// away
+pub struct EventStream {
+    bus: EventBus,
+    event_rx: UnboundedReceiver<Value>,
+    snapshot: Option<Value>,
+    snapshots: Interval,
+}
+
+impl Stream for EventStream {
+    type Item = String;
+    type Error = timer::Error;
+
+    fn poll(&mut self) -> Poll<Option<String>, timer::Error> {
+        if let Some(snapshot) = self.snapshot.take() {
+            return Ok(Async::Ready(Some(format_event(&snapshot))));
+        }
+
+        match self.event_rx.poll() {
+            Ok(Async::Ready(Some(event))) => return Ok(Async::Ready(Some(format_event(&event)))),
+            Ok(Async::Ready(None)) | Err(()) => return Ok(Async::Ready(None)),
+            Ok(Async::NotReady) => {},
+        }
+
+        match self.snapshots.poll()? {
+            Async::Ready(_) => Ok(Async::Ready(Some(format_event(&self.bus.snapshot())))),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        self.bus.state.lock().unwrap().active -= 1;
+    }
+}
+
+/// The events of a long poll, ready once there are some or the timeout
+/// passed, without events then
+pub struct EventPoll {
+    bus: EventBus,
+    events: Vec<Value>,
+    /// The subscription waiting for the next event, unless there were events
+    /// already
+    waiting: Option<(UnboundedReceiver<Value>, Delay)>,
+}
+
+impl Future for EventPoll {
+    type Item = Vec<Value>;
+    type Error = timer::Error;
+
+    fn poll(&mut self) -> Poll<Vec<Value>, timer::Error> {
+        if let Some((ref mut event_rx, ref mut timeout)) = self.waiting {
+            while let Ok(Async::Ready(Some(event))) = event_rx.poll() {
+                self.events.push(event);
+            }
+
+            if self.events.is_empty() {
+                if let Async::NotReady = timeout.poll()? {
+                    return Ok(Async::NotReady);
+                }
+            }
+        }
+
+        Ok(Async::Ready(mem::replace(&mut self.events, Vec::new())))
+    }
+}
+
+impl Drop for EventPoll {
+    fn drop(&mut self) {
+        if self.waiting.is_some() {
+            self.bus.state.lock().unwrap().active -= 1;
+        }
+    }
+}
+
+fn format_event(event: &Value) -> String {
+    format!(
+        "id: {}\nevent: {}\ndata: {}\n\n",
+        event["seq"],
+        event["type"].as_str().unwrap_or("message"),
+        event
+    )
+}
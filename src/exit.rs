@@ -1,13 +1,59 @@
+use std::fs::File;
+use std::io::Write;
 use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use nix::sys::signal::{SigSet, SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+use nix::sys::signal::{SigSet, Signal, SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+
+use serde_json::{self, Value};
 
 use errors::*;
+use config::Config;
+use logger::{log_event, RecentEvents};
+use network::get_network_status;
+
+/// Why the network thread stopped, sent to the main thread once
+pub enum ExitResult {
+    ConnectedSuccessfully,
+    /// Interrupted from the terminal with Ctrl+C
+    UserRequestedExit,
+    ActivityTimeout,
+    MaxRuntime,
+    Signal(Signal),
+    Error(Error),
+}
 
-pub type ExitResult = Result<()>;
+impl ExitResult {
+    /// The `reason` of the result printed on stdout
+    pub fn reason(&self) -> &'static str {
+        match *self {
+            ExitResult::ConnectedSuccessfully => "connected",
+            ExitResult::UserRequestedExit => "user_requested",
+            ExitResult::ActivityTimeout => "activity_timeout",
+            ExitResult::MaxRuntime => "max_runtime",
+            ExitResult::Signal(_) => "signal",
+            ExitResult::Error(_) => "error",
+        }
+    }
+
+    /// Every way of stopping short of an error is a success
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            ExitResult::Error(ref e) => exit_code(e),
+            _ => 0,
+        }
+    }
+
+    pub fn into_result(self) -> Result<()> {
+        match self {
+            ExitResult::Error(e) => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
 
 pub fn exit(exit_tx: &Sender<ExitResult>, error: Error) {
-    let _ = exit_tx.send(Err(error));
+    let _ = exit_tx.send(ExitResult::Error(error));
 }
 
 /// Block exit signals from the main thread with mask inherited by children
@@ -18,14 +64,14 @@ pub fn block_exit_signals() -> Result<()> {
 }
 
 /// Trap exit signals from a signal handling thread
-pub fn trap_exit_signals() -> Result<()> {
+pub fn trap_exit_signals() -> Result<Signal> {
     let mask = create_exit_sigmask();
 
     let sig = mask.wait().chain_err(|| ErrorKind::TrapExitSignals)?;
 
     info!("\nReceived {:?}", sig);
 
-    Ok(())
+    Ok(sig)
 }
 
 fn create_exit_sigmask() -> SigSet {
@@ -38,3 +84,98 @@ fn create_exit_sigmask() -> SigSet {
 
     mask
 }
+
+/// Writes a JSON summary of the error and the most recent log records, for
+/// supervisors to pick up without parsing stderr
+pub fn write_failure_report(path: &str, error: &Error, events: &RecentEvents) -> Result<()> {
+    // The kind name without its fields, e.g. `DeviceByInterface`
+    let kind = format!("{:?}", error.kind())
+        .split(|c: char| !c.is_alphanumeric())
+        .next()
+        .unwrap_or("")
+        .to_string();
+
+    let causes = error
+        .iter()
+        .skip(1)
+        .map(|cause| cause.to_string())
+        .collect::<Vec<_>>();
+
+    let events = match events.lock() {
+        Ok(events) => events.iter().cloned().collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let report = json!({
+        "error": kind,
+        "message": error.to_string(),
+        "causes": causes,
+        "exit_code": exit_code(error),
+        "timestamp": timestamp,
+        "events": events,
+    });
+
+    let mut file = File::create(path)?;
+    serde_json::to_writer_pretty(&mut file, &report)?;
+    file.write_all(b"\n")?;
+
+    Ok(())
+}
+
+/// Prints the outcome of the run as a single JSON object on stdout, apart
+/// from the logs on stderr, for wrapper scripts to consume, e.g.
+/// `{"result": "connected", "reason": "connected", "ssid": "Home", ...}`
+pub fn print_result(config: &Config, result: &ExitResult, duration: Duration) {
+    let report = match *result {
+        ExitResult::Error(ref e) => json!({
+            "result": "failed",
+            "reason": result.reason(),
+            "error": e.to_string(),
+            "exit_code": exit_code(e),
+            "duration_s": duration.as_secs(),
+        }),
+        _ => {
+            let status = get_network_status(config).unwrap_or(Value::Null);
+
+            let ssid = status["ssid"].as_str().map(String::from);
+
+            // The first address, without the prefix length
+            let ip = status["ipv4_addresses"][0]
+                .as_str()
+                .and_then(|address| address.split('/').next())
+                .map(String::from);
+
+            let signal = match *result {
+                ExitResult::Signal(signal) => Some(format!("{:?}", signal)),
+                _ => None,
+            };
+
+            json!({
+                "result": if ssid.is_some() { "connected" } else { "exited" },
+                "reason": result.reason(),
+                "signal": signal,
+                "ssid": ssid,
+                "ip": ip,
+                "exit_code": result.exit_code(),
+                "duration_s": duration.as_secs(),
+            })
+        },
+    };
+
+    log_event(
+        "exit",
+        json!({
+            "result": report["result"],
+            "reason": report["reason"],
+            "ssid": report["ssid"],
+            "duration": duration.as_secs(),
+        }),
+    );
+
+    println!("{}", report);
+}
@@ -0,0 +1,55 @@
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+
+/// HMAC-SHA256 of a message, as lowercase hex digits. Computed in process,
+/// as `openssl dgst -hmac` would show the key in the process list.
+pub fn hmac_sha256_hex(key: &[u8], message: &[u8]) -> Result<String, ErrorStack> {
+    let key = PKey::hmac(key)?;
+
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(message)?;
+
+    let signature = signer
+        .sign_to_vec()?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    Ok(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test cases 1, 2, 6 and 7 of RFC 4231
+    #[test]
+    fn hmac_sha256_rfc4231() {
+        assert_eq!(
+            hmac_sha256_hex(&[0x0b; 20], b"Hi There").unwrap(),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hmac_sha256_hex(b"Jefe", b"what do ya want for nothing?").unwrap(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hmac_sha256_hex(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            ).unwrap(),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        assert_eq!(
+            hmac_sha256_hex(
+                &[0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than \
+                  block-size data. The key needs to be hashed before being used by the \
+                  HMAC algorithm."
+            ).unwrap(),
+            "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use std::thread::{self, JoinHandle};
+use std::sync::mpsc::{channel, Sender};
+use std::fs::File;
+use std::io::Read;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::process::{Child, Command, Stdio};
+
+use serde_json::Value;
+
+use config::Config;
+use hmac::hmac_sha256_hex;
+use nmcli::get_ipv4_config;
+
+/// Interval of checking whether a hook command has exited (milliseconds)
+const HOOK_POLL_INTERVAL: u64 = 100;
+
+/// Random bytes of the nonce of signed webhooks
+const NONCE_BYTES: usize = 16;
+
+#[derive(Clone, Copy)]
+pub enum HookEvent {
+    Connect,
+    Disconnect,
+}
+
+impl HookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            HookEvent::Connect => "connect",
+            HookEvent::Disconnect => "disconnect",
+        }
+    }
+}
+
+struct HookJob {
+    event: HookEvent,
+    ssid: String,
+    interface: String,
+    onboarding: Option<Value>,
+}
+
+/// Commands and webhooks run when the device connects to or disconnects
+/// from a network, so that integrators can start downstream services
+/// without polling. They run one after the other on a thread of their own,
+/// so that a slow hook does not hold up the network thread. Shutting down
+/// waits for the pending ones, each up to the hook timeout, so that a hook
+/// completes even if WiFi Connect exits right after connecting.
+pub struct Hooks {
+    jobs: Option<Sender<HookJob>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Hooks {
+    pub fn new(config: &Config) -> Self {
+        let runner = HookRunner {
+            on_connect: config.on_connect.clone(),
+            on_disconnect: config.on_disconnect.clone(),
+            webhooks: config.webhooks.clone(),
+            webhook_secret: config.webhook_secret.clone(),
+            timeout: config.hook_timeout,
+        };
+
+        if runner.on_connect.is_none() && runner.on_disconnect.is_none()
+            && runner.webhooks.is_empty()
+        {
+            return Hooks {
+                jobs: None,
+                worker: None,
+            };
+        }
+
+        let (jobs_tx, jobs_rx) = channel::<HookJob>();
+
+        let worker = thread::spawn(move || {
+            for job in jobs_rx {
+                runner.run(job.event, &job.ssid, &job.interface, job.onboarding);
+            }
+        });
+
+        Hooks {
+            jobs: Some(jobs_tx),
+            worker: Some(worker),
+        }
+    }
+
+    pub fn run(&self, event: HookEvent, ssid: &str, interface: &str) {
+        self.run_with_onboarding(event, ssid, interface, None);
+    }
+
+    /// Also reports the milestones of the captive portal session which
+    /// configured the network, if any
+    pub fn run_with_onboarding(
+        &self,
+        event: HookEvent,
+        ssid: &str,
+        interface: &str,
+        onboarding: Option<Value>,
+    ) {
+        if let Some(ref jobs) = self.jobs {
+            let job = HookJob {
+                event: event,
+                ssid: ssid.to_string(),
+                interface: interface.to_string(),
+                onboarding: onboarding,
+            };
+
+            if jobs.send(job).is_err() {
+                warn!("Running the {} hooks failed: no hook thread", event.as_str());
+            }
+        }
+    }
+
+    /// Waits for the pending hooks. No more hooks run afterwards.
+    pub fn finish(&mut self) {
+        self.jobs.take();
+
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                warn!("The hook thread panicked");
+            }
+        }
+    }
+}
+
+struct HookRunner {
+    on_connect: Option<String>,
+    on_disconnect: Option<String>,
+    webhooks: Vec<String>,
+    webhook_secret: Option<String>,
+    timeout: u64,
+}
+
+impl HookRunner {
+    fn run(&self, event: HookEvent, ssid: &str, interface: &str, onboarding: Option<Value>) {
+        let command = match event {
+            HookEvent::Connect => &self.on_connect,
+            HookEvent::Disconnect => &self.on_disconnect,
+        };
+
+        if command.is_none() && self.webhooks.is_empty() {
+            return;
+        }
+
+        // There is no address left to report after disconnecting
+        let (ip, gateway) = match (event, get_ipv4_config(interface)) {
+            (HookEvent::Connect, Ok(ipv4_config)) => (
+                ipv4_config
+                    .addresses
+                    .first()
+                    .map(|&(address, _)| address.to_string()),
+                ipv4_config.gateway.map(|gateway| gateway.to_string()),
+            ),
+            _ => (None, None),
+        };
+
+        let time_to_configure = onboarding
+            .as_ref()
+            .and_then(|onboarding| onboarding["durations"]["time_to_configure_s"].as_u64())
+            .map_or_else(String::new, |seconds| seconds.to_string());
+
+        if let Some(ref command) = *command {
+            let mut child = Command::new("sh");
+
+            child
+                .args(&["-c", command])
+                .env("WIFI_CONNECT_EVENT", event.as_str())
+                .env("WIFI_CONNECT_SSID", ssid)
+                .env("WIFI_CONNECT_INTERFACE", interface)
+                .env("WIFI_CONNECT_IP", ip.clone().unwrap_or_default())
+                .env("WIFI_CONNECT_GATEWAY", gateway.clone().unwrap_or_default())
+                .env("WIFI_CONNECT_TIME_TO_CONFIGURE", time_to_configure.clone())
+                .stdin(Stdio::null())
+                .stdout(Stdio::null());
+
+            info!("Running {} hook: {}", event.as_str(), command);
+
+            match child.spawn() {
+                Ok(child) => self.wait(child, &format!("{} hook", event.as_str())),
+                Err(e) => warn!("Spawning {} hook failed: {}", event.as_str(), e),
+            }
+        }
+
+        if self.webhooks.is_empty() {
+            return;
+        }
+
+        let mut payload = json!({
+            "event": event.as_str(),
+            "ssid": ssid,
+            "interface": interface,
+            "ip": ip,
+            "gateway": gateway,
+        });
+
+        if let Some(onboarding) = onboarding {
+            payload["onboarding"] = onboarding;
+        }
+
+        for url in &self.webhooks {
+            self.post(url, &payload);
+        }
+    }
+
+    fn post(&self, url: &str, payload: &Value) {
+        let body = payload.to_string();
+
+        let mut command = Command::new("curl");
+
+        command
+            .args(&["--silent", "--show-error", "--fail"])
+            .args(&["--max-time", &self.timeout.to_string()])
+            .args(&["--header", "Content-Type: application/json"]);
+
+        if let Some(ref secret) = self.webhook_secret {
+            match get_signature_headers(secret, &body) {
+                Ok(headers) => for header in headers {
+                    command.args(&["--header", &header]);
+                },
+                Err(e) => {
+                    warn!("Signing webhook {} failed: {}", url, e);
+                    return;
+                },
+            }
+        }
+
+        let child = command
+            .args(&["--data", &body, url])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn();
+
+        match child {
+            Ok(child) => self.wait(child, &format!("webhook {}", url)),
+            Err(e) => warn!("Spawning curl for webhook {} failed: {}", url, e),
+        }
+    }
+
+    fn wait(&self, mut child: Child, name: &str) {
+        let started = Instant::now();
+        let timeout = Duration::from_secs(self.timeout);
+
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    if !status.success() {
+                        warn!("The {} failed: {}", name, status);
+                    }
+                    return;
+                },
+                Ok(None) => if started.elapsed() >= timeout {
+                    warn!("The {} timed out after {}s", name, self.timeout);
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return;
+                },
+                Err(e) => {
+                    warn!("Waiting for the {} failed: {}", name, e);
+                    return;
+                },
+            }
+
+            thread::sleep(Duration::from_millis(HOOK_POLL_INTERVAL));
+        }
+    }
+}
+
+/// Headers which let the receiver verify that a webhook comes from the
+/// device and is not replayed: the signature covers the time and a nonce
+/// besides the body
+fn get_signature_headers(secret: &str, body: &str) -> ::std::io::Result<Vec<String>> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+
+    let mut bytes = [0; NONCE_BYTES];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    let nonce = bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+
+    let message = format!("{}.{}.{}", timestamp, nonce, body);
+    let signature = hmac_sha256_hex(secret.as_bytes(), message.as_bytes())?;
+
+    Ok(vec![
+        format!("X-WiFi-Connect-Timestamp: {}", timestamp),
+        format!("X-WiFi-Connect-Nonce: {}", nonce),
+        format!("X-WiFi-Connect-Signature: sha256={}", signature),
+    ])
+}
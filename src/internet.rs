@@ -0,0 +1,201 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+/// A target of the Internet check
+#[derive(Clone, Debug, PartialEq)]
+pub enum Probe {
+    /// `icmp:<host>` - a single ping
+    Icmp(String),
+    /// `tcp:<host>:<port>` - a TCP connection
+    Tcp(String),
+    /// `http://<host>[:<port>]/<path>` - a GET answered with `204 No Content`
+    Http { host: String, path: String },
+}
+
+impl FromStr for Probe {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        if s.starts_with("icmp:") {
+            let host = &s["icmp:".len()..];
+
+            if host.is_empty() {
+                return Err(format!("missing host: {}", s));
+            }
+
+            Ok(Probe::Icmp(host.to_string()))
+        } else if s.starts_with("tcp:") {
+            let address = &s["tcp:".len()..];
+
+            match address.rfind(':') {
+                Some(index) if u16::from_str(&address[index + 1..]).is_ok() => {
+                    Ok(Probe::Tcp(address.to_string()))
+                },
+                _ => Err(format!("expected tcp:<host>:<port>: {}", s)),
+            }
+        } else if s.starts_with("http://") {
+            let rest = &s["http://".len()..];
+
+            let (host, path) = match rest.find('/') {
+                Some(index) => (&rest[..index], &rest[index..]),
+                None => (rest, "/"),
+            };
+
+            if host.is_empty() {
+                return Err(format!("missing host: {}", s));
+            }
+
+            Ok(Probe::Http {
+                host: host.to_string(),
+                path: path.to_string(),
+            })
+        } else {
+            Err(format!("expected icmp:, tcp: or http:// probe: {}", s))
+        }
+    }
+}
+
+impl fmt::Display for Probe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Probe::Icmp(ref host) => write!(f, "icmp:{}", host),
+            Probe::Tcp(ref address) => write!(f, "tcp:{}", address),
+            Probe::Http { ref host, ref path } => write!(f, "http://{}{}", host, path),
+        }
+    }
+}
+
+/// Outcome of a single probe
+pub struct ProbeResult {
+    pub probe: String,
+    pub success: bool,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+}
+
+/// Runs every probe one after another and reports which succeeded
+pub fn check_internet(probes: &[Probe], timeout: u64) -> Vec<ProbeResult> {
+    probes
+        .iter()
+        .map(|probe| {
+            let start = Instant::now();
+
+            let result = match *probe {
+                Probe::Icmp(ref host) => ping(host, timeout),
+                Probe::Tcp(ref address) => connect(address, timeout).map(|_| ()),
+                Probe::Http { ref host, ref path } => get_no_content(host, path, timeout),
+            };
+
+            ProbeResult {
+                probe: probe.to_string(),
+                success: result.is_ok(),
+                elapsed: start.elapsed(),
+                error: result.err(),
+            }
+        })
+        .collect()
+}
+
+/// Describes the probe results as JSON, e.g. for the `/internet` endpoint
+pub fn results_to_json(results: &[ProbeResult]) -> Value {
+    let probes = results
+        .iter()
+        .map(|result| {
+            let elapsed = result.elapsed.as_secs() * 1000
+                + u64::from(result.elapsed.subsec_nanos()) / 1_000_000;
+
+            json!({
+                "probe": result.probe,
+                "success": result.success,
+                "elapsed_ms": elapsed,
+                "error": result.error,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "connected": results.iter().any(|result| result.success),
+        "probes": probes,
+    })
+}
+
+fn ping(host: &str, timeout: u64) -> Result<(), String> {
+    let output = Command::new("ping")
+        .args(&["-c", "1", "-W", &timeout.to_string(), host])
+        .output()
+        .map_err(|e| format!("spawning ping failed: {}", e))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(format!("no reply: {}", output.status))
+    }
+}
+
+fn connect(address: &str, timeout: u64) -> Result<TcpStream, String> {
+    let timeout = Duration::from_secs(timeout);
+
+    let addresses = address
+        .to_socket_addrs()
+        .map_err(|e| format!("resolving {} failed: {}", address, e))?
+        .collect::<Vec<SocketAddr>>();
+
+    let mut last_error = format!("{} did not resolve", address);
+
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => {
+                let _ = stream.set_read_timeout(Some(timeout));
+                let _ = stream.set_write_timeout(Some(timeout));
+                return Ok(stream);
+            },
+            Err(e) => last_error = format!("connecting to {} failed: {}", address, e),
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Fetches the URL and expects `204 No Content`, like the connectivity
+/// checks of Android and Chrome OS. Any other answer, e.g. the redirect of a
+/// captive portal, means there is no Internet access.
+fn get_no_content(host: &str, path: &str, timeout: u64) -> Result<(), String> {
+    let address = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let mut stream = connect(&address, timeout)?;
+
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|e| format!("sending request failed: {}", e))?;
+
+    let mut status_line = [0; 12];
+
+    stream
+        .read_exact(&mut status_line)
+        .map_err(|e| format!("reading response failed: {}", e))?;
+
+    let status_line = String::from_utf8_lossy(&status_line);
+
+    match status_line.split_whitespace().nth(1) {
+        Some("204") => Ok(()),
+        Some(status) => Err(format!("unexpected status {}", status)),
+        None => Err("invalid response".to_string()),
+    }
+}
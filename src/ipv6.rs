@@ -0,0 +1,92 @@
+use std::fs::File;
+use std::io::Read;
+use std::net::{Ipv6Addr, SocketAddr, TcpStream};
+use std::str::FromStr;
+use std::time::Duration;
+
+use errors::*;
+use nmcli::get_ipv6_config;
+
+const IF_INET6: &str = "/proc/net/if_inet6";
+
+const IPV6_ADDR_LINKLOCAL: &str = "20";
+
+/// Returns the link-local IPv6 address of an interface together with the
+/// interface index, which is needed as a scope id for binding to it
+pub fn get_link_local_address(interface: &str) -> Result<Option<(Ipv6Addr, u32)>> {
+    let mut contents = String::new();
+    File::open(IF_INET6)?.read_to_string(&mut contents)?;
+
+    for line in contents.lines() {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+
+        if fields.len() != 6 || fields[5] != interface || fields[3] != IPV6_ADDR_LINKLOCAL {
+            continue;
+        }
+
+        if let (Some(address), Ok(index)) = (
+            parse_hex_address(fields[0]),
+            u32::from_str_radix(fields[1], 16),
+        ) {
+            return Ok(Some((address, index)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn parse_hex_address(hex: &str) -> Option<Ipv6Addr> {
+    if hex.len() != 32 {
+        return None;
+    }
+
+    let mut segments = [0u16; 8];
+
+    for (i, segment) in segments.iter_mut().enumerate() {
+        *segment = match u16::from_str_radix(&hex[i * 4..i * 4 + 4], 16) {
+            Ok(segment) => segment,
+            Err(_) => return None,
+        };
+    }
+
+    Some(Ipv6Addr::new(
+        segments[0],
+        segments[1],
+        segments[2],
+        segments[3],
+        segments[4],
+        segments[5],
+        segments[6],
+        segments[7],
+    ))
+}
+
+/// Well-known IPv6 host probed when NetworkManager reports no connectivity,
+/// as its check may run over IPv4 only (Google Public DNS)
+const CONNECTIVITY_PROBE: &str = "[2001:4860:4860::8888]:53";
+
+const CONNECTIVITY_PROBE_TIMEOUT: u64 = 5;
+
+/// Checks for Internet access over IPv6, for networks without IPv4
+/// connectivity
+pub fn has_ipv6_connectivity(interface: &str) -> bool {
+    match get_ipv6_config(interface) {
+        Ok(ref config) if config.is_routable() => {},
+        Ok(_) => return false,
+        Err(e) => {
+            warn!("Getting IPv6 configuration failed: {}", e);
+            return false;
+        },
+    }
+
+    let address = SocketAddr::from_str(CONNECTIVITY_PROBE).unwrap();
+    let timeout = Duration::from_secs(CONNECTIVITY_PROBE_TIMEOUT);
+
+    match TcpStream::connect_timeout(&address, timeout) {
+        Ok(_) => true,
+        Err(e) => {
+            debug!("IPv6 connectivity probe failed: {}", e);
+            false
+        },
+    }
+}
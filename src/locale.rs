@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Language of the milestone messages, which wrapper UIs show verbatim to
+/// end users
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    En,
+    De,
+    Es,
+    Fr,
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    /// Accepts POSIX locale names as well, e.g. `de_DE.UTF-8`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let language = s.split(|c| c == '_' || c == '-' || c == '.')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+
+        match language.as_str() {
+            "en" | "c" | "posix" => Ok(Locale::En),
+            "de" => Ok(Locale::De),
+            "es" => Ok(Locale::Es),
+            "fr" => Ok(Locale::Fr),
+            _ => Err(format!("Unsupported locale: {} (expected en, de, es or fr)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Locale::En => write!(f, "en"),
+            Locale::De => write!(f, "de"),
+            Locale::Es => write!(f, "es"),
+            Locale::Fr => write!(f, "fr"),
+        }
+    }
+}
+
+/// User-facing milestones of the provisioning
+pub enum Milestone<'a> {
+    PortalOpened(&'a str),
+    Connecting(&'a str),
+    Connected(&'a str),
+    ConnectFailed(&'a str),
+}
+
+impl<'a> Milestone<'a> {
+    pub fn localize(&self, locale: Locale) -> String {
+        match (locale, self) {
+            (Locale::En, &Milestone::PortalOpened(ssid)) => {
+                format!("Captive portal '{}' is open", ssid)
+            },
+            (Locale::En, &Milestone::Connecting(ssid)) => format!("Connecting to '{}'...", ssid),
+            (Locale::En, &Milestone::Connected(ssid)) => format!("Connected to '{}'", ssid),
+            (Locale::En, &Milestone::ConnectFailed(ssid)) => {
+                format!("Connecting to '{}' failed", ssid)
+            },
+
+            (Locale::De, &Milestone::PortalOpened(ssid)) => {
+                format!("Captive Portal '{}' ist geöffnet", ssid)
+            },
+            (Locale::De, &Milestone::Connecting(ssid)) => {
+                format!("Verbindung mit '{}' wird hergestellt...", ssid)
+            },
+            (Locale::De, &Milestone::Connected(ssid)) => format!("Verbunden mit '{}'", ssid),
+            (Locale::De, &Milestone::ConnectFailed(ssid)) => {
+                format!("Verbindung mit '{}' fehlgeschlagen", ssid)
+            },
+
+            (Locale::Es, &Milestone::PortalOpened(ssid)) => {
+                format!("Portal cautivo '{}' abierto", ssid)
+            },
+            (Locale::Es, &Milestone::Connecting(ssid)) => format!("Conectando a '{}'...", ssid),
+            (Locale::Es, &Milestone::Connected(ssid)) => format!("Conectado a '{}'", ssid),
+            (Locale::Es, &Milestone::ConnectFailed(ssid)) => {
+                format!("No se pudo conectar a '{}'", ssid)
+            },
+
+            (Locale::Fr, &Milestone::PortalOpened(ssid)) => {
+                format!("Portail captif '{}' ouvert", ssid)
+            },
+            (Locale::Fr, &Milestone::Connecting(ssid)) => format!("Connexion à '{}'...", ssid),
+            (Locale::Fr, &Milestone::Connected(ssid)) => format!("Connecté à '{}'", ssid),
+            (Locale::Fr, &Milestone::ConnectFailed(ssid)) => {
+                format!("Échec de la connexion à '{}'", ssid)
+            },
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use nix;
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::unistd::getpid;
+
+use errors::*;
+
+/// Exclusive lock held for the lifetime of the process, so that a second
+/// instance, e.g. a manual debug run next to the systemd service, does not
+/// fight the running one over the access point and dnsmasq. The kernel
+/// releases the lock when the process exits, even on a crash, so a stale
+/// lock file never blocks a restart.
+pub struct ProcessLock {
+    _file: File,
+}
+
+impl ProcessLock {
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let display_path = path.display().to_string();
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .chain_err(|| ErrorKind::ProcessLock(display_path.clone()))?;
+
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => {},
+            Err(nix::Error::Sys(Errno::EAGAIN)) => {
+                bail!(ErrorKind::AlreadyRunning(
+                    display_path,
+                    get_lock_owner(&mut file)
+                ))
+            },
+            Err(e) => return Err(e).chain_err(|| ErrorKind::ProcessLock(display_path)),
+        }
+
+        // The PID is informational only, the lock itself is the flock
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| writeln!(file, "{}", getpid()))
+            .chain_err(|| ErrorKind::ProcessLock(display_path.clone()))?;
+
+        debug!("Acquired process lock {}", display_path);
+
+        Ok(ProcessLock { _file: file })
+    }
+}
+
+fn get_lock_owner(file: &mut File) -> String {
+    let mut contents = String::new();
+
+    match file.read_to_string(&mut contents) {
+        Ok(_) if !contents.trim().is_empty() => format!("PID {}", contents.trim()),
+        _ => "unknown PID".to_string(),
+    }
+}
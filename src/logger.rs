@@ -1,30 +1,275 @@
 use std::env;
-use log::{LogLevel, LogLevelFilter, LogRecord};
-use env_logger::LogBuilder;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt;
+use log::{self, Log, LogLevel, LogLevelFilter, LogMetadata, LogRecord};
+use env_logger::{LogBuilder, Logger};
+use serde_json::Value;
 
-pub fn init() {
-    let mut builder = LogBuilder::new();
+use config::Config;
 
-    if env::var("RUST_LOG").is_ok() {
-        builder.parse(&env::var("RUST_LOG").unwrap());
-    } else {
-        let format = |record: &LogRecord| {
-            if record.level() == LogLevel::Info {
-                format!("{}", record.args())
-            } else {
-                format!(
-                    "[{}:{}] {}",
-                    record.location().module_path(),
-                    record.level(),
-                    record.args()
-                )
+/// Number of log records kept for the failure report
+const RECENT_EVENTS: usize = 50;
+
+/// Target of the records of provisioning events, whose message is a JSON
+/// object of their fields
+const EVENT_TARGET: &str = "wifi_connect::event";
+
+/// Crate the log level applies to, while the other crates log at the info
+/// level
+const LEVEL_TARGET: &str = "wifi_connect";
+
+/// The most recent log records, oldest first
+pub type RecentEvents = Arc<Mutex<VecDeque<String>>>;
+
+/// Log level of WiFi Connect, changed by a reload of the configuration. The
+/// logger is global anyway.
+static LOG_LEVEL: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Unsupported log format: {} (expected text or json)", s)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Log file which is moved to `<path>.1` once it exceeds its maximum size,
+/// replacing the previous one
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+}
+
+impl LogFile {
+    fn open(path: &PathBuf, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(LogFile {
+            path: path.clone(),
+            file: file,
+            size: size,
+            max_size: max_size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.max_size != 0 && self.size + line.len() as u64 + 1 > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(".1");
+
+        fs::rename(&self.path, &rotated)?;
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+struct RecordingLogger {
+    logger: Logger,
+    /// Whether the log level applies, i.e. it is not overridden by `RUST_LOG`
+    leveled: bool,
+    format: LogFormat,
+    file: Option<Mutex<LogFile>>,
+    events: RecentEvents,
+}
+
+impl Log for RecordingLogger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        self.logger.enabled(metadata) && (!self.leveled || is_level_enabled(metadata))
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let is_event = record.target() == EVENT_TARGET;
+
+        if !is_event {
+            if let Ok(mut events) = self.events.lock() {
+                if events.len() == RECENT_EVENTS {
+                    events.pop_front();
+                }
+
+                events.push_back(format!("[{}] {}", record.level(), record.args()));
             }
+        }
+
+        // The text format has the milestone messages, which the structured
+        // events would only repeat
+        let line = match self.format {
+            LogFormat::Json => format_json(record, is_event),
+            LogFormat::Text if is_event => return,
+            LogFormat::Text => format_text(record),
         };
 
-        builder.format(format).filter(None, LogLevelFilter::Info);
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                if let Err(e) = file.write_line(&line) {
+                    let _ = writeln!(io::stderr(), "Writing log file failed: {}", e);
+                }
+            }
+        }
+
+        match self.format {
+            LogFormat::Json => {
+                let _ = writeln!(io::stderr(), "{}", line);
+            },
+            LogFormat::Text => self.logger.log(record),
+        }
+    }
+}
+
+fn is_level_enabled(metadata: &LogMetadata) -> bool {
+    !metadata.target().starts_with(LEVEL_TARGET)
+        || metadata.level() as usize <= LOG_LEVEL.load(Ordering::SeqCst)
+}
+
+/// Applies from the next record on, unless `RUST_LOG` is set
+pub fn set_level(level: LogLevelFilter) {
+    LOG_LEVEL.store(level as usize, Ordering::SeqCst);
+}
+
+fn format_text(record: &LogRecord) -> String {
+    if record.level() == LogLevel::Info {
+        format!("{}", record.args())
+    } else {
+        format!(
+            "[{}:{}] {}",
+            record.location().module_path(),
+            record.level(),
+            record.args()
+        )
+    }
+}
+
+/// One JSON object per line, with the fields of provisioning events at the
+/// top level
+fn format_json(record: &LogRecord, is_event: bool) -> String {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| {
+            duration.as_secs() as f64 + f64::from(duration.subsec_nanos() / 1_000_000) / 1000.0
+        })
+        .unwrap_or(0.0);
+
+    let mut line = json!({
+        "timestamp": timestamp,
+        "level": record.level().to_string(),
+        "module": record.location().module_path(),
+    });
+
+    let message = record.args().to_string();
 
-        builder.parse("wifi-connect=info,iron::iron=off");
+    match ::serde_json::from_str::<Value>(&message) {
+        Ok(Value::Object(fields)) if is_event => for (key, value) in fields {
+            line[key] = value;
+        },
+        _ => line["message"] = Value::String(message),
     }
 
-    builder.init().unwrap();
+    line.to_string()
+}
+
+/// Logs a provisioning event with structured fields, e.g. the result of a
+/// connection attempt, for the JSON log format
+pub fn log_event(event: &str, fields: Value) {
+    let mut fields = fields;
+    fields["event"] = Value::String(event.to_string());
+
+    info!(target: EVENT_TARGET, "{}", fields);
+}
+
+pub fn init(config: &Config) -> RecentEvents {
+    let mut builder = LogBuilder::new();
+
+    // `RUST_LOG` overrides the log level
+    let leveled = env::var("RUST_LOG").is_err();
+
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse(&filters);
+    } else {
+        builder.format(format_text).filter(None, LogLevelFilter::Info);
+
+        // Filtered by the log level instead, which may be raised at runtime
+        builder.filter(Some(LEVEL_TARGET), LogLevelFilter::Trace);
+    }
+
+    set_level(config.log_level);
+
+    let file = config.log_file.as_ref().and_then(|path| {
+        match LogFile::open(path, config.log_file_max_size * 1024) {
+            Ok(file) => Some(Mutex::new(file)),
+            Err(e) => {
+                let _ = writeln!(
+                    io::stderr(),
+                    "Opening log file {} failed: {}",
+                    path.display(),
+                    e
+                );
+                None
+            },
+        }
+    });
+
+    let events = Arc::new(Mutex::new(VecDeque::with_capacity(RECENT_EVENTS)));
+
+    let logger = RecordingLogger {
+        logger: builder.build(),
+        leveled: leveled,
+        format: config.log_format,
+        file: file,
+        events: events.clone(),
+    };
+
+    log::set_logger(|max_level| {
+        max_level.set(logger.logger.filter());
+        Box::new(logger)
+    }).unwrap();
+
+    events
 }
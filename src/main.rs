@@ -6,36 +6,97 @@ extern crate log;
 #[macro_use]
 extern crate error_chain;
 
+extern crate base64;
 extern crate clap;
+extern crate dbus;
 extern crate env_logger;
-extern crate iron;
-extern crate mount;
+extern crate futures;
+extern crate hyper;
+extern crate native_tls;
 extern crate network_manager;
 extern crate nix;
-extern crate params;
-extern crate persistent;
-extern crate router;
+extern crate openssl;
+extern crate rmp_serde;
+extern crate serde;
+extern crate serde_cbor;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
 extern crate serde_json;
-extern crate staticfile;
+extern crate tokio;
+extern crate tokio_threadpool;
+extern crate tokio_tls;
+extern crate toml;
+extern crate url;
 
 mod errors;
 mod config;
 mod network;
 mod server;
 mod dnsmasq;
+mod dhcp;
+mod dns;
 mod logger;
 mod exit;
+mod state;
+mod metrics;
+mod zeroconf;
+mod nmcli;
+mod pinning;
+mod tls;
+mod subnet;
+mod ipv6;
+mod redact;
+mod backup;
+mod serializer;
+mod cli;
+mod dbus_api;
+mod mqtt;
+mod power;
+mod internet;
+mod pairing;
+mod check;
+mod qr;
+mod locale;
+mod events;
+mod ui;
+mod activity;
+mod lock;
+mod hooks;
+mod systemd;
+mod beacon;
+mod ssdp;
+mod checkpoint;
+mod hmac;
+mod provisioning;
+mod onboarding;
+mod connectivity;
+mod virtual_ap;
+mod p2p;
+mod openapi;
+mod nfc;
+#[cfg(feature = "audio")]
+mod audio;
+mod secrets;
 
 use std::path;
 use std::thread;
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, RecvTimeoutError};
 use std::io::Write;
 use std::process;
+use std::time::{Duration, Instant};
 
 use errors::*;
-use config::get_config;
+use config::{get_config, CliCommand, Config};
+use check::check_config;
+use lock::ProcessLock;
+use provisioning::import_networks;
 use network::{init_networking, process_network_commands};
-use exit::block_exit_signals;
+use exit::{block_exit_signals, print_result, write_failure_report, ExitResult};
+
+/// Time the network thread gets past the max runtime before the process exits
+/// anyway (seconds)
+const MAX_RUNTIME_GRACE: u64 = 30;
 
 fn main() {
     if let Err(ref e) = run() {
@@ -55,26 +116,93 @@ fn main() {
 fn run() -> Result<()> {
     block_exit_signals()?;
 
-    logger::init();
+    let config = get_config().map_err(ErrorKind::Configuration)?;
+
+    let events = logger::init(&config);
+
+    let started = Instant::now();
+
+    let result = run_with_config(&config);
+
+    systemd::notify("STOPPING=1");
+
+    // Subcommands and `--print-config` give no exit result on success
+    let result = match result {
+        Ok(Some(result)) => result,
+        Ok(None) => return Ok(()),
+        Err(e) => ExitResult::Error(e),
+    };
+
+    if let ExitResult::Error(ref e) = result {
+        if let Some(ref path) = config.failure_report_file {
+            if let Err(report_error) = write_failure_report(path, e, &events) {
+                error!("Writing failure report to {} failed: {}", path, report_error);
+            }
+        }
+    }
+
+    // Subcommands and `--print-config` print their own single JSON object
+    if config.command.is_none() && !config.print_config {
+        print_result(&config, &result, started.elapsed());
+    }
+
+    result.into_result()
+}
+
+fn run_with_config(config: &Config) -> Result<Option<ExitResult>> {
+    if config.print_config {
+        println!("{}", serde_json::to_string_pretty(&config.to_json())?);
+        return Ok(None);
+    }
+
+    // The configuration is checked without touching NetworkManager
+    if let Some(CliCommand::CheckConfig) = config.command {
+        return check_config(config).map(|_| None);
+    }
+
+    info!("Starting: {}", config.summary());
+
+    // Only one instance may manage the captive portal, while subcommands may
+    // run next to it
+    let _lock = if config.command.is_none() {
+        Some(ProcessLock::acquire(&config.lock_file)?)
+    } else {
+        None
+    };
 
-    let config = get_config();
+    init_networking(config)?;
 
-    init_networking()?;
+    if let Some(ref command) = config.command {
+        return cli::run(config, command).map(|_| None);
+    }
+
+    if let Some(ref path) = config.import {
+        import_networks(path)?;
+    }
 
     let (exit_tx, exit_rx) = channel();
 
+    let max_runtime = config.max_runtime;
+
+    let config = config.clone();
+
     thread::spawn(move || {
         process_network_commands(&config, &exit_tx);
     });
 
-    match exit_rx.recv() {
-        Ok(result) => if let Err(reason) = result {
-            return Err(reason);
-        },
-        Err(e) => {
-            return Err(e.into());
-        },
+    if max_runtime != 0 {
+        // The network thread handles the max runtime itself, unless it is
+        // stuck, e.g. connecting
+        let deadline = Duration::from_secs(max_runtime + MAX_RUNTIME_GRACE);
+
+        return match exit_rx.recv_timeout(deadline) {
+            Ok(result) => Ok(Some(result)),
+            Err(RecvTimeoutError::Timeout) => bail!(ErrorKind::MaxRuntime(max_runtime)),
+            Err(RecvTimeoutError::Disconnected) => bail!("Network thread exited unexpectedly"),
+        };
     }
 
-    Ok(())
+    let result = exit_rx.recv()?;
+
+    Ok(Some(result))
 }
@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use state::StateStore;
+
+const METRICS_DOCUMENT: &str = "metrics";
+
+/// Counters persisted in the state store for long-term fleet analytics
+#[derive(Clone, Default)]
+pub struct Metrics {
+    pub connect_attempts: u64,
+    pub connect_failures: BTreeMap<String, u64>,
+    pub portal_sessions: u64,
+    pub onboardings: u64,
+    pub time_to_configure_total_s: u64,
+    pub last_onboarding: Value,
+}
+
+impl Metrics {
+    pub fn load(store: &StateStore) -> Self {
+        match store.load(METRICS_DOCUMENT) {
+            Some(value) => Metrics::from_json(&value),
+            None => Metrics::default(),
+        }
+    }
+
+    pub fn save(&self, store: &StateStore) {
+        if let Err(e) = store.save(METRICS_DOCUMENT, &self.to_json()) {
+            warn!("Saving metrics failed: {}", e);
+        }
+    }
+
+    pub fn connect_attempt(&mut self, store: &StateStore) {
+        self.connect_attempts += 1;
+        self.save(store);
+    }
+
+    pub fn connect_failure(&mut self, store: &StateStore, reason: &str) {
+        *self.connect_failures.entry(reason.to_string()).or_insert(0) += 1;
+        self.save(store);
+    }
+
+    pub fn portal_session(&mut self, store: &StateStore) {
+        self.portal_sessions += 1;
+        self.save(store);
+    }
+
+    /// Adds a session completed through the captive portal, whose mean
+    /// time to configure is the total over the count
+    pub fn onboarding(&mut self, store: &StateStore, onboarding: &Value) {
+        self.onboardings += 1;
+        self.time_to_configure_total_s += onboarding["durations"]["time_to_configure_s"]
+            .as_u64()
+            .unwrap_or(0);
+        self.last_onboarding = onboarding.clone();
+        self.save(store);
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "connect_attempts": self.connect_attempts,
+            "connect_failures": self.connect_failures,
+            "portal_sessions": self.portal_sessions,
+            "onboardings": self.onboardings,
+            "time_to_configure_total_s": self.time_to_configure_total_s,
+            "last_onboarding": self.last_onboarding,
+        })
+    }
+
+    fn from_json(value: &Value) -> Self {
+        let mut connect_failures = BTreeMap::new();
+
+        if let Some(failures) = value["connect_failures"].as_object() {
+            for (reason, count) in failures {
+                if let Some(count) = count.as_u64() {
+                    connect_failures.insert(reason.clone(), count);
+                }
+            }
+        }
+
+        Metrics {
+            connect_attempts: value["connect_attempts"].as_u64().unwrap_or(0),
+            connect_failures: connect_failures,
+            portal_sessions: value["portal_sessions"].as_u64().unwrap_or(0),
+            onboardings: value["onboardings"].as_u64().unwrap_or(0),
+            time_to_configure_total_s: value["time_to_configure_total_s"].as_u64().unwrap_or(0),
+            last_onboarding: value["last_onboarding"].clone(),
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use std::fs::{self, OpenOptions};
+use std::thread;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, Sender};
+
+use serde_json::{self, Value};
+
+use errors::*;
+use config::Config;
+use network::{request_network_command, NetworkCommand, NetworkCommandResponse};
+use server::{get_connect_command, ConnectRequest, Credentials};
+
+/// Commands accepted on the command topic, e.g.
+/// `{"command": "connect", "ssid": "Home", "passphrase": "secret"}`. Connect
+/// commands take the fields of `POST /connect`.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+enum MqttCommand {
+    Scan,
+    Connect(ConnectRequest),
+    Disconnect {
+        #[serde(default)]
+        checkpoint: Option<u64>,
+    },
+    Clear {
+        #[serde(default)]
+        checkpoint: Option<u64>,
+    },
+}
+
+/// API credentials of a command, required for all but scans when
+/// authentication is configured
+#[derive(Deserialize)]
+struct MqttAuth {
+    #[serde(default)]
+    auth_username: Option<String>,
+    #[serde(default)]
+    auth_password: Option<String>,
+    #[serde(default)]
+    auth_token: Option<String>,
+}
+
+/// How `mosquitto_pub` and `mosquitto_sub` reach the broker. The username
+/// and password go to the options files which the clients read from
+/// `$XDG_CONFIG_HOME`, as arguments would show them in the process list.
+#[derive(Clone)]
+struct MqttBroker {
+    host: String,
+    port: String,
+    ca_file: Option<PathBuf>,
+    options_directory: Option<PathBuf>,
+}
+
+impl MqttBroker {
+    fn new(config: &Config) -> Option<Self> {
+        let host = config.mqtt_host.clone()?;
+
+        let options_directory = match config.mqtt_username {
+            Some(ref username) => {
+                let directory = config.state_directory.join("mqtt");
+
+                if let Err(e) = write_client_options(&directory, username, &config.mqtt_password)
+                {
+                    error!("Writing the MQTT client options failed: {}", e);
+                    return None;
+                }
+
+                Some(directory)
+            },
+            None => None,
+        };
+
+        Some(MqttBroker {
+            host: host,
+            port: config.mqtt_port.to_string(),
+            ca_file: config.mqtt_ca_file.clone(),
+            options_directory: options_directory,
+        })
+    }
+
+    fn command(&self, program: &str) -> Command {
+        let mut command = Command::new(program);
+
+        command.args(&["-h", &self.host, "-p", &self.port]);
+
+        if let Some(ref ca_file) = self.ca_file {
+            command.arg("--cafile").arg(ca_file);
+        }
+
+        if let Some(ref directory) = self.options_directory {
+            command.env("XDG_CONFIG_HOME", directory);
+        }
+
+        command
+    }
+}
+
+/// Writes the credentials as one option per line, only readable by the
+/// owner, for both clients
+fn write_client_options(
+    directory: &Path,
+    username: &str,
+    password: &Option<String>,
+) -> Result<()> {
+    fs::create_dir_all(directory)?;
+    fs::set_permissions(directory, fs::Permissions::from_mode(0o700))?;
+
+    let mut options = format!("-u {}\n", username);
+
+    if let Some(ref password) = *password {
+        options.push_str(&format!("-P {}\n", password));
+    }
+
+    for program in &["mosquitto_pub", "mosquitto_sub"] {
+        let path = directory.join(program);
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+
+        // The mode only applies to new files
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+        file.write_all(options.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+struct MqttMessage {
+    topic: String,
+    payload: String,
+    retain: bool,
+}
+
+/// Publishes messages under the configured topic prefix with
+/// `mosquitto_pub`. Messages are sent one at a time from a dedicated thread,
+/// so that they arrive in order and a slow broker does not hold up the
+/// caller.
+#[derive(Clone)]
+pub struct MqttPublisher {
+    broker: MqttBroker,
+    topic_prefix: String,
+    message_tx: Sender<MqttMessage>,
+}
+
+impl MqttPublisher {
+    pub fn start(config: &Config) -> Option<Self> {
+        let broker = MqttBroker::new(config)?;
+
+        let (message_tx, message_rx) = channel::<MqttMessage>();
+
+        let publisher_broker = broker.clone();
+
+        thread::spawn(move || {
+            for message in message_rx.iter() {
+                let mut command = publisher_broker.command("mosquitto_pub");
+
+                command.args(&["-t", &message.topic, "-m", &message.payload]);
+
+                if message.retain {
+                    command.arg("-r");
+                }
+
+                match command.status() {
+                    Ok(status) if status.success() => {},
+                    Ok(status) => warn!("Publishing to {} failed: {}", message.topic, status),
+                    Err(e) => warn!("Spawning mosquitto_pub failed: {}", e),
+                }
+            }
+        });
+
+        Some(MqttPublisher {
+            broker: broker,
+            topic_prefix: config.mqtt_topic_prefix.clone(),
+            message_tx: message_tx,
+        })
+    }
+
+    /// Publishes to `<prefix>/<topic>`. Retained messages are delivered to
+    /// clients subscribing later as well.
+    pub fn publish(&self, topic: &str, payload: &Value, retain: bool) {
+        let message = MqttMessage {
+            topic: format!("{}/{}", self.topic_prefix, topic),
+            payload: payload.to_string(),
+            retain: retain,
+        };
+
+        if self.message_tx.send(message).is_err() {
+            error!("Queueing MQTT message failed");
+        }
+    }
+}
+
+/// Subscribes to `<prefix>/command` with `mosquitto_sub` and forwards the
+/// commands to the network thread. The commands are authorized and validated
+/// like the requests of the HTTP API, and rejections are published to
+/// `<prefix>/error`. The subscription lasts while the child process is alive.
+pub fn start_mqtt_subscriber(
+    config: &Config,
+    publisher: MqttPublisher,
+    network_tx: Sender<NetworkCommand>,
+) -> Result<Child> {
+    let topic = format!("{}/command", config.mqtt_topic_prefix);
+
+    let credentials = Credentials::new(config);
+    let enrollment_token = config.enrollment_token.clone();
+
+    let mut child = publisher
+        .broker
+        .command("mosquitto_sub")
+        .args(&["-t", &topic])
+        .stdout(Stdio::piped())
+        .spawn()
+        .chain_err(|| ErrorKind::MosquittoSub)?;
+
+    let stdout = child.stdout.take().unwrap();
+
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("Reading MQTT commands failed: {}", e);
+                    return;
+                },
+            };
+
+            if let Err(e) = handle_command(
+                &line,
+                &publisher,
+                &network_tx,
+                &credentials,
+                &enrollment_token,
+            ) {
+                warn!("MQTT command failed: {}", e);
+            }
+        }
+    });
+
+    Ok(child)
+}
+
+fn handle_command(
+    line: &str,
+    publisher: &MqttPublisher,
+    network_tx: &Sender<NetworkCommand>,
+    credentials: &Credentials,
+    enrollment_token: &Option<String>,
+) -> Result<()> {
+    let (command, auth) = match serde_json::from_str::<MqttCommand>(line)
+        .and_then(|command| serde_json::from_str::<MqttAuth>(line).map(|auth| (command, auth)))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!("Ignoring invalid MQTT command: {}", e);
+            return Ok(());
+        },
+    };
+
+    let is_protected = match command {
+        MqttCommand::Scan => false,
+        _ => true,
+    };
+
+    if is_protected && credentials.is_enabled()
+        && !credentials.is_authorized(
+            auth.auth_username.as_ref().map(|s| s as &str),
+            auth.auth_password.as_ref().map(|s| s as &str),
+            auth.auth_token.as_ref().map(|s| s as &str),
+        ) {
+        warn!("Unauthorized MQTT command");
+
+        publisher.publish(
+            "error",
+            &json!({
+                "code": "UNAUTHORIZED",
+                "message": "Valid credentials are required",
+                "details": Value::Null,
+            }),
+            false,
+        );
+
+        return Ok(());
+    }
+
+    match command {
+        MqttCommand::Scan => {
+            if let NetworkCommandResponse::AccessPoints(access_points) =
+                request_network_command(network_tx, |response_tx| NetworkCommand::Activate {
+                    response_tx: response_tx,
+                })? {
+                let access_points = access_points
+                    .iter()
+                    .map(|ap| {
+                        json!({
+                            "ssid": ap.ssid,
+                            "strength": ap.strength,
+                            "security": ap.key_management.as_str(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+
+                publisher.publish("scan", &Value::Array(access_points), false);
+            }
+        },
+        MqttCommand::Connect(request) => {
+            let command = match get_connect_command(request, enrollment_token, None) {
+                Ok(command) => command,
+                Err(api_error) => {
+                    let error = api_error.to_json();
+
+                    warn!(
+                        "Refusing MQTT connect command: {}",
+                        error["message"].as_str().unwrap_or("")
+                    );
+
+                    publisher.publish("error", &error, false);
+
+                    return Ok(());
+                },
+            };
+
+            if let NetworkCommand::Connect { ref ssid, .. } = command {
+                info!("Connecting to '{}' on MQTT command", ssid);
+            }
+
+            network_tx.send(command)?;
+        },
+        MqttCommand::Disconnect { checkpoint } => {
+            request_network_command(network_tx, |response_tx| NetworkCommand::Disconnect {
+                checkpoint: checkpoint,
+                response_tx: response_tx,
+            })?;
+        },
+        MqttCommand::Clear { checkpoint } => {
+            if let NetworkCommandResponse::Cleared(count) =
+                request_network_command(network_tx, |response_tx| NetworkCommand::Clear {
+                    checkpoint: checkpoint,
+                    response_tx: response_tx,
+                })? {
+                info!("Deleted {} WiFi connections on MQTT command", count);
+            }
+        },
+    }
+
+    Ok(())
+}
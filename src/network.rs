@@ -1,40 +1,582 @@
+use std::cmp;
+use std::mem;
+use std::str;
 use std::thread;
 use std::process;
-use std::time::Duration;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::sync::mpsc::{channel, Receiver, RecvError, Sender};
 use std::error::Error;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::fs::{self, File};
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use network_manager::{AccessPoint, Connection, ConnectionState, Connectivity, Device, DeviceType,
-                      NetworkManager, ServiceState};
+use nix::sys::signal::{self, SIGHUP, SIGINT};
+use serde_json::Value;
+use futures::Stream;
+use futures::sync::mpsc::{unbounded, UnboundedSender};
+use network_manager::{AccessPoint, AccessPointCredentials, Connection, ConnectionState,
+                      Connectivity, Device, DeviceType, NetworkManager, Security, ServiceState};
 
 use errors::*;
 use exit::{exit, trap_exit_signals, ExitResult};
-use config::Config;
-use dnsmasq::start_dnsmasq;
-use server::start_server;
+use config::{reload_config, Config, ConfigReload, Scheme, SharedConfig};
+use dnsmasq::DhcpDns;
+use zeroconf::MdnsService;
+use server::{start_server, HttpServer};
+use tls::get_tls_identity;
+use ipv6::{get_link_local_address, has_ipv6_connectivity};
+use internet::{check_internet, results_to_json};
+use state::{write_json_atomically, StateStore};
+use metrics::Metrics;
+use nmcli::{add_wifi_profile, add_wired_portal, configure_wired_ipv4, connect_key_management,
+            get_active_scan_entry, get_connection_settings, get_ipv4_addresses, get_ipv4_config,
+            get_ipv6_config, get_saved_profiles, get_scan_entries, get_wifi_devices,
+            modify_connection, rescan, set_dns_servers, Band, Ipv4Config, KeyManagement,
+            SavedProfile, ScanEntry, WifiDevice, WifiProfile};
+use subnet;
+use pinning::BssidPins;
+use backup::{export_profiles, parse_backup};
+use dbus_api::start_dbus_api;
+use mqtt::{start_mqtt_subscriber, MqttPublisher};
+use pairing::PairingToken;
+use power::{apply_portal_power, get_channel, get_link_signal, get_station_count, reset_portal_power,
+            set_power_save};
+use locale::Milestone;
+use logger::{log_event, set_level};
+use events::EventBus;
+use activity::ActivityTimer;
+use hooks::{HookEvent, Hooks};
+use systemd;
+use beacon::spawn_beacon;
+use ssdp::spawn_ssdp;
+use checkpoint::Checkpoint;
+use onboarding::Onboarding;
+use virtual_ap::{create_virtual_ap, remove_virtual_ap};
+use p2p::{spawn_p2p, stop_p2p};
+use nfc::start_nfc_reader;
+use secrets::set_wifi_secret;
 
+/// Commands expecting a response carry the sending half of a channel created
+/// for that single request, so that concurrent HTTP requests always receive
+/// the response to their own command
 pub enum NetworkCommand {
-    Activate,
+    Activate {
+        response_tx: ResponseSender,
+    },
     Timeout,
-    Exit,
-    Connect { ssid: String, passphrase: String },
+    Exit {
+        signal: signal::Signal,
+    },
+    Connect {
+        ssid: String,
+        passphrase: String,
+        dns_servers: Option<Vec<Ipv4Addr>>,
+        reuse: bool,
+        selection: ApSelection,
+        /// Checkpoint timeout of the request instead of the configured one
+        checkpoint: Option<u64>,
+    },
+    Status {
+        response_tx: ResponseSender,
+    },
+    BlacklistBssid {
+        bssid: String,
+        response_tx: ResponseSender,
+    },
+    ConnectivityLost,
+    MaxRuntime,
+    Connection {
+        response_tx: ResponseSender,
+    },
+    Ethernet {
+        response_tx: ResponseSender,
+    },
+    ConfigureEthernet {
+        config: Ipv4Settings,
+        response_tx: ResponseSender,
+    },
+    ExportProfiles {
+        key: Option<String>,
+        response_tx: ResponseSender,
+    },
+    ApplyAdvanced {
+        change: AdvancedChange,
+        response_tx: ResponseSender,
+    },
+    Confirm {
+        response_tx: ResponseSender,
+    },
+    RollbackTimeout {
+        id: u64,
+    },
+    ImportProfiles {
+        backup: Value,
+        key: Option<String>,
+        response_tx: ResponseSender,
+    },
+    Disconnect {
+        checkpoint: Option<u64>,
+        response_tx: ResponseSender,
+    },
+    Clear {
+        checkpoint: Option<u64>,
+        response_tx: ResponseSender,
+    },
+    Forget {
+        ssid: String,
+        response_tx: ResponseSender,
+    },
+    ListSaved {
+        response_tx: ResponseSender,
+    },
+    Signal {
+        response_tx: ResponseSender,
+    },
+    Interfaces {
+        response_tx: ResponseSender,
+    },
+    Hotspot {
+        response_tx: ResponseSender,
+    },
+    SelectInterface {
+        interface: String,
+        response_tx: ResponseSender,
+    },
+    Health {
+        response_tx: ResponseSender,
+    },
+    PortalSleep,
+    PortalWake,
+    ConnectAny {
+        response_tx: ResponseSender,
+    },
+    RestartNetworkManager {
+        response_tx: ResponseSender,
+    },
+    /// Connects to test the credentials, and deletes the profile afterwards
+    /// and reopens the portal, unless the connection is kept
+    Verify {
+        ssid: String,
+        passphrase: String,
+        keep: bool,
+        response_tx: ResponseSender,
+    },
+    /// Recreates the captive portal, if it is open
+    RestartPortal {
+        response_tx: ResponseSender,
+    },
+    /// Rescans if the access points are older than the max age, or always
+    /// without one. Without a response sender the scan runs in the background.
+    Scan {
+        max_age: Option<u64>,
+        response_tx: Option<ResponseSender>,
+    },
+    /// Reads the configuration again, on `SIGHUP` without a response sender
+    ReloadConfig {
+        response_tx: Option<ResponseSender>,
+    },
+    /// A phone joined the Wi-Fi Direct group, which the HTTP API is served
+    /// on at the address of the device
+    P2pGroupStarted {
+        address: Ipv4Addr,
+    },
 }
 
+/// Sending half of the channel a command is answered on, which the HTTP
+/// server awaits without blocking a thread
+pub type ResponseSender = UnboundedSender<NetworkCommandResponse>;
+
 pub enum NetworkCommandResponse {
-    AccessPointsSsids(Vec<String>),
+    AccessPoints(Vec<AccessPointInfo>),
+    Status(Status),
+    BssidBlacklist(Vec<String>),
+    Connection(Option<ConnectionDetails>),
+    Ethernet(::std::result::Result<EthernetStatus, EthernetError>),
+    Backup(::std::result::Result<Value, BackupError>),
+    Advanced(::std::result::Result<u64, AdvancedError>),
+    Confirmed(bool),
+    Disconnected(bool),
+    Cleared(usize),
+    Forgotten(Forgotten),
+    Saved(Vec<SavedProfile>),
+    Signal(Option<Signal>),
+    Interfaces(Interfaces),
+    Hotspot(Option<Hotspot>),
+    InterfaceSelected(::std::result::Result<String, InterfaceError>),
+    Health(Health),
+    ConnectedAny(::std::result::Result<ConnectedAny, ConnectAnyError>),
+    NetworkManagerRestarted(::std::result::Result<NetworkManagerRestart, String>),
+    ScanResults(ScanResults),
+    Verified(::std::result::Result<Verification, VerifyError>),
+    PortalRestarted(Option<PortalRestart>),
+    ConfigReloaded(::std::result::Result<ConfigReload, String>),
+}
+
+/// The access points of the last scan, which the captive portal lists
+pub struct ScanResults {
+    pub access_points: Vec<AccessPointInfo>,
+    pub scanned_at: SystemTime,
+    /// Whether the request rescanned, rather than answering from the cache
+    pub rescanned: bool,
+}
+
+pub struct Status {
+    pub activated: bool,
+    pub portal_active: bool,
+    pub metrics: Metrics,
+}
+
+/// Result of forgetting a network: the number of deleted profiles and the
+/// remaining ones
+pub struct Forgotten {
+    pub deleted: usize,
+    pub saved: Vec<SavedProfile>,
+}
+
+/// Signal of the access point the device is connected to
+pub struct Signal {
+    pub ssid: String,
+    /// Strength as reported by NetworkManager (%)
+    pub strength: Option<u32>,
+    /// Signal level of the received frames (dBm)
+    pub rssi: Option<i32>,
+}
+
+/// The WiFi devices and the one the captive portal runs on
+pub struct Interfaces {
+    pub active: String,
+    pub devices: Vec<WifiDevice>,
+}
+
+pub enum InterfaceError {
+    NotFound,
+    NotAWiFiDevice,
+    /// The device is connecting or connected, so the portal is not open
+    Busy,
+    Switch(String),
+}
+
+/// Parameters of the captive portal access point, for join instructions
+pub struct Hotspot {
+    pub ssid: String,
+    pub passphrase: Option<String>,
+    pub interface: String,
+    pub gateway: Ipv4Addr,
+    pub channel: Option<u32>,
+    /// Channel frequency (MHz)
+    pub frequency: Option<u32>,
+}
+
+/// The captive portal access point after restarting it
+pub struct PortalRestart {
+    pub hotspot: Hotspot,
+    pub duration: u64,
+}
+
+/// Components of the network thread checked by the health endpoints
+pub struct Health {
+    pub interface: String,
+    pub portal_active: bool,
+    pub dhcp_dns_running: bool,
+}
+
+/// Result of trying the saved networks: the one which got connected, if
+/// any, and the outcome of each attempt in order
+pub struct ConnectedAny {
+    pub ssid: Option<String>,
+    pub attempts: Vec<(String, &'static str)>,
+}
+
+pub enum ConnectAnyError {
+    NotAWiFiDevice,
+    /// The device is connecting or connected, so the portal is not open
+    Busy,
+    /// None of the saved networks is in range
+    NoCandidates,
+}
+
+/// Outcome of testing the credentials of a network, with the error code of
+/// the failure
+pub struct Verification {
+    pub verified: bool,
+    pub kept: bool,
+    pub error: Option<&'static str>,
+    pub duration: u64,
+}
+
+pub enum VerifyError {
+    NotAWiFiDevice,
+    /// The device is connecting or connected, so the portal is not open
+    Busy,
+    /// Verifying would replace the saved profile of the network
+    AlreadySaved,
+}
+
+/// Outcome of restarting NetworkManager: the device acquired again and the
+/// access points of the scan after
+pub struct NetworkManagerRestart {
+    pub interface: String,
+    pub access_points: usize,
+    pub portal_active: bool,
+    /// Time the restart took (seconds)
+    pub duration: u64,
+}
+
+/// Progress of the connection attempts, e.g. `retrying` at attempt 2 of 5
+#[derive(Clone)]
+pub struct ConnectProgress {
+    pub ssid: String,
+    pub state: &'static str,
+    pub attempt: u32,
+    pub max_attempts: u32,
+    pub retry_in: Option<u64>,
+    /// Stable code of the last failure, once the attempts failed
+    pub error: Option<&'static str>,
+}
+
+impl ConnectProgress {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "ssid": self.ssid,
+            "state": self.state,
+            "attempt": self.attempt,
+            "max_attempts": self.max_attempts,
+            "retry_in": self.retry_in,
+            "error": self.error,
+        })
+    }
+}
+
+/// The latest connection progress, readable while the network thread is
+/// busy connecting
+pub type SharedConnectProgress = Arc<Mutex<Option<ConnectProgress>>>;
+
+/// Coarse state of the provisioning, as reported to other services
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProvisioningState {
+    Provisioning,
+    Connecting,
+    Online,
+}
+
+impl ProvisioningState {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            ProvisioningState::Provisioning => "provisioning",
+            ProvisioningState::Connecting => "connecting",
+            ProvisioningState::Online => "online",
+        }
+    }
+}
+
+/// An access point as listed in the captive portal
+pub struct AccessPointInfo {
+    /// The SSID, with invalid UTF-8 sequences replaced
+    pub ssid: String,
+    /// The SSID as hex, if it is not UTF-8, e.g. Latin-1
+    pub raw_ssid: Option<String>,
+    pub strength: u32,
+    pub key_management: KeyManagement,
+    /// The BSSIDs broadcasting the SSID, e.g. the nodes of a mesh network
+    pub bssids: Vec<ScanEntry>,
+}
+
+/// The access point to connect to among those sharing an SSID, e.g. on mesh
+/// networks. The created connection profile is locked to it.
+#[derive(Clone, Default)]
+pub struct ApSelection {
+    pub band: Option<Band>,
+    pub bssid: Option<String>,
+    /// The raw bytes of an SSID which is not UTF-8, which is then only known
+    /// by its lossy representation
+    pub raw_ssid: Option<Vec<u8>>,
+}
+
+impl ApSelection {
+    pub fn is_empty(&self) -> bool {
+        self.band.is_none() && self.bssid.is_none()
+    }
+}
+
+/// IPv4 configuration requested for a device
+pub enum Ipv4Settings {
+    Dhcp,
+    Static {
+        address: Ipv4Addr,
+        prefix: u8,
+        gateway: Option<Ipv4Addr>,
+        dns_servers: Vec<Ipv4Addr>,
+    },
+}
+
+/// IPv6 configuration requested for a connection: stateless address
+/// autoconfiguration, or a static address
+pub enum Ipv6Settings {
+    Auto,
+    Static {
+        address: Ipv6Addr,
+        prefix: u8,
+        gateway: Option<Ipv6Addr>,
+    },
+}
+
+/// A change to the settings of the WiFi connection, which has to be
+/// confirmed over the new configuration before the confirm timeout
+pub enum AdvancedChange {
+    Ipv4(Ipv4Settings),
+    Ipv6(Ipv6Settings),
+    Dns(Vec<Ipv4Addr>),
+    Proxy { pac_url: Option<String> },
+}
+
+pub enum AdvancedError {
+    NotConnected,
+    Failed(String),
+}
+
+/// Interval of checking for clients associated with the captive portal
+/// (seconds)
+const PORTAL_SLEEP_POLL_INTERVAL: u64 = 5;
+
+/// Upper bound of the delay between the attempts to reach the system D-Bus
+/// at startup (seconds)
+const MAX_DBUS_RETRY_DELAY: u64 = 8;
+
+/// Name of the connection profile of the captive portal on Ethernet
+const WIRED_PORTAL_CONNECTION: &str = "WiFi Connect wired portal";
+
+/// State document of the provisioning state of the latest run
+const RUN_DOCUMENT: &str = "run";
+
+/// Upper bound of the delay between connection attempts (seconds)
+const MAX_CONNECT_BACKOFF: u64 = 300;
+
+/// Time NetworkManager waits past the checkpoint timeout before rolling back
+/// on its own, should WiFi Connect not do it (seconds)
+const CHECKPOINT_GRACE: u64 = 30;
+
+/// Time to wait for NetworkManager to stop or start (seconds)
+const NM_SERVICE_TIMEOUT: u64 = 15;
+
+/// Attempts at finding the device again after restarting NetworkManager,
+/// which takes a while to pick up the devices, one per second
+const NM_RESTART_DEVICE_ATTEMPTS: u32 = 10;
+
+/// Attempts at finding the virtual interface of the captive portal, which
+/// NetworkManager takes a while to pick up, one per second
+const VIRTUAL_AP_DEVICE_ATTEMPTS: u32 = 10;
+
+/// Time the captive portal on the virtual interface stays up after
+/// connecting, for its clients to see the result (seconds)
+const VIRTUAL_AP_LINGER: u64 = 5;
+
+/// Settings of the WiFi connection restored when a change is not confirmed
+const ROLLBACK_SETTINGS: &[&str] = &[
+    "ipv4.method",
+    "ipv4.addresses",
+    "ipv4.gateway",
+    "ipv4.dns",
+    "ipv4.ignore-auto-dns",
+    "ipv6.method",
+    "ipv6.addresses",
+    "ipv6.gateway",
+    "proxy.method",
+    "proxy.pac-url",
+];
+
+struct PendingRollback {
+    id: u64,
+    uuid: String,
+    settings: Vec<(String, String)>,
+}
+
+struct PendingCheckpoint {
+    id: u64,
+    checkpoint: Checkpoint,
+}
+
+/// Link state and IPv4 configuration of the wired device
+pub struct EthernetStatus {
+    pub interface: String,
+    pub state: String,
+    pub carrier: Option<bool>,
+    pub ipv4: Ipv4Config,
+}
+
+pub enum EthernetError {
+    NotManaged,
+    Configure(String),
+}
+
+pub enum BackupError {
+    Invalid(String),
+    Failed(String),
+}
+
+/// Details of the WiFi connection established through the portal
+pub struct ConnectionDetails {
+    pub ssid: String,
+    pub bssid: Option<String>,
+    pub signal: Option<u32>,
+    pub addresses: Vec<(Ipv4Addr, u8)>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+    pub ipv6_addresses: Vec<(Ipv6Addr, u8)>,
+    pub ipv6_gateway: Option<Ipv6Addr>,
+    pub uptime: u64,
 }
 
 struct NetworkCommandHandler {
     manager: NetworkManager,
     device: Device,
+    /// Virtual interface next to the WiFi one, which the portal runs on
+    virtual_ap: Option<Device>,
+    ethernet_device: Option<Device>,
     access_points: Vec<AccessPoint>,
+    access_points_scanned_at: SystemTime,
     portal_connection: Option<Connection>,
     config: Config,
-    dnsmasq: process::Child,
-    server_tx: Sender<NetworkCommandResponse>,
+    /// The configuration shared with the HTTP server, updated on reload
+    shared_config: SharedConfig,
+    dhcp_dns: Option<DhcpDns>,
+    mdns_service: Option<MdnsService>,
+    server: Option<HttpServer>,
+    /// Servers on the addresses of the Wi-Fi Direct groups so far
+    p2p_servers: Vec<(Ipv4Addr, HttpServer)>,
+    /// Whether Wi-Fi Direct peers are invited, i.e. while provisioning
+    p2p_enabled: Arc<AtomicBool>,
+    network_tx: Sender<NetworkCommand>,
+    exit_tx: Sender<ExitResult>,
     network_rx: Receiver<NetworkCommand>,
     activated: bool,
+    state_store: StateStore,
+    metrics: Metrics,
+    bssid_pins: BssidPins,
+    bssid_blacklist: BTreeSet<String>,
+    connected_ssid: Option<String>,
+    connected_at: Option<Instant>,
+    monitoring: Arc<AtomicBool>,
+    pending_rollback: Option<PendingRollback>,
+    pending_checkpoint: Option<PendingCheckpoint>,
+    rollback_id: u64,
+    state_tx: Option<Sender<ProvisioningState>>,
+    mqtt: Option<MqttPublisher>,
+    mqtt_subscriber: Option<process::Child>,
+    nfc_reader: Option<process::Child>,
+    audio_capture: Option<process::Child>,
+    portal_asleep: bool,
+    pairing_token: PairingToken,
+    connect_progress: SharedConnectProgress,
+    connect_error: Option<&'static str>,
+    events: EventBus,
+    activity: ActivityTimer,
+    hooks: Hooks,
+    onboarding: Onboarding,
+    state: ProvisioningState,
 }
 
 impl NetworkCommandHandler {
@@ -46,228 +588,3179 @@ impl NetworkCommandHandler {
         let manager = NetworkManager::new();
         debug!("NetworkManager connection initialized");
 
-        let device = find_device(&manager, &config.interface)?;
+        let device = match find_device(&manager, &config.interface) {
+            Ok(device) => device,
+            Err(e) => if config.ethernet_portal {
+                warn!("{} - serving the captive portal over Ethernet", e);
+                find_ethernet_device(&manager, &config.ethernet_interface)?
+            } else {
+                return Err(e);
+            },
+        };
+
+        let ethernet_device = if config.manage_ethernet {
+            match find_ethernet_device(&manager, &config.ethernet_interface) {
+                Ok(ethernet_device) => Some(ethernet_device),
+                Err(e) => {
+                    warn!("Ethernet management disabled: {}", e);
+                    None
+                },
+            }
+        } else {
+            None
+        };
+
+        let virtual_ap = match config.virtual_ap {
+            Some(ref virtual_interface) if is_wifi(&device) => {
+                start_virtual_ap(&manager, &device, virtual_interface)
+            },
+            _ => None,
+        };
+
+        let access_points = get_access_points(&device, config)?;
+
+        // In change network mode an online device keeps its connection and
+        // the portal is opened only if switching networks fails for good
+        let online_connection = if config.change_network && is_wifi(&device) {
+            get_online_connection(&manager, &device)
+        } else {
+            None
+        };
+
+        let online_ssid = online_connection
+            .as_ref()
+            .and_then(|connection| connection.settings().ssid.as_str().ok())
+            .map(String::from);
+
+        let pairing_token = PairingToken::new();
+        let connect_progress = Arc::new(Mutex::new(None));
+        let events = EventBus::new();
+        let activity = ActivityTimer::new(config.activity_timeout);
+
+        let (portal_connection, dhcp_dns) = if let Some(ref ssid) = online_ssid {
+            info!("Connected to '{}' - not opening the captive portal", ssid);
+            (None, None)
+        } else {
+            let portal_device = virtual_ap.as_ref().unwrap_or(&device);
+
+            (
+                Some(create_portal(portal_device, config, &pairing_token)?),
+                tolerate(
+                    config,
+                    DhcpDns::start(config, portal_device),
+                    "Captive portal DHCP and DNS disabled",
+                )?,
+            )
+        };
+
+        let mdns_service = match MdnsService::register(config) {
+            Ok(service) => Some(service),
+            Err(e) => {
+                warn!("Portal mDNS advertisement disabled: {}", e);
+                None
+            },
+        };
+
+        let tls_identity = if config.listening_scheme == Scheme::Https {
+            Some(get_tls_identity(config)?)
+        } else {
+            None
+        };
+
+        let listening_addresses = get_listening_addresses(config, &device, online_ssid.is_some());
+
+        let shared_config = Arc::new(Mutex::new(config.clone()));
+
+        let server = start_server(
+            config,
+            shared_config.clone(),
+            listening_addresses,
+            tls_identity,
+            network_tx.clone(),
+            exit_tx.clone(),
+            pairing_token.clone(),
+            connect_progress.clone(),
+            events.clone(),
+            activity.clone(),
+        )?;
+
+        Self::spawn_activity_timeout(activity.clone(), network_tx.clone());
+
+        Self::spawn_max_runtime(config, network_tx.clone());
+
+        Self::spawn_watchdog(network_tx.clone());
+
+        spawn_beacon(config);
+        spawn_ssdp(config);
+
+        let p2p_enabled = Arc::new(AtomicBool::new(false));
+
+        if config.wifi_p2p {
+            spawn_p2p(p2p_enabled.clone(), network_tx.clone());
+        }
+
+        if is_wifi(&device) {
+            let portal_interface = virtual_ap.as_ref().unwrap_or(&device).interface();
+
+            Self::spawn_portal_sleep_watcher(config, portal_interface, network_tx.clone());
+            Self::spawn_scan_watcher(config, device.interface(), events.clone());
+        }
+
+        let state_tx = if config.dbus {
+            let (state_tx, state_rx) = channel();
+            start_dbus_api(network_tx.clone(), state_rx);
+            Some(state_tx)
+        } else {
+            None
+        };
+
+        let mqtt = MqttPublisher::start(config);
+
+        let mqtt_subscriber = match mqtt {
+            Some(ref mqtt) => {
+                match start_mqtt_subscriber(config, mqtt.clone(), network_tx.clone()) {
+                    Ok(child) => Some(child),
+                    Err(e) => {
+                        warn!("MQTT commands disabled: {}", e);
+                        None
+                    },
+                }
+            },
+            None => None,
+        };
+
+        let nfc_reader = match config.nfc_reader {
+            Some(ref command) => match start_nfc_reader(command, network_tx.clone()) {
+                Ok(child) => Some(child),
+                Err(e) => {
+                    warn!("NFC provisioning disabled: {}", e);
+                    None
+                },
+            },
+            None => None,
+        };
+
+        let audio_capture = match config.audio_capture {
+            Some(ref device) => match start_audio(device, network_tx.clone()) {
+                Ok(child) => Some(child),
+                Err(e) => {
+                    warn!("Audio provisioning disabled: {}", e);
+                    None
+                },
+            },
+            None => None,
+        };
+
+        let monitoring = Arc::new(AtomicBool::new(online_ssid.is_some() && config.monitor));
 
-        let access_points = get_access_points(&device)?;
+        Self::spawn_connectivity_watchdog(config, monitoring.clone(), network_tx.clone());
 
-        let portal_connection = Some(create_portal(&device, config)?);
+        let state_store = StateStore::new(&config.state_directory);
+
+        if let Some(run) = state_store.load(RUN_DOCUMENT) {
+            if run["ended"] == "max_runtime" {
+                info!(
+                    "Previous run reached the max runtime in the {} state",
+                    run["state"].as_str().unwrap_or("unknown")
+                );
+            }
+        }
 
-        let dnsmasq = start_dnsmasq(config, &device)?;
+        systemd::notify("READY=1");
 
-        let (server_tx, server_rx) = channel();
+        let metrics = Metrics::load(&state_store);
+        let bssid_pins = BssidPins::load(&state_store);
+        let hooks = Hooks::new(config);
 
-        Self::spawn_server(config, exit_tx, server_rx, network_tx.clone());
+        let mut onboarding = Onboarding::default();
 
-        Self::spawn_activity_timeout(config, network_tx.clone());
+        if portal_connection.is_some() {
+            onboarding.portal_opened();
+        }
 
         let config = config.clone();
         let activated = false;
 
-        Ok(NetworkCommandHandler {
+        let mut handler = NetworkCommandHandler {
             manager,
             device,
+            virtual_ap,
+            ethernet_device,
             access_points,
+            access_points_scanned_at: SystemTime::now(),
             portal_connection,
             config,
-            dnsmasq,
-            server_tx,
+            shared_config,
+            dhcp_dns,
+            mdns_service,
+            server: Some(server),
+            p2p_servers: Vec::new(),
+            p2p_enabled,
+            network_tx,
+            exit_tx: exit_tx.clone(),
             network_rx,
             activated,
-        })
-    }
-
-    fn spawn_server(
-        config: &Config,
-        exit_tx: &Sender<ExitResult>,
-        server_rx: Receiver<NetworkCommandResponse>,
-        network_tx: Sender<NetworkCommand>,
-    ) {
-        let gateway = config.gateway;
-        let exit_tx_server = exit_tx.clone();
-        let ui_directory = config.ui_directory.clone();
+            state_store,
+            metrics,
+            bssid_pins,
+            bssid_blacklist: BTreeSet::new(),
+            connected_at: online_ssid.as_ref().map(|_| Instant::now()),
+            connected_ssid: online_ssid,
+            monitoring,
+            pending_rollback: None,
+            pending_checkpoint: None,
+            rollback_id: 0,
+            state_tx,
+            mqtt,
+            mqtt_subscriber,
+            nfc_reader,
+            audio_capture,
+            portal_asleep: false,
+            pairing_token,
+            connect_progress,
+            connect_error: None,
+            events,
+            activity,
+            hooks,
+            onboarding,
+            state: ProvisioningState::Provisioning,
+        };
 
-        thread::spawn(move || {
-            start_server(
-                gateway,
-                server_rx,
-                network_tx,
-                exit_tx_server,
-                &ui_directory,
-            );
+        handler.set_state(if handler.portal_connection.is_some() {
+            ProvisioningState::Provisioning
+        } else {
+            ProvisioningState::Online
         });
-    }
 
-    fn spawn_activity_timeout(config: &Config, network_tx: Sender<NetworkCommand>) {
-        let activity_timeout = config.activity_timeout;
+        Ok(handler)
+    }
 
-        if activity_timeout == 0 {
+    /// Sleeps until the deadline of the activity timer, which may have been
+    /// pushed back in the meantime. The network thread checks the deadline
+    /// again on receiving the timeout, since it may have been busy meanwhile.
+    fn spawn_activity_timeout(activity: ActivityTimer, network_tx: Sender<NetworkCommand>) {
+        if !activity.is_enabled() {
             return;
         }
 
-        thread::spawn(move || {
-            thread::sleep(Duration::from_secs(activity_timeout));
+        thread::spawn(move || loop {
+            let remaining = match activity.remaining() {
+                Some(remaining) => remaining,
+                None => return,
+            };
+
+            if remaining > Duration::from_secs(0) {
+                thread::sleep(remaining);
+                continue;
+            }
 
             if let Err(err) = network_tx.send(NetworkCommand::Timeout) {
                 error!(
                     "Sending NetworkCommand::Timeout failed: {}",
                     err.description()
                 );
+                return;
             }
+
+            thread::sleep(Duration::from_secs(activity.timeout()));
         });
     }
 
-    fn spawn_trap_exit_signals(exit_tx: &Sender<ExitResult>, network_tx: Sender<NetworkCommand>) {
-        let exit_tx_trap = exit_tx.clone();
+    /// Notifies the systemd watchdog while the network thread answers, so that
+    /// systemd restarts the service if the thread hangs. A `WatchdogSec`
+    /// shorter than a connection attempt restarts it while connecting.
+    fn spawn_watchdog(network_tx: Sender<NetworkCommand>) {
+        let interval = match systemd::get_watchdog_interval() {
+            Some(interval) => interval / 2,
+            None => return,
+        };
 
-        thread::spawn(move || {
-            if let Err(e) = trap_exit_signals() {
-                exit(&exit_tx_trap, e);
-                return;
-            }
+        info!("Notifying the systemd watchdog every {:?}", interval);
 
-            if let Err(err) = network_tx.send(NetworkCommand::Exit) {
-                error!("Sending NetworkCommand::Exit failed: {}", err.description());
-            }
-        });
-    }
+        thread::spawn(move || loop {
+            let (response_tx, response_rx) = unbounded();
 
-    fn run(&mut self, exit_tx: &Sender<ExitResult>) {
-        let result = self.run_loop();
-        self.stop(exit_tx, result);
-    }
+            let sent = Instant::now();
 
-    fn run_loop(&mut self) -> ExitResult {
-        loop {
-            let command = self.receive_network_command()?;
+            if network_tx
+                .send(NetworkCommand::Health {
+                    response_tx: response_tx,
+                })
+                .is_err()
+            {
+                return;
+            }
 
-            match command {
-                NetworkCommand::Activate => {
-                    self.activate()?;
-                },
-                NetworkCommand::Timeout => {
-                    if !self.activated {
-                        info!("Timeout reached. Exiting...");
-                        return Ok(());
-                    }
-                },
-                NetworkCommand::Exit => {
-                    info!("Exiting...");
-                    return Ok(());
-                },
-                NetworkCommand::Connect { ssid, passphrase } => {
-                    if self.connect(&ssid, &passphrase)? {
-                        return Ok(());
+            // The answer is awaited however late, so that a busy network
+            // thread delays the notification rather than skipping it
+            match response_rx.wait().next() {
+                Some(Ok(_)) => {
+                    if sent.elapsed() > interval {
+                        warn!("Network thread answered late - watchdog notified late");
                     }
+
+                    systemd::notify("WATCHDOG=1");
                 },
+                _ => return,
             }
-        }
-    }
 
-    fn receive_network_command(&self) -> Result<NetworkCommand> {
-        match self.network_rx.recv() {
-            Ok(command) => Ok(command),
-            Err(e) => {
-                // Sleep for a second, so that other threads may log error info.
-                thread::sleep(Duration::from_secs(1));
-                Err(e).chain_err(|| ErrorKind::RecvNetworkCommand)
-            },
-        }
+            thread::sleep(interval);
+        });
     }
 
-    fn stop(&mut self, exit_tx: &Sender<ExitResult>, result: ExitResult) {
-        let _ = self.dnsmasq.kill();
+    /// Stops the run at the max runtime. A busy network thread handles this
+    /// late, which the main thread bounds as well.
+    fn spawn_max_runtime(config: &Config, network_tx: Sender<NetworkCommand>) {
+        let max_runtime = config.max_runtime;
 
-        if let Some(ref connection) = self.portal_connection {
-            let _ = stop_portal_impl(connection, &self.config);
+        if max_runtime == 0 {
+            return;
         }
 
-        let _ = exit_tx.send(result);
-    }
-
-    fn activate(&mut self) -> ExitResult {
-        self.activated = true;
-
-        let access_points_ssids = get_access_points_ssids_owned(&self.access_points);
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(max_runtime));
 
-        self.server_tx
-            .send(NetworkCommandResponse::AccessPointsSsids(
-                access_points_ssids,
-            ))
-            .chain_err(|| ErrorKind::SendAccessPointSSIDs)
+            if let Err(err) = network_tx.send(NetworkCommand::MaxRuntime) {
+                error!(
+                    "Sending NetworkCommand::MaxRuntime failed: {}",
+                    err.description()
+                );
+            }
+        });
     }
 
-    fn connect(&mut self, ssid: &str, passphrase: &str) -> Result<bool> {
-        delete_connection_if_exists(&self.manager, ssid);
+    /// Watches the clients associated with the captive portal. Once there
+    /// were none for the sleep timeout the network thread is told to put the
+    /// portal to sleep, and to wake it up as soon as a client associates.
+    fn spawn_portal_sleep_watcher(
+        config: &Config,
+        interface: &str,
+        network_tx: Sender<NetworkCommand>,
+    ) {
+        let sleep_timeout = config.portal_sleep_timeout;
 
-        if let Some(ref connection) = self.portal_connection {
-            stop_portal(connection, &self.config)?;
+        if sleep_timeout == 0 {
+            return;
         }
 
-        self.portal_connection = None;
+        let interface = interface.to_string();
 
-        self.access_points = get_access_points(&self.device)?;
+        thread::spawn(move || {
+            let mut idle_for = 0;
 
-        if let Some(access_point) = find_access_point(&self.access_points, ssid) {
-            let wifi_device = self.device.as_wifi_device().unwrap();
+            loop {
+                thread::sleep(Duration::from_secs(PORTAL_SLEEP_POLL_INTERVAL));
 
-            info!("Connecting to access point '{}'...", ssid);
+                let stations = match get_station_count(&interface) {
+                    Ok(stations) => stations,
+                    Err(e) => {
+                        warn!("Portal sleep disabled: {}", e);
+                        return;
+                    },
+                };
 
-            match wifi_device.connect(access_point, passphrase) {
-                Ok((connection, state)) => {
-                    if state == ConnectionState::Activated {
-                        match wait_for_connectivity(&self.manager, 20) {
-                            Ok(has_connectivity) => {
-                                if has_connectivity {
-                                    info!("Internet connectivity established");
-                                } else {
-                                    warn!("Cannot establish Internet connectivity");
-                                }
-                            },
-                            Err(err) => error!("Getting Internet connectivity failed: {}", err),
-                        }
+                // The commands are repeated while the conditions last, the
+                // network thread ignores them unless the portal is open
+                let command = if stations > 0 {
+                    let was_idle = idle_for >= sleep_timeout;
+                    idle_for = 0;
 
-                        return Ok(true);
+                    if !was_idle {
+                        continue;
                     }
 
-                    if let Err(err) = connection.delete() {
-                        error!("Deleting connection object failed: {}", err)
+                    NetworkCommand::PortalWake
+                } else {
+                    idle_for += PORTAL_SLEEP_POLL_INTERVAL;
+
+                    if idle_for < sleep_timeout {
+                        continue;
                     }
 
-                    warn!(
-                        "Connection to access point not activated '{}': {:?}",
-                        ssid, state
-                    );
-                },
-                Err(e) => {
-                    warn!("Error connecting to access point '{}': {}", ssid, e);
-                },
+                    NetworkCommand::PortalSleep
+                };
+
+                if network_tx.send(command).is_err() {
+                    return;
+                }
             }
+        });
+    }
+
+    /// Rescans periodically and reports the access points which came into or
+    /// went out of range since the previous scan on the event streams
+    fn spawn_scan_watcher(config: &Config, interface: &str, events: EventBus) {
+        let interval = config.scan_watch_interval;
+
+        if interval == 0 {
+            return;
         }
 
-        self.access_points = get_access_points(&self.device)?;
+        let interface = interface.to_string();
+        let hidden_ssids = config.hidden_ssids.clone();
 
-        self.portal_connection = Some(create_portal(&self.device, &self.config)?);
+        thread::spawn(move || {
+            let mut previous: Option<HashMap<String, ScanEntry>> = None;
 
-        Ok(false)
-    }
-}
+            loop {
+                // Refused while the portal is open by some drivers, the cached
+                // results are compared then
+                if let Err(e) = rescan(&interface, &hidden_ssids) {
+                    debug!("Background rescan failed: {}", e);
+                }
 
-pub fn process_network_commands(config: &Config, exit_tx: &Sender<ExitResult>) {
-    let mut command_handler = match NetworkCommandHandler::new(config, exit_tx) {
-        Ok(command_handler) => command_handler,
-        Err(e) => {
-            exit(exit_tx, e);
-            return;
-        },
-    };
+                match get_scan_entries(&interface) {
+                    Ok(entries) => {
+                        let current = entries
+                            .into_iter()
+                            .filter(|entry| !entry.bssid.is_empty())
+                            .map(|entry| (entry.bssid.clone(), entry))
+                            .collect::<HashMap<_, _>>();
 
-    command_handler.run(exit_tx);
+                        if let Some(ref previous) = previous {
+                            for (bssid, entry) in &current {
+                                if !previous.contains_key(bssid) {
+                                    events.emit("ap_appeared", scan_entry_to_json(entry));
+                                }
+                            }
+
+                            for (bssid, entry) in previous {
+                                if !current.contains_key(bssid) {
+                                    events.emit("ap_disappeared", scan_entry_to_json(entry));
+                                }
+                            }
+                        }
+
+                        previous = Some(current);
+                    },
+                    Err(e) => warn!("Background scan failed: {}", e),
+                }
+
+                thread::sleep(Duration::from_secs(interval));
+            }
+        });
+    }
+
+    /// In monitor mode checks the connectivity periodically while connected
+    /// and notifies the network thread once it has been lost for longer than
+    /// the grace period
+    fn spawn_connectivity_watchdog(
+        config: &Config,
+        monitoring: Arc<AtomicBool>,
+        network_tx: Sender<NetworkCommand>,
+    ) {
+        if !config.monitor {
+            return;
+        }
+
+        let interval = config.monitor_interval;
+        let grace_period = config.monitor_grace_period;
+
+        thread::spawn(move || {
+            let manager = NetworkManager::new();
+            let mut lost_for = 0;
+
+            loop {
+                thread::sleep(Duration::from_secs(interval));
+
+                if !monitoring.load(Ordering::SeqCst) {
+                    lost_for = 0;
+                    continue;
+                }
+
+                match manager.get_connectivity() {
+                    // `Unknown` is reported when connectivity checking is
+                    // disabled in NetworkManager, so it is not a loss
+                    Ok(Connectivity::Full)
+                    | Ok(Connectivity::Limited)
+                    | Ok(Connectivity::Unknown) => {
+                        lost_for = 0;
+                    },
+                    Ok(connectivity) => {
+                        lost_for += interval;
+                        debug!("Connectivity: {:?} / lost for {}s", connectivity, lost_for);
+                    },
+                    Err(e) => {
+                        lost_for += interval;
+                        warn!("Getting connectivity failed: {}", e);
+                    },
+                }
+
+                if lost_for >= grace_period {
+                    lost_for = 0;
+                    monitoring.store(false, Ordering::SeqCst);
+
+                    if let Err(err) = network_tx.send(NetworkCommand::ConnectivityLost) {
+                        error!(
+                            "Sending NetworkCommand::ConnectivityLost failed: {}",
+                            err.description()
+                        );
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    fn spawn_trap_exit_signals(exit_tx: &Sender<ExitResult>, network_tx: Sender<NetworkCommand>) {
+        let exit_tx_trap = exit_tx.clone();
+
+        thread::spawn(move || loop {
+            let signal = match trap_exit_signals() {
+                Ok(signal) => signal,
+                Err(e) => {
+                    exit(&exit_tx_trap, e);
+                    return;
+                },
+            };
+
+            // `SIGHUP` reloads the configuration, like for most daemons
+            if signal == SIGHUP {
+                let command = NetworkCommand::ReloadConfig { response_tx: None };
+
+                if let Err(err) = network_tx.send(command) {
+                    error!("Sending NetworkCommand::ReloadConfig failed: {}", err.description());
+                    return;
+                }
+
+                continue;
+            }
+
+            if let Err(err) = network_tx.send(NetworkCommand::Exit { signal: signal }) {
+                error!("Sending NetworkCommand::Exit failed: {}", err.description());
+            }
+
+            return;
+        });
+    }
+
+    fn run(&mut self, exit_tx: &Sender<ExitResult>) {
+        let result = self.run_loop().unwrap_or_else(ExitResult::Error);
+        self.stop(exit_tx, result);
+    }
+
+    fn run_loop(&mut self) -> Result<ExitResult> {
+        loop {
+            let command = self.receive_network_command()?;
+
+            let is_activity = match command {
+                NetworkCommand::Timeout
+                | NetworkCommand::Exit { .. }
+                | NetworkCommand::MaxRuntime
+                | NetworkCommand::ConnectivityLost
+                | NetworkCommand::RollbackTimeout { .. }
+                | NetworkCommand::Health { .. }
+                | NetworkCommand::ReloadConfig { .. }
+                | NetworkCommand::PortalSleep
+                | NetworkCommand::PortalWake => false,
+                _ => true,
+            };
+
+            if is_activity {
+                self.activity.kick();
+            }
+
+            match command {
+                NetworkCommand::Activate { response_tx } => {
+                    self.activate(&response_tx)?;
+                },
+                NetworkCommand::Timeout => {
+                    if self.activity.is_expired() {
+                        info!("Timeout reached. Exiting...");
+                        return Ok(ExitResult::ActivityTimeout);
+                    }
+                },
+                NetworkCommand::Exit { signal } => {
+                    info!("Exiting...");
+
+                    // Ctrl+C in the terminal, rather than a supervisor
+                    return Ok(if signal == SIGINT {
+                        ExitResult::UserRequestedExit
+                    } else {
+                        ExitResult::Signal(signal)
+                    });
+                },
+                NetworkCommand::MaxRuntime => {
+                    info!("Max runtime reached. Exiting...");
+                    self.save_run_state(Some("max_runtime"));
+                    return Ok(ExitResult::MaxRuntime);
+                },
+                NetworkCommand::Connect {
+                    ssid,
+                    passphrase,
+                    dns_servers,
+                    reuse,
+                    selection,
+                    checkpoint,
+                } => {
+                    self.begin_checkpoint(checkpoint);
+
+                    self.set_state(ProvisioningState::Connecting);
+
+                    let connected =
+                        self.connect(&ssid, &passphrase, dns_servers, reuse, &selection)?;
+
+                    // Switching networks only succeeds with connectivity
+                    if connected {
+                        self.destroy_checkpoint();
+                    } else {
+                        self.roll_back_checkpoint()?;
+                    }
+
+                    // A failed switch in change network mode rolls back to
+                    // the previous connection instead of opening the portal
+                    self.set_state(if connected || self.portal_connection.is_none() {
+                        ProvisioningState::Online
+                    } else {
+                        ProvisioningState::Provisioning
+                    });
+
+                    if connected {
+                        self.stop_portal_after_connecting()?;
+                        self.run_connect_hooks(&ssid);
+
+                        // A network saved through the wired portal is only
+                        // joined once there is a WiFi device
+                        if !self.config.monitor && !self.config.change_network
+                            || !is_wifi(&self.device)
+                        {
+                            return Ok(ExitResult::ConnectedSuccessfully);
+                        }
+
+                        self.stay_connected(&ssid);
+                    }
+                },
+                NetworkCommand::Status { response_tx } => {
+                    self.status(&response_tx)?;
+                },
+                NetworkCommand::BlacklistBssid { bssid, response_tx } => {
+                    self.blacklist_bssid(bssid, &response_tx)?;
+                },
+                NetworkCommand::ConnectivityLost => {
+                    self.connectivity_lost()?;
+                },
+                NetworkCommand::Connection { response_tx } => {
+                    self.connection(&response_tx)?;
+                },
+                NetworkCommand::Ethernet { response_tx } => {
+                    self.ethernet(&response_tx)?;
+                },
+                NetworkCommand::ConfigureEthernet {
+                    config,
+                    response_tx,
+                } => {
+                    self.configure_ethernet(&config, &response_tx)?;
+                },
+                NetworkCommand::ExportProfiles { key, response_tx } => {
+                    self.export_profiles(key, &response_tx)?;
+                },
+                NetworkCommand::ApplyAdvanced {
+                    change,
+                    response_tx,
+                } => {
+                    self.apply_advanced(&change, &response_tx)?;
+                },
+                NetworkCommand::Confirm { response_tx } => {
+                    self.confirm(&response_tx)?;
+                },
+                NetworkCommand::RollbackTimeout { id } => {
+                    self.rollback_timeout(id)?;
+                },
+                NetworkCommand::ImportProfiles {
+                    backup,
+                    key,
+                    response_tx,
+                } => {
+                    self.import_profiles(backup, key, &response_tx)?;
+                },
+                NetworkCommand::Disconnect {
+                    checkpoint,
+                    response_tx,
+                } => {
+                    self.disconnect(checkpoint, &response_tx)?;
+                },
+                NetworkCommand::Clear {
+                    checkpoint,
+                    response_tx,
+                } => {
+                    self.clear(checkpoint, &response_tx)?;
+                },
+                NetworkCommand::Forget { ssid, response_tx } => {
+                    self.forget(&ssid, &response_tx)?;
+                },
+                NetworkCommand::ListSaved { response_tx } => {
+                    self.list_saved(&response_tx)?;
+                },
+                NetworkCommand::Signal { response_tx } => {
+                    self.signal(&response_tx)?;
+                },
+                NetworkCommand::Interfaces { response_tx } => {
+                    self.interfaces(&response_tx)?;
+                },
+                NetworkCommand::Hotspot { response_tx } => {
+                    self.hotspot(&response_tx)?;
+                },
+                NetworkCommand::SelectInterface {
+                    interface,
+                    response_tx,
+                } => {
+                    self.select_interface(&interface, &response_tx)?;
+                },
+                NetworkCommand::Health { response_tx } => {
+                    self.health(&response_tx);
+                },
+                NetworkCommand::PortalSleep => {
+                    self.portal_sleep();
+                },
+                NetworkCommand::PortalWake => {
+                    self.portal_wake();
+                },
+                NetworkCommand::RestartNetworkManager { response_tx } => {
+                    self.restart_network_manager(&response_tx)?;
+                },
+                NetworkCommand::Scan {
+                    max_age,
+                    response_tx,
+                } => {
+                    self.scan(max_age, response_tx.as_ref())?;
+                },
+                NetworkCommand::RestartPortal { response_tx } => {
+                    self.restart_portal(&response_tx)?;
+                },
+                NetworkCommand::ReloadConfig { response_tx } => {
+                    self.reload_config(response_tx.as_ref())?;
+                },
+                NetworkCommand::P2pGroupStarted { address } => {
+                    self.p2p_group_started(address);
+                },
+                NetworkCommand::Verify {
+                    ssid,
+                    passphrase,
+                    keep,
+                    response_tx,
+                } => {
+                    if self.verify(&ssid, &passphrase, keep, &response_tx)? {
+                        self.stop_portal_after_connecting()?;
+                        self.run_connect_hooks(&ssid);
+
+                        if !self.config.monitor && !self.config.change_network {
+                            return Ok(ExitResult::ConnectedSuccessfully);
+                        }
+
+                        self.stay_connected(&ssid);
+                    }
+                },
+                NetworkCommand::ConnectAny { response_tx } => {
+                    if let Some(ssid) = self.connect_any(&response_tx)? {
+                        self.run_connect_hooks(&ssid);
+
+                        if !self.config.monitor && !self.config.change_network {
+                            return Ok(ExitResult::ConnectedSuccessfully);
+                        }
+
+                        self.stay_connected(&ssid);
+                    }
+                },
+            }
+
+            // Commands such as connecting may take a long while, which does
+            // not count as inactivity
+            if is_activity {
+                self.activity.kick();
+            }
+        }
+    }
+
+    fn receive_network_command(&self) -> Result<NetworkCommand> {
+        match self.network_rx.recv() {
+            Ok(command) => Ok(command),
+            Err(e) => {
+                // Sleep for a second, so that other threads may log error info.
+                thread::sleep(Duration::from_secs(1));
+                Err(e).chain_err(|| ErrorKind::RecvNetworkCommand)
+            },
+        }
+    }
+
+    fn stop(&mut self, exit_tx: &Sender<ExitResult>, result: ExitResult) {
+        self.shut_down();
+
+        let _ = exit_tx.send(result);
+    }
+
+    /// Closes the HTTP servers, reaps the child processes and removes the
+    /// hotspot connection profile. Every step is done at most once, so that
+    /// this may run both on a regular exit and when unwinding from a panic.
+    fn shut_down(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.close();
+        }
+
+        for (_, server) in self.p2p_servers.drain(..) {
+            server.close();
+        }
+
+        if self.config.wifi_p2p {
+            self.p2p_enabled.store(false, Ordering::SeqCst);
+            stop_p2p();
+        }
+
+        self.stop_dhcp_dns();
+
+        if let Some(mdns_service) = self.mdns_service.take() {
+            mdns_service.unregister();
+        }
+
+        if let Some(mut mqtt_subscriber) = self.mqtt_subscriber.take() {
+            let _ = mqtt_subscriber.kill();
+            let _ = mqtt_subscriber.wait();
+        }
+
+        if let Some(mut nfc_reader) = self.nfc_reader.take() {
+            let _ = nfc_reader.kill();
+            let _ = nfc_reader.wait();
+        }
+
+        if let Some(mut audio_capture) = self.audio_capture.take() {
+            let _ = audio_capture.kill();
+            let _ = audio_capture.wait();
+        }
+
+        if let Some(connection) = self.portal_connection.take() {
+            let _ = stop_portal_impl(&connection, &self.config);
+        }
+
+        if let Some(virtual_ap) = self.virtual_ap.take() {
+            remove_virtual_ap(virtual_ap.interface());
+        }
+
+        self.hooks.finish();
+
+        self.write_status_file("stopped");
+    }
+
+    /// Serves the HTTP API on the Wi-Fi Direct group, with the same state as
+    /// the server of the captive portal. The server stays and serves the next
+    /// group on the same address, which NetworkManager usually reuses.
+    fn p2p_group_started(&mut self, address: Ipv4Addr) {
+        if self.p2p_servers.iter().any(|&(served, _)| served == address) {
+            info!("Serving the captive portal over Wi-Fi Direct on {}", address);
+            return;
+        }
+
+        let tls_identity = if self.config.listening_scheme == Scheme::Https {
+            match get_tls_identity(&self.config) {
+                Ok(tls_identity) => Some(tls_identity),
+                Err(e) => {
+                    warn!("Serving over Wi-Fi Direct failed: {}", e);
+                    return;
+                },
+            }
+        } else {
+            None
+        };
+
+        let listening_address = SocketAddr::new(IpAddr::V4(address), self.config.listening_port);
+
+        let server = start_server(
+            &self.config,
+            self.shared_config.clone(),
+            vec![listening_address],
+            tls_identity,
+            self.network_tx.clone(),
+            self.exit_tx.clone(),
+            self.pairing_token.clone(),
+            self.connect_progress.clone(),
+            self.events.clone(),
+            self.activity.clone(),
+        );
+
+        match server {
+            Ok(server) => {
+                info!("Serving the captive portal over Wi-Fi Direct on {}", address);
+                self.p2p_servers.push((address, server));
+            },
+            Err(e) => warn!("Serving over Wi-Fi Direct failed: {}", e),
+        }
+    }
+
+    /// Runs the connect hooks with the time it took to configure the device,
+    /// if it was configured through the captive portal
+    fn run_connect_hooks(&mut self, ssid: &str) {
+        let onboarding = self.onboarding.connectivity_confirmed();
+
+        if let Some(ref onboarding) = onboarding {
+            log_event("onboarding", onboarding.clone());
+            self.metrics.onboarding(&self.state_store, onboarding);
+        }
+
+        self.hooks
+            .run_with_onboarding(HookEvent::Connect, ssid, self.device.interface(), onboarding);
+    }
+
+    /// Keeps running after connecting, in monitor or change network mode
+    fn stay_connected(&mut self, ssid: &str) {
+        // The portal network is gone, so DHCP and DNS must not answer on the
+        // interface until the portal is opened again
+        self.stop_dhcp_dns();
+
+        self.connected_ssid = Some(ssid.to_string());
+        self.connected_at = Some(Instant::now());
+
+        if self.config.monitor {
+            info!("Monitoring connectivity of '{}'...", ssid);
+            self.monitoring.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn connectivity_lost(&mut self) -> Result<()> {
+        warn!("Internet connectivity lost");
+
+        if let Some(ref ssid) = self.connected_ssid {
+            self.publish_connectivity(ssid, false);
+        }
+
+        if self.reconnect() {
+            if let Some(ref ssid) = self.connected_ssid {
+                self.publish_connectivity(ssid, true);
+            }
+
+            info!("Internet connectivity restored");
+            self.connected_at = Some(Instant::now());
+            self.monitoring.store(true, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        self.reopen_portal()
+    }
+
+    /// Deactivates the WiFi client connection and reopens the captive portal
+    fn disconnect(&mut self, checkpoint: Option<u64>, response_tx: &ResponseSender) -> Result<()> {
+        let connection = if self.portal_connection.is_none() {
+            get_device_active_connection(&self.manager, &self.device)
+        } else {
+            None
+        };
+
+        if connection.is_some() {
+            self.begin_checkpoint(checkpoint);
+        }
+
+        let disconnected = match connection {
+            Some(connection) => {
+                info!("Disconnecting from {:?}...", connection.settings().ssid);
+
+                match connection.deactivate() {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warn!("Disconnecting failed: {}", e);
+                        false
+                    },
+                }
+            },
+            None => false,
+        };
+
+        if disconnected {
+            self.monitoring.store(false, Ordering::SeqCst);
+            self.reopen_portal()?;
+        } else {
+            self.destroy_checkpoint();
+        }
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Disconnected(disconnected))
+            .chain_err(|| ErrorKind::SendDisconnected)
+    }
+
+    /// Deletes all saved WiFi client profiles and reopens the captive portal
+    /// if the device was connected through one of them
+    fn clear(&mut self, checkpoint: Option<u64>, response_tx: &ResponseSender) -> Result<()> {
+        let connected = self.portal_connection.is_none();
+        let mut cleared = 0;
+
+        if connected {
+            self.begin_checkpoint(checkpoint);
+        }
+
+        match self.manager.get_connections() {
+            Ok(connections) => for connection in connections {
+                if &connection.settings().kind != "802-11-wireless"
+                    || &connection.settings().mode == "ap"
+                {
+                    continue;
+                }
+
+                info!("Deleting WiFi connection: {:?}", connection.settings().ssid);
+
+                match connection.delete() {
+                    Ok(()) => cleared += 1,
+                    Err(e) => error!("Deleting WiFi connection failed: {}", e),
+                }
+            },
+            Err(e) => error!("Getting existing connections failed: {}", e),
+        }
+
+        if connected && cleared > 0 {
+            self.monitoring.store(false, Ordering::SeqCst);
+            self.reopen_portal()?;
+        } else {
+            self.destroy_checkpoint();
+        }
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Cleared(cleared))
+            .chain_err(|| ErrorKind::SendCleared)
+    }
+
+    /// Deletes the saved profiles of a single network. The captive portal is
+    /// reopened if the device was connected to it.
+    fn forget(&mut self, ssid: &str, response_tx: &ResponseSender) -> Result<()> {
+        let deleted = delete_connection_if_exists(&self.manager, ssid);
+
+        let was_connected = self.portal_connection.is_none()
+            && self.connected_ssid.as_ref().map(|connected| connected as &str) == Some(ssid);
+
+        if was_connected && deleted > 0 {
+            self.monitoring.store(false, Ordering::SeqCst);
+            self.reopen_portal()?;
+        }
+
+        let forgotten = Forgotten {
+            deleted: deleted,
+            saved: list_saved_profiles(),
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Forgotten(forgotten))
+            .chain_err(|| ErrorKind::SendForgotten)
+    }
+
+    fn list_saved(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Saved(list_saved_profiles()))
+            .chain_err(|| ErrorKind::SendSaved)
+    }
+
+    fn reopen_portal(&mut self) -> Result<()> {
+        info!("Reopening the captive portal...");
+
+        if let Some(ssid) = self.connected_ssid.take() {
+            self.hooks
+                .run(HookEvent::Disconnect, &ssid, self.device.interface());
+        }
+
+        self.connected_at = None;
+        self.activated = false;
+
+        self.open_portal()?;
+        self.onboarding.portal_opened();
+
+        self.set_state(ProvisioningState::Provisioning);
+
+        Ok(())
+    }
+
+    fn open_portal(&mut self) -> Result<()> {
+        self.refresh_access_points()?;
+        self.portal_connection = Some(create_portal(
+            self.portal_device(),
+            &self.config,
+            &self.pairing_token,
+        )?);
+        self.dhcp_dns = tolerate(
+            &self.config,
+            DhcpDns::start(&self.config, self.portal_device()),
+            "Captive portal DHCP and DNS disabled",
+        )?;
+
+        Ok(())
+    }
+
+    /// The device the captive portal runs on
+    fn portal_device(&self) -> &Device {
+        self.virtual_ap.as_ref().unwrap_or(&self.device)
+    }
+
+    /// Stops the captive portal before connecting, unless it runs on a
+    /// virtual interface and stays up for its clients to follow the attempt
+    fn stop_portal_for_connecting(&mut self) -> Result<()> {
+        if self.virtual_ap.is_some() {
+            return Ok(());
+        }
+
+        if let Some(ref connection) = self.portal_connection {
+            stop_portal(connection, &self.config)?;
+        }
+
+        self.portal_connection = None;
+        self.portal_asleep = false;
+
+        Ok(())
+    }
+
+    /// Stops the captive portal which stayed up while connecting, once its
+    /// clients had a moment to see the result
+    fn stop_portal_after_connecting(&mut self) -> Result<()> {
+        if self.virtual_ap.is_none() || self.portal_connection.is_none() {
+            return Ok(());
+        }
+
+        thread::sleep(Duration::from_secs(VIRTUAL_AP_LINGER));
+
+        if let Some(connection) = self.portal_connection.take() {
+            stop_portal(&connection, &self.config)?;
+        }
+
+        self.portal_asleep = false;
+
+        Ok(())
+    }
+
+    /// Reopens the captive portal after connecting failed, unless it stayed
+    /// up on the virtual interface
+    fn restore_portal(&mut self) -> Result<()> {
+        if self.portal_connection.is_none() {
+            self.portal_connection = Some(create_portal(
+                self.portal_device(),
+                &self.config,
+                &self.pairing_token,
+            )?);
+        }
+
+        Ok(())
+    }
+
+    fn interfaces(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let devices = match get_wifi_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                warn!("Listing WiFi devices failed: {}", e);
+                Vec::new()
+            },
+        };
+
+        let interfaces = Interfaces {
+            active: self.device.interface().to_string(),
+            devices,
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Interfaces(interfaces))
+            .chain_err(|| ErrorKind::SendInterfaces)
+    }
+
+    fn hotspot(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let hotspot = self.get_hotspot();
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Hotspot(hotspot))
+            .chain_err(|| ErrorKind::SendHotspot)
+    }
+
+    fn get_hotspot(&self) -> Option<Hotspot> {
+        if self.portal_connection.is_some() {
+            let interface = self.portal_device().interface();
+
+            let channel = match get_channel(interface) {
+                Ok(channel) => channel,
+                Err(_) if !is_wifi(&self.device) => None,
+                Err(e) => {
+                    warn!("Getting the channel of {} failed: {}", interface, e);
+                    None
+                },
+            };
+
+            Some(Hotspot {
+                ssid: get_portal_ssid(&self.config, &self.pairing_token),
+                passphrase: self.config.passphrase.clone(),
+                interface: interface.to_string(),
+                gateway: self.config.gateway,
+                channel: channel.map(|(channel, _)| channel),
+                frequency: channel.map(|(_, frequency)| frequency),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Tears down and recreates the access point and the DHCP and DNS
+    /// servers of the captive portal, e.g. when clients cannot associate
+    /// anymore, with the SSID and channel of the current configuration
+    fn restart_portal(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let started = Instant::now();
+
+        let restarted = if self.portal_connection.is_some() {
+            info!("Restarting the captive portal...");
+
+            self.stop_dhcp_dns();
+
+            if let Some(connection) = self.portal_connection.take() {
+                if let Err(e) = stop_portal(&connection, &self.config) {
+                    warn!("Stopping the captive portal failed: {}", e);
+                }
+            }
+
+            self.portal_asleep = false;
+
+            self.open_portal()?;
+
+            self.get_hotspot().map(|hotspot| PortalRestart {
+                hotspot: hotspot,
+                duration: started.elapsed().as_secs(),
+            })
+        } else {
+            None
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::PortalRestarted(restarted))
+            .chain_err(|| ErrorKind::SendPortalRestarted)
+    }
+
+    /// Reads the configuration again and applies the reloadable options,
+    /// without dropping the captive portal or the connection
+    fn reload_config(&mut self, response_tx: Option<&ResponseSender>) -> Result<()> {
+        info!("Reloading the configuration...");
+
+        let reloaded = reload_config().map(|reloaded| {
+            let reload = self.config.apply_reload(&reloaded);
+
+            set_level(self.config.log_level);
+
+            self.hooks = Hooks::new(&self.config);
+
+            if let Some(ref dhcp_dns) = self.dhcp_dns {
+                let dns_rules_changed =
+                    reload.applied.iter().any(|name| name == "portal_dns_rules");
+
+                if dns_rules_changed && !dhcp_dns.set_dns_rules(&self.config) {
+                    info!("The DNS rules apply the next time the captive portal is opened");
+                }
+            }
+
+            *self.shared_config.lock().unwrap() = self.config.clone();
+
+            info!("Configuration reloaded, applied: {:?}", reload.applied);
+
+            if !reload.restart_required.is_empty() {
+                warn!("Changed options which need a restart: {:?}", reload.restart_required);
+            }
+
+            log_event(
+                "config_reloaded",
+                json!({
+                    "applied": reload.applied,
+                    "restart_required": reload.restart_required,
+                }),
+            );
+
+            reload
+        });
+
+        if let Err(ref e) = reloaded {
+            warn!("Reloading the configuration failed: {}", e);
+        }
+
+        match response_tx {
+            Some(response_tx) => response_tx
+                .unbounded_send(NetworkCommandResponse::ConfigReloaded(reloaded))
+                .chain_err(|| ErrorKind::SendConfigReloaded),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the captive portal to another WiFi device. This is only possible
+    /// while the portal is open, as switching the device of a client
+    /// connection would drop it.
+    fn select_interface(&mut self, interface: &str, response_tx: &ResponseSender) -> Result<()> {
+        let selected = if interface == self.device.interface() {
+            Ok(())
+        } else if self.state != ProvisioningState::Provisioning
+            || self.portal_connection.is_none()
+        {
+            Err(InterfaceError::Busy)
+        } else {
+            match find_device(&self.manager, &Some(interface.to_string())) {
+                Ok(device) => self.switch_device(device)?,
+                Err(e) => match *e.kind() {
+                    ErrorKind::NotAWiFiDevice(_) => Err(InterfaceError::NotAWiFiDevice),
+                    _ => Err(InterfaceError::NotFound),
+                },
+            }
+        };
+
+        let selected = selected.map(|_| self.device.interface().to_string());
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::InterfaceSelected(selected))
+            .chain_err(|| ErrorKind::SendInterfaces)
+    }
+
+    /// Reopens the captive portal on the new device, or on the previous one
+    /// again if that fails
+    fn switch_device(
+        &mut self,
+        device: Device,
+    ) -> Result<::std::result::Result<(), InterfaceError>> {
+        info!(
+            "Moving the captive portal from {} to {}...",
+            self.device.interface(),
+            device.interface()
+        );
+
+        self.stop_dhcp_dns();
+
+        if let Some(connection) = self.portal_connection.take() {
+            stop_portal(&connection, &self.config)?;
+        }
+
+        self.portal_asleep = false;
+
+        let previous = mem::replace(&mut self.device, device);
+
+        match self.open_portal() {
+            Ok(()) => Ok(Ok(())),
+            Err(e) => {
+                warn!(
+                    "Opening the captive portal on {} failed: {}",
+                    self.device.interface(),
+                    e
+                );
+
+                self.stop_dhcp_dns();
+
+                if let Some(connection) = self.portal_connection.take() {
+                    let _ = stop_portal_impl(&connection, &self.config);
+                }
+
+                self.device = previous;
+                self.open_portal()?;
+
+                Ok(Err(InterfaceError::Switch(e.to_string())))
+            },
+        }
+    }
+
+    /// Reports a provisioning state change to the event streams, the D-Bus
+    /// API and the MQTT broker, if enabled, and keeps it for the next run
+    fn set_state(&mut self, state: ProvisioningState) {
+        self.state = state;
+        self.save_run_state(None);
+
+        self.p2p_enabled.store(state != ProvisioningState::Online, Ordering::SeqCst);
+
+        self.events.publish(json!({ "state": state.as_str() }));
+
+        self.write_status_file(state.as_str());
+
+        systemd::notify(&format!("STATUS={}", state.as_str()));
+
+        if let Some(ref state_tx) = self.state_tx {
+            let _ = state_tx.send(state);
+        }
+
+        if let Some(ref mqtt) = self.mqtt {
+            mqtt.publish("state", &json!({ "state": state.as_str() }), true);
+        }
+    }
+
+    /// Keeps the provisioning state for the next run, with the reason the run
+    /// was stopped for once it is
+    fn save_run_state(&self, ended: Option<&str>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        let run = json!({
+            "state": self.state.as_str(),
+            "ended": ended,
+            "timestamp": timestamp,
+        });
+
+        if let Err(e) = self.state_store.save(RUN_DOCUMENT, &run) {
+            warn!("Saving run state failed: {}", e);
+        }
+    }
+
+    /// Replaces the status file, if enabled, for other services to read
+    fn write_status_file(&self, state: &str) {
+        let path = match self.config.status_file {
+            Some(ref path) => path,
+            None => return,
+        };
+
+        let ssid = if state == ProvisioningState::Online.as_str() {
+            get_device_active_connection(&self.manager, &self.device)
+                .map(|connection| {
+                    String::from_utf8_lossy(connection.settings().ssid.as_bytes()).into_owned()
+                })
+        } else {
+            None
+        };
+
+        let ip = get_ipv4_addresses(self.device.interface())
+            .ok()
+            .and_then(|addresses| addresses.first().map(|&(address, _)| address.to_string()));
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+
+        let status = json!({
+            "state": state,
+            "ssid": ssid,
+            "ip": ip,
+            "timestamp": timestamp,
+        });
+
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        if let Err(e) = write_json_atomically(path, &status) {
+            warn!("Writing status file {} failed: {}", path.display(), e);
+        }
+    }
+
+    fn publish_connectivity(&self, ssid: &str, connectivity: bool) {
+        let had_connectivity = self.events.get("connectivity") == Some(Value::Bool(true));
+
+        if connectivity && !had_connectivity {
+            self.events.emit("connectivity_gained", json!({ "ssid": ssid }));
+        } else if !connectivity && had_connectivity {
+            self.events.emit("connectivity_lost", json!({ "ssid": ssid }));
+        }
+
+        self.events.publish(json!({
+            "ssid": ssid,
+            "connectivity": connectivity,
+        }));
+
+        if let Some(ref mqtt) = self.mqtt {
+            let message = json!({
+                "ssid": ssid,
+                "connectivity": connectivity,
+            });
+
+            mqtt.publish("connectivity", &message, false);
+        }
+    }
+
+    fn reconnect(&self) -> bool {
+        let ssid = match self.connected_ssid {
+            Some(ref ssid) => ssid,
+            None => return false,
+        };
+
+        info!("Reconnecting to access point '{}'...", ssid);
+
+        let visible_bssids = get_visible_scan_entries(&self.device, ssid)
+            .into_iter()
+            .map(|entry| entry.bssid)
+            .collect::<Vec<_>>();
+
+        if !self.is_access_point_trusted(ssid, &visible_bssids) {
+            return false;
+        }
+
+        let connection = match find_connection(&self.manager, ssid) {
+            Ok(Some(connection)) => connection,
+            Ok(None) => {
+                warn!("Connection profile of '{}' not found", ssid);
+                return false;
+            },
+            Err(e) => {
+                error!("Getting existing connections failed: {}", e);
+                return false;
+            },
+        };
+
+        match connection.activate() {
+            Ok(ConnectionState::Activated) => {},
+            Ok(state) => {
+                warn!("Connection to '{}' not activated: {:?}", ssid, state);
+                return false;
+            },
+            Err(e) => {
+                warn!("Error reconnecting to '{}': {}", ssid, e);
+                return false;
+            },
+        }
+
+        // Unlike connecting on request, reconnecting does not pin new BSSIDs
+        if let Some(bssid) = get_associated_bssid(&self.device) {
+            if !self.is_associated_bssid_trusted(ssid, &bssid) {
+                let _ = connection.deactivate();
+                return false;
+            }
+        }
+
+        match wait_for_connectivity(&self.manager, &self.device, &self.config, 20) {
+            Ok(has_connectivity) => has_connectivity,
+            Err(e) => {
+                error!("Getting Internet connectivity failed: {}", e);
+                false
+            },
+        }
+    }
+
+    fn stop_dhcp_dns(&mut self) {
+        if let Some(mut dhcp_dns) = self.dhcp_dns.take() {
+            dhcp_dns.stop();
+        }
+    }
+
+    fn activate(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        self.mark_activated();
+
+        let access_points = get_access_point_infos(&self.device, &self.access_points);
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::AccessPoints(access_points))
+            .chain_err(|| ErrorKind::SendAccessPointSSIDs)
+    }
+
+    /// A client of the captive portal asked for the access points
+    fn mark_activated(&mut self) {
+        if !self.activated {
+            self.metrics.portal_session(&self.state_store);
+            self.onboarding.client_request();
+        }
+
+        self.activated = true;
+    }
+
+    fn scan(&mut self, max_age: Option<u64>, response_tx: Option<&ResponseSender>) -> Result<()> {
+        self.mark_activated();
+
+        let age = SystemTime::now()
+            .duration_since(self.access_points_scanned_at)
+            .unwrap_or_default();
+
+        let rescanned = match max_age {
+            Some(max_age) if age < Duration::from_secs(max_age) => false,
+            _ => self.rescan_access_points(),
+        };
+
+        let response_tx = match response_tx {
+            Some(response_tx) => response_tx,
+            None => return Ok(()),
+        };
+
+        let scan_results = ScanResults {
+            access_points: get_access_point_infos(&self.device, &self.access_points),
+            scanned_at: self.access_points_scanned_at,
+            rescanned: rescanned,
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::ScanResults(scan_results))
+            .chain_err(|| ErrorKind::SendScanResults)
+    }
+
+    /// Keeps the access points of the previous scan if scanning fails, e.g.
+    /// as some drivers refuse to scan while the portal is open
+    fn rescan_access_points(&mut self) -> bool {
+        if !is_wifi(&self.device) {
+            return false;
+        }
+
+        info!("Rescanning the access points...");
+
+        if let Err(e) = rescan(self.device.interface(), &self.config.hidden_ssids) {
+            warn!("Rescanning failed: {}", e);
+            return false;
+        }
+
+        match get_access_points(&self.device, &self.config) {
+            Ok(access_points) => {
+                self.access_points = access_points;
+                self.access_points_scanned_at = SystemTime::now();
+
+                self.events.emit(
+                    "scan_complete",
+                    json!({ "access_points": self.access_points.len() }),
+                );
+
+                true
+            },
+            Err(e) => {
+                warn!("Getting the rescanned access points failed: {}", e);
+                false
+            },
+        }
+    }
+
+    fn refresh_access_points(&mut self) -> Result<()> {
+        self.access_points = get_access_points(&self.device, &self.config)?;
+        self.access_points_scanned_at = SystemTime::now();
+
+        Ok(())
+    }
+
+    fn status(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let status = Status {
+            activated: self.activated,
+            portal_active: self.portal_connection.is_some(),
+            metrics: self.metrics.clone(),
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Status(status))
+            .chain_err(|| ErrorKind::SendStatus)
+    }
+
+    /// Enables power save of the interface while no client is associated with
+    /// the captive portal. The HTTP server needs no suspending, as it only
+    /// wakes up for connections.
+    fn portal_sleep(&mut self) {
+        if self.portal_connection.is_none() || self.portal_asleep {
+            return;
+        }
+
+        info!("No clients for a while - putting the captive portal to sleep");
+
+        if let Err(e) = set_power_save(self.portal_device().interface(), true) {
+            warn!("Enabling power save failed: {}", e);
+        }
+
+        self.portal_asleep = true;
+    }
+
+    fn portal_wake(&mut self) {
+        if !self.portal_asleep {
+            return;
+        }
+
+        self.portal_asleep = false;
+
+        if self.portal_connection.is_none() {
+            return;
+        }
+
+        info!("Client associated - waking the captive portal up");
+
+        if !self.config.portal_power_save {
+            if let Err(e) = set_power_save(self.portal_device().interface(), false) {
+                warn!("Disabling power save failed: {}", e);
+            }
+        }
+    }
+
+    /// Failing to answer is not an error, since the server stops waiting for
+    /// the health after a timeout
+    fn health(&mut self, response_tx: &ResponseSender) {
+        let dhcp_dns_running = match self.dhcp_dns {
+            Some(ref mut dhcp_dns) => dhcp_dns.is_running(),
+            None => false,
+        };
+
+        let health = Health {
+            interface: self.device.interface().to_string(),
+            portal_active: self.portal_connection.is_some(),
+            dhcp_dns_running: dhcp_dns_running,
+        };
+
+        let _ = response_tx.unbounded_send(NetworkCommandResponse::Health(health));
+    }
+
+    fn connection(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let details = match (&self.connected_ssid, self.connected_at) {
+            (&Some(ref ssid), Some(connected_at)) => {
+                Some(get_connection_details(&self.device, ssid, connected_at))
+            },
+            _ => None,
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Connection(details))
+            .chain_err(|| ErrorKind::SendConnection)
+    }
+
+    /// Re-reads the access point of the connection from the WiFi device, so
+    /// that installers may position antennas by polling
+    fn signal(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let signal = match self.connected_ssid {
+            Some(ref ssid) => {
+                let wifi_device = self.device.as_wifi_device().unwrap();
+
+                let strength = match wifi_device.get_access_points() {
+                    Ok(access_points) => access_points
+                        .iter()
+                        .filter(|ap| ap.ssid().as_str().ok() == Some(ssid as &str))
+                        .map(|ap| ap.strength)
+                        .max(),
+                    Err(e) => {
+                        warn!("Getting access points failed: {}", e);
+                        None
+                    },
+                };
+
+                let rssi = match get_link_signal(self.device.interface()) {
+                    Ok(rssi) => rssi,
+                    Err(e) => {
+                        warn!("Getting link signal failed: {}", e);
+                        None
+                    },
+                };
+
+                Some(Signal {
+                    ssid: ssid.clone(),
+                    strength: strength,
+                    rssi: rssi,
+                })
+            },
+            None => None,
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Signal(signal))
+            .chain_err(|| ErrorKind::SendSignal)
+    }
+
+    fn ethernet(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let status = match self.ethernet_device {
+            Some(ref device) => Ok(get_ethernet_status(device)),
+            None => Err(EthernetError::NotManaged),
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Ethernet(status))
+            .chain_err(|| ErrorKind::SendEthernet)
+    }
+
+    fn configure_ethernet(
+        &mut self,
+        config: &Ipv4Settings,
+        response_tx: &ResponseSender,
+    ) -> Result<()> {
+        let status = match self.ethernet_device {
+            Some(ref device) => match configure_ethernet(device, config) {
+                Ok(()) => Ok(get_ethernet_status(device)),
+                Err(e) => {
+                    warn!("Configuring {} failed: {}", device.interface(), e);
+                    Err(EthernetError::Configure(e.to_string()))
+                },
+            },
+            None => Err(EthernetError::NotManaged),
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Ethernet(status))
+            .chain_err(|| ErrorKind::SendEthernet)
+    }
+
+    fn export_profiles(&mut self, key: Option<String>, response_tx: &ResponseSender) -> Result<()> {
+        info!("Exporting WiFi connection profiles");
+
+        let key = key.as_ref().map(|key| key as &str);
+
+        let result = export_profiles(key).map_err(to_backup_error);
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Backup(result))
+            .chain_err(|| ErrorKind::SendBackup)
+    }
+
+    fn import_profiles(
+        &mut self,
+        backup: Value,
+        key: Option<String>,
+        response_tx: &ResponseSender,
+    ) -> Result<()> {
+        let key = key.as_ref().map(|key| key as &str);
+
+        let result = self.import_profiles_impl(backup, key)
+            .map(|imported| json!({ "imported": imported }))
+            .map_err(to_backup_error);
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Backup(result))
+            .chain_err(|| ErrorKind::SendBackup)
+    }
+
+    /// Recreates the profiles of a backup, replacing existing profiles of the
+    /// same SSIDs
+    fn import_profiles_impl(&mut self, backup: Value, key: Option<&str>) -> Result<Vec<String>> {
+        let profiles = parse_backup(backup, key)?;
+
+        let mut imported = Vec::new();
+
+        for profile in profiles {
+            info!("Importing WiFi connection profile '{}'", profile.ssid);
+
+            delete_connection_if_exists(&self.manager, &profile.ssid);
+
+            add_wifi_profile(&profile)?;
+
+            imported.push(profile.ssid);
+        }
+
+        Ok(imported)
+    }
+
+    fn apply_advanced(
+        &mut self,
+        change: &AdvancedChange,
+        response_tx: &ResponseSender,
+    ) -> Result<()> {
+        let result = match get_device_active_connection(&self.manager, &self.device) {
+            Some(connection) => self.apply_advanced_impl(&connection, change)
+                .map(|()| self.config.confirm_timeout)
+                .map_err(|e| {
+                    warn!("Applying advanced settings failed: {}", e);
+                    AdvancedError::Failed(e.to_string())
+                }),
+            None => Err(AdvancedError::NotConnected),
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Advanced(result))
+            .chain_err(|| ErrorKind::SendAdvanced)
+    }
+
+    /// Applies a change and arms the rollback timer. The settings from before
+    /// the first unconfirmed change are the ones restored.
+    fn apply_advanced_impl(
+        &mut self,
+        connection: &Connection,
+        change: &AdvancedChange,
+    ) -> Result<()> {
+        let uuid = connection.settings().uuid.clone();
+
+        let previous = match self.pending_rollback.take() {
+            Some(ref pending) if pending.uuid == uuid => pending.settings.clone(),
+            _ => get_connection_settings(&uuid, ROLLBACK_SETTINGS)?,
+        };
+
+        self.rollback_id += 1;
+
+        self.pending_rollback = Some(PendingRollback {
+            id: self.rollback_id,
+            uuid: uuid.clone(),
+            settings: previous,
+        });
+
+        self.spawn_rollback_timer(self.rollback_id, self.config.confirm_timeout);
+
+        info!(
+            "Applying advanced settings - confirm within {}s",
+            self.config.confirm_timeout
+        );
+
+        modify_connection(&uuid, &get_advanced_settings(change))
+    }
+
+    fn spawn_rollback_timer(&self, id: u64, timeout: u64) {
+        let network_tx = self.network_tx.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_secs(timeout));
+
+            if let Err(err) = network_tx.send(NetworkCommand::RollbackTimeout { id: id }) {
+                error!(
+                    "Sending NetworkCommand::RollbackTimeout failed: {}",
+                    err.description()
+                );
+            }
+        });
+    }
+
+    fn confirm(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let mut confirmed = false;
+
+        if self.pending_rollback.take().is_some() {
+            info!("Advanced settings confirmed");
+            confirmed = true;
+        }
+
+        if self.pending_checkpoint.is_some() {
+            info!("Network changes confirmed");
+            self.destroy_checkpoint();
+            confirmed = true;
+        }
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Confirmed(confirmed))
+            .chain_err(|| ErrorKind::SendAdvanced)
+    }
+
+    fn rollback_timeout(&mut self, id: u64) -> Result<()> {
+        let is_checkpoint = match self.pending_checkpoint {
+            Some(ref pending) => pending.id == id,
+            None => false,
+        };
+
+        if is_checkpoint {
+            return self.roll_back_checkpoint();
+        }
+
+        // Timers of changes that have been confirmed or superseded are stale
+        match self.pending_rollback {
+            Some(ref pending) if pending.id == id => {},
+            _ => return Ok(()),
+        }
+
+        let pending = self.pending_rollback.take().unwrap();
+
+        warn!("Advanced settings not confirmed - restoring previous settings");
+
+        if let Err(e) = modify_connection(&pending.uuid, &pending.settings) {
+            error!("Restoring previous settings failed: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// Takes a NetworkManager checkpoint of the WiFi device before a change
+    /// which may strand a connected device. Changes made from the captive
+    /// portal fall back to the portal instead. The state from before the
+    /// first unconfirmed change is the one restored.
+    fn begin_checkpoint(&mut self, timeout: Option<u64>) {
+        let timeout = timeout.unwrap_or(self.config.checkpoint_timeout);
+
+        if timeout == 0 || self.portal_connection.is_some() || !is_wifi(&self.device) {
+            return;
+        }
+
+        self.rollback_id += 1;
+        let id = self.rollback_id;
+
+        if let Some(ref mut pending) = self.pending_checkpoint {
+            pending.id = id;
+
+            if let Err(e) = pending.checkpoint.adjust(timeout + CHECKPOINT_GRACE) {
+                warn!("Extending the checkpoint failed: {}", e);
+            }
+        } else {
+            match Checkpoint::create(self.device.interface(), timeout + CHECKPOINT_GRACE) {
+                Ok(checkpoint) => {
+                    self.pending_checkpoint = Some(PendingCheckpoint {
+                        id: id,
+                        checkpoint: checkpoint,
+                    })
+                },
+                Err(e) => {
+                    warn!("Creating a checkpoint failed: {}", e);
+                    return;
+                },
+            }
+        }
+
+        info!("Network checkpoint taken - confirm within {}s", timeout);
+
+        self.spawn_rollback_timer(id, timeout);
+    }
+
+    /// Keeps the changes made since the checkpoint
+    fn destroy_checkpoint(&mut self) {
+        if let Some(pending) = self.pending_checkpoint.take() {
+            if let Err(e) = pending.checkpoint.destroy() {
+                warn!("Destroying the checkpoint failed: {}", e);
+            }
+        }
+    }
+
+    /// Restores the connection and profiles of the checkpoint, and resumes
+    /// monitoring it, or opens the captive portal if none is active after
+    fn roll_back_checkpoint(&mut self) -> Result<()> {
+        let pending = match self.pending_checkpoint.take() {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+
+        warn!("Network changes not confirmed - rolling back to the checkpoint");
+
+        self.monitoring.store(false, Ordering::SeqCst);
+        self.stop_dhcp_dns();
+
+        if let Some(connection) = self.portal_connection.take() {
+            stop_portal(&connection, &self.config)?;
+        }
+
+        self.portal_asleep = false;
+
+        if let Err(e) = pending.checkpoint.roll_back() {
+            error!("Rolling back to the checkpoint failed: {}", e);
+        }
+
+        let ssid = get_device_active_connection(&self.manager, &self.device).and_then(
+            |connection| {
+                connection
+                    .settings()
+                    .ssid
+                    .as_str()
+                    .ok()
+                    .map(String::from)
+            },
+        );
+
+        match ssid {
+            Some(ssid) => {
+                info!("Restored the connection to '{}'", ssid);
+
+                self.set_state(ProvisioningState::Online);
+
+                if self.connected_ssid.as_ref() != Some(&ssid) {
+                    self.hooks
+                        .run(HookEvent::Connect, &ssid, self.device.interface());
+                }
+
+                self.stay_connected(&ssid);
+
+                Ok(())
+            },
+            None => self.reopen_portal(),
+        }
+    }
+
+    fn blacklist_bssid(&mut self, bssid: String, response_tx: &ResponseSender) -> Result<()> {
+        info!("Blacklisting BSSID {}", bssid);
+
+        self.bssid_blacklist.insert(bssid);
+
+        let blacklist = self.bssid_blacklist.iter().cloned().collect();
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::BssidBlacklist(blacklist))
+            .chain_err(|| ErrorKind::SendBssidBlacklist)
+    }
+
+    fn connect(
+        &mut self,
+        ssid: &str,
+        passphrase: &str,
+        dns_servers: Option<Vec<Ipv4Addr>>,
+        reuse: bool,
+        selection: &ApSelection,
+    ) -> Result<bool> {
+        self.metrics.connect_attempt(&self.state_store);
+        self.onboarding.credentials_submitted();
+        self.connect_error = None;
+
+        let started = Instant::now();
+
+        // Malformed SSIDs are refused before touching the portal, as
+        // NetworkManager rejects them with obscure D-Bus errors
+        if let Err(reason) = match selection.raw_ssid {
+            Some(ref raw_ssid) => validate_raw_ssid(raw_ssid),
+            None => validate_ssid(ssid),
+        } {
+            warn!("Refusing to connect to {:?}: {}", ssid, reason);
+            self.metrics.connect_failure(&self.state_store, "invalid_ssid");
+            return Ok(false);
+        }
+
+        if !is_wifi(&self.device) {
+            return Ok(self.save_network(ssid, passphrase));
+        }
+
+        let previous_connection = self.get_rollback_connection(ssid);
+
+        let dns_servers = dns_servers.unwrap_or_else(|| self.config.dns_servers.clone());
+
+        // A profile locked to an access point is always created anew
+        let existing_connection = if (reuse || self.config.preserve_existing_profiles)
+            && selection.is_empty()
+        {
+            find_connection(&self.manager, ssid)?
+        } else {
+            None
+        };
+
+        if existing_connection.is_none() {
+            delete_connection_if_exists(&self.manager, ssid);
+        }
+
+        self.stop_portal_for_connecting()?;
+
+        self.refresh_access_points()?;
+
+        let max_attempts = cmp::max(self.config.connect_attempts, 1);
+        let mut backoff = self.config.connect_backoff;
+
+        for attempt in 1..max_attempts + 1 {
+            if attempt > 1 {
+                info!(
+                    "Retrying connection to '{}' in {}s ({}/{})",
+                    ssid, backoff, attempt, max_attempts
+                );
+
+                self.set_connect_progress(ssid, "retrying", attempt, max_attempts, Some(backoff));
+
+                thread::sleep(Duration::from_secs(backoff));
+                backoff = cmp::min(backoff.saturating_mul(2), MAX_CONNECT_BACKOFF);
+
+                self.refresh_access_points()?;
+            }
+
+            self.set_connect_progress(ssid, "connecting", attempt, max_attempts, None);
+
+            let result = self.connect_attempt(
+                ssid,
+                passphrase,
+                &dns_servers,
+                &previous_connection,
+                &existing_connection,
+                selection,
+            )?;
+
+            if let Some(connected) = result {
+                let progress = if connected { "connected" } else { "failed" };
+                self.set_connect_progress(ssid, progress, attempt, max_attempts, None);
+
+                let milestone = if connected {
+                    Milestone::Connected(ssid)
+                } else {
+                    Milestone::ConnectFailed(ssid)
+                };
+                info!("{}", milestone.localize(self.config.locale));
+
+                log_event(
+                    "connect",
+                    json!({
+                        "ssid": ssid,
+                        "result": progress,
+                        "duration": started.elapsed().as_secs(),
+                    }),
+                );
+
+                return Ok(connected);
+            }
+        }
+
+        self.set_connect_progress(ssid, "failed", max_attempts, max_attempts, None);
+
+        info!("{}", Milestone::ConnectFailed(ssid).localize(self.config.locale));
+
+        log_event(
+            "connect",
+            json!({
+                "ssid": ssid,
+                "result": "failed",
+                "duration": started.elapsed().as_secs(),
+            }),
+        );
+
+        if let Some(ref previous) = previous_connection {
+            roll_back(previous);
+            return Ok(false);
+        }
+
+        self.refresh_access_points()?;
+        self.restore_portal()?;
+
+        Ok(false)
+    }
+
+    /// Connects to a network to test its credentials. Unless the connection is
+    /// kept, the profile is deleted afterwards and the portal reopened, with
+    /// the portal down in between unless it runs on a virtual interface.
+    /// Returns whether the connection was kept.
+    fn verify(
+        &mut self,
+        ssid: &str,
+        passphrase: &str,
+        keep: bool,
+        response_tx: &ResponseSender,
+    ) -> Result<bool> {
+        let started = Instant::now();
+
+        let result = if !is_wifi(&self.device) {
+            Err(VerifyError::NotAWiFiDevice)
+        } else if self.state != ProvisioningState::Provisioning
+            || self.portal_connection.is_none()
+        {
+            Err(VerifyError::Busy)
+        } else if find_connection(&self.manager, ssid)?.is_some() {
+            Err(VerifyError::AlreadySaved)
+        } else {
+            info!("Verifying the credentials of '{}'...", ssid);
+
+            self.set_state(ProvisioningState::Connecting);
+
+            let verified = self.connect(ssid, passphrase, None, false, &ApSelection::default())?;
+            let kept = verified && keep;
+
+            if verified && !keep {
+                info!("Verified '{}' - deleting the profile", ssid);
+
+                delete_connection_if_exists(&self.manager, ssid);
+
+                self.refresh_access_points()?;
+                self.restore_portal()?;
+            }
+
+            self.set_state(if kept {
+                ProvisioningState::Online
+            } else {
+                ProvisioningState::Provisioning
+            });
+
+            Ok(Verification {
+                verified: verified,
+                kept: kept,
+                error: if verified { None } else { self.connect_error },
+                duration: started.elapsed().as_secs(),
+            })
+        };
+
+        let kept = match result {
+            Ok(ref verification) => verification.kept,
+            Err(_) => false,
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::Verified(result))
+            .chain_err(|| ErrorKind::SendVerified)?;
+
+        Ok(kept)
+    }
+
+    /// Restarts NetworkManager, e.g. when it got wedged, and acquires the
+    /// device again. The captive portal is torn down before and reopened
+    /// after, while a client connection is left to NetworkManager to
+    /// autoconnect.
+    fn restart_network_manager(&mut self, response_tx: &ResponseSender) -> Result<()> {
+        let started = Instant::now();
+        let portal_was_active = self.portal_connection.is_some();
+
+        warn!("Restarting NetworkManager...");
+
+        self.monitoring.store(false, Ordering::SeqCst);
+        self.stop_dhcp_dns();
+
+        if let Some(connection) = self.portal_connection.take() {
+            if let Err(e) = stop_portal(&connection, &self.config) {
+                warn!("Stopping the captive portal failed: {}", e);
+            }
+        }
+
+        self.portal_asleep = false;
+
+        let result = self.restart_network_manager_impl().map_err(|e| {
+            error!("Restarting NetworkManager failed: {}", e);
+            e.to_string()
+        });
+
+        if result.is_ok() {
+            info!("NetworkManager restarted");
+        }
+
+        // The portal is reopened even if the scan failed, and tried on the
+        // previous device if NetworkManager did not come back properly
+        if portal_was_active {
+            self.open_portal()?;
+            self.set_state(ProvisioningState::Provisioning);
+        } else if self.config.monitor {
+            self.monitoring.store(true, Ordering::SeqCst);
+        }
+
+        let result = result.map(|access_points| NetworkManagerRestart {
+            interface: self.device.interface().to_string(),
+            access_points: access_points,
+            portal_active: self.portal_connection.is_some(),
+            duration: started.elapsed().as_secs(),
+        });
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::NetworkManagerRestarted(result))
+            .chain_err(|| ErrorKind::SendNetworkManagerRestarted)
+    }
+
+    /// Returns the number of access points of the scan after the restart
+    fn restart_network_manager_impl(&mut self) -> Result<usize> {
+        let state = NetworkManager::stop_service(NM_SERVICE_TIMEOUT)
+            .chain_err(|| ErrorKind::StopNetworkManager)?;
+
+        if state != ServiceState::Inactive {
+            bail!(ErrorKind::StopNetworkManager);
+        }
+
+        start_network_manager_service()?;
+
+        // The D-Bus object paths of the devices change with the restart
+        self.manager = NetworkManager::new();
+
+        let interface = Some(self.device.interface().to_string());
+        let mut attempt = 1;
+
+        self.device = loop {
+            let device = if is_wifi(&self.device) {
+                find_device(&self.manager, &interface)
+            } else {
+                find_ethernet_device(&self.manager, &interface)
+            };
+
+            match device {
+                Ok(device) => break device,
+                Err(e) => if attempt == NM_RESTART_DEVICE_ATTEMPTS {
+                    return Err(e);
+                },
+            }
+
+            attempt += 1;
+            thread::sleep(Duration::from_secs(1));
+        };
+
+        if let Some(virtual_ap) = self.virtual_ap.take() {
+            let virtual_interface = Some(virtual_ap.interface().to_string());
+
+            match find_device(&self.manager, &virtual_interface) {
+                Ok(virtual_ap) => self.virtual_ap = Some(virtual_ap),
+                Err(e) => warn!("Finding the virtual access point failed: {}", e),
+            }
+        }
+
+        if let Some(ethernet_device) = self.ethernet_device.take() {
+            let ethernet_interface = Some(ethernet_device.interface().to_string());
+
+            match find_ethernet_device(&self.manager, &ethernet_interface) {
+                Ok(ethernet_device) => self.ethernet_device = Some(ethernet_device),
+                Err(e) => warn!("Finding the Ethernet device failed: {}", e),
+            }
+        }
+
+        self.refresh_access_points()?;
+
+        Ok(self.access_points.len())
+    }
+
+    /// Tries the saved networks in range one after another, by autoconnect
+    /// priority and then by the signal strength of the last scan, until one
+    /// gets Internet connectivity. The captive portal is reopened if none
+    /// does. Returns the SSID of the connected network.
+    fn connect_any(&mut self, response_tx: &ResponseSender) -> Result<Option<String>> {
+        let result = if !is_wifi(&self.device) {
+            Err(ConnectAnyError::NotAWiFiDevice)
+        } else if self.state != ProvisioningState::Provisioning
+            || self.portal_connection.is_none()
+        {
+            Err(ConnectAnyError::Busy)
+        } else {
+            let candidates = self.get_connect_any_candidates();
+
+            if candidates.is_empty() {
+                Err(ConnectAnyError::NoCandidates)
+            } else {
+                self.set_state(ProvisioningState::Connecting);
+                self.onboarding.credentials_submitted();
+
+                let connected = self.try_saved_networks(&candidates)?;
+
+                self.set_state(if connected.ssid.is_some() {
+                    ProvisioningState::Online
+                } else {
+                    ProvisioningState::Provisioning
+                });
+
+                Ok(connected)
+            }
+        };
+
+        let ssid = match result {
+            Ok(ref connected) => connected.ssid.clone(),
+            Err(_) => None,
+        };
+
+        response_tx
+            .unbounded_send(NetworkCommandResponse::ConnectedAny(result))
+            .chain_err(|| ErrorKind::SendConnectedAny)?;
+
+        Ok(ssid)
+    }
+
+    /// The SSIDs of the saved networks seen by the last scan, best first
+    fn get_connect_any_candidates(&self) -> Vec<String> {
+        let mut candidates = list_saved_profiles()
+            .into_iter()
+            .filter_map(|profile| {
+                find_access_point(&self.access_points, &profile.ssid)
+                    .map(|access_point| (profile.priority, access_point.strength, profile.ssid))
+            })
+            .collect::<Vec<_>>();
+
+        candidates.sort_by(|a, b| (b.0, b.1).cmp(&(a.0, a.1)));
+
+        // Several profiles of a network are tried once, by the best of them
+        let mut ssids: Vec<String> = Vec::new();
+
+        for (_, _, ssid) in candidates {
+            if !ssids.contains(&ssid) {
+                ssids.push(ssid);
+            }
+        }
+
+        ssids
+    }
+
+    fn try_saved_networks(&mut self, candidates: &[String]) -> Result<ConnectedAny> {
+        self.metrics.connect_attempt(&self.state_store);
+        self.connect_error = None;
+
+        self.stop_portal_for_connecting()?;
+
+        let total = candidates.len() as u32;
+        let mut attempts = Vec::new();
+
+        for (index, ssid) in candidates.iter().enumerate() {
+            let attempt = index as u32 + 1;
+            let started = Instant::now();
+
+            info!("Trying saved network '{}' ({}/{})", ssid, attempt, total);
+
+            self.set_connect_progress(ssid, "connecting", attempt, total, None);
+
+            let result = self.try_saved_network(ssid)?;
+
+            log_event(
+                "connect",
+                json!({
+                    "ssid": ssid,
+                    "result": result,
+                    "duration": started.elapsed().as_secs(),
+                }),
+            );
+
+            attempts.push((ssid.clone(), result));
+
+            if result == "connected" {
+                self.set_connect_progress(ssid, "connected", attempt, total, None);
+                info!("{}", Milestone::Connected(ssid).localize(self.config.locale));
+
+                self.stop_portal_after_connecting()?;
+
+                return Ok(ConnectedAny {
+                    ssid: Some(ssid.clone()),
+                    attempts: attempts,
+                });
+            }
+        }
+
+        warn!("None of the saved networks got connected");
+
+        self.connect_failed("no_saved_network", "NO_SAVED_NETWORK_CONNECTED");
+
+        if let Some(ssid) = candidates.last() {
+            self.set_connect_progress(ssid, "failed", total, total, None);
+        }
+
+        self.refresh_access_points()?;
+        self.restore_portal()?;
+
+        Ok(ConnectedAny {
+            ssid: None,
+            attempts: attempts,
+        })
+    }
+
+    /// Activates the saved profile of a network and waits for connectivity
+    /// for the short connect any timeout. Returns the outcome.
+    fn try_saved_network(&mut self, ssid: &str) -> Result<&'static str> {
+        let connection = match find_connection(&self.manager, ssid)? {
+            Some(connection) => connection,
+            None => return Ok("not_found"),
+        };
+
+        match connection.activate() {
+            Ok(ConnectionState::Activated) => {},
+            Ok(state) => {
+                warn!("Activating '{}' failed: {:?}", ssid, state);
+                return Ok("not_activated");
+            },
+            Err(e) => {
+                warn!("Activating '{}' failed: {}", ssid, e);
+                return Ok("not_activated");
+            },
+        }
+
+        let has_connectivity = match wait_for_connectivity(
+            &self.manager,
+            &self.device,
+            &self.config,
+            self.config.connect_any_timeout,
+        ) {
+            Ok(has_connectivity) => has_connectivity,
+            Err(e) => {
+                error!("Getting Internet connectivity failed: {}", e);
+                false
+            },
+        };
+
+        self.publish_connectivity(ssid, has_connectivity);
+
+        if has_connectivity {
+            return Ok("connected");
+        }
+
+        warn!("No Internet connectivity on '{}'", ssid);
+
+        if let Err(e) = connection.deactivate() {
+            warn!("Deactivating '{}' failed: {}", ssid, e);
+        }
+
+        Ok("no_connectivity")
+    }
+
+    /// Saves a network entered in the wired portal for any WiFi device, which
+    /// NetworkManager joins once one shows up
+    fn save_network(&mut self, ssid: &str, passphrase: &str) -> bool {
+        info!("No WiFi device - saving '{}' for later", ssid);
+
+        delete_connection_if_exists(&self.manager, ssid);
+
+        let profile = WifiProfile {
+            ssid: ssid.to_string(),
+            key_management: if passphrase.is_empty() {
+                String::new()
+            } else {
+                KeyManagement::WpaPsk.as_str().to_string()
+            },
+            passphrase: if passphrase.is_empty() {
+                None
+            } else {
+                Some(passphrase.to_string())
+            },
+            ipv4_method: "auto".to_string(),
+            ipv4_addresses: Vec::new(),
+            ipv4_gateway: None,
+            ipv4_dns: Vec::new(),
+        };
+
+        match add_wifi_profile(&profile) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Saving '{}' failed: {}", ssid, e);
+                self.metrics.connect_failure(&self.state_store, "save_failed");
+                false
+            },
+        }
+    }
+
+    /// Makes a single connection attempt. Returns `None` if the attempt
+    /// failed and may be retried, or whether the device got connected
+    /// otherwise.
+    fn connect_attempt(
+        &mut self,
+        ssid: &str,
+        passphrase: &str,
+        dns_servers: &[Ipv4Addr],
+        previous_connection: &Option<Connection>,
+        existing_connection: &Option<Connection>,
+        selection: &ApSelection,
+    ) -> Result<Option<bool>> {
+        let scan_entries = get_visible_scan_entries(&self.device, ssid);
+        let visible_bssids = scan_entries
+            .iter()
+            .map(|entry| entry.bssid.clone())
+            .collect::<Vec<_>>();
+
+        if !self.is_access_point_trusted(ssid, &visible_bssids) {
+            self.connect_failed("untrusted_bssid", "UNTRUSTED_BSSID");
+        } else if let Some(access_point) = match selection.raw_ssid {
+            Some(ref raw_ssid) => find_access_point_by_bytes(&self.access_points, raw_ssid),
+            None => find_access_point(&self.access_points, ssid),
+        } {
+            info!("{}", Milestone::Connecting(ssid).localize(self.config.locale));
+
+            let result = match *existing_connection {
+                Some(ref connection) => reactivate_connection(connection, passphrase),
+                None => self.connect_access_point(
+                    access_point,
+                    ssid,
+                    passphrase,
+                    &scan_entries,
+                    selection,
+                ),
+            };
+
+            match result {
+                Ok((connection, state)) => {
+                    if state == ConnectionState::Activated {
+                        let bssid = get_associated_bssid(&self.device);
+
+                        let trusted = match bssid {
+                            Some(ref bssid) => self.is_associated_bssid_trusted(ssid, bssid),
+                            None => true,
+                        };
+
+                        if !trusted {
+                            self.connect_failed("untrusted_bssid", "UNTRUSTED_BSSID");
+
+                            if existing_connection.is_none() {
+                                delete_connection(&connection);
+                            } else {
+                                let _ = connection.deactivate();
+                            }
+
+                            if let Some(ref previous) = *previous_connection {
+                                roll_back(previous);
+                            }
+
+                            return Ok(Some(false));
+                        }
+
+                        if !dns_servers.is_empty() {
+                            apply_dns_servers(&connection, dns_servers);
+                        }
+
+                        check_split_horizon(&self.device, &self.config);
+
+                        let timeout = if previous_connection.is_some() {
+                            self.config.rollback_timeout
+                        } else {
+                            self.config.connect_attempt_timeout
+                        };
+
+                        match wait_for_connectivity(
+                            &self.manager,
+                            &self.device,
+                            &self.config,
+                            timeout,
+                        ) {
+                            Ok(has_connectivity) => {
+                                self.publish_connectivity(ssid, has_connectivity);
+
+                                if has_connectivity {
+                                    info!("Internet connectivity established");
+                                } else {
+                                    warn!("Cannot establish Internet connectivity");
+                                }
+
+                                if let (false, &Some(ref previous)) =
+                                    (has_connectivity, previous_connection)
+                                {
+                                    self.connect_failed("no_connectivity", "NO_CONNECTIVITY");
+
+                                    if existing_connection.is_none() {
+                                        delete_connection(&connection);
+                                    }
+
+                                    roll_back(previous);
+                                    return Ok(Some(false));
+                                }
+                            },
+                            Err(err) => error!("Getting Internet connectivity failed: {}", err),
+                        }
+
+                        if let Some(ref bssid) = bssid {
+                            self.bssid_pins.pin(&self.state_store, ssid, bssid);
+                        }
+
+                        return Ok(Some(true));
+                    }
+
+                    // NetworkManager mostly gives up on activating a secured
+                    // network because of a wrong passphrase
+                    let code = if passphrase.is_empty() {
+                        "CONNECTION_NOT_ACTIVATED"
+                    } else {
+                        "WRONG_PASSPHRASE"
+                    };
+                    self.connect_failed("not_activated", code);
+
+                    if existing_connection.is_none() {
+                        delete_connection(&connection);
+                    }
+
+                    warn!(
+                        "Connection to access point not activated '{}': {:?}",
+                        ssid, state
+                    );
+                },
+                Err(e) => {
+                    self.connect_failed("connection_error", "CONNECTION_FAILED");
+
+                    warn!("Error connecting to access point '{}': {}", ssid, e);
+
+                    // The profile the attempt may have added would otherwise
+                    // be duplicated by the next one
+                    if existing_connection.is_none() {
+                        delete_connection_if_exists(&self.manager, ssid);
+                    }
+                },
+            }
+        } else {
+            self.connect_failed("access_point_not_found", "AP_NOT_FOUND");
+        }
+
+        Ok(None)
+    }
+
+    /// Counts the failed attempt and keeps its stable code for the progress
+    /// reports
+    fn connect_failed(&mut self, reason: &str, code: &'static str) {
+        self.metrics.connect_failure(&self.state_store, reason);
+        self.connect_error = Some(code);
+    }
+
+    /// Shares the progress of the connection attempts with the server and
+    /// MQTT, as the network thread is busy until they are over
+    fn set_connect_progress(
+        &self,
+        ssid: &str,
+        state: &'static str,
+        attempt: u32,
+        max_attempts: u32,
+        retry_in: Option<u64>,
+    ) {
+        let progress = ConnectProgress {
+            ssid: ssid.to_string(),
+            state: state,
+            attempt: attempt,
+            max_attempts: max_attempts,
+            retry_in: retry_in,
+            error: if state == "failed" {
+                self.connect_error
+            } else {
+                None
+            },
+        };
+
+        self.events.publish(json!({ "connect": progress.to_json() }));
+
+        match state {
+            "connecting" if attempt == 1 => self.events.emit("connect_started", progress.to_json()),
+            "failed" => self.events.emit("connect_failed", progress.to_json()),
+            _ => {},
+        }
+
+        if let Some(ref mqtt) = self.mqtt {
+            mqtt.publish("connect", &progress.to_json(), false);
+        }
+
+        *self.connect_progress.lock().unwrap() = Some(progress);
+    }
+
+    /// In change network mode returns the connection to roll back to, should
+    /// switching to another network fail
+    fn get_rollback_connection(&self, ssid: &str) -> Option<Connection> {
+        if !self.config.change_network || self.portal_connection.is_some() {
+            return None;
+        }
+
+        let connection = get_online_connection(&self.manager, &self.device)?;
+
+        if connection.settings().ssid.as_str().ok() == Some(ssid) {
+            warn!("Reconnecting to '{}' - rollback not possible", ssid);
+            return None;
+        }
+
+        Some(connection)
+    }
+
+    /// Connects through NetworkManager, unless some of the BSSIDs of the
+    /// access point are blacklisted or it requires a key management other
+    /// than WPA-PSK. In the former case the connection is locked to the
+    /// strongest BSSID that is not blacklisted, in the latter the profile is
+    /// created with the SAE or OWE key management.
+    fn connect_access_point(
+        &self,
+        access_point: &AccessPoint,
+        ssid: &str,
+        passphrase: &str,
+        scan_entries: &[ScanEntry],
+        selection: &ApSelection,
+    ) -> Result<(Connection, ConnectionState)> {
+        // Only the access points of the requested band are candidates
+        let scan_entries = scan_entries
+            .iter()
+            .filter(|entry| {
+                selection.band.is_none() || Band::from_frequency(entry.frequency) == selection.band
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let has_blacklisted = scan_entries
+            .iter()
+            .any(|entry| self.bssid_blacklist.contains(&entry.bssid));
+
+        let key_management = get_key_management(&scan_entries);
+
+        let needs_key_management =
+            key_management == KeyManagement::Sae || key_management == KeyManagement::Owe;
+
+        if !has_blacklisted && !needs_key_management && selection.is_empty() {
+            let wifi_device = self.device.as_wifi_device().unwrap();
+            let credentials = get_credentials(access_point, passphrase);
+            return Ok(wifi_device.connect(access_point, &credentials)?);
+        }
+
+        let bssid = if let Some(ref bssid) = selection.bssid {
+            Some(bssid.clone())
+        } else if has_blacklisted {
+            match scan_entries
+                .iter()
+                .filter(|entry| !self.bssid_blacklist.contains(&entry.bssid))
+                .max_by_key(|entry| entry.signal)
+            {
+                Some(entry) => Some(entry.bssid.clone()),
+                None => bail!(ErrorKind::BssidsBlacklisted(ssid.to_string())),
+            }
+        } else {
+            None
+        };
+
+        if needs_key_management || selection.band.is_some() {
+            info!(
+                "Connecting to '{}' with {} key management{}...",
+                ssid,
+                key_management.as_str(),
+                selection
+                    .band
+                    .map_or(String::new(), |band| format!(" on band {}", band.as_str()))
+            );
+
+            connect_key_management(
+                self.device.interface(),
+                ssid,
+                selection.raw_ssid.as_ref().map(|raw_ssid| raw_ssid as &[u8]),
+                passphrase,
+                key_management,
+                bssid.as_ref().map(|bssid| bssid as &str),
+                selection.band,
+            )?;
+        } else if let Some(ref bssid) = bssid {
+            info!("Connecting to BSSID {} of '{}'...", bssid, ssid);
+
+            connect_key_management(
+                self.device.interface(),
+                ssid,
+                selection.raw_ssid.as_ref().map(|raw_ssid| raw_ssid as &[u8]),
+                passphrase,
+                key_management,
+                Some(bssid),
+                None,
+            )?;
+        }
+
+        let ssid_bytes = match selection.raw_ssid {
+            Some(ref raw_ssid) => raw_ssid as &[u8],
+            None => ssid.as_bytes(),
+        };
+
+        match find_active_connection(&self.manager, ssid_bytes)? {
+            Some(connection) => {
+                let state = connection.get_state()?;
+                Ok((connection, state))
+            },
+            None => bail!(ErrorKind::NoActiveConnection(ssid.to_string())),
+        }
+    }
+
+    fn is_access_point_trusted(&self, ssid: &str, visible_bssids: &[String]) -> bool {
+        if self.bssid_pins.is_trusted(ssid, visible_bssids) {
+            return true;
+        }
+
+        warn!(
+            "Access point '{}' is visible only from unknown BSSIDs {:?} - possible evil twin",
+            ssid, visible_bssids
+        );
+
+        self.events.emit(
+            "untrusted_bssid",
+            json!({
+                "ssid": ssid,
+                "visible": visible_bssids,
+                "pinned": self.bssid_pins.get(ssid),
+            }),
+        );
+
+        !self.config.refuse_unknown_bssids
+    }
+
+    /// Checks the BSSID the device associated with, which may be an evil twin
+    /// next to the pinned access points
+    fn is_associated_bssid_trusted(&self, ssid: &str, bssid: &str) -> bool {
+        if self.bssid_pins.is_known(ssid, bssid) {
+            return true;
+        }
+
+        warn!(
+            "Associated with unknown BSSID {} of access point '{}' - possible evil twin",
+            bssid, ssid
+        );
+
+        self.events.emit(
+            "untrusted_bssid",
+            json!({
+                "ssid": ssid,
+                "bssid": bssid,
+                "pinned": self.bssid_pins.get(ssid),
+            }),
+        );
+
+        !self.config.refuse_unknown_bssids
+    }
+}
+
+fn to_backup_error(e: ::errors::Error) -> BackupError {
+    warn!("{}", e);
+
+    match *e.kind() {
+        ErrorKind::InvalidBackup(_) => BackupError::Invalid(e.to_string()),
+        _ => BackupError::Failed(e.to_string()),
+    }
+}
+
+impl Drop for NetworkCommandHandler {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+pub fn process_network_commands(config: &Config, exit_tx: &Sender<ExitResult>) {
+    let mut command_handler = match NetworkCommandHandler::new(config, exit_tx) {
+        Ok(command_handler) => command_handler,
+        Err(e) => {
+            exit(exit_tx, e);
+            return;
+        },
+    };
+
+    command_handler.run(exit_tx);
+}
+
+/// Lists the visible access points without starting the captive portal
+pub fn scan_networks(config: &Config) -> Result<Vec<AccessPointInfo>> {
+    let manager = NetworkManager::new();
+    let device = find_device(&manager, &config.interface)?;
+
+    let access_points = get_access_points(&device, config)?;
+
+    Ok(get_access_point_infos(&device, &access_points))
+}
+
+/// Connects to an access point without starting the captive portal and
+/// returns whether Internet connectivity was established
+pub fn connect_network(config: &Config, ssid: &str, passphrase: &str, reuse: bool) -> Result<bool> {
+    if let Err(reason) = validate_ssid(ssid) {
+        bail!(ErrorKind::InvalidSsid(reason.to_string()));
+    }
+
+    let manager = NetworkManager::new();
+    let device = find_device(&manager, &config.interface)?;
+
+    if reuse || config.preserve_existing_profiles {
+        if let Some(connection) = find_connection(&manager, ssid)? {
+            let (_, state) = reactivate_connection(&connection, passphrase)?;
+
+            if state != ConnectionState::Activated {
+                bail!(ErrorKind::ConnectionNotActivated(ssid.to_string()));
+            }
+
+            return wait_for_connectivity(&manager, &device, config, config.connect_attempt_timeout);
+        }
+    }
+
+    delete_connection_if_exists(&manager, ssid);
+
+    let access_points = get_access_points(&device, config)?;
+
+    let access_point = match find_access_point(&access_points, ssid) {
+        Some(access_point) => access_point,
+        None => bail!(ErrorKind::AccessPointNotFound(ssid.to_string())),
+    };
+
+    info!("{}", Milestone::Connecting(ssid).localize(config.locale));
+
+    let scan_entries = get_visible_scan_entries(&device, ssid);
+    let key_management = get_key_management(&scan_entries);
+
+    let state = if key_management == KeyManagement::Sae || key_management == KeyManagement::Owe {
+        connect_key_management(
+            device.interface(),
+            ssid,
+            None,
+            passphrase,
+            key_management,
+            None,
+            None,
+        )?;
+
+        match find_active_connection(&manager, ssid.as_bytes())? {
+            Some(connection) => connection.get_state()?,
+            None => bail!(ErrorKind::NoActiveConnection(ssid.to_string())),
+        }
+    } else {
+        let wifi_device = device.as_wifi_device().unwrap();
+        let credentials = get_credentials(access_point, passphrase);
+        let (connection, state) = wifi_device.connect(access_point, &credentials)?;
+
+        if state != ConnectionState::Activated {
+            delete_connection(&connection);
+        }
+
+        state
+    };
+
+    if state != ConnectionState::Activated {
+        bail!(ErrorKind::ConnectionNotActivated(ssid.to_string()));
+    }
+
+    if !config.dns_servers.is_empty() {
+        if let Some(connection) = find_active_connection(&manager, ssid.as_bytes())? {
+            apply_dns_servers(&connection, &config.dns_servers);
+        }
+    }
+
+    wait_for_connectivity(&manager, &device, config, config.connect_attempt_timeout)
+}
+
+/// Describes the state of the WiFi device and its active connection
+pub fn get_network_status(config: &Config) -> Result<Value> {
+    let manager = NetworkManager::new();
+    let device = find_device(&manager, &config.interface)?;
+
+    let ssid = get_device_active_connection(&manager, &device).and_then(|connection| {
+        connection
+            .settings()
+            .ssid
+            .as_str()
+            .ok()
+            .map(|ssid| ssid.to_string())
+    });
+
+    let addresses = get_ipv4_addresses(device.interface())
+        .unwrap_or_default()
+        .iter()
+        .map(|&(address, prefix)| format!("{}/{}", address, prefix))
+        .collect::<Vec<_>>();
+
+    let ipv6_addresses = get_ipv6_config(device.interface())
+        .map(|config| config.addresses)
+        .unwrap_or_default()
+        .iter()
+        .map(|&(address, prefix)| format!("{}/{}", address, prefix))
+        .collect::<Vec<_>>();
+
+    Ok(json!({
+        "interface": device.interface(),
+        "state": format!("{:?}", manager.get_state()?),
+        "connectivity": format!("{:?}", manager.get_connectivity()?),
+        "ssid": ssid,
+        "ipv4_addresses": addresses,
+        "ipv6_addresses": ipv6_addresses,
+    }))
+}
+
+/// Deletes the saved profiles of an SSID and returns how many were deleted
+pub fn forget_network(ssid: &str) -> usize {
+    let manager = NetworkManager::new();
+
+    delete_connection_if_exists(&manager, ssid)
+}
+
+/// Checks whether NetworkManager answers on D-Bus
+pub fn is_network_manager_reachable() -> bool {
+    match NetworkManager::new().get_state() {
+        Ok(_) => true,
+        Err(e) => {
+            warn!("NetworkManager not reachable: {}", e);
+            false
+        },
+    }
+}
+
+/// Sends a command to the network thread and waits for its response, for the
+/// transports other than HTTP
+pub fn request_network_command<F>(
+    network_tx: &Sender<NetworkCommand>,
+    command: F,
+) -> Result<NetworkCommandResponse>
+where
+    F: FnOnce(ResponseSender) -> NetworkCommand,
+{
+    let (response_tx, response_rx) = unbounded();
+
+    network_tx.send(command(response_tx))?;
+
+    match response_rx.wait().next() {
+        Some(Ok(response)) => Ok(response),
+        _ => Err(RecvError.into()),
+    }
 }
 
-pub fn init_networking() -> Result<()> {
+pub fn init_networking(config: &Config) -> Result<()> {
+    if config.dbus_wait_timeout != 0 {
+        wait_for_system_bus(config.dbus_wait_timeout)?;
+    }
+
     start_network_manager_service()?;
 
-    delete_access_point_profiles().chain_err(|| ErrorKind::DeleteAccessPoint)
+    tolerate(
+        config,
+        delete_access_point_profiles().chain_err(|| ErrorKind::DeleteAccessPoint),
+        "Stale access point profiles kept",
+    ).map(|_| ())
+}
+
+/// Starts decoding the credentials played as DTMF tones, in builds with the
+/// experimental `audio` feature
+#[cfg(feature = "audio")]
+fn start_audio(device: &str, network_tx: Sender<NetworkCommand>) -> Result<process::Child> {
+    ::audio::start_audio_capture(device, network_tx)
+}
+
+#[cfg(not(feature = "audio"))]
+fn start_audio(_: &str, _: Sender<NetworkCommand>) -> Result<process::Child> {
+    bail!(ErrorKind::AudioUnsupported)
+}
+
+/// Adds the virtual interface for the captive portal and waits for
+/// NetworkManager to pick it up. The portal runs on the WiFi interface itself
+/// if the radio does not support it.
+fn start_virtual_ap(
+    manager: &NetworkManager,
+    device: &Device,
+    virtual_interface: &str,
+) -> Option<Device> {
+    if let Err(e) = create_virtual_ap(device.interface(), virtual_interface) {
+        warn!("Virtual access point disabled: {}", e);
+        return None;
+    }
+
+    let interface = Some(virtual_interface.to_string());
+
+    for attempt in 1..VIRTUAL_AP_DEVICE_ATTEMPTS + 1 {
+        match find_device(manager, &interface) {
+            Ok(virtual_ap) => {
+                info!(
+                    "Running the captive portal on {} next to {}",
+                    virtual_interface,
+                    device.interface()
+                );
+                return Some(virtual_ap);
+            },
+            Err(e) => if attempt == VIRTUAL_AP_DEVICE_ATTEMPTS {
+                warn!("Virtual access point disabled: {}", e);
+            },
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    remove_virtual_ap(virtual_interface);
+
+    None
+}
+
+/// Turns the failure of a non-essential startup step into a warning under
+/// the lenient startup policy, leaving the feature disabled
+fn tolerate<T>(config: &Config, result: Result<T>, degraded: &str) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => if config.lenient {
+            warn!("{}: {}", degraded, e);
+            Ok(None)
+        } else {
+            Err(e)
+        },
+    }
 }
 
 pub fn find_device(manager: &NetworkManager, interface: &Option<String>) -> Result<Device> {
@@ -293,26 +3786,173 @@ pub fn find_device(manager: &NetworkManager, interface: &Option<String>) -> Resu
             info!("WiFi device: {}", devices[index].interface());
             Ok(devices[index].clone())
         } else {
-            bail!(ErrorKind::NoWiFiDevice)
+            bail!(ErrorKind::NoWiFiDevice)
+        }
+    }
+}
+
+pub fn find_ethernet_device(
+    manager: &NetworkManager,
+    interface: &Option<String>,
+) -> Result<Device> {
+    if let Some(ref interface) = *interface {
+        let device = manager
+            .get_device_by_interface(interface)
+            .chain_err(|| ErrorKind::DeviceByInterface(interface.clone()))?;
+
+        if *device.device_type() == DeviceType::Ethernet {
+            info!("Targeted Ethernet device: {}", interface);
+            Ok(device)
+        } else {
+            bail!(ErrorKind::NotAnEthernetDevice(interface.clone()))
+        }
+    } else {
+        let devices = manager.get_devices()?;
+
+        let index = devices
+            .iter()
+            .position(|d| *d.device_type() == DeviceType::Ethernet);
+
+        if let Some(index) = index {
+            info!("Ethernet device: {}", devices[index].interface());
+            Ok(devices[index].clone())
+        } else {
+            bail!(ErrorKind::NoEthernetDevice)
+        }
+    }
+}
+
+/// The HTTP server listens on the configured address and, so that clients
+/// preferring IPv6 reach the portal as well, on the link-local IPv6 address
+/// of the portal interface
+fn get_listening_addresses(config: &Config, device: &Device, online: bool) -> Vec<SocketAddr> {
+    let mut addresses = Vec::new();
+
+    if !config.disable_ipv4_listener {
+        let address = if config.bind_interface {
+            get_interface_address(device, config)
+        } else if online && config.listening_address == IpAddr::V4(config.gateway) {
+            // There is no portal network to listen on, the API has to be
+            // reachable over the network the device is online with
+            IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+        } else {
+            config.listening_address
+        };
+
+        addresses.push(SocketAddr::new(address, config.listening_port));
+    }
+
+    if !config.disable_ipv6_listener {
+        match get_link_local_address(device.interface()) {
+            Ok(Some((address, scope_id))) => addresses.push(SocketAddr::V6(SocketAddrV6::new(
+                address,
+                config.listening_port,
+                0,
+                scope_id,
+            ))),
+            Ok(None) => debug!("No link-local IPv6 address on {}", device.interface()),
+            Err(e) => warn!("Getting link-local IPv6 address failed: {}", e),
+        }
+    }
+
+    addresses
+}
+
+/// Resolves the IPv4 address of the portal interface, so that the API is not
+/// reachable over other interfaces like Ethernet or an existing uplink
+fn get_interface_address(device: &Device, config: &Config) -> IpAddr {
+    match get_ipv4_addresses(device.interface()) {
+        Ok(ref addresses) if !addresses.is_empty() => {
+            let address = addresses[0].0;
+            info!("Binding HTTP server to {} ({})", device.interface(), address);
+            IpAddr::V4(address)
+        },
+        Ok(_) => {
+            warn!("No IPv4 address on {} - binding to the gateway", device.interface());
+            IpAddr::V4(config.gateway)
+        },
+        Err(e) => {
+            warn!("Getting IPv4 address of {} failed: {}", device.interface(), e);
+            IpAddr::V4(config.gateway)
+        },
+    }
+}
+
+/// SSIDs are at most 32 bytes long. Control characters are refused as well,
+/// since they cannot be entered in the portal and break `nmcli` output.
+pub fn validate_ssid(ssid: &str) -> ::std::result::Result<(), &'static str> {
+    if ssid.is_empty() {
+        Err("SSID is empty")
+    } else if ssid.len() > 32 {
+        Err("SSID is longer than 32 bytes")
+    } else if ssid.chars().any(|c| c.is_control()) {
+        Err("SSID contains control characters")
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks the passphrase as far as possible without knowing the security of
+/// the network. Empty passphrases are for open networks and 64 hex digits
+/// are a raw PSK, while 5 characters are only good for WEP.
+pub fn validate_passphrase(passphrase: &str) -> ::std::result::Result<(), &'static str> {
+    let length = passphrase.chars().count();
+
+    if passphrase.chars().any(|c| c.is_control()) {
+        Err("Passphrase contains control characters")
+    } else if length == 64 {
+        if passphrase.chars().all(|c| c.is_digit(16)) {
+            Ok(())
+        } else {
+            Err("Passphrase of 64 characters is not a hexadecimal PSK")
         }
+    } else if length > 63 {
+        Err("Passphrase is longer than 63 characters")
+    } else if length != 0 && length != 5 && length < 8 {
+        Err("Passphrase is shorter than 8 characters")
+    } else {
+        Ok(())
     }
 }
 
-fn get_access_points(device: &Device) -> Result<Vec<AccessPoint>> {
-    get_access_points_impl(device).chain_err(|| ErrorKind::NoAccessPoints)
+fn scan_entry_to_json(entry: &ScanEntry) -> Value {
+    json!({
+        "ssid": entry.ssid,
+        "bssid": entry.bssid,
+        "strength": entry.signal,
+        "security": entry.key_management.as_str(),
+        "frequency": entry.frequency,
+        "band": Band::from_frequency(entry.frequency).map(|band| band.as_str()),
+    })
+}
+
+fn get_access_points(device: &Device, config: &Config) -> Result<Vec<AccessPoint>> {
+    // There is nothing to scan with on a wired portal
+    if !is_wifi(device) {
+        return Ok(Vec::new());
+    }
+
+    get_access_points_impl(device, config).chain_err(|| ErrorKind::NoAccessPoints)
 }
 
-fn get_access_points_impl(device: &Device) -> Result<Vec<AccessPoint>> {
-    let retries_allowed = 10;
+fn get_access_points_impl(device: &Device, config: &Config) -> Result<Vec<AccessPoint>> {
+    let retries_allowed = config.scan_retries;
     let mut retries = 0;
 
     // After stopping the hotspot we may have to wait a bit for the list
     // of access points to become available
     while retries < retries_allowed {
+        if !config.hidden_ssids.is_empty() {
+            if let Err(e) = rescan(device.interface(), &config.hidden_ssids) {
+                // NetworkManager refuses scan requests while scanning already
+                debug!("Requesting active scan failed: {}", e);
+            }
+        }
+
         let wifi_device = device.as_wifi_device().unwrap();
-        let mut access_points = wifi_device.get_access_points()?;
+        let access_points = wifi_device.get_access_points()?;
 
-        access_points.retain(|ap| ap.ssid().as_str().is_ok());
+        let access_points = merge_access_points(access_points, &config.ssid);
 
         if !access_points.is_empty() {
             info!(
@@ -324,27 +3964,171 @@ fn get_access_points_impl(device: &Device) -> Result<Vec<AccessPoint>> {
 
         retries += 1;
         debug!("No access points found - retry #{}", retries);
-        thread::sleep(Duration::from_secs(1));
+        thread::sleep(Duration::from_secs(config.scan_interval));
     }
 
     warn!("No access points found - giving up...");
     Ok(vec![])
 }
 
-fn get_access_points_ssids(access_points: &[AccessPoint]) -> Vec<&str> {
+/// Merges access points broadcasting the same SSID from multiple BSSIDs into
+/// the one with the strongest signal, orders them by descending signal
+/// strength and leaves out the SSID of the captive portal itself, as well as
+/// hidden networks that did not reveal their SSID
+fn merge_access_points(mut access_points: Vec<AccessPoint>, portal_ssid: &str) -> Vec<AccessPoint> {
+    access_points.sort_by(|a, b| b.strength.cmp(&a.strength));
+
+    let mut seen = HashSet::new();
+
+    access_points
+        .into_iter()
+        .filter(|ap| {
+            let ssid = ap.ssid().as_bytes().to_vec();
+            !ssid.is_empty() && ssid != portal_ssid.as_bytes() && seen.insert(ssid)
+        })
+        .collect()
+}
+
+fn get_access_points_ssids(access_points: &[AccessPoint]) -> Vec<String> {
     access_points
         .iter()
-        .map(|ap| ap.ssid().as_str().unwrap())
+        .map(|ap| String::from_utf8_lossy(ap.ssid().as_bytes()).into_owned())
         .collect()
 }
 
-fn get_access_points_ssids_owned(access_points: &[AccessPoint]) -> Vec<String> {
+/// Hex representation of an SSID which is not UTF-8, e.g. Latin-1 or binary,
+/// which lets clients select it by its raw bytes
+fn get_raw_ssid(ssid: &[u8]) -> Option<String> {
+    if str::from_utf8(ssid).is_ok() {
+        return None;
+    }
+
+    Some(ssid.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+pub fn parse_raw_ssid(hex: &str) -> ::std::result::Result<Vec<u8>, &'static str> {
+    if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_digit(16)) {
+        return Err("Raw SSID is not an even number of hex digits");
+    }
+
+    let raw_ssid = (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+        .collect::<Vec<_>>();
+
+    validate_raw_ssid(&raw_ssid)?;
+
+    Ok(raw_ssid)
+}
+
+fn validate_raw_ssid(raw_ssid: &[u8]) -> ::std::result::Result<(), &'static str> {
+    if raw_ssid.is_empty() {
+        Err("SSID is empty")
+    } else if raw_ssid.len() > 32 {
+        Err("SSID is longer than 32 bytes")
+    } else {
+        Ok(())
+    }
+}
+
+/// Lists the access points with their key management. It is taken from the
+/// `nmcli` scan, which unlike the NetworkManager bindings knows about WPA3
+/// and OWE, with the security flags of the bindings as a fallback.
+fn get_access_point_infos(device: &Device, access_points: &[AccessPoint]) -> Vec<AccessPointInfo> {
+    let scan_entries = match get_scan_entries(device.interface()) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Getting access point security failed: {}", e);
+            vec![]
+        },
+    };
+
     access_points
         .iter()
-        .map(|ap| ap.ssid().as_str().unwrap().to_string())
+        .map(|ap| {
+            let ssid = String::from_utf8_lossy(ap.ssid().as_bytes()).into_owned();
+
+            let entries = scan_entries
+                .iter()
+                .filter(|entry| entry.ssid == ssid)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            let key_management = if entries.is_empty() {
+                get_key_management_from_security(&ap.security)
+            } else {
+                get_key_management(&entries)
+            };
+
+            AccessPointInfo {
+                ssid: ssid,
+                raw_ssid: get_raw_ssid(ap.ssid().as_bytes()),
+                strength: ap.strength,
+                key_management: key_management,
+                bssids: entries,
+            }
+        })
         .collect()
 }
 
+/// The key management of an SSID is that of its strongest BSSID
+fn get_key_management(scan_entries: &[ScanEntry]) -> KeyManagement {
+    scan_entries
+        .iter()
+        .max_by_key(|entry| entry.signal)
+        .map_or(KeyManagement::WpaPsk, |entry| entry.key_management)
+}
+
+fn get_key_management_from_security(security: &Security) -> KeyManagement {
+    if security.contains(Security::ENTERPRISE) {
+        KeyManagement::Enterprise
+    } else if security.contains(Security::WPA2) || security.contains(Security::WPA) {
+        KeyManagement::WpaPsk
+    } else if security.contains(Security::WEP) {
+        KeyManagement::Wep
+    } else {
+        KeyManagement::Open
+    }
+}
+
+/// Secured networks which are not WEP get a WPA passphrase, like
+/// NetworkManager does for a passphrase given without the key management
+fn get_credentials(access_point: &AccessPoint, passphrase: &str) -> AccessPointCredentials {
+    let passphrase = passphrase.to_string();
+
+    match get_key_management_from_security(&access_point.security) {
+        KeyManagement::Open => AccessPointCredentials::None,
+        KeyManagement::Wep => AccessPointCredentials::Wep {
+            passphrase: passphrase,
+        },
+        _ => AccessPointCredentials::Wpa {
+            passphrase: passphrase,
+        },
+    }
+}
+
+fn get_associated_bssid(device: &Device) -> Option<String> {
+    match get_active_scan_entry(device.interface()) {
+        Ok(entry) => entry.map(|entry| entry.bssid),
+        Err(e) => {
+            warn!("Getting associated BSSID failed: {}", e);
+            None
+        },
+    }
+}
+
+fn get_visible_scan_entries(device: &Device, ssid: &str) -> Vec<ScanEntry> {
+    match get_scan_entries(device.interface()) {
+        Ok(entries) => entries
+            .into_iter()
+            .filter(|entry| entry.ssid == ssid)
+            .collect(),
+        Err(e) => {
+            warn!("Getting access point BSSIDs failed: {}", e);
+            vec![]
+        },
+    }
+}
+
 fn find_access_point<'a>(access_points: &'a [AccessPoint], ssid: &str) -> Option<&'a AccessPoint> {
     for access_point in access_points.iter() {
         if let Ok(access_point_ssid) = access_point.ssid().as_str() {
@@ -357,11 +4141,120 @@ fn find_access_point<'a>(access_points: &'a [AccessPoint], ssid: &str) -> Option
     None
 }
 
-fn create_portal(device: &Device, config: &Config) -> Result<Connection> {
+fn find_access_point_by_bytes<'a>(
+    access_points: &'a [AccessPoint],
+    ssid: &[u8],
+) -> Option<&'a AccessPoint> {
+    access_points.iter().find(|ap| ap.ssid().as_bytes() == ssid)
+}
+
+fn create_portal(
+    device: &Device,
+    config: &Config,
+    pairing_token: &PairingToken,
+) -> Result<Connection> {
     let portal_passphrase = config.passphrase.as_ref().map(|p| p as &str);
 
-    create_portal_impl(device, &config.ssid, &config.gateway, &portal_passphrase)
-        .chain_err(|| ErrorKind::CreateCaptivePortal)
+    if config.pairing_token {
+        pairing_token.rotate()?;
+    }
+
+    let ssid = get_portal_ssid(config, pairing_token);
+
+    if !is_wifi(device) {
+        let connection =
+            create_wired_portal(device, config).chain_err(|| ErrorKind::CreateCaptivePortal)?;
+
+        info!("{}", Milestone::PortalOpened(device.interface()).localize(config.locale));
+        log_event("portal_opened", json!({ "interface": device.interface() }));
+
+        return Ok(connection);
+    }
+
+    let connection = create_portal_impl(device, &ssid, &config.gateway, &portal_passphrase)
+        .chain_err(|| ErrorKind::CreateCaptivePortal)?;
+
+    apply_portal_radio(&connection, config).chain_err(|| ErrorKind::CreateCaptivePortal)?;
+
+    apply_portal_power(device.interface(), config);
+
+    info!("{}", Milestone::PortalOpened(&ssid).localize(config.locale));
+    log_event("portal_opened", json!({ "ssid": ssid }));
+
+    Ok(connection)
+}
+
+/// Serves the portal on an Ethernet device, given the gateway address like
+/// the access point
+fn create_wired_portal(device: &Device, config: &Config) -> Result<Connection> {
+    info!("Starting wired captive portal on {}...", device.interface());
+
+    let manager = NetworkManager::new();
+
+    // Left behind if the previous run was killed
+    for connection in manager.get_connections()? {
+        if connection.settings().id == WIRED_PORTAL_CONNECTION {
+            delete_connection(&connection);
+        }
+    }
+
+    add_wired_portal(
+        device.interface(),
+        WIRED_PORTAL_CONNECTION,
+        config.gateway,
+        config.portal_prefix,
+    )?;
+
+    for connection in manager.get_connections()? {
+        if connection.settings().id == WIRED_PORTAL_CONNECTION {
+            info!("Wired captive portal started on {}", device.interface());
+            return Ok(connection);
+        }
+    }
+
+    bail!(ErrorKind::NoActiveConnection(WIRED_PORTAL_CONNECTION.to_string()))
+}
+
+fn is_wifi(device: &Device) -> bool {
+    *device.device_type() == DeviceType::WiFi
+}
+
+/// The SSID of the captive portal, with the pairing token if enabled
+fn get_portal_ssid(config: &Config, pairing_token: &PairingToken) -> String {
+    match pairing_token.get() {
+        Some(ref token) if config.pairing_token => format!("{}-{}", config.ssid, token),
+        _ => config.ssid.clone(),
+    }
+}
+
+/// Fixes the band and channel of the access point, which the NetworkManager
+/// bindings leave to NetworkManager, and the prefix length of its network
+fn apply_portal_radio(connection: &Connection, config: &Config) -> Result<()> {
+    let mut settings = Vec::new();
+
+    if let Some(band) = config.portal_band {
+        settings.push(("802-11-wireless.band".to_string(), band.as_str().to_string()));
+    }
+
+    if let Some(channel) = config.portal_channel {
+        settings.push(("802-11-wireless.channel".to_string(), channel.to_string()));
+    }
+
+    // The access point is created with a /24 network
+    if config.portal_prefix != subnet::DEFAULT_PREFIX {
+        settings.push((
+            "ipv4.addresses".to_string(),
+            format!("{}/{}", config.gateway, config.portal_prefix),
+        ));
+    }
+
+    if settings.is_empty() {
+        return Ok(());
+    }
+
+    info!("Setting access point radio: {:?}", settings);
+
+    modify_connection(&connection.settings().uuid, &settings)
 }
 
 fn create_portal_impl(
@@ -372,6 +4265,8 @@ fn create_portal_impl(
 ) -> Result<Connection> {
     info!("Starting access point...");
     let wifi_device = device.as_wifi_device().unwrap();
+    // The SSID is sent over the air as its UTF-8 bytes, which clients display
+    // as text
     let (portal_connection, _) = wifi_device.create_hotspot(ssid, *passphrase, Some(*gateway))?;
     info!("Access point '{}' created", ssid);
     Ok(portal_connection)
@@ -383,6 +4278,13 @@ fn stop_portal(connection: &Connection, config: &Config) -> Result<()> {
 
 fn stop_portal_impl(connection: &Connection, config: &Config) -> Result<()> {
     info!("Stopping access point '{}'...", config.ssid);
+
+    if let Ok(devices) = connection.get_devices() {
+        for device in devices.iter().filter(|device| is_wifi(device)) {
+            reset_portal_power(device.interface(), config);
+        }
+    }
+
     connection.deactivate()?;
     connection.delete()?;
     thread::sleep(Duration::from_secs(1));
@@ -390,7 +4292,18 @@ fn stop_portal_impl(connection: &Connection, config: &Config) -> Result<()> {
     Ok(())
 }
 
-fn wait_for_connectivity(manager: &NetworkManager, timeout: u64) -> Result<bool> {
+/// Waits for NetworkManager to report connectivity. Networks without IPv4
+/// connectivity are checked over IPv6 before giving up.
+fn wait_for_connectivity(
+    manager: &NetworkManager,
+    device: &Device,
+    config: &Config,
+    timeout: u64,
+) -> Result<bool> {
+    if !config.internet_checks.is_empty() {
+        return Ok(wait_for_internet_checks(config, timeout));
+    }
+
     let mut total_time = 0;
 
     loop {
@@ -409,6 +4322,11 @@ fn wait_for_connectivity(manager: &NetworkManager, timeout: u64) -> Result<bool>
                 connectivity, total_time
             );
 
+            if has_ipv6_connectivity(device.interface()) {
+                info!("Connectivity established over IPv6");
+                return Ok(true);
+            }
+
             return Ok(false);
         }
 
@@ -423,6 +4341,56 @@ fn wait_for_connectivity(manager: &NetworkManager, timeout: u64) -> Result<bool>
     }
 }
 
+/// Runs the configured Internet check probes until one succeeds
+fn wait_for_internet_checks(config: &Config, timeout: u64) -> bool {
+    let start = Instant::now();
+
+    loop {
+        let results = check_internet(&config.internet_checks, config.internet_check_timeout);
+
+        if results.iter().any(|result| result.success) {
+            debug!("Internet check passed: {}", results_to_json(&results));
+            return true;
+        } else if start.elapsed().as_secs() >= timeout {
+            warn!("Internet check failed: {}", results_to_json(&results));
+            return false;
+        }
+
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Waits for the system D-Bus, which may not be up yet early during boot, by
+/// querying the NetworkManager service state with increasing delays
+fn wait_for_system_bus(timeout: u64) -> Result<()> {
+    let started = Instant::now();
+    let mut delay = 1;
+
+    loop {
+        let error = match NetworkManager::get_service_state() {
+            Ok(_) => return Ok(()),
+            Err(e) => e,
+        };
+
+        let elapsed = started.elapsed().as_secs();
+
+        if elapsed >= timeout {
+            return Err(error).chain_err(|| ErrorKind::SystemBusUnavailable(timeout));
+        }
+
+        let wait = cmp::min(delay, timeout - elapsed);
+
+        info!(
+            "System D-Bus not available yet - retrying in {}s ({}s of {}s): {}",
+            wait, elapsed, timeout, error
+        );
+
+        thread::sleep(Duration::from_secs(wait));
+
+        delay = cmp::min(delay * 2, MAX_DBUS_RETRY_DELAY);
+    }
+}
+
 pub fn start_network_manager_service() -> Result<()> {
     let state =
         NetworkManager::get_service_state().chain_err(|| ErrorKind::NetworkManagerServiceState)?;
@@ -459,15 +4427,359 @@ fn delete_access_point_profiles() -> Result<()> {
     Ok(())
 }
 
-fn delete_connection_if_exists(manager: &NetworkManager, ssid: &str) {
+fn configure_ethernet(device: &Device, config: &Ipv4Settings) -> Result<()> {
+    match *config {
+        Ipv4Settings::Dhcp => {
+            info!("Configuring {} with DHCP", device.interface());
+            configure_wired_ipv4(device.interface(), None, None, &[])
+        },
+        Ipv4Settings::Static {
+            address,
+            prefix,
+            gateway,
+            ref dns_servers,
+        } => {
+            info!(
+                "Configuring {} with address {}/{}",
+                device.interface(),
+                address,
+                prefix
+            );
+            configure_wired_ipv4(
+                device.interface(),
+                Some((address, prefix)),
+                gateway,
+                dns_servers,
+            )
+        },
+    }
+}
+
+fn get_ethernet_status(device: &Device) -> EthernetStatus {
+    let state = match device.get_state() {
+        Ok(state) => format!("{:?}", state),
+        Err(e) => {
+            warn!("Getting state of {} failed: {}", device.interface(), e);
+            "Unknown".to_string()
+        },
+    };
+
+    let ipv4 = match get_ipv4_config(device.interface()) {
+        Ok(ipv4) => ipv4,
+        Err(e) => {
+            warn!("Getting IPv4 configuration failed: {}", e);
+            Ipv4Config {
+                addresses: vec![],
+                gateway: None,
+                dns_servers: vec![],
+            }
+        },
+    };
+
+    EthernetStatus {
+        interface: device.interface().to_string(),
+        state: state,
+        carrier: get_carrier(device.interface()),
+        ipv4: ipv4,
+    }
+}
+
+/// Reads whether a cable is plugged in. The kernel reports the carrier only
+/// for interfaces that are up.
+fn get_carrier(interface: &str) -> Option<bool> {
+    let path = Path::new("/sys/class/net").join(interface).join("carrier");
+
+    let mut carrier = String::new();
+
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut carrier)) {
+        Ok(_) => Some(carrier.trim() == "1"),
+        Err(_) => None,
+    }
+}
+
+/// Collects the details of the current connection. These are best-effort,
+/// so missing information is left out instead of failing the request.
+fn get_connection_details(device: &Device, ssid: &str, connected_at: Instant) -> ConnectionDetails {
+    let active = match get_active_scan_entry(device.interface()) {
+        Ok(active) => active,
+        Err(e) => {
+            warn!("Getting active access point failed: {}", e);
+            None
+        },
+    };
+
+    let (addresses, gateway, dns_servers) = match get_ipv4_config(device.interface()) {
+        Ok(config) => (config.addresses, config.gateway, config.dns_servers),
+        Err(e) => {
+            warn!("Getting IPv4 configuration failed: {}", e);
+            (vec![], None, vec![])
+        },
+    };
+
+    let (ipv6_addresses, ipv6_gateway) = match get_ipv6_config(device.interface()) {
+        Ok(config) => (config.addresses, config.gateway),
+        Err(e) => {
+            warn!("Getting IPv6 configuration failed: {}", e);
+            (vec![], None)
+        },
+    };
+
+    ConnectionDetails {
+        ssid: ssid.to_string(),
+        bssid: active.as_ref().map(|entry| entry.bssid.clone()),
+        signal: active.as_ref().map(|entry| entry.signal),
+        addresses: addresses,
+        gateway: gateway,
+        dns_servers: dns_servers,
+        ipv6_addresses: ipv6_addresses,
+        ipv6_gateway: ipv6_gateway,
+        uptime: connected_at.elapsed().as_secs(),
+    }
+}
+
+/// Warns when the network the device joined overlaps with the captive portal
+/// network, since the portal would be unreachable the next time it is opened
+fn check_split_horizon(device: &Device, config: &Config) {
+    let addresses = match get_ipv4_addresses(device.interface()) {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            debug!("Getting device IPv4 addresses failed: {}", e);
+            return;
+        },
+    };
+
+    for (address, prefix) in addresses {
+        if !subnet::overlaps(address, prefix, config.gateway, config.portal_prefix) {
+            continue;
+        }
+
+        warn!(
+            "Network {}/{} overlaps with the captive portal network of gateway {}",
+            address, prefix, config.gateway
+        );
+
+        let suggestion =
+            subnet::suggest_gateway(address, prefix, config.gateway, config.portal_prefix);
+
+        if let Some(gateway) = suggestion {
+            warn!(
+                "Consider changing the portal gateway, e.g. --portal-gateway {}",
+                gateway
+            );
+        }
+    }
+}
+
+fn apply_dns_servers(connection: &Connection, dns_servers: &[Ipv4Addr]) {
+    info!("Setting DNS servers: {:?}", dns_servers);
+
+    if let Err(e) = set_dns_servers(&connection.settings().uuid, dns_servers) {
+        error!("Setting DNS servers failed: {}", e);
+    }
+}
+
+/// Returns the active WiFi client connection of a device
+fn get_device_active_connection(manager: &NetworkManager, device: &Device) -> Option<Connection> {
+    let connections = match manager.get_active_connections() {
+        Ok(connections) => connections,
+        Err(e) => {
+            warn!("Getting active connections failed: {}", e);
+            return None;
+        },
+    };
+
+    connections.into_iter().find(|connection| {
+        &connection.settings().kind == "802-11-wireless" && &connection.settings().mode != "ap"
+            && connection
+                .get_devices()
+                .map(|devices| {
+                    devices
+                        .iter()
+                        .any(|other| other.interface() == device.interface())
+                })
+                .unwrap_or(false)
+    })
+}
+
+/// Returns the WiFi client connection of a device, if it has connectivity
+fn get_online_connection(manager: &NetworkManager, device: &Device) -> Option<Connection> {
+    let connection = get_device_active_connection(manager, device)?;
+
+    match manager.get_connectivity() {
+        Ok(Connectivity::Full) | Ok(Connectivity::Limited) => Some(connection),
+        Ok(connectivity) => {
+            debug!("Connected, but connectivity is {:?}", connectivity);
+            None
+        },
+        Err(e) => {
+            warn!("Getting connectivity failed: {}", e);
+            None
+        },
+    }
+}
+
+/// Translates a change to connection profile settings
+fn get_advanced_settings(change: &AdvancedChange) -> Vec<(String, String)> {
+    let join = |addresses: &[Ipv4Addr]| {
+        addresses
+            .iter()
+            .map(|address| address.to_string())
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let settings = match *change {
+        AdvancedChange::Ipv4(Ipv4Settings::Dhcp) => vec![
+            ("ipv4.method", "auto".to_string()),
+            ("ipv4.addresses", String::new()),
+            ("ipv4.gateway", String::new()),
+        ],
+        AdvancedChange::Ipv4(Ipv4Settings::Static {
+            address,
+            prefix,
+            gateway,
+            ref dns_servers,
+        }) => vec![
+            ("ipv4.method", "manual".to_string()),
+            ("ipv4.addresses", format!("{}/{}", address, prefix)),
+            (
+                "ipv4.gateway",
+                gateway.map_or_else(String::new, |gateway| gateway.to_string()),
+            ),
+            ("ipv4.dns", join(dns_servers)),
+        ],
+        AdvancedChange::Ipv6(Ipv6Settings::Auto) => vec![
+            ("ipv6.method", "auto".to_string()),
+            ("ipv6.addresses", String::new()),
+            ("ipv6.gateway", String::new()),
+        ],
+        AdvancedChange::Ipv6(Ipv6Settings::Static {
+            address,
+            prefix,
+            gateway,
+        }) => vec![
+            ("ipv6.method", "manual".to_string()),
+            ("ipv6.addresses", format!("{}/{}", address, prefix)),
+            (
+                "ipv6.gateway",
+                gateway.map_or_else(String::new, |gateway| gateway.to_string()),
+            ),
+        ],
+        AdvancedChange::Dns(ref dns_servers) => vec![
+            ("ipv4.dns", join(dns_servers)),
+            (
+                "ipv4.ignore-auto-dns",
+                if dns_servers.is_empty() { "no" } else { "yes" }.to_string(),
+            ),
+        ],
+        AdvancedChange::Proxy { ref pac_url } => match *pac_url {
+            Some(ref pac_url) => vec![
+                ("proxy.method", "auto".to_string()),
+                ("proxy.pac-url", pac_url.clone()),
+            ],
+            None => vec![
+                ("proxy.method", "none".to_string()),
+                ("proxy.pac-url", String::new()),
+            ],
+        },
+    };
+
+    settings
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
+
+/// Reactivates the connection the device was online with before switching
+/// networks
+fn roll_back(connection: &Connection) {
+    warn!(
+        "Rolling back to the previous connection {:?}",
+        connection.settings().ssid
+    );
+
+    match connection.activate() {
+        Ok(ConnectionState::Activated) => info!("Previous connection restored"),
+        Ok(state) => error!("Previous connection not activated: {:?}", state),
+        Err(e) => error!("Restoring previous connection failed: {}", e),
+    }
+}
+
+/// Activates a saved connection profile, keeping its settings. Only the
+/// passphrase is updated, if one is given.
+fn reactivate_connection(
+    connection: &Connection,
+    passphrase: &str,
+) -> Result<(Connection, ConnectionState)> {
+    info!("Reusing existing WiFi connection: {:?}", connection.settings().ssid);
+
+    if !passphrase.is_empty() {
+        set_wifi_secret(&connection.settings().uuid, "psk", passphrase)?;
+    }
+
+    let state = connection.activate()?;
+
+    Ok((connection.clone(), state))
+}
+
+fn delete_connection(connection: &Connection) {
+    if let Err(err) = connection.delete() {
+        error!("Deleting connection object failed: {}", err)
+    }
+}
+
+fn find_connection(manager: &NetworkManager, ssid: &str) -> Result<Option<Connection>> {
+    for connection in manager.get_connections()? {
+        if let Ok(connection_ssid) = connection.settings().ssid.as_str() {
+            if &connection.settings().kind == "802-11-wireless" && connection_ssid == ssid {
+                return Ok(Some(connection));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Finds the active WiFi connection of an SSID by its bytes, which need not be
+/// UTF-8
+fn find_active_connection(manager: &NetworkManager, ssid: &[u8]) -> Result<Option<Connection>> {
+    for connection in manager.get_active_connections()? {
+        let settings = connection.settings();
+
+        if &settings.kind == "802-11-wireless" && settings.ssid.as_bytes() == ssid {
+            return Ok(Some(connection));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Deletes the WiFi profiles of an SSID and returns how many were deleted
+/// Lists the saved WiFi connection profiles, most recently used first
+fn list_saved_profiles() -> Vec<SavedProfile> {
+    match get_saved_profiles() {
+        Ok(mut profiles) => {
+            profiles.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+            profiles
+        },
+        Err(e) => {
+            error!("Listing saved connections failed: {}", e);
+            vec![]
+        },
+    }
+}
+
+fn delete_connection_if_exists(manager: &NetworkManager, ssid: &str) -> usize {
     let connections = match manager.get_connections() {
         Ok(connections) => connections,
         Err(e) => {
             error!("Getting existing connections failed: {}", e);
-            return;
+            return 0;
         },
     };
 
+    let mut deleted = 0;
+
     for connection in connections {
         if let Ok(connection_ssid) = connection.settings().ssid.as_str() {
             if &connection.settings().kind == "802-11-wireless" && connection_ssid == ssid {
@@ -476,10 +4788,13 @@ fn delete_connection_if_exists(manager: &NetworkManager, ssid: &str) {
                     connection.settings().ssid,
                 );
 
-                if let Err(e) = connection.delete() {
-                    error!("Deleting existing WiFi connection failed: {}", e);
+                match connection.delete() {
+                    Ok(()) => deleted += 1,
+                    Err(e) => error!("Deleting existing WiFi connection failed: {}", e),
                 }
             }
         }
     }
+
+    deleted
 }
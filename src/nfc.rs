@@ -0,0 +1,303 @@
+use std::thread;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use errors::*;
+use network::{validate_passphrase, validate_ssid, ApSelection, NetworkCommand};
+
+/// Record type of the Wi-Fi Simple Configuration credentials of NDEF WiFi
+/// configuration tags, as written by the tag apps of phones
+const WSC_RECORD_TYPE: &[u8] = b"application/vnd.wfa.wsc";
+
+/// Type name format of records with a MIME type
+const TNF_MEDIA_TYPE: u8 = 0x02;
+
+const WSC_CREDENTIAL: u16 = 0x100E;
+const WSC_SSID: u16 = 0x1045;
+const WSC_NETWORK_KEY: u16 = 0x1027;
+
+/// Readers report a tag as long as it lies on them, which connects once
+/// unless the tag is presented again after this time (seconds)
+const REPEAT_INTERVAL: u64 = 10;
+
+/// WiFi credentials read from a tag
+#[derive(Clone, PartialEq)]
+struct NfcCredential {
+    ssid: String,
+    passphrase: String,
+}
+
+/// Runs the NFC reader command, which prints the NDEF message of every tag
+/// presented as a line of hex digits, and connects to the network of the
+/// first WiFi configuration record. Other tags are ignored. The reader runs
+/// while the child process is alive.
+pub fn start_nfc_reader(command: &str, network_tx: Sender<NetworkCommand>) -> Result<Child> {
+    let mut child = Command::new("sh")
+        .args(&["-c", command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .chain_err(|| ErrorKind::NfcReader)?;
+
+    let stdout = child.stdout.take().unwrap();
+
+    info!("Reading WiFi credentials from NFC tags...");
+
+    thread::spawn(move || {
+        let mut last: Option<(NfcCredential, Instant)> = None;
+
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("Reading NFC tags failed: {}", e);
+                    return;
+                },
+            };
+
+            let credential = match parse_tag(line.trim()) {
+                Ok(Some(credential)) => credential,
+                Ok(None) => {
+                    debug!("Ignoring NFC tag without WiFi configuration");
+                    continue;
+                },
+                Err(reason) => {
+                    warn!("Ignoring invalid NFC tag: {}", reason);
+                    continue;
+                },
+            };
+
+            let is_repeated = match last {
+                Some((ref previous, ref read_at)) => {
+                    *previous == credential
+                        && read_at.elapsed() < Duration::from_secs(REPEAT_INTERVAL)
+                },
+                None => false,
+            };
+
+            last = Some((credential.clone(), Instant::now()));
+
+            if is_repeated {
+                continue;
+            }
+
+            info!("Connecting to '{}' from NFC tag", credential.ssid);
+
+            let command = NetworkCommand::Connect {
+                ssid: credential.ssid,
+                passphrase: credential.passphrase,
+                dns_servers: None,
+                reuse: false,
+                selection: ApSelection::default(),
+                checkpoint: None,
+            };
+
+            if network_tx.send(command).is_err() {
+                return;
+            }
+        }
+
+        warn!("NFC reader exited");
+    });
+
+    Ok(child)
+}
+
+/// Parses a hex encoded NDEF message and returns the credentials of its
+/// first WiFi configuration record
+fn parse_tag(hex: &str) -> ::std::result::Result<Option<NfcCredential>, String> {
+    if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_digit(16)) {
+        return Err("not an even number of hex digits".to_string());
+    }
+
+    let message = (0..hex.len() / 2)
+        .map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+        .collect::<Vec<_>>();
+
+    for (tnf, record_type, payload) in parse_ndef(&message)? {
+        if tnf == TNF_MEDIA_TYPE && record_type == WSC_RECORD_TYPE {
+            return parse_wsc_credential(payload).map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+/// Type name format, type and payload of an NDEF record
+type NdefRecord<'a> = (u8, &'a [u8], &'a [u8]);
+
+/// Splits an NDEF message into its records. Chunked records are not used by
+/// WiFi configuration tags and are refused.
+fn parse_ndef(message: &[u8]) -> ::std::result::Result<Vec<NdefRecord>, String> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while offset < message.len() {
+        let header = message[offset];
+        let is_last = header & 0x40 != 0;
+        let is_chunked = header & 0x20 != 0;
+        let is_short = header & 0x10 != 0;
+        let has_id = header & 0x08 != 0;
+        let tnf = header & 0x07;
+
+        if is_chunked {
+            return Err("chunked NDEF records are not supported".to_string());
+        }
+
+        offset += 1;
+
+        let type_length = *message.get(offset).ok_or("truncated NDEF record")? as usize;
+        offset += 1;
+
+        let payload_length = if is_short {
+            let length = *message.get(offset).ok_or("truncated NDEF record")? as usize;
+            offset += 1;
+            length
+        } else {
+            let bytes = message.get(offset..offset + 4).ok_or("truncated NDEF record")?;
+            offset += 4;
+            bytes.iter().fold(0, |length, byte| length << 8 | *byte as usize)
+        };
+
+        let id_length = if has_id {
+            let length = *message.get(offset).ok_or("truncated NDEF record")? as usize;
+            offset += 1;
+            length
+        } else {
+            0
+        };
+
+        let record_type = message
+            .get(offset..offset + type_length)
+            .ok_or("truncated NDEF record")?;
+        offset += type_length + id_length;
+
+        let payload = message
+            .get(offset..offset + payload_length)
+            .ok_or("truncated NDEF record")?;
+        offset += payload_length;
+
+        records.push((tnf, record_type, payload));
+
+        if is_last {
+            break;
+        }
+    }
+
+    Ok(records)
+}
+
+/// Reads the SSID and network key of the credential attribute, which may
+/// come with others, e.g. the authentication type, which NetworkManager
+/// derives from the scan instead
+fn parse_wsc_credential(payload: &[u8]) -> ::std::result::Result<NfcCredential, String> {
+    let credential = parse_wsc_attributes(payload)?
+        .into_iter()
+        .find(|&(kind, _)| kind == WSC_CREDENTIAL)
+        .map(|(_, value)| value)
+        .ok_or("no WiFi credential")?;
+
+    let mut ssid = None;
+    let mut passphrase = String::new();
+
+    for (kind, value) in parse_wsc_attributes(credential)? {
+        match kind {
+            WSC_SSID => {
+                ssid = Some(String::from_utf8(value.to_vec()).map_err(|_| "SSID is not UTF-8")?);
+            },
+            WSC_NETWORK_KEY => {
+                passphrase =
+                    String::from_utf8(value.to_vec()).map_err(|_| "network key is not UTF-8")?;
+            },
+            _ => {},
+        }
+    }
+
+    let ssid = ssid.ok_or("no SSID")?;
+
+    validate_ssid(&ssid)?;
+    validate_passphrase(&passphrase)?;
+
+    Ok(NfcCredential {
+        ssid: ssid,
+        passphrase: passphrase,
+    })
+}
+
+/// Splits Wi-Fi Simple Configuration attributes, which are a big endian
+/// type and length followed by the value
+fn parse_wsc_attributes(data: &[u8]) -> ::std::result::Result<Vec<(u16, &[u8])>, String> {
+    let mut attributes = Vec::new();
+    let mut offset = 0;
+
+    while offset < data.len() {
+        let header = data.get(offset..offset + 4).ok_or("truncated WiFi attribute")?;
+
+        let kind = u16::from(header[0]) << 8 | u16::from(header[1]);
+        let length = (header[2] as usize) << 8 | header[3] as usize;
+        offset += 4;
+
+        let value = data.get(offset..offset + length).ok_or("truncated WiFi attribute")?;
+        offset += length;
+
+        attributes.push((kind, value));
+    }
+
+    Ok(attributes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// WiFi configuration record after its header: the type, then version
+    /// 1.0 and a credential with network index, SSID `Home`, WPA2-Personal,
+    /// AES, network key `secret123` and the broadcast MAC address
+    const WSC_RECORD: &str = "17396170706c69636174696f6e2f766e642e7766612e777363104a000110\
+                              100e0030102600010110450004486f6d65100300020020100f00020008\
+                              1027000973656372657431323310200006ffffffffffff";
+
+    /// URI record of `https://example`, without the message end flag
+    const URI_RECORD: &str = "91010855046578616d706c65";
+
+    #[test]
+    fn wifi_configuration_tag() {
+        let credential = parse_tag(&format!("d2{}", WSC_RECORD)).unwrap().unwrap();
+
+        assert_eq!(credential.ssid, "Home");
+        assert_eq!(credential.passphrase, "secret123");
+    }
+
+    #[test]
+    fn wifi_record_after_other_records() {
+        let credential = parse_tag(&format!("{}52{}", URI_RECORD, WSC_RECORD.to_uppercase()))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(credential.ssid, "Home");
+    }
+
+    #[test]
+    fn long_record() {
+        // Same record with a four byte payload length
+        let record = format!("c2{}000000{}", &WSC_RECORD[..2], &WSC_RECORD[2..]);
+
+        assert!(parse_tag(&record).unwrap().is_some());
+    }
+
+    #[test]
+    fn tag_without_wifi_record() {
+        assert!(parse_tag(&format!("d1{}", &URI_RECORD[2..])).unwrap().is_none());
+        assert!(parse_tag("").unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_tags() {
+        assert!(parse_tag("d").is_err());
+        assert!(parse_tag("zz").is_err());
+        assert!(parse_tag(&format!("d2{}", &WSC_RECORD[..WSC_RECORD.len() - 2])).is_err());
+        assert!(parse_tag(&format!("f2{}", WSC_RECORD)).is_err());
+    }
+}
@@ -0,0 +1,1018 @@
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+use errors::*;
+use redact::redact_args;
+use secrets::{set_wifi_secret, set_wifi_ssid};
+
+/// SSID of the profiles of SSIDs which are not UTF-8 until the raw bytes
+/// replace it, as the lossy conversion may exceed the 32 bytes `nmcli` takes
+const RAW_SSID_PLACEHOLDER: &str = "wifi-connect";
+
+/// A single BSSID entry of a WiFi scan as reported by `nmcli`
+#[derive(Clone)]
+pub struct ScanEntry {
+    pub ssid: String,
+    pub bssid: String,
+    pub signal: u32,
+    pub key_management: KeyManagement,
+    /// Channel frequency (MHz)
+    pub frequency: u32,
+}
+
+/// Frequency band of `802-11-wireless.band`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Band {
+    /// 2.4 GHz
+    Bg,
+    /// 5 GHz
+    A,
+}
+
+impl Band {
+    pub fn from_frequency(frequency: u32) -> Option<Self> {
+        match frequency {
+            2400...2500 => Some(Band::Bg),
+            4900...5900 => Some(Band::A),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Band::Bg => "bg",
+            Band::A => "a",
+        }
+    }
+}
+
+impl FromStr for Band {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bg" | "2.4" | "2.4ghz" => Ok(Band::Bg),
+            "a" | "5" | "5ghz" => Ok(Band::A),
+            _ => Err(format!("Invalid band: {} (expected `2.4ghz` or `5ghz`)", s)),
+        }
+    }
+}
+
+/// Key management of an access point as derived from the security flags of
+/// a scan
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyManagement {
+    Open,
+    Wep,
+    WpaPsk,
+    Sae,
+    Owe,
+    Enterprise,
+}
+
+impl KeyManagement {
+    /// Parses the `SECURITY` field of `nmcli`, e.g. `WPA2 WPA3` or `OWE`.
+    /// Transition mode access points which accept both WPA2 and WPA3 are
+    /// treated as WPA-PSK, which every supplicant handles.
+    pub fn from_security(security: &str) -> Self {
+        let flags = security.split_whitespace().collect::<Vec<_>>();
+        let has = |flag: &str| flags.contains(&flag);
+
+        if has("802.1X") {
+            KeyManagement::Enterprise
+        } else if has("OWE") {
+            KeyManagement::Owe
+        } else if has("WPA3") && !has("WPA2") && !has("WPA1") {
+            KeyManagement::Sae
+        } else if has("WPA1") || has("WPA2") || has("WPA3") {
+            KeyManagement::WpaPsk
+        } else if has("WEP") {
+            KeyManagement::Wep
+        } else {
+            KeyManagement::Open
+        }
+    }
+
+    /// Parses the `802-11-wireless-security.key-mgmt` setting of a profile,
+    /// which is unset for open networks
+    pub fn from_key_mgmt(key_mgmt: &str) -> Self {
+        match key_mgmt {
+            "none" | "ieee8021x" => KeyManagement::Wep,
+            "wpa-psk" => KeyManagement::WpaPsk,
+            "sae" => KeyManagement::Sae,
+            "owe" => KeyManagement::Owe,
+            "wpa-eap" | "wpa-eap-suite-b-192" => KeyManagement::Enterprise,
+            _ => KeyManagement::Open,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            KeyManagement::Open => "open",
+            KeyManagement::Wep => "wep",
+            KeyManagement::WpaPsk => "wpa-psk",
+            KeyManagement::Sae => "sae",
+            KeyManagement::Owe => "owe",
+            KeyManagement::Enterprise => "wpa-eap",
+        }
+    }
+}
+
+/// Lists the access points visible to the device by BSSID, since the
+/// NetworkManager bindings expose access points by SSID only
+pub fn get_scan_entries(interface: &str) -> Result<Vec<ScanEntry>> {
+    let output = run(&[
+        "--terse",
+        "--fields",
+        "SSID,BSSID,SIGNAL,SECURITY,FREQ",
+        "device",
+        "wifi",
+        "list",
+        "ifname",
+        interface,
+    ])?;
+
+    let mut entries = Vec::new();
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() != 5 {
+            continue;
+        }
+
+        entries.push(parse_scan_entry(fields));
+    }
+
+    Ok(entries)
+}
+
+/// Builds a scan entry from the `SSID,BSSID,SIGNAL,SECURITY,FREQ` fields,
+/// where the frequency reads e.g. `2412 MHz`
+fn parse_scan_entry(mut fields: Vec<String>) -> ScanEntry {
+    let frequency = fields
+        .pop()
+        .unwrap()
+        .split_whitespace()
+        .next()
+        .and_then(|frequency| u32::from_str(frequency).ok())
+        .unwrap_or(0);
+    let key_management = KeyManagement::from_security(&fields.pop().unwrap());
+    let signal = fields.pop().unwrap().parse().unwrap_or(0);
+    let bssid = fields.pop().unwrap().to_uppercase();
+    let ssid = fields.pop().unwrap();
+
+    ScanEntry {
+        ssid: ssid,
+        bssid: bssid,
+        signal: signal,
+        key_management: key_management,
+        frequency: frequency,
+    }
+}
+
+/// Requests a new scan. Hidden SSIDs are probed for actively, as they only
+/// appear in the results when an access point answers a probe request for
+/// its SSID.
+pub fn rescan(interface: &str, ssids: &[String]) -> Result<()> {
+    let mut args = vec!["device", "wifi", "rescan", "ifname", interface];
+
+    for ssid in ssids {
+        args.push("ssid");
+        args.push(ssid);
+    }
+
+    run(&args).map(|_| ())
+}
+
+/// Creates and activates a connection profile with an explicit key
+/// management, as `nmcli device wifi connect` and the NetworkManager
+/// bindings assume WPA-PSK for every protected network. Used for WPA3-only
+/// (SAE) and Enhanced Open (OWE) access points, and for profiles locked to a
+/// band or BSSID. The passphrase is stored over D-Bus rather than passed on
+/// the command line, as is the raw SSID of networks which are not UTF-8,
+/// whose lossy `ssid` only names the profile.
+pub fn connect_key_management(
+    interface: &str,
+    ssid: &str,
+    raw_ssid: Option<&[u8]>,
+    passphrase: &str,
+    key_management: KeyManagement,
+    bssid: Option<&str>,
+    band: Option<Band>,
+) -> Result<()> {
+    let mut args = vec![
+        "connection",
+        "add",
+        "type",
+        "wifi",
+        "ifname",
+        interface,
+        "con-name",
+        ssid,
+        "ssid",
+        if raw_ssid.is_some() {
+            RAW_SSID_PLACEHOLDER
+        } else {
+            ssid
+        },
+    ];
+
+    let secret = match key_management {
+        KeyManagement::Open => None,
+        KeyManagement::Wep => {
+            args.extend_from_slice(&["wifi-sec.key-mgmt", "none"]);
+            Some("wep-key0")
+        },
+        KeyManagement::WpaPsk | KeyManagement::Sae => {
+            args.extend_from_slice(&["wifi-sec.key-mgmt", key_management.as_str()]);
+            Some("psk")
+        },
+        KeyManagement::Owe | KeyManagement::Enterprise => {
+            args.extend_from_slice(&["wifi-sec.key-mgmt", key_management.as_str()]);
+            None
+        },
+    };
+
+    if let Some(bssid) = bssid {
+        args.push("802-11-wireless.bssid");
+        args.push(bssid);
+    }
+
+    if let Some(band) = band {
+        args.push("802-11-wireless.band");
+        args.push(band.as_str());
+    }
+
+    let uuid = add_connection(&args)?;
+
+    let result = raw_ssid
+        .map_or(Ok(()), |raw_ssid| set_wifi_ssid(&uuid, raw_ssid))
+        .and_then(|_| secret.map_or(Ok(()), |secret| set_wifi_secret(&uuid, secret, passphrase)))
+        .and_then(|_| run(&["connection", "up", "uuid", &uuid]).map(|_| ()));
+
+    delete_on_error(&uuid, result)
+}
+
+/// Overrides the DNS servers handed out by DHCP on a connection profile and
+/// reactivates it, so that the new servers take effect immediately
+pub fn set_dns_servers(uuid: &str, dns_servers: &[Ipv4Addr]) -> Result<()> {
+    let dns_servers = dns_servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    run(&[
+        "connection",
+        "modify",
+        uuid,
+        "ipv4.dns",
+        &dns_servers,
+        "ipv4.ignore-auto-dns",
+        "yes",
+    ])?;
+
+    run(&["connection", "up", uuid]).map(|_| ())
+}
+
+/// The IPv4 configuration of a device
+pub struct Ipv4Config {
+    pub addresses: Vec<(Ipv4Addr, u8)>,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns_servers: Vec<Ipv4Addr>,
+}
+
+/// Returns the IPv4 addresses of a device with their prefix lengths
+pub fn get_ipv4_addresses(interface: &str) -> Result<Vec<(Ipv4Addr, u8)>> {
+    get_ipv4_config(interface).map(|config| config.addresses)
+}
+
+/// Returns the IPv4 addresses, gateway and DNS servers of a device
+pub fn get_ipv4_config(interface: &str) -> Result<Ipv4Config> {
+    let output = run(&[
+        "--terse",
+        "--fields",
+        "IP4.ADDRESS,IP4.GATEWAY,IP4.DNS",
+        "device",
+        "show",
+        interface,
+    ])?;
+
+    let mut config = Ipv4Config {
+        addresses: Vec::new(),
+        gateway: None,
+        dns_servers: Vec::new(),
+    };
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() != 2 {
+            continue;
+        }
+
+        // Multi-valued fields are reported as `IP4.ADDRESS[1]`, `IP4.ADDRESS[2]`...
+        let name = fields[0].split('[').next().unwrap();
+
+        match name {
+            "IP4.ADDRESS" => {
+                let mut parts = fields[1].split('/');
+
+                let address = parts.next().and_then(|a| Ipv4Addr::from_str(a).ok());
+                let prefix = parts.next().and_then(|p| u8::from_str(p).ok());
+
+                if let (Some(address), Some(prefix)) = (address, prefix) {
+                    config.addresses.push((address, prefix));
+                }
+            },
+            "IP4.GATEWAY" => {
+                config.gateway = Ipv4Addr::from_str(&fields[1]).ok();
+            },
+            "IP4.DNS" => if let Ok(address) = Ipv4Addr::from_str(&fields[1]) {
+                config.dns_servers.push(address);
+            },
+            _ => {},
+        }
+    }
+
+    Ok(config)
+}
+
+/// The IPv6 configuration of a device
+pub struct Ipv6Config {
+    pub addresses: Vec<(Ipv6Addr, u8)>,
+    pub gateway: Option<Ipv6Addr>,
+}
+
+impl Ipv6Config {
+    /// Whether the device has a routable address and a default route, i.e.
+    /// more than the link-local configuration every interface gets
+    pub fn is_routable(&self) -> bool {
+        self.gateway.is_some() && self.addresses.iter().any(|&(address, _)| {
+            let first = address.segments()[0];
+            !address.is_loopback() && first & 0xffc0 != 0xfe80
+        })
+    }
+}
+
+/// Returns the IPv6 addresses and gateway of a device
+pub fn get_ipv6_config(interface: &str) -> Result<Ipv6Config> {
+    let output = run(&[
+        "--terse",
+        "--fields",
+        "IP6.ADDRESS,IP6.GATEWAY",
+        "device",
+        "show",
+        interface,
+    ])?;
+
+    let mut config = Ipv6Config {
+        addresses: Vec::new(),
+        gateway: None,
+    };
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() != 2 {
+            continue;
+        }
+
+        let name = fields[0].split('[').next().unwrap();
+
+        match name {
+            "IP6.ADDRESS" => {
+                let mut parts = fields[1].split('/');
+
+                let address = parts.next().and_then(|a| Ipv6Addr::from_str(a).ok());
+                let prefix = parts.next().and_then(|p| u8::from_str(p).ok());
+
+                if let (Some(address), Some(prefix)) = (address, prefix) {
+                    config.addresses.push((address, prefix));
+                }
+            },
+            "IP6.GATEWAY" => {
+                config.gateway = Ipv6Addr::from_str(&fields[1]).ok();
+            },
+            _ => {},
+        }
+    }
+
+    Ok(config)
+}
+
+/// Returns the name of the connection profile active on a device
+pub fn get_device_connection(interface: &str) -> Result<Option<String>> {
+    let output = run(&[
+        "--terse",
+        "--fields",
+        "GENERAL.CONNECTION",
+        "device",
+        "show",
+        interface,
+    ])?;
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() == 2 && !fields[1].is_empty() && fields[1] != "--" {
+            return Ok(Some(fields[1].clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Configures a wired device with DHCP, or with a static address when one
+/// is given, and reactivates its connection profile. A profile is created
+/// if the device has none.
+pub fn configure_wired_ipv4(
+    interface: &str,
+    address: Option<(Ipv4Addr, u8)>,
+    gateway: Option<Ipv4Addr>,
+    dns_servers: &[Ipv4Addr],
+) -> Result<()> {
+    let connection = match get_device_connection(interface)? {
+        Some(connection) => connection,
+        None => {
+            let connection = format!("Wired connection {}", interface);
+
+            run(&[
+                "connection",
+                "add",
+                "type",
+                "ethernet",
+                "ifname",
+                interface,
+                "con-name",
+                &connection,
+            ])?;
+
+            connection
+        },
+    };
+
+    let (method, address) = match address {
+        Some((address, prefix)) => ("manual", format!("{}/{}", address, prefix)),
+        None => ("auto", String::new()),
+    };
+
+    let gateway = gateway.map_or_else(String::new, |gateway| gateway.to_string());
+
+    let dns_servers = dns_servers
+        .iter()
+        .map(|server| server.to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let ignore_auto_dns = if dns_servers.is_empty() { "no" } else { "yes" };
+
+    run(&[
+        "connection",
+        "modify",
+        "id",
+        &connection,
+        "ipv4.method",
+        method,
+        "ipv4.addresses",
+        &address,
+        "ipv4.gateway",
+        &gateway,
+        "ipv4.dns",
+        &dns_servers,
+        "ipv4.ignore-auto-dns",
+        ignore_auto_dns,
+    ])?;
+
+    run(&["connection", "up", "id", &connection]).map(|_| ())
+}
+
+/// Creates and activates a connection profile giving a wired device the
+/// portal gateway address
+pub fn add_wired_portal(interface: &str, name: &str, gateway: Ipv4Addr, prefix: u8) -> Result<()> {
+    let address = format!("{}/{}", gateway, prefix);
+
+    run(&[
+        "connection",
+        "add",
+        "type",
+        "ethernet",
+        "ifname",
+        interface,
+        "con-name",
+        name,
+        "autoconnect",
+        "no",
+        "ipv4.method",
+        "manual",
+        "ipv4.addresses",
+        &address,
+        "ipv6.method",
+        "ignore",
+    ])?;
+
+    run(&["connection", "up", "id", name]).map(|_| ())
+}
+
+/// Returns the Wi-Fi Direct device, e.g. `p2p-dev-wlan0`, which
+/// NetworkManager adds next to a WiFi device whose driver supports P2P
+pub fn get_p2p_device() -> Result<Option<String>> {
+    let output = run(&["--terse", "--fields", "DEVICE,TYPE", "device", "status"])?;
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() == 2 && fields[1] == "wifi-p2p" {
+            return Ok(Some(fields[0].clone()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Creates and activates a connection profile inviting a peer into a P2P
+/// group, which the device owns and shares its addresses on. Activation
+/// waits until the peer accepted or declined the invitation.
+pub fn add_p2p_group(interface: &str, name: &str, peer: &str) -> Result<()> {
+    run(&[
+        "connection",
+        "add",
+        "type",
+        "wifi-p2p",
+        "ifname",
+        interface,
+        "con-name",
+        name,
+        "autoconnect",
+        "no",
+        "wifi-p2p.peer",
+        peer,
+        "ipv4.method",
+        "shared",
+        "ipv6.method",
+        "ignore",
+    ])?;
+
+    run(&["connection", "up", "id", name]).map(|_| ())
+}
+
+pub fn delete_connection(name: &str) -> Result<()> {
+    run(&["connection", "delete", "id", name]).map(|_| ())
+}
+
+/// A saved WiFi connection profile with its secrets
+pub struct WifiProfile {
+    pub ssid: String,
+    pub key_management: String,
+    pub passphrase: Option<String>,
+    pub ipv4_method: String,
+    pub ipv4_addresses: Vec<String>,
+    pub ipv4_gateway: Option<String>,
+    pub ipv4_dns: Vec<String>,
+}
+
+/// Lists the saved WiFi client profiles. Access point profiles, like the one
+/// of the captive portal, are left out.
+pub fn get_wifi_profiles() -> Result<Vec<WifiProfile>> {
+    let output = run(&["--terse", "--fields", "UUID,TYPE", "connection", "show"])?;
+
+    let mut profiles = Vec::new();
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() != 2 || fields[1] != "802-11-wireless" {
+            continue;
+        }
+
+        if let Some(profile) = get_wifi_profile(&fields[0])? {
+            profiles.push(profile);
+        }
+    }
+
+    Ok(profiles)
+}
+
+fn get_wifi_profile(uuid: &str) -> Result<Option<WifiProfile>> {
+    let output = run(&[
+        "--show-secrets",
+        "--terse",
+        "--fields",
+        "802-11-wireless.ssid,802-11-wireless.mode,802-11-wireless-security.key-mgmt,\
+         802-11-wireless-security.psk,ipv4.method,ipv4.addresses,ipv4.gateway,ipv4.dns",
+        "connection",
+        "show",
+        "uuid",
+        uuid,
+    ])?;
+
+    let mut profile = WifiProfile {
+        ssid: String::new(),
+        key_management: String::new(),
+        passphrase: None,
+        ipv4_method: "auto".into(),
+        ipv4_addresses: vec![],
+        ipv4_gateway: None,
+        ipv4_dns: vec![],
+    };
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() != 2 || fields[1].is_empty() || fields[1] == "--" {
+            continue;
+        }
+
+        let value = fields[1].clone();
+
+        match fields[0].as_str() {
+            "802-11-wireless.ssid" => profile.ssid = value,
+            "802-11-wireless.mode" if value == "ap" => return Ok(None),
+            "802-11-wireless-security.key-mgmt" => profile.key_management = value,
+            "802-11-wireless-security.psk" => profile.passphrase = Some(value),
+            "ipv4.method" => profile.ipv4_method = value,
+            "ipv4.addresses" => profile.ipv4_addresses = split_list(&value),
+            "ipv4.gateway" => profile.ipv4_gateway = Some(value),
+            "ipv4.dns" => profile.ipv4_dns = split_list(&value),
+            _ => {},
+        }
+    }
+
+    Ok(Some(profile))
+}
+
+/// A saved WiFi connection profile
+pub struct SavedProfile {
+    pub ssid: String,
+    pub key_management: KeyManagement,
+    pub autoconnect: bool,
+    /// Last activation in seconds since the epoch, 0 if never activated
+    pub timestamp: u64,
+    /// Autoconnect priority, higher ones are preferred
+    pub priority: i32,
+}
+
+/// Lists the saved WiFi connection profiles, without access point ones like
+/// the captive portal
+pub fn get_saved_profiles() -> Result<Vec<SavedProfile>> {
+    let output = run(&[
+        "--terse",
+        "--fields",
+        "UUID,TYPE,AUTOCONNECT,TIMESTAMP",
+        "connection",
+        "show",
+    ])?;
+
+    let mut profiles = Vec::new();
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() != 4 || (fields[1] != "802-11-wireless" && fields[1] != "wifi") {
+            continue;
+        }
+
+        let settings = get_connection_settings(
+            &fields[0],
+            &[
+                "802-11-wireless.ssid",
+                "802-11-wireless.mode",
+                "802-11-wireless-security.key-mgmt",
+                "connection.autoconnect-priority",
+            ],
+        )?;
+
+        let get = |name: &str| {
+            settings
+                .iter()
+                .find(|&&(ref setting, _)| setting == name)
+                .map_or("", |&(_, ref value)| value as &str)
+        };
+
+        if get("802-11-wireless.mode") == "ap" {
+            continue;
+        }
+
+        profiles.push(SavedProfile {
+            ssid: get("802-11-wireless.ssid").to_string(),
+            key_management: KeyManagement::from_key_mgmt(get("802-11-wireless-security.key-mgmt")),
+            autoconnect: fields[2] == "yes",
+            timestamp: u64::from_str(&fields[3]).unwrap_or(0),
+            priority: i32::from_str(get("connection.autoconnect-priority")).unwrap_or(0),
+        });
+    }
+
+    Ok(profiles)
+}
+
+/// A WiFi device as reported by `nmcli device show`
+pub struct WifiDevice {
+    pub interface: String,
+    pub mac: String,
+    pub driver: String,
+    pub state: String,
+    /// The supported `WIFI-PROPERTIES`, e.g. `ap`, `5ghz` or `wpa2`
+    pub capabilities: Vec<String>,
+}
+
+/// Lists the WiFi devices known to NetworkManager
+pub fn get_wifi_devices() -> Result<Vec<WifiDevice>> {
+    let output = run(&[
+        "--terse",
+        "--fields",
+        "DEVICE,TYPE,STATE",
+        "device",
+        "status",
+    ])?;
+
+    let mut devices = Vec::new();
+
+    for line in output.lines() {
+        let fields = split_terse(line);
+
+        if fields.len() != 3 || fields[1] != "wifi" {
+            continue;
+        }
+
+        let mut device = WifiDevice {
+            interface: fields[0].clone(),
+            mac: String::new(),
+            driver: String::new(),
+            state: fields[2].clone(),
+            capabilities: Vec::new(),
+        };
+
+        let properties = run(&[
+            "--terse",
+            "--fields",
+            "GENERAL.HWADDR,GENERAL.DRIVER,WIFI-PROPERTIES",
+            "device",
+            "show",
+            &device.interface,
+        ])?;
+
+        for line in properties.lines() {
+            let fields = split_terse(line);
+
+            if fields.len() != 2 {
+                continue;
+            }
+
+            match fields[0].as_str() {
+                "GENERAL.HWADDR" => device.mac = fields[1].clone(),
+                "GENERAL.DRIVER" => device.driver = fields[1].clone(),
+                name if name.starts_with("WIFI-PROPERTIES.") && fields[1] == "yes" => {
+                    let capability = &name["WIFI-PROPERTIES.".len()..];
+                    device.capabilities.push(capability.to_lowercase());
+                },
+                _ => {},
+            }
+        }
+
+        devices.push(device);
+    }
+
+    Ok(devices)
+}
+
+/// Creates a WiFi connection profile without activating it
+pub fn add_wifi_profile(profile: &WifiProfile) -> Result<()> {
+    let addresses = profile.ipv4_addresses.join(",");
+    let dns = profile.ipv4_dns.join(",");
+
+    let mut args = vec![
+        "connection",
+        "add",
+        "type",
+        "wifi",
+        "ifname",
+        "*",
+        "con-name",
+        &profile.ssid,
+        "ssid",
+        &profile.ssid,
+        "ipv4.method",
+        &profile.ipv4_method,
+    ];
+
+    if !profile.key_management.is_empty() {
+        args.push("wifi-sec.key-mgmt");
+        args.push(&profile.key_management);
+    }
+
+    if !addresses.is_empty() {
+        args.push("ipv4.addresses");
+        args.push(&addresses);
+    }
+
+    if let Some(ref gateway) = profile.ipv4_gateway {
+        args.push("ipv4.gateway");
+        args.push(gateway);
+    }
+
+    if !dns.is_empty() {
+        args.push("ipv4.dns");
+        args.push(&dns);
+    }
+
+    let uuid = add_connection(&args)?;
+
+    let result = match profile.passphrase {
+        Some(ref passphrase) => set_wifi_secret(&uuid, "psk", passphrase),
+        None => Ok(()),
+    };
+
+    delete_on_error(&uuid, result)
+}
+
+/// Loads a connection profile file from the system connections directory
+pub fn load_connection_file(path: &Path) -> Result<()> {
+    let path = path.to_string_lossy();
+
+    run(&["connection", "load", &path]).map(|_| ())
+}
+
+/// Splits list values, which `nmcli` separates with commas and optional
+/// spaces
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Reads settings of a connection profile, e.g. `ipv4.method`. Unset values
+/// are returned as empty strings.
+pub fn get_connection_settings(uuid: &str, names: &[&str]) -> Result<Vec<(String, String)>> {
+    let fields = names.join(",");
+
+    let output = run(&[
+        "--terse",
+        "--fields",
+        &fields,
+        "connection",
+        "show",
+        "uuid",
+        uuid,
+    ])?;
+
+    let mut settings = Vec::new();
+
+    for line in output.lines() {
+        let mut fields = split_terse(line);
+
+        if fields.len() != 2 {
+            continue;
+        }
+
+        let mut value = fields.pop().unwrap();
+        let name = fields.pop().unwrap();
+
+        if value == "--" {
+            value = String::new();
+        }
+
+        settings.push((name, value));
+    }
+
+    Ok(settings)
+}
+
+/// Modifies settings of a connection profile and reactivates it
+pub fn modify_connection(uuid: &str, settings: &[(String, String)]) -> Result<()> {
+    let mut args = vec!["connection", "modify", "uuid", uuid];
+
+    for &(ref name, ref value) in settings {
+        args.push(name);
+        args.push(value);
+    }
+
+    run(&args)?;
+
+    run(&["connection", "up", "uuid", uuid]).map(|_| ())
+}
+
+/// Returns the access point the device is currently associated with
+pub fn get_active_scan_entry(interface: &str) -> Result<Option<ScanEntry>> {
+    let output = run(&[
+        "--terse",
+        "--fields",
+        "ACTIVE,SSID,BSSID,SIGNAL,SECURITY,FREQ",
+        "device",
+        "wifi",
+        "list",
+        "ifname",
+        interface,
+    ])?;
+
+    for line in output.lines() {
+        let mut fields = split_terse(line);
+
+        if fields.len() != 6 || fields[0] != "yes" {
+            continue;
+        }
+
+        fields.remove(0);
+
+        return Ok(Some(parse_scan_entry(fields)));
+    }
+
+    Ok(None)
+}
+
+/// Adds a connection profile and returns its UUID, which `nmcli` confirms
+/// with e.g. `Connection 'Home' (<uuid>) successfully added.`
+fn add_connection(args: &[&str]) -> Result<String> {
+    let output = run(args)?;
+
+    match (output.rfind('('), output.rfind(')')) {
+        (Some(start), Some(end)) if start < end => Ok(output[start + 1..end].to_string()),
+        _ => bail!(ErrorKind::NmcliOutput(redact_args(args).join(" "))),
+    }
+}
+
+/// Deletes a profile which was added but could not be set up completely, so
+/// that a retry does not leave a duplicate behind
+fn delete_on_error(uuid: &str, result: Result<()>) -> Result<()> {
+    if result.is_err() {
+        if let Err(e) = run(&["connection", "delete", "uuid", uuid]) {
+            warn!("Deleting incomplete connection profile {} failed: {}", uuid, e);
+        }
+    }
+
+    result
+}
+
+fn run(args: &[&str]) -> Result<String> {
+    let command = redact_args(args).join(" ");
+
+    let output = Command::new("nmcli")
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::Nmcli(command.clone()))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        bail!(ErrorKind::NmcliStatus(command, stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Splits a line of `nmcli --terse` output, where `:` separates fields and
+/// literal colons and backslashes in values are escaped with a backslash
+fn split_terse(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => if let Some(escaped) = chars.next() {
+                field.push(escaped);
+            },
+            ':' => {
+                fields.push(field);
+                field = String::new();
+            },
+            _ => field.push(c),
+        }
+    }
+
+    fields.push(field);
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terse_fields_are_split_at_unescaped_colons() {
+        assert_eq!(split_terse("yes:Home:70"), ["yes", "Home", "70"]);
+        assert_eq!(split_terse(""), [""]);
+        assert_eq!(split_terse("::"), ["", "", ""]);
+        assert_eq!(split_terse(r"a\:b\\:c"), [r"a:b\", "c"]);
+        assert_eq!(split_terse(r"trailing\"), ["trailing"]);
+    }
+
+    #[test]
+    fn scan_line_with_escaped_bssid() {
+        let fields = split_terse(r"Café\: 2.4:aa\:bb\:cc\:dd\:ee\:ff:70:WPA2:2437 MHz");
+
+        assert_eq!(fields.len(), 5);
+
+        let entry = parse_scan_entry(fields);
+
+        assert_eq!(entry.ssid, "Café: 2.4");
+        assert_eq!(entry.bssid, "AA:BB:CC:DD:EE:FF");
+        assert_eq!(entry.signal, 70);
+        assert_eq!(entry.key_management, KeyManagement::WpaPsk);
+        assert_eq!(entry.frequency, 2437);
+    }
+}
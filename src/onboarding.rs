@@ -0,0 +1,87 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+
+/// Milestones of provisioning a device through the captive portal, from
+/// which the time it took the user to configure it is derived
+#[derive(Default)]
+pub struct Onboarding {
+    portal_opened: Option<SystemTime>,
+    first_request: Option<SystemTime>,
+    credentials_submitted: Option<SystemTime>,
+    connectivity_confirmed: Option<SystemTime>,
+}
+
+impl Onboarding {
+    /// Starts a new session, forgetting the milestones of the previous one
+    pub fn portal_opened(&mut self) {
+        *self = Onboarding {
+            portal_opened: Some(SystemTime::now()),
+            ..Onboarding::default()
+        };
+    }
+
+    pub fn client_request(&mut self) {
+        if self.portal_opened.is_some() && self.first_request.is_none() {
+            self.first_request = Some(SystemTime::now());
+        }
+    }
+
+    /// Only the last of several attempts counts, as the time to connect is
+    /// that of the attempt which succeeded
+    pub fn credentials_submitted(&mut self) {
+        if self.portal_opened.is_some() {
+            self.credentials_submitted = Some(SystemTime::now());
+        }
+    }
+
+    /// Ends the session and returns its milestones and durations, unless the
+    /// device was connected without the portal, e.g. on start
+    pub fn connectivity_confirmed(&mut self) -> Option<Value> {
+        self.portal_opened?;
+
+        self.connectivity_confirmed = Some(SystemTime::now());
+
+        let report = self.to_json();
+
+        *self = Onboarding::default();
+
+        Some(report)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "portal_opened": get_timestamp(self.portal_opened),
+            "first_request": get_timestamp(self.first_request),
+            "credentials_submitted": get_timestamp(self.credentials_submitted),
+            "connectivity_confirmed": get_timestamp(self.connectivity_confirmed),
+            "durations": {
+                "portal_to_first_request_s": get_duration(self.portal_opened, self.first_request),
+                "first_request_to_submit_s": get_duration(
+                    self.first_request,
+                    self.credentials_submitted,
+                ),
+                "submit_to_connected_s": get_duration(
+                    self.credentials_submitted,
+                    self.connectivity_confirmed,
+                ),
+                "time_to_configure_s": get_duration(
+                    self.portal_opened,
+                    self.connectivity_confirmed,
+                ),
+            },
+        })
+    }
+}
+
+fn get_timestamp(time: Option<SystemTime>) -> Option<u64> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+fn get_duration(from: Option<SystemTime>, to: Option<SystemTime>) -> Option<u64> {
+    match (from, to) {
+        (Some(from), Some(to)) => to.duration_since(from).ok().map(|duration| duration.as_secs()),
+        _ => None,
+    }
+}
@@ -0,0 +1,492 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess,
+                Visitor};
+use serde::de::value::StrDeserializer;
+use serde::Deserializer;
+use serde_json::{Map, Value};
+
+/// An operation of the HTTP API, as listed in the OpenAPI description
+pub struct Operation {
+    pub method: String,
+    /// Path below the versioned prefix, e.g. `/connect/any`
+    pub path: &'static str,
+    pub summary: &'static str,
+    pub query: &'static [&'static str],
+    /// Schema of the request body, if the operation takes one
+    pub request: Option<Value>,
+    pub media_type: &'static str,
+    pub protected: bool,
+}
+
+/// Builds the OpenAPI 3.0 description of the HTTP API. Response bodies are
+/// assembled from the network thread results rather than typed structs, so
+/// only the error body has a schema.
+pub fn get_document(
+    version: &str,
+    prefix: &str,
+    operations: &[Operation],
+    basic: bool,
+    bearer: bool,
+) -> Value {
+    let mut paths = Map::new();
+
+    for operation in operations {
+        let mut item = json!({
+            "summary": operation.summary,
+            "operationId": get_operation_id(operation),
+            "responses": {
+                "200": {
+                    "description": "Success",
+                    "content": { operation.media_type: {} },
+                },
+                "default": { "$ref": "#/components/responses/Error" },
+            },
+        });
+
+        if !operation.query.is_empty() {
+            item["parameters"] = operation
+                .query
+                .iter()
+                .map(|name| {
+                    json!({
+                        "name": name,
+                        "in": "query",
+                        "schema": { "type": "string" },
+                    })
+                })
+                .collect();
+        }
+
+        // Form encoded bodies are accepted as well, with the same fields
+        if let Some(ref request) = operation.request {
+            item["requestBody"] = json!({
+                "required": true,
+                "content": {
+                    "application/json": { "schema": request },
+                    "application/x-www-form-urlencoded": { "schema": request },
+                },
+            });
+        }
+
+        if operation.protected && (basic || bearer) {
+            let mut security = Vec::new();
+
+            if basic {
+                security.push(json!({ "basic": [] }));
+            }
+
+            if bearer {
+                security.push(json!({ "bearer": [] }));
+            }
+
+            item["security"] = Value::Array(security);
+        }
+
+        let path = paths
+            .entry(format!("{}{}", prefix, operation.path))
+            .or_insert_with(|| json!({}));
+
+        path[operation.method.to_lowercase()] = item;
+    }
+
+    json!({
+        "openapi": "3.0.0",
+        "info": {
+            "title": "WiFi Connect",
+            "version": version,
+        },
+        "paths": paths,
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "properties": {
+                        "code": { "type": "string" },
+                        "message": { "type": "string" },
+                        "details": {},
+                    },
+                    "required": ["code", "message", "details"],
+                },
+            },
+            "responses": {
+                "Error": {
+                    "description": "Error",
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/Error" },
+                        },
+                    },
+                },
+            },
+            "securitySchemes": {
+                "basic": { "type": "http", "scheme": "basic" },
+                "bearer": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+/// E.g. `postConnectAny` for `POST /connect/any`
+fn get_operation_id(operation: &Operation) -> String {
+    let mut id = operation.method.to_lowercase();
+
+    for word in operation.path.split(|c| c == '/' || c == '-') {
+        let mut chars = word.chars();
+
+        if let Some(first) = chars.next() {
+            id.extend(first.to_uppercase());
+            id.push_str(chars.as_str());
+        }
+    }
+
+    id
+}
+
+/// Derives the JSON schema of a request struct from its `Deserialize`
+/// implementation, by deserializing it from a tracer which records the type
+/// every field asks for. A field is required if deserializing fails without
+/// it. Fields parsed through `Value`, e.g. flags which also accept strings,
+/// have no type.
+pub fn get_request_schema<T>() -> Value
+where
+    T: DeserializeOwned,
+{
+    let mut schema = Value::Null;
+
+    if T::deserialize(Tracer::new(&mut schema, None)).is_err() {
+        return json!({ "type": "object" });
+    }
+
+    let fields = schema["properties"]
+        .as_object()
+        .map_or_else(Vec::new, |properties| properties.keys().cloned().collect());
+
+    let required = fields
+        .into_iter()
+        .filter(|field| {
+            let mut ignored = Value::Null;
+            T::deserialize(Tracer::new(&mut ignored, Some(field))).is_err()
+        })
+        .collect::<Vec<_>>();
+
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+
+    schema
+}
+
+#[derive(Debug)]
+struct TraceError(String);
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for TraceError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl de::Error for TraceError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        TraceError(msg.to_string())
+    }
+}
+
+/// Records the schema of the value it deserializes, handing the visitors
+/// empty sample values
+struct Tracer<'a> {
+    schema: &'a mut Value,
+    /// Field of the struct left out, to find out whether it is required
+    omitted: Option<&'a str>,
+}
+
+impl<'a> Tracer<'a> {
+    fn new(schema: &'a mut Value, omitted: Option<&'a str>) -> Self {
+        Tracer {
+            schema: schema,
+            omitted: omitted,
+        }
+    }
+
+    fn set(&mut self, kind: &str) {
+        *self.schema = json!({ "type": kind });
+    }
+}
+
+impl<'de, 'a> Deserializer<'de> for Tracer<'a> {
+    type Error = TraceError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json!({});
+        visitor.visit_str("")
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set("boolean");
+        visitor.visit_bool(false)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_i64(visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set("integer");
+        visitor.visit_i64(0)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_u64(visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json!({ "type": "integer", "minimum": 0 });
+        visitor.visit_u64(0)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_f64(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set("number");
+        visitor.visit_f64(0.0)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set("string");
+        visitor.visit_char(' ')
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set("string");
+        visitor.visit_str("")
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        self.deserialize_byte_buf(visitor)
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(
+        mut self,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.set("string");
+        visitor.visit_bytes(&[])
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let value = visitor.visit_some(Tracer::new(&mut *self.schema, None))?;
+
+        if let Some(schema) = self.schema.as_object_mut() {
+            schema.insert("nullable".to_string(), Value::Bool(true));
+        }
+
+        Ok(value)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        *self.schema = json!({});
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        let mut items = Value::Null;
+
+        let value = visitor.visit_seq(SeqTracer {
+            items: &mut items,
+            remaining: 1,
+        })?;
+
+        *self.schema = json!({ "type": "array", "items": items });
+
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(mut self, visitor: V) -> Result<V::Value, TraceError> {
+        self.set("object");
+
+        let mut properties = Map::new();
+
+        visitor.visit_map(StructTracer {
+            fields: &[],
+            index: 0,
+            omitted: None,
+            properties: &mut properties,
+        })
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        let mut properties = Map::new();
+
+        let value = visitor.visit_map(StructTracer {
+            fields: fields,
+            index: 0,
+            omitted: self.omitted,
+            properties: &mut properties,
+        })?;
+
+        *self.schema = json!({ "type": "object", "properties": properties });
+
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        *self.schema = json!({ "type": "string", "enum": variants });
+
+        let variant = variants.first().cloned().unwrap_or("");
+
+        visitor.visit_enum(get_str_deserializer(variant))
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, TraceError> {
+        visitor.visit_str("")
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(
+        self,
+        visitor: V,
+    ) -> Result<V::Value, TraceError> {
+        visitor.visit_unit()
+    }
+}
+
+/// Hands the fields of a struct to its visitor in declaration order
+struct StructTracer<'a> {
+    fields: &'static [&'static str],
+    index: usize,
+    omitted: Option<&'a str>,
+    properties: &'a mut Map<String, Value>,
+}
+
+impl<'de, 'a> MapAccess<'de> for StructTracer<'a> {
+    type Error = TraceError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, TraceError> {
+        if self.omitted.map_or(false, |omitted| {
+            self.fields.get(self.index) == Some(&omitted)
+        }) {
+            self.index += 1;
+        }
+
+        match self.fields.get(self.index) {
+            Some(field) => seed.deserialize(get_str_deserializer(field)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, TraceError> {
+        let field = self.fields[self.index];
+        self.index += 1;
+
+        let mut schema = Value::Null;
+        let value = seed.deserialize(Tracer::new(&mut schema, None))?;
+
+        self.properties.insert(field.to_string(), schema);
+
+        Ok(value)
+    }
+}
+
+/// Hands a single sample item to the visitor of a sequence
+struct SeqTracer<'a> {
+    items: &'a mut Value,
+    remaining: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for SeqTracer<'a> {
+    type Error = TraceError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, TraceError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+
+        self.remaining -= 1;
+
+        seed.deserialize(Tracer::new(&mut *self.items, None)).map(Some)
+    }
+}
+
+fn get_str_deserializer(value: &str) -> StrDeserializer<TraceError> {
+    value.into_deserializer()
+}
@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use dbus::{BusType, Connection, Message, Path};
+use dbus::arg::{Array, Dict, Iter, Variant};
+use network_manager::NetworkManager;
+
+use errors::*;
+use network::NetworkCommand;
+use logger::log_event;
+use nmcli::{add_p2p_group, delete_connection, get_device_connection, get_ipv4_addresses,
+            get_p2p_device};
+
+const NM_DBUS_NAME: &str = "org.freedesktop.NetworkManager";
+const NM_DBUS_PATH: &str = "/org/freedesktop/NetworkManager";
+const NM_DBUS_INTERFACE: &str = "org.freedesktop.NetworkManager";
+const NM_DEVICE_INTERFACE: &str = "org.freedesktop.NetworkManager.Device";
+const NM_P2P_DEVICE_INTERFACE: &str = "org.freedesktop.NetworkManager.Device.WifiP2P";
+const NM_P2P_PEER_INTERFACE: &str = "org.freedesktop.NetworkManager.WifiP2PPeer";
+const DBUS_PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+
+/// Time to wait for NetworkManager to answer (milliseconds)
+const CALL_TIMEOUT: i32 = 5_000;
+
+/// Connection profile of the P2P group with the companion app
+pub const P2P_CONNECTION: &str = "wifi-connect-p2p";
+
+/// How often the peers and the group are checked (seconds)
+const POLL_INTERVAL: u64 = 2;
+
+/// Time NetworkManager searches for peers per call, after which the search
+/// is started again (seconds)
+const FIND_TIMEOUT: i32 = 30;
+
+/// The P2P group with the companion app, which the HTTP API is served on
+struct P2pGroup {
+    peer: String,
+    interface: String,
+}
+
+/// Offers the captive portal API over Wi-Fi Direct while provisioning: the
+/// device searches for peers, which phones are while their Wi-Fi Direct
+/// settings or the companion app look for devices, and invites the first one
+/// into a P2P group. The network thread serves the HTTP API on the group
+/// once the invitation is accepted. The group ends once the device is online
+/// or the phone leaves it, after which the next peer is invited.
+pub fn spawn_p2p(enabled: Arc<AtomicBool>, network_tx: Sender<NetworkCommand>) {
+    let interface = match get_p2p_device() {
+        Ok(Some(interface)) => interface,
+        Ok(None) => {
+            warn!("Wi-Fi Direct disabled: no P2P device, which needs NetworkManager 1.16");
+            return;
+        },
+        Err(e) => {
+            warn!("Wi-Fi Direct disabled: {}", e);
+            return;
+        },
+    };
+
+    let path = match get_device_path(&interface) {
+        Some(path) => path,
+        None => {
+            warn!("Wi-Fi Direct disabled: cannot find {}", interface);
+            return;
+        },
+    };
+
+    info!("Offering the captive portal over Wi-Fi Direct on {}", interface);
+
+    // Left behind if the previous run was killed
+    let _ = delete_connection(P2P_CONNECTION);
+
+    thread::spawn(move || {
+        let mut group: Option<P2pGroup> = None;
+        let mut declined = HashSet::new();
+        let mut last_find: Option<Instant> = None;
+
+        loop {
+            thread::sleep(Duration::from_secs(POLL_INTERVAL));
+
+            let is_active = group.as_ref().map_or(false, |group| {
+                get_device_connection(&group.interface)
+                    .ok()
+                    .and_then(|connection| connection)
+                    .map_or(false, |connection| connection == P2P_CONNECTION)
+            });
+
+            if !enabled.load(Ordering::SeqCst) || (group.is_some() && !is_active) {
+                if let Some(group) = group.take() {
+                    stop_group(&group);
+                }
+
+                if !enabled.load(Ordering::SeqCst) {
+                    last_find = None;
+                    continue;
+                }
+            }
+
+            if group.is_some() {
+                continue;
+            }
+
+            let find_expired = last_find.map_or(true, |last_find| {
+                last_find.elapsed() >= Duration::from_secs(FIND_TIMEOUT as u64)
+            });
+
+            if find_expired {
+                if let Err(e) = start_find(&path) {
+                    warn!("Searching for Wi-Fi Direct peers failed: {}", e);
+                }
+
+                last_find = Some(Instant::now());
+            }
+
+            let peers = match get_peers(&path) {
+                Ok(peers) => peers,
+                Err(e) => {
+                    debug!("Listing Wi-Fi Direct peers failed: {}", e);
+                    continue;
+                },
+            };
+
+            // A peer which declined is invited again only once it searched
+            // anew, i.e. left and rejoined the peer list
+            declined.retain(|address| peers.iter().any(|&(ref peer, _)| peer == address));
+
+            let peer = peers
+                .into_iter()
+                .find(|&(ref address, _)| !declined.contains(address));
+
+            if let Some((address, name)) = peer {
+                match start_group(&interface, &address, &name) {
+                    Ok((started, gateway)) => {
+                        let command = NetworkCommand::P2pGroupStarted { address: gateway };
+
+                        group = Some(started);
+
+                        if network_tx.send(command).is_err() {
+                            return;
+                        }
+                    },
+                    Err(e) => {
+                        warn!("Wi-Fi Direct peer '{}' did not join: {}", name, e);
+                        declined.insert(address);
+                    },
+                }
+            }
+        }
+    });
+}
+
+/// Removes the P2P group profile on shutdown, which ends the group
+pub fn stop_p2p() {
+    let _ = delete_connection(P2P_CONNECTION);
+}
+
+/// Invites the peer with push button configuration, which the user accepts
+/// on the phone, and returns the address of the device on the group
+fn start_group(interface: &str, peer: &str, name: &str) -> Result<(P2pGroup, Ipv4Addr)> {
+    info!("Inviting Wi-Fi Direct peer '{}' ({})...", name, peer);
+
+    if let Err(e) = add_p2p_group(interface, P2P_CONNECTION, peer) {
+        let _ = delete_connection(P2P_CONNECTION);
+        return Err(e);
+    }
+
+    let group_interface = get_group_interface().ok_or_else(|| {
+        let _ = delete_connection(P2P_CONNECTION);
+        Error::from(ErrorKind::P2p("group interface".to_string()))
+    })?;
+
+    let address = match get_ipv4_addresses(&group_interface) {
+        Ok(ref addresses) if !addresses.is_empty() => addresses[0].0,
+        _ => {
+            let _ = delete_connection(P2P_CONNECTION);
+            bail!(ErrorKind::P2p("group address".to_string()));
+        },
+    };
+
+    info!(
+        "Wi-Fi Direct group with '{}' started on {} ({})",
+        name, group_interface, address
+    );
+
+    log_event(
+        "p2p_group_started",
+        json!({
+            "peer": name,
+            "interface": group_interface,
+            "address": address.to_string(),
+        }),
+    );
+
+    let group = P2pGroup {
+        peer: name.to_string(),
+        interface: group_interface,
+    };
+
+    Ok((group, address))
+}
+
+fn stop_group(group: &P2pGroup) {
+    info!("Stopping Wi-Fi Direct group with '{}'...", group.peer);
+
+    if let Err(e) = delete_connection(P2P_CONNECTION) {
+        warn!("Stopping Wi-Fi Direct group failed: {}", e);
+    }
+
+    log_event("p2p_group_stopped", json!({ "peer": group.peer }));
+}
+
+/// The group interface, e.g. `p2p-wlan0-0`, is created for the group and
+/// differs from the P2P device
+fn get_group_interface() -> Option<String> {
+    let manager = NetworkManager::new();
+
+    manager
+        .get_devices()
+        .ok()?
+        .into_iter()
+        .map(|device| device.interface().to_string())
+        .filter(|interface| interface.starts_with("p2p-") && !interface.starts_with("p2p-dev-"))
+        .find(|interface| {
+            get_device_connection(interface)
+                .ok()
+                .and_then(|connection| connection)
+                .map_or(false, |connection| connection == P2P_CONNECTION)
+        })
+}
+
+/// The D-Bus object path of the device, which is looked up by its interface
+/// name, as P2P devices have no IP interface
+fn get_device_path(interface: &str) -> Option<String> {
+    let reply = call(NM_DBUS_PATH, NM_DBUS_INTERFACE, "GetAllDevices", |message| message).ok()?;
+
+    reply
+        .get1::<Vec<Path>>()?
+        .into_iter()
+        .map(|path| path.to_string())
+        .find(|path| {
+            get_string_property(path, NM_DEVICE_INTERFACE, "Interface")
+                .map_or(false, |name| name == interface)
+        })
+}
+
+fn start_find(path: &str) -> Result<()> {
+    let options = Dict::new(vec![("timeout", Variant(FIND_TIMEOUT))]);
+
+    call(path, NM_P2P_DEVICE_INTERFACE, "StartFind", |message| {
+        message.append1(options)
+    }).map(|_| ())
+}
+
+/// The hardware addresses and names of the peers found
+fn get_peers(path: &str) -> Result<Vec<(String, String)>> {
+    let reply = get_property(path, NM_P2P_DEVICE_INTERFACE, "Peers")?;
+
+    let peers = reply
+        .get1::<Variant<Array<Path, Iter>>>()
+        .ok_or_else(|| ErrorKind::P2p("Peers".to_string()))?
+        .0
+        .map(|peer| peer.to_string())
+        .collect::<Vec<_>>();
+
+    let mut found = Vec::new();
+
+    for peer in peers {
+        let address = get_string_property(&peer, NM_P2P_PEER_INTERFACE, "HwAddress")?;
+        let name = get_string_property(&peer, NM_P2P_PEER_INTERFACE, "Name")?;
+
+        found.push((address, name));
+    }
+
+    Ok(found)
+}
+
+fn get_string_property(path: &str, interface: &str, name: &str) -> Result<String> {
+    let reply = get_property(path, interface, name)?;
+
+    let value = reply
+        .get1::<Variant<&str>>()
+        .ok_or_else(|| ErrorKind::P2p(name.to_string()))?
+        .0
+        .to_string();
+
+    Ok(value)
+}
+
+fn get_property(path: &str, interface: &str, name: &str) -> Result<Message> {
+    call(path, DBUS_PROPERTIES_INTERFACE, "Get", |message| {
+        message.append2(interface, name)
+    })
+}
+
+fn call<F>(path: &str, interface: &str, method: &str, append: F) -> Result<Message>
+where
+    F: FnOnce(Message) -> Message,
+{
+    let connection =
+        Connection::get_private(BusType::System).chain_err(|| ErrorKind::P2p(method.to_string()))?;
+
+    let message = Message::new_method_call(NM_DBUS_NAME, path, interface, method)
+        .map_err(|e| Error::from(ErrorKind::P2p(format!("{}: {}", method, e))))?;
+
+    connection
+        .send_with_reply_and_block(append(message), CALL_TIMEOUT)
+        .chain_err(|| ErrorKind::P2p(method.to_string()))
+}
@@ -0,0 +1,46 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+use errors::*;
+
+/// Random bytes of a token, shown as twice as many hex digits
+const TOKEN_BYTES: usize = 2;
+
+/// Length of the `-<token>` suffix of the portal SSID
+pub const SSID_SUFFIX_LENGTH: usize = 1 + 2 * TOKEN_BYTES;
+
+/// Short token appended to the portal SSID, which clients have to echo in the
+/// `X-Pairing-Token` header. Reading it off the network name proves that the
+/// companion app is within radio range of the device. A new token is drawn
+/// every time the portal is opened.
+#[derive(Clone)]
+pub struct PairingToken {
+    token: Arc<Mutex<Option<String>>>,
+}
+
+impl PairingToken {
+    pub fn new() -> Self {
+        PairingToken {
+            token: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn rotate(&self) -> Result<String> {
+        let mut bytes = [0; TOKEN_BYTES];
+        File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+        let token = bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<String>();
+
+        *self.token.lock().unwrap() = Some(token.clone());
+
+        Ok(token)
+    }
+
+    pub fn get(&self) -> Option<String> {
+        self.token.lock().unwrap().clone()
+    }
+}
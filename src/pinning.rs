@@ -0,0 +1,78 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde_json::Value;
+
+use state::StateStore;
+
+const PINS_DOCUMENT: &str = "bssid_pins";
+
+/// BSSIDs the device associated with for saved SSIDs. A saved SSID showing
+/// up only from unknown BSSIDs, or associating with one, may indicate an evil
+/// twin access point.
+pub struct BssidPins {
+    pins: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl BssidPins {
+    pub fn load(store: &StateStore) -> Self {
+        let mut pins = BTreeMap::new();
+
+        if let Some(value) = store.load(PINS_DOCUMENT) {
+            if let Some(ssids) = value.as_object() {
+                for (ssid, bssids) in ssids {
+                    if let Some(bssids) = bssids.as_array() {
+                        let bssids = bssids
+                            .iter()
+                            .filter_map(|bssid| bssid.as_str().map(String::from))
+                            .collect();
+                        pins.insert(ssid.clone(), bssids);
+                    }
+                }
+            }
+        }
+
+        BssidPins { pins: pins }
+    }
+
+    /// Returns false if BSSIDs are pinned for the SSID and none of the
+    /// visible ones is among them
+    pub fn is_trusted(&self, ssid: &str, visible: &[String]) -> bool {
+        match self.pins.get(ssid) {
+            Some(pinned) => visible.is_empty() || visible.iter().any(|b| pinned.contains(b)),
+            None => true,
+        }
+    }
+
+    /// Returns false if BSSIDs are pinned for the SSID and the one associated
+    /// with is not among them
+    pub fn is_known(&self, ssid: &str, bssid: &str) -> bool {
+        self.pins
+            .get(ssid)
+            .map_or(true, |pinned| pinned.contains(bssid))
+    }
+
+    pub fn get(&self, ssid: &str) -> Vec<String> {
+        self.pins
+            .get(ssid)
+            .map_or_else(Vec::new, |pinned| pinned.iter().cloned().collect())
+    }
+
+    pub fn pin(&mut self, store: &StateStore, ssid: &str, bssid: &str) {
+        let inserted = self.pins
+            .entry(ssid.to_string())
+            .or_insert_with(BTreeSet::new)
+            .insert(bssid.to_string());
+
+        if !inserted {
+            return;
+        }
+
+        if let Err(e) = store.save(PINS_DOCUMENT, &self.to_json()) {
+            warn!("Saving BSSID pins failed: {}", e);
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!(self.pins)
+    }
+}
@@ -0,0 +1,134 @@
+use std::process::Command;
+
+use errors::*;
+use config::Config;
+
+/// Whether the beacon interval and DTIM period of the captive portal can be
+/// set with the NetworkManager backend
+pub const BEACON_SETTINGS_SUPPORTED: bool = false;
+
+/// Lowers the transmit power and enables power saving of the interface while
+/// the captive portal waits for a client, if configured
+pub fn apply_portal_power(interface: &str, config: &Config) {
+    if let Some(tx_power) = config.portal_tx_power {
+        // `iw` takes the power in mBm
+        let tx_power_mbm = (tx_power * 100).to_string();
+
+        match iw(interface, &["set", "txpower", "fixed", &tx_power_mbm]) {
+            Ok(()) => info!("Portal TX power set to {} dBm", tx_power),
+            Err(e) => warn!("Setting portal TX power failed: {}", e),
+        }
+    }
+
+    if config.portal_power_save {
+        match iw(interface, &["set", "power_save", "on"]) {
+            Ok(()) => info!("Portal power save enabled"),
+            Err(e) => warn!("Enabling portal power save failed: {}", e),
+        }
+    }
+
+    // NetworkManager creates the access point without a way to pass these
+    if !BEACON_SETTINGS_SUPPORTED
+        && (config.portal_beacon_interval.is_some() || config.portal_dtim_period.is_some())
+    {
+        warn!("Beacon interval and DTIM period are not supported by NetworkManager - ignored");
+    }
+}
+
+/// Restores the defaults once the captive portal is stopped, so that the
+/// client connection is not weakened
+pub fn reset_portal_power(interface: &str, config: &Config) {
+    if config.portal_tx_power.is_some() {
+        if let Err(e) = iw(interface, &["set", "txpower", "auto"]) {
+            warn!("Resetting TX power failed: {}", e);
+        }
+    }
+
+    if config.portal_power_save || config.portal_sleep_timeout > 0 {
+        if let Err(e) = set_power_save(interface, false) {
+            warn!("Disabling power save failed: {}", e);
+        }
+    }
+}
+
+pub fn set_power_save(interface: &str, enabled: bool) -> Result<()> {
+    iw(
+        interface,
+        &["set", "power_save", if enabled { "on" } else { "off" }],
+    )
+}
+
+/// Counts the clients associated with the access point of the interface
+pub fn get_station_count(interface: &str) -> Result<usize> {
+    let output = Command::new("iw")
+        .args(&["dev", interface, "station", "dump"])
+        .output()
+        .chain_err(|| ErrorKind::Iw)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        bail!(ErrorKind::IwStatus(stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.starts_with("Station "))
+        .count())
+}
+
+/// Returns the signal level of the link to the access point in dBm, as
+/// averaged by the driver over the latest received frames
+pub fn get_link_signal(interface: &str) -> Result<Option<i32>> {
+    let output = iw_output(interface, &["link"])?;
+
+    // e.g. `	signal: -52 dBm`
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("signal:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|signal| signal.parse::<i32>().ok()))
+}
+
+/// Returns the channel and frequency (MHz) the interface operates on, e.g.
+/// that of the captive portal access point
+pub fn get_channel(interface: &str) -> Result<Option<(u32, u32)>> {
+    let output = iw_output(interface, &["info"])?;
+
+    // e.g. `	channel 6 (2437 MHz), width: 20 MHz, center1: 2437 MHz`
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("channel "))
+        .and_then(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let channel = fields.next()?.parse::<u32>().ok()?;
+            let frequency = fields.next()?.trim_left_matches('(').parse::<u32>().ok()?;
+            Some((channel, frequency))
+        }))
+}
+
+fn iw(interface: &str, args: &[&str]) -> Result<()> {
+    iw_output(interface, args).map(|_| ())
+}
+
+fn iw_output(interface: &str, args: &[&str]) -> Result<String> {
+    let mut dev_args = vec!["dev", interface];
+    dev_args.extend_from_slice(args);
+
+    run_iw(&dev_args)
+}
+
+pub fn run_iw(args: &[&str]) -> Result<String> {
+    let output = Command::new("iw")
+        .args(args)
+        .output()
+        .chain_err(|| ErrorKind::Iw)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        bail!(ErrorKind::IwStatus(stderr));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
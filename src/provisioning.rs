@@ -0,0 +1,289 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use serde_json::{self, Value};
+
+use errors::*;
+use backup::SUPPORTED_KEY_MANAGEMENT;
+use logger::log_event;
+use network::{validate_passphrase, validate_ssid};
+use nmcli::{add_wifi_profile, get_saved_profiles, load_connection_file, WifiProfile};
+
+/// Directory NetworkManager keeps the system connection profiles in
+const SYSTEM_CONNECTIONS: &str = "/etc/NetworkManager/system-connections";
+
+/// Networks file for factory pre-provisioning, either a list of networks or
+/// an object with a `networks` list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NetworksFile {
+    List(Vec<Value>),
+    Object { networks: Vec<Value> },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NetworkEntry {
+    ssid: String,
+    #[serde(default)]
+    passphrase: String,
+    #[serde(default)]
+    security: Option<String>,
+    #[serde(default)]
+    ipv4: Option<NetworkIpv4>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NetworkIpv4 {
+    method: String,
+    #[serde(default)]
+    addresses: Vec<String>,
+    #[serde(default)]
+    gateway: Option<String>,
+    #[serde(default)]
+    dns: Vec<String>,
+}
+
+/// Creates the WiFi profiles of a networks file, or loads the `.nmconnection`
+/// files of a directory, before the captive portal is opened. Entries whose
+/// SSID has a saved profile already are left alone, so that the import may
+/// run on every start without undoing changes made in the portal since.
+/// Invalid entries are skipped and reported, only an unreadable file fails.
+pub fn import_networks(path: &Path) -> Result<Value> {
+    let display_path = path.display().to_string();
+
+    let saved = get_saved_profiles()
+        .chain_err(|| ErrorKind::ImportNetworks(display_path.clone()))?
+        .into_iter()
+        .map(|profile| profile.ssid)
+        .collect::<Vec<_>>();
+
+    let results = if path.is_dir() {
+        import_connection_files(path, &saved)
+    } else {
+        import_networks_file(path, &saved)
+    }.chain_err(|| ErrorKind::ImportNetworks(display_path.clone()))?;
+
+    let count = |status: &str| {
+        results
+            .iter()
+            .filter(|result| result["status"] == status)
+            .count()
+    };
+
+    info!(
+        "Imported networks from {}: {} applied, {} existing, {} skipped",
+        display_path,
+        count("applied"),
+        count("existing"),
+        count("skipped")
+    );
+
+    for result in &results {
+        if result["status"] == "skipped" {
+            warn!(
+                "Skipped network {}: {}",
+                result["entry"],
+                result["error"].as_str().unwrap_or("")
+            );
+        }
+    }
+
+    let report = json!({
+        "path": display_path,
+        "networks": results,
+    });
+
+    log_event("import", report.clone());
+
+    Ok(report)
+}
+
+fn import_networks_file(path: &Path, saved: &[String]) -> Result<Vec<Value>> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+
+    let entries = match serde_json::from_str::<NetworksFile>(&contents) {
+        Ok(NetworksFile::List(entries)) | Ok(NetworksFile::Object { networks: entries }) => {
+            entries
+        },
+        Err(e) => bail!(ErrorKind::InvalidNetworksFile(e.to_string())),
+    };
+
+    let mut results = Vec::new();
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        let name = entry["ssid"]
+            .as_str()
+            .map_or_else(|| format!("#{}", index + 1), String::from);
+
+        let result = serde_json::from_value::<NetworkEntry>(entry)
+            .map_err(|e| e.to_string())
+            .and_then(parse_entry)
+            .and_then(|profile| {
+                if saved.contains(&profile.ssid) {
+                    return Ok("existing");
+                }
+
+                info!("Creating WiFi connection profile '{}'", profile.ssid);
+
+                add_wifi_profile(&profile)
+                    .map(|_| "applied")
+                    .map_err(|e| e.to_string())
+            });
+
+        results.push(to_result(&name, result));
+    }
+
+    Ok(results)
+}
+
+fn parse_entry(entry: NetworkEntry) -> ::std::result::Result<WifiProfile, String> {
+    validate_ssid(&entry.ssid).map_err(String::from)?;
+    validate_passphrase(&entry.passphrase).map_err(String::from)?;
+
+    // A passphrase alone means the common WPA2 personal security
+    let security = match entry.security {
+        Some(security) => security,
+        None if entry.passphrase.is_empty() => String::new(),
+        None => "wpa-psk".to_string(),
+    };
+
+    if !SUPPORTED_KEY_MANAGEMENT.contains(&security.as_str()) {
+        return Err(format!("unsupported security {}", security));
+    }
+
+    if (security == "wpa-psk" || security == "sae") && entry.passphrase.is_empty() {
+        return Err(format!("security {} requires a passphrase", security));
+    }
+
+    let ipv4 = entry.ipv4.unwrap_or_else(|| NetworkIpv4 {
+        method: "auto".to_string(),
+        addresses: Vec::new(),
+        gateway: None,
+        dns: Vec::new(),
+    });
+
+    match ipv4.method.as_str() {
+        "auto" => {},
+        "manual" => if ipv4.addresses.is_empty() {
+            return Err("manual IPv4 method requires addresses".to_string());
+        },
+        method => return Err(format!("unsupported IPv4 method {}", method)),
+    }
+
+    Ok(WifiProfile {
+        ssid: entry.ssid,
+        key_management: security,
+        passphrase: if entry.passphrase.is_empty() {
+            None
+        } else {
+            Some(entry.passphrase)
+        },
+        ipv4_method: ipv4.method,
+        ipv4_addresses: ipv4.addresses,
+        ipv4_gateway: ipv4.gateway,
+        ipv4_dns: ipv4.dns,
+    })
+}
+
+/// Copies the `.nmconnection` files into the system connections directory,
+/// which NetworkManager only reads files owned by root and private to it
+/// from, and loads them
+fn import_connection_files(directory: &Path, saved: &[String]) -> Result<Vec<Value>> {
+    let mut paths = fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |extension| extension == "nmconnection"))
+        .collect::<Vec<_>>();
+
+    paths.sort();
+
+    let mut results = Vec::new();
+
+    for path in paths {
+        let name = path.file_name()
+            .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+
+        let result = get_connection_file_ssid(&path).and_then(|ssid| {
+            if saved.contains(&ssid) {
+                return Ok("existing");
+            }
+
+            info!("Loading WiFi connection profile '{}' from {}", ssid, name);
+
+            let target = Path::new(SYSTEM_CONNECTIONS).join(&name);
+
+            fs::copy(&path, &target)
+                .and_then(|_| fs::set_permissions(&target, fs::Permissions::from_mode(0o600)))
+                .map_err(|e| e.to_string())?;
+
+            load_connection_file(&target)
+                .map(|_| "applied")
+                .map_err(|e| e.to_string())
+        });
+
+        results.push(to_result(&name, result));
+    }
+
+    Ok(results)
+}
+
+/// Checks that a keyfile is a WiFi profile and returns its SSID
+fn get_connection_file_ssid(path: &Path) -> ::std::result::Result<String, String> {
+    let mut contents = String::new();
+
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|e| e.to_string())?;
+
+    let mut section = String::new();
+    let mut kind = None;
+    let mut ssid = None;
+
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_string();
+            continue;
+        }
+
+        let mut parts = line.splitn(2, '=');
+
+        match (section.as_str(), parts.next(), parts.next()) {
+            ("connection", Some("type"), Some(value)) => kind = Some(value.trim().to_string()),
+            ("wifi", Some("ssid"), Some(value))
+            | ("802-11-wireless", Some("ssid"), Some(value)) => {
+                ssid = Some(value.trim().to_string())
+            },
+            _ => {},
+        }
+    }
+
+    match kind {
+        Some(ref kind) if kind == "wifi" || kind == "802-11-wireless" => {},
+        Some(kind) => return Err(format!("not a WiFi profile, but of type {}", kind)),
+        None => return Err("no connection type".to_string()),
+    }
+
+    let ssid = ssid.ok_or_else(|| "no SSID".to_string())?;
+
+    validate_ssid(&ssid).map_err(String::from)?;
+
+    Ok(ssid)
+}
+
+fn to_result(name: &str, result: ::std::result::Result<&str, String>) -> Value {
+    match result {
+        Ok(status) => json!({
+            "entry": name,
+            "status": status,
+        }),
+        Err(error) => json!({
+            "entry": name,
+            "status": "skipped",
+            "error": error,
+        }),
+    }
+}
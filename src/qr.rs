@@ -0,0 +1,818 @@
+/// QR code generator for the join instructions of the captive portal. Only
+/// what these need is supported: byte mode, error correction level M and
+/// versions 1 to 10, i.e. up to 213 bytes.
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+}
+
+/// Error correction codewords per block of level M, by version
+const ECC_CODEWORDS_PER_BLOCK: [usize; 11] = [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26];
+
+/// Error correction blocks of level M, by version
+const NUM_BLOCKS: [usize; 11] = [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5];
+
+const MAX_VERSION: usize = 10;
+
+/// Light modules around the symbol, as required by the standard
+const QUIET_ZONE: usize = 4;
+
+impl QrCode {
+    /// Encodes the data with the smallest version it fits in
+    pub fn encode(data: &[u8]) -> Result<Self, String> {
+        let version = (1..MAX_VERSION + 1)
+            .find(|&version| {
+                4 + char_count_bits(version) + data.len() * 8 <= data_capacity(version) * 8
+            })
+            .ok_or_else(|| format!("{} bytes do not fit in a QR code", data.len()))?;
+
+        let codewords = add_error_correction(&encode_data(data, version), version);
+
+        let size = version * 4 + 17;
+
+        let mut qr = QrCode {
+            size: size,
+            modules: vec![false; size * size],
+        };
+
+        let mut is_function = vec![false; size * size];
+
+        qr.draw_function_patterns(version, &mut is_function);
+        qr.draw_codewords(&codewords, &is_function);
+
+        let mask = (0..8)
+            .min_by_key(|&mask| {
+                qr.apply_mask(mask, &is_function);
+                qr.draw_format_bits(mask, &mut is_function);
+                let penalty = qr.penalty();
+                qr.apply_mask(mask, &is_function);
+                penalty
+            })
+            .unwrap();
+
+        qr.apply_mask(mask, &is_function);
+        qr.draw_format_bits(mask, &mut is_function);
+
+        Ok(qr)
+    }
+
+    /// Whether the module is dark. Modules of the quiet zone are light.
+    fn get(&self, x: isize, y: isize) -> bool {
+        let size = self.size as isize;
+        x >= 0 && y >= 0 && x < size && y < size
+            && self.modules[y as usize * self.size + x as usize]
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool, is_function: &mut [bool]) {
+        self.modules[y * self.size + x] = dark;
+        is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize, is_function: &mut [bool]) {
+        let size = self.size;
+
+        for i in 0..size {
+            self.set(6, i, i % 2 == 0, is_function);
+            self.set(i, 6, i % 2 == 0, is_function);
+        }
+
+        self.draw_finder_pattern(3, 3, is_function);
+        self.draw_finder_pattern(size - 4, 3, is_function);
+        self.draw_finder_pattern(3, size - 4, is_function);
+
+        let positions = alignment_pattern_positions(version);
+        let last = positions.len().saturating_sub(1);
+
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // The corners are taken by the finder patterns
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+
+                for dy in 0..5 {
+                    for dx in 0..5 {
+                        let distance = cmp_distance(dx, dy, 2);
+                        self.set(x + dx - 2, y + dy - 2, distance != 1, is_function);
+                    }
+                }
+            }
+        }
+
+        // Reserves the format areas, drawn for real once the mask is chosen
+        self.draw_format_bits(0, is_function);
+
+        if version >= 7 {
+            let mut remainder = version;
+
+            for _ in 0..12 {
+                remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+            }
+
+            let bits = version << 12 | remainder;
+
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 != 0;
+                let a = size - 11 + i % 3;
+                let b = i / 3;
+                self.set(a, b, dark, is_function);
+                self.set(b, a, dark, is_function);
+            }
+        }
+    }
+
+    fn draw_finder_pattern(&mut self, x: usize, y: usize, is_function: &mut [bool]) {
+        for dy in 0..9 {
+            for dx in 0..9 {
+                let (xx, yy) = (x as isize + dx - 4, y as isize + dy - 4);
+
+                if xx < 0 || yy < 0 || xx >= self.size as isize || yy >= self.size as isize {
+                    continue;
+                }
+
+                let distance = cmp_distance(dx as usize, dy as usize, 4);
+                self.set(xx as usize, yy as usize, distance != 2 && distance != 4, is_function);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: usize, is_function: &mut [bool]) {
+        // Level M is encoded as 0
+        let data = mask;
+        let mut remainder = data;
+
+        for _ in 0..10 {
+            remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+        }
+
+        let bits = (data << 10 | remainder) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+        let size = self.size;
+
+        for i in 0..6 {
+            self.set(8, i, bit(i), is_function);
+        }
+
+        self.set(8, 7, bit(6), is_function);
+        self.set(8, 8, bit(7), is_function);
+        self.set(7, 8, bit(8), is_function);
+
+        for i in 9..15 {
+            self.set(14 - i, 8, bit(i), is_function);
+        }
+
+        for i in 0..8 {
+            self.set(size - 1 - i, 8, bit(i), is_function);
+        }
+
+        for i in 8..15 {
+            self.set(8, size - 15 + i, bit(i), is_function);
+        }
+
+        self.set(8, size - 8, true, is_function);
+    }
+
+    /// Places the codewords in the zigzag order of two module wide columns,
+    /// from the bottom right corner
+    fn draw_codewords(&mut self, codewords: &[u8], is_function: &[bool]) {
+        let size = self.size;
+        let mut i = 0;
+        let mut right = size - 1;
+
+        loop {
+            // The vertical timing pattern is skipped
+            if right == 6 {
+                right = 5;
+            }
+
+            for vertical in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vertical } else { vertical };
+
+                    if !is_function[y * size + x] && i < codewords.len() * 8 {
+                        self.modules[y * size + x] = (codewords[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+
+            if right < 2 {
+                break;
+            }
+
+            right -= 2;
+        }
+    }
+
+    /// Inverts the data modules selected by the mask, so applying a mask twice
+    /// removes it
+    fn apply_mask(&mut self, mask: usize, is_function: &[bool]) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+
+                let index = y * self.size + x;
+
+                if invert && !is_function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// Scores the patterns that make a symbol hard to read, lower is better
+    fn penalty(&self) -> usize {
+        let size = self.size as isize;
+        let mut penalty = 0;
+
+        for &horizontal in &[true, false] {
+            for a in 0..size {
+                let module = |b: isize| {
+                    if horizontal {
+                        self.get(b, a)
+                    } else {
+                        self.get(a, b)
+                    }
+                };
+
+                // Runs of five or more modules of the same color
+                let mut run = 1;
+
+                for b in 1..size {
+                    if module(b) == module(b - 1) {
+                        run += 1;
+
+                        if run == 5 {
+                            penalty += 3;
+                        } else if run > 5 {
+                            penalty += 1;
+                        }
+                    } else {
+                        run = 1;
+                    }
+                }
+
+                // Finder like patterns with four light modules on one side
+                for b in -4..size {
+                    let pattern = (0..11).map(|i| module(b + i)).collect::<Vec<_>>();
+                    let core = [true, false, true, true, true, false, true];
+
+                    if pattern[4..11] == core && pattern[0..4].iter().all(|&dark| !dark) {
+                        penalty += 40;
+                    }
+
+                    if pattern[0..7] == core && pattern[7..11].iter().all(|&dark| !dark) {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+
+        // Blocks of 2x2 modules of the same color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.get(x, y);
+
+                if color == self.get(x + 1, y) && color == self.get(x, y + 1)
+                    && color == self.get(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Deviation of the dark modules from half of the symbol
+        let total = self.modules.len();
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let deviation = (dark * 20).max(total * 10) - (dark * 20).min(total * 10);
+
+        penalty + ((deviation + total - 1) / total).saturating_sub(1) * 10
+    }
+
+    pub fn to_svg(&self) -> String {
+        let dimension = self.size + QUIET_ZONE * 2;
+
+        let mut path = String::new();
+
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.modules[y * self.size + x] {
+                    path.push_str(&format!(
+                        "M{},{}h1v1h-1z",
+                        x + QUIET_ZONE,
+                        y + QUIET_ZONE
+                    ));
+                }
+            }
+        }
+
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\" \
+             shape-rendering=\"crispEdges\">\
+             <rect width=\"100%\" height=\"100%\" fill=\"#fff\"/>\
+             <path d=\"{1}\" fill=\"#000\"/></svg>\n",
+            dimension, path
+        )
+    }
+
+    /// Renders a black and white PNG with `scale` pixels per module. The
+    /// image data is stored uncompressed, which keeps the encoder trivial
+    /// while a 1 bit image stays small.
+    pub fn to_png(&self, scale: usize) -> Vec<u8> {
+        let dimension = (self.size + QUIET_ZONE * 2) * scale;
+        let row_bytes = (dimension + 7) / 8;
+
+        let mut image = Vec::with_capacity((row_bytes + 1) * dimension);
+
+        for py in 0..dimension {
+            // No filter
+            image.push(0);
+
+            let y = (py / scale) as isize - QUIET_ZONE as isize;
+
+            for byte in 0..row_bytes {
+                let mut value = 0u8;
+
+                for bit in 0..8 {
+                    let px = byte * 8 + bit;
+                    let x = (px / scale) as isize - QUIET_ZONE as isize;
+
+                    // Set bits are white
+                    if px >= dimension || !self.get(x, y) {
+                        value |= 0x80 >> bit;
+                    }
+                }
+
+                image.push(value);
+            }
+        }
+
+        let mut header = Vec::new();
+        push_u32(&mut header, dimension as u32);
+        push_u32(&mut header, dimension as u32);
+        // Bit depth 1, grayscale, deflate, no filters, no interlacing
+        header.extend_from_slice(&[1, 0, 0, 0, 0]);
+
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        push_chunk(&mut png, b"IHDR", &header);
+        push_chunk(&mut png, b"IDAT", &zlib_stored(&image));
+        push_chunk(&mut png, b"IEND", &[]);
+
+        png
+    }
+}
+
+/// Chebyshev distance of a module of a pattern from its center
+fn cmp_distance(dx: usize, dy: usize, center: usize) -> usize {
+    let dx = if dx > center { dx - center } else { center - dx };
+    let dy = if dy > center { dy - center } else { center - dy };
+    dx.max(dy)
+}
+
+fn char_count_bits(version: usize) -> usize {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+/// Modules available for data and error correction codewords
+fn raw_data_modules(version: usize) -> usize {
+    let mut modules = (16 * version + 128) * version + 64;
+
+    if version >= 2 {
+        let alignments = version / 7 + 2;
+        modules -= (25 * alignments - 10) * alignments - 55;
+
+        if version >= 7 {
+            modules -= 36;
+        }
+    }
+
+    modules
+}
+
+fn data_capacity(version: usize) -> usize {
+    raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[version] * NUM_BLOCKS[version]
+}
+
+fn alignment_pattern_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+
+    let alignments = version / 7 + 2;
+    let step = (version * 4 + alignments * 2 + 1) / (alignments * 2 - 2) * 2;
+
+    let mut positions = vec![6];
+    let mut position = version * 4 + 17 - 7;
+
+    for _ in 0..alignments - 1 {
+        positions.insert(1, position);
+        position -= step;
+    }
+
+    positions
+}
+
+/// Builds the data codewords: byte mode indicator, length, data, terminator
+/// and padding
+fn encode_data(data: &[u8], version: usize) -> Vec<u8> {
+    let mut bits = Vec::new();
+
+    {
+        let mut push_bits = |value: usize, count: usize| {
+            for i in (0..count).rev() {
+                bits.push((value >> i) & 1 != 0);
+            }
+        };
+
+        push_bits(0b0100, 4);
+        push_bits(data.len(), char_count_bits(version));
+
+        for &byte in data {
+            push_bits(byte as usize, 8);
+        }
+    }
+
+    let capacity = data_capacity(version) * 8;
+    let terminator = (capacity - bits.len()).min(4);
+    bits.extend(::std::iter::repeat(false).take(terminator));
+
+    while bits.len() % 8 != 0 {
+        bits.push(false);
+    }
+
+    let mut codewords = bits
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |value, &bit| value << 1 | bit as u8))
+        .collect::<Vec<_>>();
+
+    for &pad in [0xec, 0x11].iter().cycle() {
+        if codewords.len() >= data_capacity(version) {
+            break;
+        }
+
+        codewords.push(pad);
+    }
+
+    codewords
+}
+
+/// Splits the data into blocks, appends the Reed-Solomon codewords of each
+/// and interleaves the blocks
+fn add_error_correction(data: &[u8], version: usize) -> Vec<u8> {
+    let num_blocks = NUM_BLOCKS[version];
+    let ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    let divisor = reed_solomon_divisor(ecc_len);
+
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+
+    for i in 0..num_blocks {
+        let len = short_block_len - ecc_len + if i < num_short_blocks { 0 } else { 1 };
+        let mut block = data[offset..offset + len].to_vec();
+        offset += len;
+
+        let ecc = reed_solomon_remainder(&block, &divisor);
+
+        // Keeps the error correction codewords of all blocks aligned
+        if i < num_short_blocks {
+            block.push(0);
+        }
+
+        block.extend_from_slice(&ecc);
+        blocks.push(block);
+    }
+
+    let mut codewords = Vec::with_capacity(raw_codewords);
+
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - ecc_len || j >= num_short_blocks {
+                codewords.push(block[i]);
+            }
+        }
+    }
+
+    codewords
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut divisor = vec![0; degree];
+    divisor[degree - 1] = 1;
+
+    let mut root = 1;
+
+    for _ in 0..degree {
+        for j in 0..degree {
+            divisor[j] = gf_multiply(divisor[j], root);
+
+            if j + 1 < degree {
+                divisor[j] ^= divisor[j + 1];
+            }
+        }
+
+        root = gf_multiply(root, 0x02);
+    }
+
+    divisor
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut remainder = vec![0; divisor.len()];
+
+    for &byte in data {
+        let factor = byte ^ remainder.remove(0);
+        remainder.push(0);
+
+        for (value, &coefficient) in remainder.iter_mut().zip(divisor) {
+            *value ^= gf_multiply(coefficient, factor);
+        }
+    }
+
+    remainder
+}
+
+/// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= ((u32::from(y) >> i) & 1) * u32::from(x);
+    }
+
+    z as u8
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&[
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]);
+}
+
+fn push_chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    push_u32(png, data.len() as u32);
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    push_u32(png, crc);
+}
+
+/// Wraps the data in a zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut stream = vec![0x78, 0x01];
+
+    let mut blocks = data.chunks(0xffff).peekable();
+
+    if blocks.peek().is_none() {
+        stream.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+
+        stream.push(if last { 1 } else { 0 });
+        stream.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        stream.extend_from_slice(block);
+    }
+
+    let (mut a, mut b) = (1u32, 0u32);
+
+    for &byte in data {
+        a = (a + u32::from(byte)) % 65_521;
+        b = (b + a) % 65_521;
+    }
+
+    push_u32(&mut stream, b << 16 | a);
+
+    stream
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for &byte in data {
+        crc ^= u32::from(byte);
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Format bits of level M by mask, most significant bit first
+    const FORMAT_BITS: [u16; 8] = [
+        0b101010000010010,
+        0b101000100100101,
+        0b101111001111100,
+        0b101101101001011,
+        0b100010111111001,
+        0b100000011001110,
+        0b100111110010111,
+        0b100101010100000,
+    ];
+
+    #[test]
+    fn data_capacity_of_level_m() {
+        let capacities = (1..MAX_VERSION + 1)
+            .map(data_capacity)
+            .collect::<Vec<_>>();
+
+        assert_eq!(capacities, [16, 28, 44, 64, 86, 108, 124, 154, 182, 216]);
+    }
+
+    #[test]
+    fn alignment_pattern_positions_by_version() {
+        assert!(alignment_pattern_positions(1).is_empty());
+        assert_eq!(alignment_pattern_positions(2), [6, 18]);
+        assert_eq!(alignment_pattern_positions(6), [6, 34]);
+        assert_eq!(alignment_pattern_positions(7), [6, 22, 38]);
+        assert_eq!(alignment_pattern_positions(10), [6, 28, 50]);
+    }
+
+    #[test]
+    fn encode_data_pads_byte_mode() {
+        assert_eq!(
+            encode_data(b"A", 1),
+            [
+                0x40, 0x14, 0x10, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11,
+                0xec, 0x11, 0xec,
+            ]
+        );
+    }
+
+    // Version 1-M codewords of "HELLO WORLD"
+    #[test]
+    fn reed_solomon_known_answer() {
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+
+        assert_eq!(
+            reed_solomon_remainder(&data, &reed_solomon_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn version_fits_data() {
+        let size = |len: usize| QrCode::encode(&vec![b'a'; len]).unwrap().size;
+
+        assert_eq!(size(1), 21);
+        assert_eq!(size(14), 21);
+        assert_eq!(size(15), 25);
+        assert_eq!(size(122), 45);
+        assert_eq!(size(213), 57);
+        assert!(QrCode::encode(&[b'a'; 214]).is_err());
+    }
+
+    #[test]
+    fn function_patterns() {
+        let qr = QrCode::encode(b"WIFI:S:wifi-connect;;").unwrap();
+        let size = qr.size as isize;
+
+        // Finder patterns, with their light separators
+        for &(x, y) in &[(0, 0), (size - 7, 0), (0, size - 7)] {
+            for i in 0..7 {
+                assert!(qr.get(x + i, y));
+                assert!(qr.get(x + i, y + 6));
+                assert!(qr.get(x, y + i));
+                assert!(qr.get(x + 6, y + i));
+            }
+
+            assert!(qr.get(x + 3, y + 3));
+            assert!(!qr.get(x + 1, y + 1));
+        }
+
+        for i in 8..size - 8 {
+            assert_eq!(qr.get(i, 6), i % 2 == 0);
+            assert_eq!(qr.get(6, i), i % 2 == 0);
+        }
+
+        assert!(qr.get(8, size - 8));
+    }
+
+    #[test]
+    fn format_bits_match_the_standard() {
+        let qr = QrCode::encode(b"http://192.168.42.1/").unwrap();
+        let size = qr.size as isize;
+
+        // Both copies, least significant bit first
+        let mut positions = (0..6).map(|i| (8, i)).collect::<Vec<_>>();
+        positions.extend_from_slice(&[(8, 7), (8, 8), (7, 8)]);
+        positions.extend((9..15).map(|i| (14 - i, 8)));
+
+        let mut copy = (0..8).map(|i| (size - 1 - i, 8)).collect::<Vec<_>>();
+        copy.extend((8..15).map(|i| (8, size - 15 + i)));
+
+        let read = |positions: &[(isize, isize)]| {
+            positions
+                .iter()
+                .enumerate()
+                .fold(0u16, |bits, (i, &(x, y))| bits | (qr.get(x, y) as u16) << i)
+        };
+
+        let bits = read(&positions);
+
+        assert_eq!(read(&copy), bits);
+        assert!(FORMAT_BITS.contains(&bits));
+    }
+
+    #[test]
+    fn version_bits_match_the_standard() {
+        let qr = QrCode::encode(&[b'a'; 122]).unwrap();
+        let size = qr.size as isize;
+
+        let bits = (0..18).fold(0u32, |bits, i| {
+            let (a, b) = (size - 11 + i % 3, i / 3);
+            assert_eq!(qr.get(a, b), qr.get(b, a));
+            bits | (qr.get(a, b) as u32) << i
+        });
+
+        assert_eq!(bits, 0x07c94);
+    }
+
+    #[test]
+    fn crc32_known_answer() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn zlib_stored_known_answer() {
+        assert_eq!(
+            zlib_stored(b"abc"),
+            [0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27]
+        );
+        assert_eq!(
+            zlib_stored(b""),
+            [0x78, 0x01, 0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01]
+        );
+    }
+
+    #[test]
+    fn png_structure() {
+        let png = QrCode::encode(b"wifi-connect").unwrap().to_png(2);
+
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+
+        // IHDR of a 1 bit grayscale image of 21 modules and the quiet zone
+        assert_eq!(png[8..16], [0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(png[16..29], [0, 0, 0, 58, 0, 0, 0, 58, 1, 0, 0, 0, 0]);
+
+        // Every chunk carries the CRC of its type and data
+        let mut offset = 8;
+        let mut kinds = Vec::new();
+
+        while offset < png.len() {
+            let len = png[offset..offset + 4]
+                .iter()
+                .fold(0usize, |len, &byte| len << 8 | byte as usize);
+            let end = offset + 8 + len;
+            let mut crc = Vec::new();
+            push_u32(&mut crc, crc32(&png[offset + 4..end]));
+
+            assert_eq!(png[end..end + 4], crc[..]);
+
+            kinds.push(png[offset + 4..offset + 8].to_vec());
+            offset = end + 4;
+        }
+
+        assert_eq!(kinds, [b"IHDR".to_vec(), b"IDAT".to_vec(), b"IEND".to_vec()]);
+    }
+}
@@ -0,0 +1,107 @@
+use serde_json::Value;
+
+/// Placeholder that secrets are replaced with in logs and error messages
+pub const REDACTED: &str = "<redacted>";
+
+/// Parts of field and argument names that mark their values as secrets, e.g.
+/// `passphrase`, `wifi-sec.psk`, `802-1x.password` or `auth_token`
+const SENSITIVE_NAMES: &[&str] = &[
+    "passphrase",
+    "password",
+    "psk",
+    "token",
+    "secret",
+    "encryption_key",
+];
+
+pub fn is_sensitive(name: &str) -> bool {
+    let name = name.to_lowercase();
+
+    SENSITIVE_NAMES.iter().any(|sensitive| name.contains(sensitive))
+}
+
+/// Returns a copy of a JSON value with the values of all sensitive fields
+/// replaced, at any depth
+pub fn redact_json(value: &Value) -> Value {
+    match *value {
+        Value::Object(ref map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| {
+                    let value = if is_sensitive(key) {
+                        Value::String(REDACTED.into())
+                    } else {
+                        redact_json(value)
+                    };
+
+                    (key.clone(), value)
+                })
+                .collect(),
+        ),
+        Value::Array(ref values) => Value::Array(values.iter().map(redact_json).collect()),
+        _ => value.clone(),
+    }
+}
+
+/// Collects the values of all sensitive fields of a JSON value, so that they
+/// can be scrubbed from messages that may quote them
+pub fn get_json_secrets(value: &Value) -> Vec<String> {
+    let mut secrets = Vec::new();
+    collect_json_secrets(value, false, &mut secrets);
+    secrets
+}
+
+fn collect_json_secrets(value: &Value, sensitive: bool, secrets: &mut Vec<String>) {
+    match *value {
+        Value::Object(ref map) => for (key, value) in map.iter() {
+            collect_json_secrets(value, sensitive || is_sensitive(key), secrets);
+        },
+        Value::Array(ref values) => for value in values.iter() {
+            collect_json_secrets(value, sensitive, secrets);
+        },
+        Value::String(ref secret) if sensitive => secrets.push(secret.clone()),
+        Value::Number(ref secret) if sensitive => secrets.push(secret.to_string()),
+        _ => {},
+    }
+}
+
+/// Replaces every occurrence of the given secrets in a message
+pub fn scrub(message: &str, secrets: &[String]) -> String {
+    let mut message = message.to_string();
+
+    for secret in secrets.iter().filter(|secret| !secret.is_empty()) {
+        message = message.replace(secret as &str, REDACTED);
+    }
+
+    message
+}
+
+/// Redacts the argument following a sensitive one in command lines like
+/// `nmcli device wifi connect SSID password SECRET`
+pub fn redact_args<'a>(args: &[&'a str]) -> Vec<&'a str> {
+    let mut redacted = Vec::with_capacity(args.len());
+    let mut after_sensitive = false;
+
+    for arg in args {
+        redacted.push(if after_sensitive { REDACTED } else { *arg });
+        after_sensitive = is_sensitive(arg);
+    }
+
+    redacted
+}
+
+/// Redacts the values of sensitive parameters of an URL query string
+pub fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap();
+
+            match parts.next() {
+                Some(_) if is_sensitive(key) => format!("{}={}", key, REDACTED),
+                _ => pair.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
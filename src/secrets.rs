@@ -0,0 +1,121 @@
+use std::borrow::Cow;
+
+use dbus::{BusType, Connection, Message, MessageItem, Path};
+
+use errors::*;
+
+const NM_DBUS_NAME: &str = "org.freedesktop.NetworkManager";
+const NM_SETTINGS_PATH: &str = "/org/freedesktop/NetworkManager/Settings";
+const NM_SETTINGS_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings";
+const NM_CONNECTION_INTERFACE: &str = "org.freedesktop.NetworkManager.Settings.Connection";
+
+const WIRELESS_SETTING: &str = "802-11-wireless";
+const WIRELESS_SECURITY_SETTING: &str = "802-11-wireless-security";
+
+/// Time to wait for NetworkManager to answer (milliseconds)
+const CALL_TIMEOUT: i32 = 30_000;
+
+/// Stores a secret of the WiFi security setting of a connection profile,
+/// e.g. `psk` or `wep-key0`. The secret is passed over D-Bus, as any user
+/// can read the command line arguments of `nmcli` from the process list.
+pub fn set_wifi_secret(uuid: &str, key: &str, value: &str) -> Result<()> {
+    let value = MessageItem::Str(value.to_string());
+
+    update_property(uuid, WIRELESS_SECURITY_SETTING, key, value)
+}
+
+/// Sets the SSID of a connection profile to its raw bytes, for SSIDs which
+/// are not UTF-8, as `nmcli` only takes SSIDs as strings
+pub fn set_wifi_ssid(uuid: &str, ssid: &[u8]) -> Result<()> {
+    let bytes = ssid.iter().map(|byte| MessageItem::Byte(*byte)).collect();
+
+    update_property(uuid, WIRELESS_SETTING, "ssid", MessageItem::Array(bytes, Cow::Borrowed("y")))
+}
+
+fn update_property(uuid: &str, setting: &str, key: &str, value: MessageItem) -> Result<()> {
+    let reply = call(
+        NM_SETTINGS_PATH,
+        NM_SETTINGS_INTERFACE,
+        "GetConnectionByUuid",
+        |message| message.append1(uuid),
+    )?;
+
+    let path = reply
+        .get1::<Path>()
+        .ok_or_else(|| ErrorKind::ConnectionUpdate("GetConnectionByUuid".to_string()))?
+        .to_string();
+
+    // The settings come without the secrets and are replaced as a whole
+    let mut settings = call(&path, NM_CONNECTION_INTERFACE, "GetSettings", |message| message)?
+        .get_items();
+
+    if settings.len() != 1 || !insert_property(&mut settings[0], setting, key, value) {
+        bail!(ErrorKind::ConnectionUpdate("GetSettings".to_string()));
+    }
+
+    call(&path, NM_CONNECTION_INTERFACE, "Update", |mut message| {
+        message.append_items(&settings);
+        message
+    }).map(|_| ())
+}
+
+/// Adds the property to a setting of `a{sa{sv}}` settings, replacing the
+/// previous value. Returns false if there is no such setting.
+fn insert_property(
+    settings: &mut MessageItem,
+    setting: &str,
+    key: &str,
+    value: MessageItem,
+) -> bool {
+    let settings = match *settings {
+        MessageItem::Array(ref mut settings, _) => settings,
+        _ => return false,
+    };
+
+    let setting_name = MessageItem::Str(setting.to_string());
+    let key = MessageItem::Str(key.to_string());
+
+    for entry in settings.iter_mut() {
+        let properties = match *entry {
+            MessageItem::DictEntry(ref name, ref mut properties) => {
+                if **name != setting_name {
+                    continue;
+                }
+
+                properties
+            },
+            _ => continue,
+        };
+
+        if let MessageItem::Array(ref mut properties, _) = **properties {
+            properties.retain(|property| match *property {
+                MessageItem::DictEntry(ref name, _) => **name != key,
+                _ => true,
+            });
+
+            properties.push(MessageItem::DictEntry(
+                Box::new(key),
+                Box::new(MessageItem::Variant(Box::new(value))),
+            ));
+
+            return true;
+        }
+    }
+
+    false
+}
+
+fn call<F>(path: &str, interface: &str, method: &str, append: F) -> Result<Message>
+where
+    F: FnOnce(Message) -> Message,
+{
+    let connection = Connection::get_private(BusType::System)
+        .chain_err(|| ErrorKind::ConnectionUpdate(method.to_string()))?;
+
+    let message = Message::new_method_call(NM_DBUS_NAME, path, interface, method)
+        .map_err(|e| Error::from(ErrorKind::ConnectionUpdate(format!("{}: {}", method, e))))?;
+
+    connection
+        .send_with_reply_and_block(append(message), CALL_TIMEOUT)
+        .chain_err(|| ErrorKind::ConnectionUpdate(method.to_string()))
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde_json::{self, Value};
+use serde_cbor;
+use rmp_serde;
+
+use errors::*;
+
+/// Formats the API responses can be serialized to. CBOR and MessagePack are
+/// more compact and cheaper to parse for microcontroller clients.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Serializer {
+    Json,
+    Cbor,
+    MessagePack,
+}
+
+impl Serializer {
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" => Some(Serializer::Json),
+            "application/cbor" => Some(Serializer::Cbor),
+            "application/msgpack" | "application/x-msgpack" => Some(Serializer::MessagePack),
+            _ => None,
+        }
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        match *self {
+            Serializer::Json => "application/json",
+            Serializer::Cbor => "application/cbor",
+            Serializer::MessagePack => "application/msgpack",
+        }
+    }
+
+    pub fn serialize(&self, value: &Value) -> Result<Vec<u8>> {
+        self.serialize_impl(value)
+            .chain_err(|| ErrorKind::SerializeResponse(self.media_type().into()))
+    }
+
+    fn serialize_impl(&self, value: &Value) -> Result<Vec<u8>> {
+        Ok(match *self {
+            Serializer::Json => serde_json::to_vec(value)?,
+            Serializer::Cbor => serde_cbor::to_vec(value)?,
+            Serializer::MessagePack => rmp_serde::to_vec(value)?,
+        })
+    }
+}
+
+/// Casing of the field names of JSON responses. camelCase is for frontends
+/// written against forks which use it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsonCase {
+    Snake,
+    Camel,
+}
+
+impl FromStr for JsonCase {
+    type Err = String;
+
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "snake" | "snake_case" => Ok(JsonCase::Snake),
+            "camel" | "camelcase" => Ok(JsonCase::Camel),
+            _ => Err(format!("Invalid JSON case: {} (expected snake or camel)", s)),
+        }
+    }
+}
+
+impl fmt::Display for JsonCase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JsonCase::Snake => write!(f, "snake"),
+            JsonCase::Camel => write!(f, "camel"),
+        }
+    }
+}
+
+/// Renames the keys of all objects of the value to the casing
+pub fn convert_case(value: Value, case: JsonCase) -> Value {
+    match (value, case) {
+        (value, JsonCase::Snake) => value,
+        (Value::Object(map), JsonCase::Camel) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (snake_to_camel(&key), convert_case(value, case)))
+                .collect(),
+        ),
+        (Value::Array(values), JsonCase::Camel) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| convert_case(value, case))
+                .collect(),
+        ),
+        (value, JsonCase::Camel) => value,
+    }
+}
+
+fn snake_to_camel(key: &str) -> String {
+    let mut camel = String::with_capacity(key.len());
+    let mut upper = false;
+
+    for c in key.chars() {
+        if c == '_' && !camel.is_empty() {
+            upper = true;
+        } else if upper {
+            camel.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            camel.push(c);
+        }
+    }
+
+    camel
+}
@@ -1,214 +1,3545 @@
-use std::sync::mpsc::{Receiver, Sender};
-use std::fmt;
-use std::net::Ipv4Addr;
+use std::sync::mpsc::{RecvError, SendError, Sender};
+use std::time::Duration;
+use std::cmp::Ordering;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
 use std::error::Error as StdError;
+use std::fs::File;
+use std::io::Read;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-use serde_json;
+use serde::{Deserialize, Deserializer};
+use serde::de::{DeserializeOwned, Error as DeError};
+use serde_json::{self, Map, Value};
 use path::PathBuf;
-use iron::prelude::*;
-use iron::{headers, status, typemap, AfterMiddleware, Iron, IronError, IronResult, Request,
-           Response, Url};
-use iron::modifiers::Redirect;
-use router::Router;
-use staticfile::Static;
-use mount::Mount;
-use persistent::Write;
-use params::{FromValue, Params};
+use base64;
+use futures::{future, Future, Stream};
+use futures::future::Either;
+use futures::sync::mpsc::unbounded;
+use hyper::{self, Body, Chunk, HeaderMap, Method, StatusCode, Uri};
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use native_tls::{self, Identity};
+use tokio;
+use tokio::net::TcpListener;
+use tokio::reactor::Handle;
+use tokio::runtime::{self, Runtime};
+use tokio::timer::{Delay, Timeout};
+use tokio_threadpool::blocking;
+use tokio_tls::TlsAcceptor;
+use url::form_urlencoded;
 
 use errors::*;
-use network::{NetworkCommand, NetworkCommandResponse};
+use config::{parse_ip_list, Config, SharedConfig};
+use network::{is_network_manager_reachable, parse_raw_ssid, validate_passphrase, validate_ssid,
+              AdvancedChange, AdvancedError,
+              ApSelection, BackupError, ConnectAnyError, EthernetError, EthernetStatus,
+              Hotspot, InterfaceError,
+              Ipv4Settings, Ipv6Settings, NetworkCommand, NetworkCommandResponse, ScanResults,
+              SharedConnectProgress, VerifyError};
 use exit::{exit, ExitResult};
+use serializer::{convert_case, JsonCase, Serializer};
+use power::BEACON_SETTINGS_SUPPORTED;
+use internet::{check_internet, results_to_json};
+use pairing::PairingToken;
+use nmcli::{Band, SavedProfile};
+use redact::{get_json_secrets, redact_json, redact_query, scrub};
+use qr::QrCode;
+use events::EventBus;
+use ui::{Ui, ASSET_DIRECTORIES};
+use ssdp::{get_description, DESCRIPTION_PATH};
+use activity::ActivityTimer;
+use openapi::{get_document, get_request_schema, Operation};
+use systemd::take_listen_sockets;
+use connectivity::check_connectivity;
+use network_manager::Connectivity;
+
+/// Version of the HTTP API, announced to companion apps, which is raised on
+/// incompatible changes
+pub const API_VERSION: u32 = 1;
+
+/// Time the network thread has to answer a health check (seconds)
+const HEALTH_TIMEOUT: u64 = 5;
+
+const PAIRING_TOKEN_HEADER: &str = "X-Pairing-Token";
+const ENROLLMENT_TOKEN_HEADER: &str = "X-Enrollment-Token";
+
+/// Window the rate limit of the mutating requests applies to (seconds)
+const RATE_LIMIT_WINDOW: u64 = 60;
+
+/// Time a long poll of the events waits for the next one (seconds)
+const LONG_POLL_TIMEOUT: u64 = 30;
+
+/// Time browsers may cache the answer to a CORS preflight request (seconds)
+const CORS_MAX_AGE: u32 = 600;
+
+/// Threads serving the requests. Requests waiting for the network thread or
+/// for events hold none of them.
+const SERVER_THREADS: usize = 2;
+
+/// Requests which may block at once, e.g. on D-Bus or the Internet check
+/// probes, each on a thread of its own
+const MAX_BLOCKING_REQUESTS: usize = 4;
+
+/// Time to wait before accepting connections again after a failure, e.g.
+/// when the process ran out of file descriptors (seconds)
+const ACCEPT_RETRY_DELAY: u64 = 1;
+
+/// Time a client has to complete the TLS handshake, so that idle sockets do
+/// not pile up (seconds)
+const TLS_HANDSHAKE_TIMEOUT: u64 = 10;
+
+/// Largest request body which is read, enough for a configuration backup
+/// (bytes)
+const MAX_BODY_SIZE: usize = 1024 * 1024;
 
 struct RequestSharedState {
     gateway: Ipv4Addr,
-    server_rx: Receiver<NetworkCommandResponse>,
+    portal_url: String,
+    /// The senders are only locked for sending, as they cannot be shared
+    /// between the server threads otherwise
+    network_tx: Mutex<Sender<NetworkCommand>>,
+    exit_tx: Mutex<Sender<ExitResult>>,
+    capabilities: Value,
+    /// The reloadable options, such as the Internet check probes, are read
+    /// from it
+    config: SharedConfig,
+    connect_progress: SharedConnectProgress,
+    events: EventBus,
+    activity: ActivityTimer,
+    enrollment_token: Option<String>,
+}
+
+impl RequestSharedState {
+    fn send(
+        &self,
+        command: NetworkCommand,
+    ) -> ::std::result::Result<(), SendError<NetworkCommand>> {
+        self.network_tx.lock().unwrap().send(command)
+    }
+
+    fn exit(&self, error: Error) {
+        exit(&self.exit_tx.lock().unwrap(), error);
+    }
+}
+
+/// A request as the middlewares and the handlers see it. The body is only
+/// read once the before middlewares let the request through.
+struct Request {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    remote_addr: SocketAddr,
+    body: Chunk,
+    state: Arc<RequestSharedState>,
+}
+
+impl Request {
+    /// The segments of the path, e.g. `["api", "v1", "ssids"]`
+    fn path(&self) -> Vec<&str> {
+        let path = self.uri.path();
+        let path = if path.starts_with('/') { &path[1..] } else { path };
+        path.split('/').collect()
+    }
+
+    /// The first value of a header, if it is visible ASCII
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+}
+
+/// Body of a response, which the after middlewares may rewrite unless it is
+/// streamed, like the events
+enum ResponseBody {
+    Buffered(Vec<u8>),
+    Streamed(Body),
+}
+
+type Response = hyper::Response<ResponseBody>;
+
+/// The response of a handler, which may wait for the network thread or for
+/// events without holding a server thread
+type ResponseFuture = Box<Future<Item = Response, Error = hyper::Error> + Send>;
+
+fn response<B>(status: StatusCode, body: B) -> Response
+where
+    B: Into<Vec<u8>>,
+{
+    let mut res = hyper::Response::new(ResponseBody::Buffered(body.into()));
+    *res.status_mut() = status;
+    res
+}
+
+fn typed_response<B>(status: StatusCode, media_type: &str, body: B) -> Response
+where
+    B: Into<Vec<u8>>,
+{
+    let mut res = response(status, body);
+    set_header(&mut res, header::CONTENT_TYPE, media_type);
+    res
+}
+
+/// Replaces a header. Values come from the configuration or the server, and
+/// those which are not valid header values are left out.
+fn set_header(res: &mut Response, name: HeaderName, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            res.headers_mut().insert(name, value);
+        },
+        Err(_) => warn!("Invalid value of the {} header: {}", name, value),
+    }
+}
+
+fn respond(res: Response) -> ResponseFuture {
+    Box::new(future::ok(res))
+}
+
+macro_rules! get_request_body {
+    ($req:ident, $ty:ty) => (
+        match parse_request_body::<$ty>($req) {
+            Ok(body) => body,
+            Err(response) => return respond(response),
+        }
+    )
+}
+
+/// Sends a command to the network thread and answers with the closure once
+/// the network thread responds. The command is built with the sending half
+/// of a channel created for this request only, so that concurrent requests
+/// never get each other's responses.
+macro_rules! request_network_command {
+    (
+        $req:ident,
+        $response_tx:ident => $command:expr,
+        $response:ident,
+        $send_e_kind:expr,
+        $recv_e_kind:expr,
+        |$value:pat| $respond:expr
+    ) => (
+        {
+            let ($response_tx, response_rx) = unbounded();
+
+            let state = $req.state.clone();
+
+            if let Err(e) = state.send($command) {
+                return respond(exit_with_error(&state, e, $send_e_kind));
+            }
+
+            Box::new(response_rx.into_future().then(move |result| {
+                Ok::<_, hyper::Error>(match result {
+                    Ok((Some(NetworkCommandResponse::$response($value)), _)) => $respond,
+                    Ok((Some(_), _)) => unexpected_response(&state),
+                    Ok((None, _)) | Err(_) => exit_with_error(&state, RecvError, $recv_e_kind),
+                })
+            }))
+        }
+    )
+}
+
+fn exit_with_error<E>(state: &RequestSharedState, e: E, e_kind: ErrorKind) -> Response
+where
+    E: ::std::error::Error + Send + 'static,
+{
+    let api_error = ApiError::from_kind(&e_kind).with_details(json!({ "cause": e.to_string() }));
+    let err = Err::<(), E>(e).chain_err(|| e_kind);
+    state.exit(err.unwrap_err());
+    api_error.into_response()
+}
+
+/// Runs a call which blocks, e.g. on D-Bus, on a thread set aside for it, so
+/// that the server threads go on serving the other requests
+fn run_blocking<F>(f: F) -> ResponseFuture
+where
+    F: FnOnce() -> Response + Send + 'static,
+{
+    let mut f = Some(f);
+
+    Box::new(
+        future::poll_fn(move || blocking(|| (f.take().unwrap())())).or_else(|e| {
+            Ok(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "SERVER_BUSY",
+                &e.to_string(),
+            ))
+        }),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct ConnectRequest {
+    #[serde(default)]
+    ssid: String,
+    /// Hex bytes of an SSID which is not UTF-8, instead of `ssid`
+    #[serde(default)]
+    raw_ssid: Option<String>,
+    #[serde(default)]
+    passphrase: String,
+    #[serde(default)]
+    dns: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    reuse: bool,
+    #[serde(default)]
+    band: Option<String>,
+    #[serde(default)]
+    bssid: Option<String>,
+    /// Token from the label of the device, instead of the header
+    #[serde(default)]
+    enrollment_token: Option<String>,
+    /// Checkpoint timeout instead of the configured one, 0 to skip it
+    #[serde(default, deserialize_with = "deserialize_seconds")]
+    checkpoint: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct VerifyRequest {
+    #[serde(default)]
+    ssid: String,
+    #[serde(default)]
+    passphrase: String,
+    /// Stays connected once verified, like a connect request
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    keep: bool,
+    #[serde(default)]
+    enrollment_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ForgetRequest {
+    ssid: String,
+}
+
+#[derive(Deserialize)]
+struct InterfaceRequest {
+    interface: String,
+}
+
+#[derive(Deserialize)]
+struct Ipv4Request {
+    method: String,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    gateway: Option<String>,
+    #[serde(default)]
+    dns: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Ipv6Request {
+    method: String,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    gateway: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DnsRequest {
+    dns: String,
+}
+
+#[derive(Deserialize)]
+struct ProxyRequest {
+    #[serde(default)]
+    pac_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExportRequest {
+    #[serde(default)]
+    encryption_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ImportRequest {
+    backup: Value,
+    #[serde(default)]
+    encryption_key: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BlacklistBssidRequest {
+    bssid: String,
+}
+
+/// Deserializes the body of a POST request into a typed request struct.
+/// JSON bodies are parsed with serde, while form and query parameters are
+/// converted to JSON first, so that both go through the same validation.
+fn parse_request_body<T>(req: &Request) -> ::std::result::Result<T, Response>
+where
+    T: DeserializeOwned,
+{
+    let is_json = match get_media_type(&req.headers) {
+        Some(ref media_type) if media_type == "application/json" => true,
+        Some(ref media_type) if media_type == "application/x-www-form-urlencoded" => false,
+        None => false,
+        Some(media_type) => {
+            return Err(error_response(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "UNSUPPORTED_MEDIA_TYPE",
+                &format!("Unsupported content type: {}", media_type),
+            ))
+        },
+    };
+
+    let value = if is_json {
+        match serde_json::from_slice::<Value>(&req.body) {
+            Ok(value) => value,
+            Err(e) => {
+                return Err(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_REQUEST",
+                    &format!("Malformed JSON: {}", e),
+                ))
+            },
+        }
+    } else {
+        // Form fields take precedence over the query parameters
+        let query = req.uri.query().unwrap_or("").as_bytes();
+
+        let params = form_urlencoded::parse(query)
+            .chain(form_urlencoded::parse(&req.body))
+            .map(|(name, value)| (name.into_owned(), Value::String(value.into_owned())))
+            .collect::<Map<_, _>>();
+
+        Value::Object(params)
+    };
+
+    debug!("Request body: {}", redact_json(&value));
+
+    // Deserialization errors may quote the offending values
+    let secrets = get_json_secrets(&value);
+
+    serde_json::from_value(value).map_err(|e| {
+        error_response(
+            StatusCode::BAD_REQUEST,
+            "INVALID_REQUEST",
+            &scrub(&format!("Invalid request: {}", e), &secrets),
+        )
+    })
+}
+
+/// The media type of the `Content-Type` header, without its parameters
+fn get_media_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+        .map(|value| value.split(';').next().unwrap_or("").trim().to_lowercase())
+}
+
+/// Returns the first value of a query parameter
+fn get_query_param(req: &Request, name: &str) -> Option<String> {
+    let query = req.uri.query()?;
+
+    for pair in query.split('&') {
+        let mut parts = pair.splitn(2, '=');
+
+        if parts.next() == Some(name) {
+            return Some(parts.next().unwrap_or("").to_string());
+        }
+    }
+
+    None
+}
+
+/// Formats the URL of a request for logging, without the values of
+/// sensitive query parameters
+fn get_log_url(req: &Request) -> String {
+    match req.uri.query() {
+        Some(query) => format!("{}?{}", req.uri.path(), redact_query(query)),
+        None => req.uri.path().to_string(),
+    }
+}
+
+/// Accepts flags both as JSON booleans and as the strings of form encoded
+/// requests
+fn deserialize_flag<'de, D>(deserializer: D) -> ::std::result::Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Bool(value) => Ok(value),
+        Value::String(ref value) => Ok(value == "true" || value == "1"),
+        Value::Null => Ok(false),
+        value => Err(D::Error::custom(format!("invalid flag: {}", value))),
+    }
+}
+
+/// Accepts the numbers of form fields, which are strings
+fn deserialize_seconds<'de, D>(deserializer: D) -> ::std::result::Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Value::deserialize(deserializer)? {
+        Value::Number(ref value) if value.is_u64() => Ok(value.as_u64()),
+        Value::String(ref value) if value.is_empty() => Ok(None),
+        Value::String(ref value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| D::Error::custom(format!("invalid seconds: {}", value))),
+        Value::Null => Ok(None),
+        value => Err(D::Error::custom(format!("invalid seconds: {}", value))),
+    }
+}
+
+/// Error reported to API clients as `{code, message, details}`. Clients
+/// should rely on the stable codes rather than on the messages.
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Value,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, code: &'static str, message: &str) -> Self {
+        ApiError {
+            status: status,
+            code: code,
+            message: message.to_string(),
+            details: Value::Null,
+        }
+    }
+
+    fn from_kind(e_kind: &ErrorKind) -> Self {
+        let code = api_error_code(e_kind);
+
+        let status = match code {
+            "AP_NOT_FOUND" => StatusCode::NOT_FOUND,
+            "INVALID_SSID" => StatusCode::BAD_REQUEST,
+            "NM_UNAVAILABLE" => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        ApiError::new(status, code, &e_kind.to_string())
+    }
+
+    fn with_details(mut self, details: Value) -> Self {
+        self.details = details;
+        self
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "code": self.code,
+            "message": self.message,
+            "details": self.details,
+        })
+    }
+
+    fn to_body(&self) -> String {
+        self.to_json().to_string()
+    }
+
+    fn into_response(self) -> Response {
+        debug!("{}: {}", self.code, self.message);
+
+        typed_response(self.status, "application/json", self.to_body())
+    }
+}
+
+fn error_response(status: StatusCode, code: &'static str, message: &str) -> Response {
+    ApiError::new(status, code, message).into_response()
+}
+
+/// Picks the response format from the `Accept` header, by quality. JSON is
+/// the default for clients which accept any or no known format.
+fn get_serializer(req: &Request) -> Serializer {
+    let accept = match req.header("Accept") {
+        Some(accept) => accept,
+        None => return Serializer::Json,
+    };
+
+    let mut items = accept
+        .split(',')
+        .map(|item| {
+            let mut parts = item.split(';');
+            let media_type = parts.next().unwrap_or("").trim().to_lowercase();
+
+            let quality = parts
+                .filter_map(|param| {
+                    let param = param.trim();
+
+                    if param.starts_with("q=") {
+                        param[2..].parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+
+            (media_type, quality)
+        })
+        .collect::<Vec<_>>();
+
+    // The sort is stable, so the order of the client breaks ties
+    items.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+
+    for (media_type, _) in items {
+        if let Some(serializer) = Serializer::from_media_type(&media_type) {
+            return serializer;
+        }
+    }
+
+    Serializer::Json
+}
+
+fn serialized_response(serializer: Serializer, status: StatusCode, value: &Value) -> Response {
+    match serializer.serialize(value) {
+        Ok(body) => typed_response(status, serializer.media_type(), body),
+        Err(e) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "SERIALIZATION_FAILED",
+            &e.to_string(),
+        ),
+    }
+}
+
+/// Answers the requests of a route
+trait Handler: Send + Sync {
+    fn handle(&self, req: &Request) -> ResponseFuture;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Request) -> ResponseFuture + Send + Sync,
+{
+    fn handle(&self, req: &Request) -> ResponseFuture {
+        (*self)(req)
+    }
+}
+
+/// Runs before the handler, and answers in its place to reject a request
+trait BeforeMiddleware: Send + Sync {
+    fn before(&self, req: &Request) -> ::std::result::Result<(), Response>;
+}
+
+/// Runs on every response, including those of the before middlewares
+trait AfterMiddleware: Send + Sync {
+    fn after(&self, req: &Request, res: Response) -> Response;
+}
+
+/// Finds the handler of a request by its method and path. The UI is served
+/// at the root and below its asset directories.
+struct Router {
+    routes: Vec<(Method, String, Box<Handler>)>,
+    ui: Ui,
+}
+
+impl Router {
+    fn new(ui: Ui) -> Self {
+        Router {
+            routes: Vec::new(),
+            ui: ui,
+        }
+    }
+
+    fn route<H>(&mut self, method: Method, path: &str, handler: H)
+    where
+        H: Handler + 'static,
+    {
+        self.routes
+            .push((method, path.to_string(), Box::new(handler)));
+    }
+
+    fn handle(&self, req: &Request) -> ResponseFuture {
+        let path = req.uri.path();
+
+        let mut methods = self.routes
+            .iter()
+            .filter(|&&(_, ref route, _)| route == path)
+            .peekable();
+
+        if methods.peek().is_some() {
+            // HEAD requests are answered like GET ones, without the body
+            let is_allowed = |method: &Method| {
+                *method == req.method || (req.method == Method::HEAD && *method == Method::GET)
+            };
+
+            return match methods.find(|&&(ref method, _, _)| is_allowed(method)) {
+                Some(&(_, _, ref handler)) => handler.handle(req),
+                None => respond(error_response(
+                    StatusCode::METHOD_NOT_ALLOWED,
+                    "METHOD_NOT_ALLOWED",
+                    &format!("Method {} not allowed for {}", req.method, path),
+                )),
+            };
+        }
+
+        let is_ui = path == "/" || ASSET_DIRECTORIES
+            .iter()
+            .any(|directory| path.starts_with(&format!("/{}/", directory)));
+
+        if is_ui && (req.method == Method::GET || req.method == Method::HEAD) {
+            if let Some((content, media_type)) = self.ui.get(&path[1..]) {
+                return respond(typed_response(StatusCode::OK, media_type, content));
+            }
+        }
+
+        respond(error_response(
+            StatusCode::NOT_FOUND,
+            "NOT_FOUND",
+            &format!("No such route: {}", path),
+        ))
+    }
+}
+
+/// The router with the middlewares around it, which run in the order they
+/// are linked
+struct Chain {
+    router: Router,
+    before: Vec<Box<BeforeMiddleware>>,
+    after: Vec<Box<AfterMiddleware>>,
+}
+
+impl Chain {
+    fn new(router: Router) -> Self {
+        Chain {
+            router: router,
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    fn link_before<M>(&mut self, middleware: M)
+    where
+        M: BeforeMiddleware + 'static,
+    {
+        self.before.push(Box::new(middleware));
+    }
+
+    fn link_after<M>(&mut self, middleware: M)
+    where
+        M: AfterMiddleware + 'static,
+    {
+        self.after.push(Box::new(middleware));
+    }
+
+    /// Runs the before middlewares until one of them rejects the request
+    fn before(&self, req: &Request) -> ::std::result::Result<(), Response> {
+        for middleware in &self.before {
+            middleware.before(req)?;
+        }
+
+        Ok(())
+    }
+
+    fn after(&self, req: &Request, res: Response) -> Response {
+        self.after
+            .iter()
+            .fold(res, |res, middleware| middleware.after(req, res))
+    }
+}
+
+/// GET, HEAD and OPTIONS requests change nothing on the device
+fn is_mutating(method: &Method) -> bool {
+    *method != Method::GET && *method != Method::HEAD && *method != Method::OPTIONS
+}
+
+/// The configured API credentials, a username and password or a bearer
+/// token, which the HTTP API and the MQTT commands are checked against
+pub struct Credentials {
+    username: Option<String>,
+    password: Option<String>,
+    token: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(config: &Config) -> Self {
+        Credentials {
+            username: config.auth_username.clone(),
+            password: config.auth_password.clone(),
+            token: config.auth_token.clone(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.username.is_some() || self.token.is_some()
+    }
+
+    /// Whether either the username and password or the token match, where a
+    /// missing password counts as empty
+    pub fn is_authorized(
+        &self,
+        username: Option<&str>,
+        password: Option<&str>,
+        token: Option<&str>,
+    ) -> bool {
+        if let (&Some(ref expected_username), Some(username)) = (&self.username, username) {
+            let expected_password = self.password.as_ref().map_or("", |p| p as &str);
+
+            if constant_time_eq(username, expected_username)
+                && constant_time_eq(password.unwrap_or(""), expected_password)
+            {
+                return true;
+            }
+        }
+
+        if let (&Some(ref expected_token), Some(token)) = (&self.token, token) {
+            if constant_time_eq(token, expected_token) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// Protects the mutating endpoints and the configuration with either HTTP
+/// basic authentication or a bearer token, when credentials are configured
+struct AuthMiddleware {
+    credentials: Credentials,
+}
+
+impl AuthMiddleware {
+    fn new(config: &Config) -> Self {
+        AuthMiddleware {
+            credentials: Credentials::new(config),
+        }
+    }
+
+    fn is_authorized(&self, req: &Request) -> bool {
+        let authorization = match req.header("Authorization") {
+            Some(authorization) => authorization,
+            None => return false,
+        };
+
+        if let Some((username, password)) = parse_basic_credentials(authorization) {
+            return self.credentials
+                .is_authorized(Some(&username), Some(&password), None);
+        }
+
+        if authorization.starts_with("Bearer ") {
+            let token = authorization["Bearer ".len()..].trim();
+
+            return self.credentials.is_authorized(None, None, Some(token));
+        }
+
+        false
+    }
+}
+
+/// The username and password of HTTP basic authentication, where the
+/// password may be left out
+fn parse_basic_credentials(authorization: &str) -> Option<(String, String)> {
+    if !authorization.starts_with("Basic ") {
+        return None;
+    }
+
+    let decoded = base64::decode(authorization["Basic ".len()..].trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+
+    let mut parts = decoded.splitn(2, ':');
+
+    let username = parts.next().unwrap_or("").to_string();
+    let password = parts.next().unwrap_or("").to_string();
+
+    Some((username, password))
+}
+
+impl BeforeMiddleware for AuthMiddleware {
+    fn before(&self, req: &Request) -> ::std::result::Result<(), Response> {
+        let is_protected = is_protected(&req.method, &get_route_path(req));
+
+        if !is_protected || !self.credentials.is_enabled() || self.is_authorized(req) {
+            return Ok(());
+        }
+
+        warn!("Unauthorized {} request to {}", req.method, get_log_url(req));
+
+        let mut res = error_response(
+            StatusCode::UNAUTHORIZED,
+            "UNAUTHORIZED",
+            "Valid credentials are required",
+        );
+
+        if self.credentials.username.is_some() {
+            set_header(
+                &mut res,
+                header::WWW_AUTHENTICATE,
+                "Basic realm=\"WiFi Connect\"",
+            );
+        }
+
+        Err(res)
+    }
+}
+
+/// Pushes back the activity timeout on every request, except for the ones
+/// of monitoring tools, which would otherwise keep WiFi Connect running
+struct ActivityMiddleware {
+    activity: ActivityTimer,
+}
+
+impl BeforeMiddleware for ActivityMiddleware {
+    fn before(&self, req: &Request) -> ::std::result::Result<(), Response> {
+        let is_monitoring = match get_route_path(req).first() {
+            Some(&"health") | Some(&"ready") | Some(&"timeout") => true,
+            _ => false,
+        };
+
+        if !is_monitoring {
+            self.activity.kick();
+        }
+
+        Ok(())
+    }
+}
+
+/// Limits the mutating requests of every client, so that a neighbor cannot
+/// keep the device busy with connection attempts
+struct RateLimitMiddleware {
+    limit: usize,
+    requests: Mutex<HashMap<IpAddr, VecDeque<Instant>>>,
+}
+
+impl BeforeMiddleware for RateLimitMiddleware {
+    fn before(&self, req: &Request) -> ::std::result::Result<(), Response> {
+        if !is_mutating(&req.method) || self.limit == 0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let window = Duration::from_secs(RATE_LIMIT_WINDOW);
+
+        let mut requests = self.requests.lock().unwrap();
+
+        // Clients quiet for a whole window are forgotten
+        requests.retain(|_, times| {
+            times
+                .back()
+                .map_or(false, |time| now.duration_since(*time) < window)
+        });
+
+        let times = requests
+            .entry(req.remote_addr.ip())
+            .or_insert_with(VecDeque::new);
+
+        while times
+            .front()
+            .map_or(false, |time| now.duration_since(*time) >= window)
+        {
+            times.pop_front();
+        }
+
+        if times.len() < self.limit {
+            times.push_back(now);
+            return Ok(());
+        }
+
+        let retry_after = RATE_LIMIT_WINDOW - now.duration_since(times[0]).as_secs();
+
+        warn!(
+            "Rate limited {} request to {} from {}",
+            req.method,
+            get_log_url(req),
+            req.remote_addr.ip()
+        );
+
+        let mut res = error_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            "RATE_LIMITED",
+            &format!("Too many requests - retry in {}s", retry_after),
+        );
+
+        set_header(&mut res, header::RETRY_AFTER, &retry_after.to_string());
+
+        Err(res)
+    }
+}
+
+/// Rejects mutating requests which do not echo the pairing token of the
+/// portal SSID in the `X-Pairing-Token` header
+struct PairingMiddleware {
+    token: PairingToken,
+}
+
+impl BeforeMiddleware for PairingMiddleware {
+    fn before(&self, req: &Request) -> ::std::result::Result<(), Response> {
+        if !is_mutating(&req.method) {
+            return Ok(());
+        }
+
+        let is_paired = match self.token.get() {
+            Some(ref expected) => req.headers
+                .get_all(PAIRING_TOKEN_HEADER)
+                .iter()
+                .any(|value| {
+                    let value = String::from_utf8_lossy(value.as_bytes());
+                    constant_time_eq(&value.trim().to_uppercase(), expected)
+                }),
+            None => false,
+        };
+
+        if is_paired {
+            return Ok(());
+        }
+
+        warn!("Unpaired {} request to {}", req.method, get_log_url(req));
+
+        Err(error_response(
+            StatusCode::FORBIDDEN,
+            "PAIRING_TOKEN_REQUIRED",
+            "The X-Pairing-Token header must match the token of the WiFi network name",
+        ))
+    }
+}
+
+/// Compares secrets without leaking the position of the first difference
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.bytes().zip(b.bytes()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Allows the configured origins to call the API from a browser, e.g. a
+/// provisioning UI hosted elsewhere, and answers their preflight requests
+struct CorsMiddleware {
+    origins: Vec<String>,
+    methods: String,
+    headers: String,
+}
+
+impl CorsMiddleware {
+    fn get_allowed_origin(&self, req: &Request) -> Option<String> {
+        let origin = req.headers.get(header::ORIGIN)?;
+        let origin = String::from_utf8_lossy(origin.as_bytes()).trim().to_string();
+
+        if self.origins
+            .iter()
+            .any(|allowed| allowed == "*" || *allowed == origin)
+        {
+            Some(origin)
+        } else {
+            None
+        }
+    }
+
+    fn is_preflight(req: &Request) -> bool {
+        req.method == Method::OPTIONS
+            && req.headers
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    fn preflight_response(&self, origin: &str) -> Response {
+        let mut res = response(StatusCode::NO_CONTENT, Vec::new());
+
+        set_header(&mut res, header::ACCESS_CONTROL_ALLOW_METHODS, &self.methods);
+        set_header(&mut res, header::ACCESS_CONTROL_ALLOW_HEADERS, &self.headers);
+        set_header(
+            &mut res,
+            header::ACCESS_CONTROL_MAX_AGE,
+            &CORS_MAX_AGE.to_string(),
+        );
+
+        set_allowed_origin(&mut res, origin);
+
+        res
+    }
+}
+
+fn set_allowed_origin(res: &mut Response, origin: &str) {
+    set_header(res, header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+    set_header(res, header::VARY, "Origin");
+}
+
+impl AfterMiddleware for CorsMiddleware {
+    fn after(&self, req: &Request, mut res: Response) -> Response {
+        if let Some(origin) = self.get_allowed_origin(req) {
+            // Routes without an OPTIONS handler, i.e. all of them, fail the
+            // preflight otherwise
+            if CorsMiddleware::is_preflight(req) {
+                return self.preflight_response(&origin);
+            }
+
+            set_allowed_origin(&mut res, &origin);
+        }
+
+        res
+    }
+}
+
+/// Reshapes the JSON responses for frontends written against forks of this
+/// project: renames the fields to another casing and wraps the responses in
+/// an envelope
+struct ResponseShapeMiddleware {
+    case: JsonCase,
+    envelope: bool,
+}
+
+impl AfterMiddleware for ResponseShapeMiddleware {
+    fn after(&self, _: &Request, mut res: Response) -> Response {
+        if get_media_type(res.headers()).map_or(true, |media_type| media_type != "application/json")
+        {
+            return res;
+        }
+
+        let value = match *res.body() {
+            ResponseBody::Buffered(ref body) => serde_json::from_slice::<Value>(body).ok(),
+            ResponseBody::Streamed(_) => None,
+        };
+
+        let value = match value {
+            Some(value) => convert_case(value, self.case),
+            None => return res,
+        };
+
+        let value = if !self.envelope {
+            value
+        } else if res.status().is_success() {
+            json!({ "success": true, "data": value })
+        } else {
+            json!({ "success": false, "error": value })
+        };
+
+        *res.body_mut() = ResponseBody::Buffered(value.to_string().into_bytes());
+
+        res
+    }
+}
+
+/// Logs every request and the status of its response. All request details
+/// go through the redaction rules, so secrets never reach the logs.
+struct RequestLogMiddleware;
+
+impl AfterMiddleware for RequestLogMiddleware {
+    fn after(&self, req: &Request, res: Response) -> Response {
+        debug!("{} {} -> {}", req.method, get_log_url(req), res.status());
+
+        res
+    }
+}
+
+/// Sends the pages missing from the portal to it when they were requested
+/// for another host, e.g. the probes of captive portal detection. Errors of
+/// the API reach its clients whatever host name they use for the device.
+struct RedirectMiddleware {
+    /// First path segments of the API routes
+    api_paths: Vec<String>,
+}
+
+impl RedirectMiddleware {
+    fn new() -> Self {
+        let mut api_paths = get_api_routes()
+            .into_iter()
+            .filter_map(|route| route.path[1..].split('/').next().map(String::from))
+            .collect::<Vec<_>>();
+
+        api_paths.push("api".to_string());
+        api_paths.sort();
+        api_paths.dedup();
+
+        RedirectMiddleware {
+            api_paths: api_paths,
+        }
+    }
+}
+
+impl AfterMiddleware for RedirectMiddleware {
+    fn after(&self, req: &Request, res: Response) -> Response {
+        let is_api = match req.path().first() {
+            Some(segment) => self.api_paths.iter().any(|path| path == segment),
+            None => false,
+        };
+
+        if is_api || res.status() != StatusCode::NOT_FOUND {
+            return res;
+        }
+
+        let host = match req.header("Host") {
+            Some(host) => host,
+            None => return res,
+        };
+
+        // The port is left out, unlike the brackets of IPv6 addresses
+        let hostname = match host.rfind(':') {
+            Some(index) if !host[index..].contains(']') => &host[..index],
+            _ => host,
+        };
+
+        if hostname == req.state.gateway.to_string() {
+            return res;
+        }
+
+        let mut res = response(StatusCode::FOUND, Vec::new());
+        set_header(&mut res, header::LOCATION, &req.state.portal_url);
+        res
+    }
+}
+
+/// A route of the HTTP API, which both the router and the OpenAPI
+/// description are built from
+struct ApiRoute {
+    method: Method,
+    path: &'static str,
+    handler: fn(&Request) -> ResponseFuture,
+    summary: &'static str,
+    query: &'static [&'static str],
+    request: Option<Value>,
+    media_type: &'static str,
+}
+
+impl ApiRoute {
+    fn new(
+        method: Method,
+        path: &'static str,
+        handler: fn(&Request) -> ResponseFuture,
+        summary: &'static str,
+    ) -> Self {
+        ApiRoute {
+            method: method,
+            path: path,
+            handler: handler,
+            summary: summary,
+            query: &[],
+            request: None,
+            media_type: "application/json",
+        }
+    }
+
+    fn query(mut self, query: &'static [&'static str]) -> Self {
+        self.query = query;
+        self
+    }
+
+    /// Schema of the request body, derived from its typed request struct
+    fn request(mut self, schema: Value) -> Self {
+        self.request = Some(schema);
+        self
+    }
+
+    fn media_type(mut self, media_type: &'static str) -> Self {
+        self.media_type = media_type;
+        self
+    }
+}
+
+fn get_api_routes() -> Vec<ApiRoute> {
+    vec![
+        ApiRoute::new(Method::GET, "/ssid", ssid, "SSID of the captive portal"),
+        ApiRoute::new(Method::GET, "/ssids", ssids, "Access points visible to the device")
+            .query(&["format", "max_age"]),
+        ApiRoute::new(Method::POST, "/scan", scan, "Rescan the access points").query(&["wait"]),
+        ApiRoute::new(Method::POST, "/connect", connect, "Connect to an access point")
+            .request(get_request_schema::<ConnectRequest>()),
+        ApiRoute::new(
+            Method::GET,
+            "/connect/progress",
+            progress,
+            "Progress of the connection attempt",
+        ),
+        ApiRoute::new(
+            Method::POST,
+            "/connect/any",
+            connect_any,
+            "Connect to any saved network in range",
+        ),
+        ApiRoute::new(
+            Method::POST,
+            "/verify",
+            verify,
+            "Test credentials without keeping the profile",
+        ).request(get_request_schema::<VerifyRequest>()),
+        ApiRoute::new(
+            Method::GET,
+            "/events",
+            event_stream,
+            "Stream of provisioning events, or a long poll of those after `since`",
+        ).query(&["since"])
+            .media_type("text/event-stream"),
+        ApiRoute::new(
+            Method::GET,
+            "/timeout",
+            timeout,
+            "Time left until the activity timeout",
+        ),
+        ApiRoute::new(
+            Method::POST,
+            "/forget",
+            forget,
+            "Delete the saved profiles of an SSID",
+        ).request(get_request_schema::<ForgetRequest>()),
+        ApiRoute::new(Method::GET, "/saved", saved, "Saved WiFi profiles"),
+        ApiRoute::new(Method::GET, "/signal", signal, "Signal of the current connection"),
+        ApiRoute::new(Method::GET, "/hotspot", hotspot, "Captive portal access point"),
+        ApiRoute::new(
+            Method::POST,
+            "/portal/restart",
+            restart_portal,
+            "Recreate the captive portal",
+        ),
+        ApiRoute::new(Method::GET, "/qr", qr, "QR code for joining the portal")
+            .query(&["format", "type"])
+            .media_type("image/svg+xml"),
+        ApiRoute::new(Method::GET, "/interfaces", interfaces, "WiFi interfaces"),
+        ApiRoute::new(
+            Method::PUT,
+            "/interface",
+            select_interface,
+            "Select the WiFi interface",
+        ).request(get_request_schema::<InterfaceRequest>()),
+        ApiRoute::new(Method::GET, "/status", status, "Provisioning status"),
+        ApiRoute::new(Method::GET, "/metrics", metrics, "Provisioning metrics"),
+        ApiRoute::new(Method::GET, "/connection", connection, "Current connection"),
+        ApiRoute::new(Method::GET, "/health", health, "Liveness of the network thread"),
+        ApiRoute::new(Method::GET, "/ready", ready, "Readiness of the captive portal"),
+        ApiRoute::new(
+            Method::GET,
+            "/capabilities",
+            capabilities,
+            "Features of the device",
+        ),
+        ApiRoute::new(
+            Method::GET,
+            "/internet",
+            internet,
+            "Run the Internet check probes",
+        ),
+        ApiRoute::new(
+            Method::POST,
+            "/connectivity/recheck",
+            recheck_connectivity,
+            "Have NetworkManager check the connectivity",
+        ),
+        ApiRoute::new(Method::GET, "/ethernet", ethernet, "Ethernet status"),
+        ApiRoute::new(
+            Method::POST,
+            "/ethernet",
+            configure_ethernet,
+            "Configure the Ethernet IPv4 settings",
+        ).request(get_request_schema::<Ipv4Request>()),
+        ApiRoute::new(
+            Method::POST,
+            "/advanced/ipv4",
+            advanced_ipv4,
+            "Change the IPv4 settings of the connection",
+        ).request(get_request_schema::<Ipv4Request>()),
+        ApiRoute::new(
+            Method::POST,
+            "/advanced/ipv6",
+            advanced_ipv6,
+            "Change the IPv6 settings of the connection",
+        ).request(get_request_schema::<Ipv6Request>()),
+        ApiRoute::new(
+            Method::POST,
+            "/advanced/dns",
+            advanced_dns,
+            "Change the DNS servers of the connection",
+        ).request(get_request_schema::<DnsRequest>()),
+        ApiRoute::new(
+            Method::POST,
+            "/advanced/proxy",
+            advanced_proxy,
+            "Change the proxy of the connection",
+        ).request(get_request_schema::<ProxyRequest>()),
+        ApiRoute::new(
+            Method::POST,
+            "/confirm",
+            confirm,
+            "Confirm the connection before the rollback",
+        ),
+        ApiRoute::new(
+            Method::POST,
+            "/config/export",
+            export_config,
+            "Export the saved profiles",
+        ).request(get_request_schema::<ExportRequest>()),
+        ApiRoute::new(
+            Method::POST,
+            "/config/import",
+            import_config,
+            "Import saved profiles",
+        ).request(get_request_schema::<ImportRequest>()),
+        ApiRoute::new(
+            Method::GET,
+            "/config",
+            get_config,
+            "Effective configuration with its sources",
+        ),
+        ApiRoute::new(
+            Method::PUT,
+            "/config/reload",
+            reload_config,
+            "Reload the configuration",
+        ),
+        ApiRoute::new(
+            Method::POST,
+            "/bssid-blacklist",
+            bssid_blacklist,
+            "Blacklist a BSSID",
+        ).request(get_request_schema::<BlacklistBssidRequest>()),
+        ApiRoute::new(
+            Method::POST,
+            "/nm/restart",
+            restart_network_manager,
+            "Restart NetworkManager",
+        ),
+    ]
+}
+
+/// E.g. `/api/v1`
+fn get_api_prefix() -> String {
+    format!("/api/v{}", API_VERSION)
+}
+
+/// The path of a request below the versioned prefix, which is the same as
+/// that of the legacy alias
+fn get_route_path(req: &Request) -> Vec<&str> {
+    let mut path = req.path();
+
+    if path.len() > 2 && path[0] == "api" && path[1] == format!("v{}", API_VERSION) {
+        path.drain(..2);
+    }
+
+    path
+}
+
+/// Whether the route requires credentials, when they are configured: the
+/// mutating ones and the configuration. CORS preflights never carry
+/// credentials, so they are left to the CORS middleware.
+fn is_protected(method: &Method, path: &[&str]) -> bool {
+    is_mutating(method) || (path == ["config"] && *method != Method::OPTIONS)
+}
+
+fn get_openapi_document(config: &Config) -> Value {
+    let operations = get_api_routes()
+        .into_iter()
+        .map(|route| {
+            let path = route.path[1..].split('/').collect::<Vec<_>>();
+
+            Operation {
+                method: route.method.to_string(),
+                path: route.path,
+                summary: route.summary,
+                query: route.query,
+                request: route.request,
+                media_type: route.media_type,
+                protected: is_protected(&route.method, &path),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    get_document(
+        env!("CARGO_PKG_VERSION"),
+        &get_api_prefix(),
+        &operations,
+        config.auth_username.is_some(),
+        config.auth_token.is_some(),
+    )
+}
+
+/// The HTTP server, which serves on its own runtime until it is closed
+pub struct HttpServer {
+    runtime: Runtime,
+}
+
+impl HttpServer {
+    /// Closes the listeners and the open connections, e.g. the event
+    /// streams, without waiting for the requests in progress
+    pub fn close(self) {
+        let _ = self.runtime.shutdown_now();
+    }
+}
+
+/// Starts the HTTP server on every listening address. The returned server
+/// is owned by the caller, which closes it on shutdown.
+pub fn start_server(
+    config: &Config,
+    shared_config: SharedConfig,
+    listening_addresses: Vec<SocketAddr>,
+    tls_identity: Option<PathBuf>,
     network_tx: Sender<NetworkCommand>,
     exit_tx: Sender<ExitResult>,
+    pairing_token: PairingToken,
+    connect_progress: SharedConnectProgress,
+    events: EventBus,
+    activity: ActivityTimer,
+) -> Result<HttpServer> {
+    let request_state = RequestSharedState {
+        gateway: config.gateway,
+        portal_url: get_portal_url(config),
+        network_tx: Mutex::new(network_tx),
+        exit_tx: Mutex::new(exit_tx),
+        capabilities: get_capabilities(config),
+        config: shared_config,
+        connect_progress: connect_progress,
+        events: events,
+        activity: activity.clone(),
+        enrollment_token: config.enrollment_token.clone(),
+    };
+
+    let ui = match config.ui_directory {
+        Some(ref ui_directory) => Ui::Directory(ui_directory.clone()),
+        None => Ui::Embedded,
+    };
+
+    let mut router = Router::new(ui);
+
+    // The routes are served under the versioned prefix and, for the clients
+    // of earlier releases, at the root
+    for route in get_api_routes() {
+        let versioned_path = format!("{}{}", get_api_prefix(), route.path);
+
+        router.route(route.method.clone(), route.path, route.handler);
+        router.route(route.method, &versioned_path, route.handler);
+    }
+
+    let openapi = get_openapi_document(config);
+
+    router.route(Method::GET, "/api/openapi.json", move |req: &Request| {
+        let serializer = get_serializer(req);
+        respond(serialized_response(serializer, StatusCode::OK, &openapi))
+    });
+
+    if config.ssdp {
+        let description = get_description(config);
+
+        router.route(
+            Method::GET,
+            &format!("/{}", DESCRIPTION_PATH),
+            move |_: &Request| {
+                respond(typed_response(
+                    StatusCode::OK,
+                    "text/xml",
+                    description.clone(),
+                ))
+            },
+        );
+    }
+
+    let mut chain = Chain::new(router);
+    chain.link_before(RateLimitMiddleware {
+        limit: config.rate_limit as usize,
+        requests: Mutex::new(HashMap::new()),
+    });
+    chain.link_before(AuthMiddleware::new(config));
+
+    if config.pairing_token {
+        chain.link_before(PairingMiddleware { token: pairing_token });
+    }
+
+    // Only requests which passed authentication count as activity
+    chain.link_before(ActivityMiddleware { activity: activity });
+
+    if config.json_case != JsonCase::Snake || config.json_envelope {
+        chain.link_after(ResponseShapeMiddleware {
+            case: config.json_case,
+            envelope: config.json_envelope,
+        });
+    }
+
+    if !config.cors_origins.is_empty() {
+        chain.link_after(CorsMiddleware {
+            origins: config.cors_origins.clone(),
+            methods: config.cors_methods.clone(),
+            headers: config.cors_headers.clone(),
+        });
+    }
+
+    chain.link_after(RedirectMiddleware::new());
+    chain.link_after(RequestLogMiddleware);
+
+    let tls_acceptor = match tls_identity {
+        Some(identity) => match get_tls_acceptor(&identity) {
+            Ok(tls_acceptor) => Some(tls_acceptor),
+            Err(e) => {
+                let address = format!("{:?}", listening_addresses);
+                bail!(ErrorKind::StartHTTPServer(address, e.to_string()));
+            },
+        },
+        None => None,
+    };
+
+    let mut listeners = Vec::new();
+
+    // Sockets passed by systemd replace the listening addresses
+    let activated_sockets = take_listen_sockets();
+
+    if !activated_sockets.is_empty() {
+        for socket in activated_sockets {
+            let address = socket
+                .local_addr()
+                .map(|address| address.to_string())
+                .unwrap_or_else(|_| "activated socket".to_string());
+
+            info!(
+                "Starting HTTP server on {}://{} (socket activated)",
+                config.listening_scheme, address
+            );
+
+            match TcpListener::from_std(socket, &Handle::default()) {
+                Ok(listener) => listeners.push(listener),
+                Err(e) => bail!(ErrorKind::StartHTTPServer(address, e.to_string())),
+            }
+        }
+    } else {
+        for (index, address) in listening_addresses.into_iter().enumerate() {
+            info!(
+                "Starting HTTP server on {}://{}",
+                config.listening_scheme, address
+            );
+
+            match TcpListener::bind(&address) {
+                Ok(listener) => listeners.push(listener),
+                Err(e) => {
+                    // Only the first listener is essential, the rest are best-effort
+                    if index == 0 {
+                        bail!(ErrorKind::StartHTTPServer(address.to_string(), e.to_string()));
+                    }
+
+                    warn!("Cannot start HTTP server on {}: {}", address, e);
+                },
+            }
+        }
+    }
+
+    let runtime = match runtime::Builder::new()
+        .core_threads(SERVER_THREADS)
+        .blocking_threads(MAX_BLOCKING_REQUESTS)
+        .name_prefix("http-")
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => bail!(ErrorKind::StartHTTPServer(
+            "runtime".to_string(),
+            e.to_string()
+        )),
+    };
+
+    let chain = Arc::new(chain);
+    let request_state = Arc::new(request_state);
+
+    for listener in listeners {
+        runtime.executor().spawn(serve(
+            listener,
+            chain.clone(),
+            request_state.clone(),
+            tls_acceptor.clone(),
+        ));
+    }
+
+    Ok(HttpServer { runtime: runtime })
+}
+
+/// Reads the PKCS #12 identity of the HTTPS listeners, which has no password
+fn get_tls_acceptor(identity: &Path) -> ::std::result::Result<TlsAcceptor, Box<StdError>> {
+    let mut der = Vec::new();
+    File::open(identity)?.read_to_end(&mut der)?;
+
+    let identity = Identity::from_pkcs12(&der, "")?;
+
+    Ok(TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?))
+}
+
+/// Accepts the connections of a listener and serves each of them as a task
+/// of its own, after the TLS handshake of HTTPS
+fn serve(
+    listener: TcpListener,
+    chain: Arc<Chain>,
+    state: Arc<RequestSharedState>,
+    tls_acceptor: Option<TlsAcceptor>,
+) -> Box<Future<Item = (), Error = ()> + Send> {
+    let http = Http::new();
+
+    let sockets = listener
+        .incoming()
+        .then(|result| match result {
+            Ok(socket) => Either::A(future::ok(Some(socket))),
+            Err(e) => {
+                warn!("Accepting an HTTP connection failed: {}", e);
+
+                let retry = Instant::now() + Duration::from_secs(ACCEPT_RETRY_DELAY);
+                Either::B(Delay::new(retry).then(|_| Ok(None)))
+            },
+        })
+        .filter_map(|socket| socket);
+
+    Box::new(sockets.for_each(move |socket| {
+        let remote_addr = match socket.peer_addr() {
+            Ok(remote_addr) => remote_addr,
+            Err(_) => return Ok(()),
+        };
+
+        let chain = chain.clone();
+        let state = state.clone();
+
+        let service = service_fn(move |req| handle(&chain, &state, req, remote_addr));
+
+        let connection = match tls_acceptor {
+            Some(ref tls_acceptor) => {
+                let http = http.clone();
+
+                Either::A(
+                    Timeout::new(
+                        tls_acceptor.accept(socket),
+                        Duration::from_secs(TLS_HANDSHAKE_TIMEOUT),
+                    ).map_err(|e| debug!("TLS handshake failed: {}", e))
+                        .and_then(move |stream| {
+                            http.serve_connection(stream, service)
+                                .map_err(|e| debug!("HTTP connection failed: {}", e))
+                        }),
+                )
+            },
+            None => Either::B(
+                http.serve_connection(socket, service)
+                    .map_err(|e| debug!("HTTP connection failed: {}", e)),
+            ),
+        };
+
+        tokio::spawn(connection);
+
+        Ok(())
+    }))
+}
+
+/// Passes a request through the middlewares and the router
+fn handle(
+    chain: &Arc<Chain>,
+    state: &Arc<RequestSharedState>,
+    req: hyper::Request<Body>,
+    remote_addr: SocketAddr,
+) -> Box<Future<Item = hyper::Response<Body>, Error = hyper::Error> + Send> {
+    let (parts, body) = req.into_parts();
+
+    let req = Request {
+        method: parts.method,
+        uri: parts.uri,
+        headers: parts.headers,
+        remote_addr: remote_addr,
+        body: Chunk::default(),
+        state: state.clone(),
+    };
+
+    let handled = match chain.before(&req) {
+        Err(res) => Either::A(future::ok((req, res))),
+        Ok(()) => {
+            let chain = chain.clone();
+
+            Either::B(read_body(body).then(move |result| match result {
+                Ok(body) => {
+                    let mut req = req;
+                    req.body = body;
+
+                    Either::A(chain.router.handle(&req).map(move |res| (req, res)))
+                },
+                Err(None) => {
+                    let res = error_response(
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        "PAYLOAD_TOO_LARGE",
+                        &format!("Request body exceeds {} bytes", MAX_BODY_SIZE),
+                    );
+
+                    Either::B(future::ok((req, res)))
+                },
+                Err(Some(e)) => Either::B(future::err(e)),
+            }))
+        },
+    };
+
+    let chain = chain.clone();
+
+    Box::new(handled.map(move |(req, res)| {
+        chain.after(&req, res).map(|body| match body {
+            ResponseBody::Buffered(body) => Body::from(body),
+            ResponseBody::Streamed(body) => body,
+        })
+    }))
+}
+
+/// Reads the body of a request. Reading stops with no error, i.e. `None`, as
+/// soon as the body grows larger than `MAX_BODY_SIZE`.
+fn read_body(body: Body) -> Box<Future<Item = Chunk, Error = Option<hyper::Error>> + Send> {
+    Box::new(
+        body.map_err(Some)
+            .fold(Vec::new(), |mut buffer, chunk| {
+                if buffer.len() + chunk.len() > MAX_BODY_SIZE {
+                    return Err(None);
+                }
+
+                buffer.extend_from_slice(&chunk);
+                Ok(buffer)
+            })
+            .map(Chunk::from),
+    )
+}
+
+pub fn get_portal_url(config: &Config) -> String {
+    if config.listening_port == config.listening_scheme.default_port() {
+        format!("{}://{}/", config.listening_scheme, config.gateway)
+    } else {
+        format!(
+            "{}://{}:{}/",
+            config.listening_scheme, config.gateway, config.listening_port
+        )
+    }
+}
+
+fn ssid(req: &Request) -> ResponseFuture {
+    info!("User connected to the captive portal");
+
+    let state = req.state.clone();
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Activate {
+            response_tx: response_tx,
+        },
+        AccessPoints,
+        ErrorKind::SendNetworkCommandActivate,
+        ErrorKind::RecvAccessPointSSIDs,
+        |access_points| {
+            let access_points_ssids = access_points
+                .into_iter()
+                .map(|access_point| access_point.ssid)
+                .collect::<Vec<_>>();
+
+            match serde_json::to_string(&access_points_ssids) {
+                Ok(json) => response(StatusCode::OK, json),
+                Err(e) => exit_with_error(&state, e, ErrorKind::SerializeAccessPointSSIDs),
+            }
+        }
+    )
+}
+
+/// Lists the access points with their signal strength and the key
+/// management they require, so that clients know whether to ask for a
+/// passphrase, and the BSSIDs broadcasting each SSID. With
+/// `?format=legacy` only the SSIDs are listed, as expected by older clients.
+/// The access points of the last scan are listed, unless they are older than
+/// `?max_age` seconds. The `Age` header tells how old they are.
+fn ssids(req: &Request) -> ResponseFuture {
+    info!("User connected to the captive portal");
+
+    let serializer = get_serializer(req);
+
+    let legacy = match get_query_param(req, "format") {
+        Some(ref format) if format == "legacy" => true,
+        Some(ref format) if format == "full" => false,
+        None => false,
+        Some(format) => {
+            return respond(error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_REQUEST",
+                &format!("Invalid format: {} (expected `legacy` or `full`)", format),
+            ))
+        },
+    };
+
+    let max_age = match get_query_param(req, "max_age").map(|max_age| max_age.parse::<u64>()) {
+        Some(Ok(max_age)) => Some(max_age),
+        None => None,
+        Some(Err(_)) => {
+            return respond(error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_REQUEST",
+                "Invalid max_age (expected seconds)",
+            ))
+        },
+    };
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Scan {
+            max_age: max_age,
+            response_tx: Some(response_tx),
+        },
+        ScanResults,
+        ErrorKind::SendNetworkCommandScan,
+        ErrorKind::RecvScanResults,
+        |scan_results| {
+            let access_points_json = if legacy {
+                scan_results
+                    .access_points
+                    .iter()
+                    .map(|access_point| json!(access_point.ssid))
+                    .collect::<Vec<_>>()
+            } else {
+                access_points_to_json(&scan_results)
+            };
+
+            let mut response =
+                serialized_response(serializer, StatusCode::OK, &json!(access_points_json));
+
+            set_scan_headers(&mut response, &scan_results);
+
+            response
+        }
+    )
+}
+
+/// Rescans the access points. With `?wait=true` the request waits for the
+/// scan and returns the access points, otherwise the scan runs in the
+/// background for `/ssids` to list the access points afterwards.
+fn scan(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let wait = match get_query_param(req, "wait") {
+        Some(ref wait) => wait == "true" || wait == "1",
+        None => false,
+    };
+
+    if !wait {
+        let command = NetworkCommand::Scan {
+            max_age: None,
+            response_tx: None,
+        };
+
+        if let Err(e) = req.state.send(command) {
+            return respond(exit_with_error(&req.state, e, ErrorKind::SendNetworkCommandScan));
+        }
+
+        return respond(serialized_response(
+            serializer,
+            StatusCode::ACCEPTED,
+            &json!({ "scanning": true }),
+        ));
+    }
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Scan {
+            max_age: None,
+            response_tx: Some(response_tx),
+        },
+        ScanResults,
+        ErrorKind::SendNetworkCommandScan,
+        ErrorKind::RecvScanResults,
+        |scan_results| {
+            let mut response = serialized_response(
+                serializer,
+                StatusCode::OK,
+                &json!({
+                    "rescanned": scan_results.rescanned,
+                    "scanned_at": get_unix_time(&scan_results),
+                    "age_s": get_scan_age(&scan_results),
+                    "access_points": access_points_to_json(&scan_results),
+                }),
+            );
+
+            set_scan_headers(&mut response, &scan_results);
+
+            response
+        }
+    )
+}
+
+fn access_points_to_json(scan_results: &ScanResults) -> Vec<Value> {
+    scan_results
+        .access_points
+        .iter()
+        .map(|access_point| {
+            json!({
+                "ssid": access_point.ssid,
+                "raw_ssid": access_point.raw_ssid,
+                "strength": access_point.strength,
+                "security": access_point.key_management.as_str(),
+                "bssids": access_point
+                    .bssids
+                    .iter()
+                    .map(|entry| {
+                        json!({
+                            "bssid": entry.bssid,
+                            "strength": entry.signal,
+                            "frequency": entry.frequency,
+                            "band": Band::from_frequency(entry.frequency)
+                                .map(|band| band.as_str()),
+                        })
+                    })
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect()
+}
+
+fn get_scan_age(scan_results: &ScanResults) -> u64 {
+    SystemTime::now()
+        .duration_since(scan_results.scanned_at)
+        .map(|age| age.as_secs())
+        .unwrap_or(0)
+}
+
+fn get_unix_time(scan_results: &ScanResults) -> u64 {
+    scan_results
+        .scanned_at
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// The age of the access points in the standard `Age` header, and the time
+/// of the scan as a Unix timestamp
+fn set_scan_headers(response: &mut Response, scan_results: &ScanResults) {
+    set_header(
+        response,
+        header::AGE,
+        &get_scan_age(scan_results).to_string(),
+    );
+    set_header(
+        response,
+        HeaderName::from_static("x-scanned-at"),
+        &get_unix_time(scan_results).to_string(),
+    );
+}
+
+fn connect(req: &Request) -> ResponseFuture {
+    let header_token = get_header_token(req);
+
+    let request = get_request_body!(req, ConnectRequest);
+
+    let command = match get_connect_command(request, &req.state.enrollment_token, header_token) {
+        Ok(command) => command,
+        Err(api_error) => return respond(api_error.into_response()),
+    };
+
+    if let Err(e) = req.state.send(command) {
+        respond(exit_with_error(&req.state, e, ErrorKind::SendNetworkCommandConnect))
+    } else {
+        respond(response(StatusCode::OK, Vec::new()))
+    }
+}
+
+/// Validates a connect request, which comes from the HTTP API or from an MQTT
+/// command, and turns it into the command for the network thread
+pub fn get_connect_command(
+    request: ConnectRequest,
+    expected_enrollment_token: &Option<String>,
+    header_token: Option<String>,
+) -> ::std::result::Result<NetworkCommand, ApiError> {
+    let ConnectRequest {
+        ssid,
+        raw_ssid,
+        passphrase,
+        dns,
+        reuse,
+        band,
+        bssid,
+        enrollment_token,
+        checkpoint,
+    } = request;
+
+    if let Some(api_error) =
+        check_enrollment_token(expected_enrollment_token, enrollment_token, header_token)
+    {
+        return Err(api_error);
+    }
+
+    let raw_ssid = match raw_ssid {
+        Some(ref raw_ssid) if !raw_ssid.is_empty() => match parse_raw_ssid(raw_ssid) {
+            Ok(raw_ssid) => Some(raw_ssid),
+            Err(reason) => {
+                return Err(ApiError::new(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "INVALID_SSID",
+                    &format!("Invalid SSID: {}", reason),
+                ))
+            },
+        },
+        _ => None,
+    };
+
+    // UTF-8 SSIDs go the usual way, whichever way they were given
+    let (ssid, raw_ssid) = match raw_ssid {
+        Some(raw_ssid) => match String::from_utf8(raw_ssid) {
+            Ok(ssid) => (ssid, None),
+            Err(e) => (
+                String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                Some(e.into_bytes()),
+            ),
+        },
+        None => (ssid, None),
+    };
+
+    // Refused before reaching the network thread, which would otherwise tear
+    // down the portal for a connection attempt bound to fail
+    if let Err(reason) = if raw_ssid.is_some() {
+        Ok(())
+    } else {
+        validate_ssid(&ssid)
+    } {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "INVALID_SSID",
+            &format!("Invalid SSID: {}", reason),
+        ));
+    }
+
+    if let Err(reason) = validate_passphrase(&passphrase) {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "INVALID_PASSPHRASE",
+            &format!("Invalid passphrase: {}", reason),
+        ));
+    }
+
+    let dns_servers = match dns {
+        Some(ref dns) if !dns.is_empty() => match parse_ip_list(dns) {
+            Ok(dns_servers) => Some(dns_servers),
+            Err(e) => {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_REQUEST",
+                    &format!("Invalid DNS server address: {}", e),
+                ))
+            },
+        },
+        _ => None,
+    };
+
+    let band = match band {
+        Some(ref band) if !band.is_empty() => match band.parse::<Band>() {
+            Ok(band) => Some(band),
+            Err(message) => {
+                return Err(ApiError::new(StatusCode::BAD_REQUEST, "INVALID_REQUEST", &message))
+            },
+        },
+        _ => None,
+    };
+
+    let bssid = match bssid {
+        Some(ref bssid) if !bssid.is_empty() => {
+            let bssid = bssid.to_uppercase();
+
+            if !is_valid_bssid(&bssid) {
+                return Err(ApiError::new(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_REQUEST",
+                    &format!("Invalid BSSID: {}", bssid),
+                ));
+            }
+
+            Some(bssid)
+        },
+        _ => None,
+    };
+
+    debug!("Incoming `connect` to access point `{}` request", ssid);
+
+    Ok(NetworkCommand::Connect {
+        ssid: ssid,
+        passphrase: passphrase,
+        dns_servers: dns_servers,
+        reuse: reuse,
+        selection: ApSelection {
+            band: band,
+            bssid: bssid,
+            raw_ssid: raw_ssid,
+        },
+        checkpoint: checkpoint,
+    })
+}
+
+/// Tests the credentials of a network by connecting to it, and reports the
+/// outcome. Unless `keep` is set, the profile is deleted afterwards and the
+/// captive portal reopened, so that the UI can validate a passphrase before
+/// committing the device to the network. Clients lose the portal in between,
+/// unless it runs on a virtual interface.
+fn verify(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let header_token = get_header_token(req);
+
+    let VerifyRequest {
+        ssid,
+        passphrase,
+        keep,
+        enrollment_token,
+    } = get_request_body!(req, VerifyRequest);
+
+    if let Some(api_error) =
+        check_enrollment_token(&req.state.enrollment_token, enrollment_token, header_token)
+    {
+        return respond(api_error.into_response());
+    }
+
+    if let Err(reason) = validate_ssid(&ssid) {
+        return respond(error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "INVALID_SSID",
+            &format!("Invalid SSID: {}", reason),
+        ));
+    }
+
+    if let Err(reason) = validate_passphrase(&passphrase) {
+        return respond(error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "INVALID_PASSPHRASE",
+            &format!("Invalid passphrase: {}", reason),
+        ));
+    }
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Verify {
+            ssid: ssid.clone(),
+            passphrase: passphrase,
+            keep: keep,
+            response_tx: response_tx,
+        },
+        Verified,
+        ErrorKind::SendNetworkCommandVerify,
+        ErrorKind::RecvVerified,
+        |verified| match verified {
+            Ok(verification) => serialized_response(
+                serializer,
+                StatusCode::OK,
+                &json!({
+                    "ssid": ssid,
+                    "verified": verification.verified,
+                    "kept": verification.kept,
+                    "error": if verification.verified {
+                        None
+                    } else {
+                        Some(verification.error.unwrap_or("CONNECT_FAILED"))
+                    },
+                    "duration_s": verification.duration,
+                }),
+            ),
+            Err(VerifyError::NotAWiFiDevice) => error_response(
+                StatusCode::CONFLICT,
+                "NOT_A_WIFI_DEVICE",
+                "Credentials can only be verified on a WiFi device",
+            ),
+            Err(VerifyError::Busy) => error_response(
+                StatusCode::CONFLICT,
+                "CONNECT_BUSY",
+                "The device is connecting or connected",
+            ),
+            Err(VerifyError::AlreadySaved) => error_response(
+                StatusCode::CONFLICT,
+                "NETWORK_SAVED",
+                "The network is saved already, verifying it would replace its profile",
+            ),
+        }
+    )
+}
+
+fn get_header_token(req: &Request) -> Option<String> {
+    req.headers
+        .get(ENROLLMENT_TOKEN_HEADER)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned())
+}
+
+/// Checks the enrollment token of a request which connects the device, if
+/// one is configured, and returns the rejection of an invalid one
+fn check_enrollment_token(
+    expected_token: &Option<String>,
+    enrollment_token: Option<String>,
+    header_token: Option<String>,
+) -> Option<ApiError> {
+    let expected_token = match *expected_token {
+        Some(ref expected_token) => expected_token,
+        None => return None,
+    };
+
+    // The portal form submits an empty field if the header is used
+    let token = match enrollment_token {
+        Some(ref token) if !token.trim().is_empty() => token.trim().to_uppercase(),
+        _ => header_token.map_or_else(String::new, |token| token.trim().to_uppercase()),
+    };
+
+    if token.is_empty() {
+        warn!("Connect request without an enrollment token");
+
+        return Some(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "ENROLLMENT_TOKEN_REQUIRED",
+            "The enrollment token from the label of the device is required",
+        ));
+    }
+
+    if !constant_time_eq(&token, &expected_token.trim().to_uppercase()) {
+        warn!("Connect request with an invalid enrollment token");
+
+        return Some(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "INVALID_ENROLLMENT_TOKEN",
+            "The enrollment token does not match the label of the device",
+        ));
+    }
+
+    None
+}
+
+/// Reports the progress of the connection attempts. It is read without the
+/// network thread, which is busy until the attempts are over.
+fn progress(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let progress = req.state.connect_progress.lock().unwrap().clone();
+
+    let progress_json = match progress {
+        Some(progress) => progress.to_json(),
+        None => json!({ "state": "idle" }),
+    };
+
+    respond(serialized_response(serializer, StatusCode::OK, &progress_json))
+}
+
+/// Reports when the activity timeout runs out, unless there is further
+/// activity in the meantime
+fn timeout(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let activity = &req.state.activity;
+
+    let timeout_json = match activity.remaining() {
+        Some(remaining) => json!({
+            "timeout": activity.timeout(),
+            "remaining": remaining.as_secs(),
+            "deadline": activity
+                .deadline()
+                .and_then(|deadline| deadline.duration_since(UNIX_EPOCH).ok())
+                .map(|deadline| deadline.as_secs()),
+        }),
+        None => json!({
+            "timeout": 0,
+            "remaining": null,
+            "deadline": null,
+        }),
+    };
+
+    respond(serialized_response(serializer, StatusCode::OK, &timeout_json))
+}
+
+/// Streams the changes of the provisioning state as server-sent events:
+/// a snapshot of all fields first and periodically, and deltas with the
+/// changed fields in between. With `since`, the events are long-polled.
+fn event_stream(req: &Request) -> ResponseFuture {
+    if let Some(since) = get_query_param(req, "since") {
+        return poll_events(req, &since);
+    }
+
+    let stream = match req.state.events.subscribe() {
+        Some(stream) => stream,
+        None => {
+            return respond(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "TOO_MANY_SUBSCRIBERS",
+                "Too many event streams are open",
+            ))
+        },
+    };
+
+    let mut response =
+        hyper::Response::new(ResponseBody::Streamed(Body::wrap_stream(stream)));
+
+    set_header(&mut response, header::CONTENT_TYPE, "text/event-stream");
+    set_header(&mut response, header::CACHE_CONTROL, "no-cache");
+
+    respond(response)
+}
+
+/// Answers with the events after the sequence number, for clients which
+/// cannot keep an event stream open. The request waits for the next event
+/// if there is none yet, and answers without events on the timeout.
+fn poll_events(req: &Request, since: &str) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let since = match since.parse::<u64>() {
+        Ok(since) => since,
+        Err(_) => {
+            return respond(error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_REQUEST",
+                &format!("Invalid sequence number: {}", since),
+            ))
+        },
+    };
+
+    let poll = match req.state
+        .events
+        .poll(since, Duration::from_secs(LONG_POLL_TIMEOUT))
+    {
+        Some(poll) => poll,
+        None => {
+            return respond(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "TOO_MANY_SUBSCRIBERS",
+                "Too many event streams are open",
+            ))
+        },
+    };
+
+    Box::new(poll.then(move |result| {
+        // The timer only fails when the server shuts down
+        let events = result.unwrap_or_else(|_| Vec::new());
+
+        // The next poll continues after the last event, or where it started
+        let seq = events
+            .last()
+            .and_then(|event| event["seq"].as_u64())
+            .unwrap_or(since);
+
+        Ok(serialized_response(
+            serializer,
+            StatusCode::OK,
+            &json!({
+                "seq": seq,
+                "events": events,
+            }),
+        ))
+    }))
+}
+
+fn status(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Status {
+            response_tx: response_tx,
+        },
+        Status,
+        ErrorKind::SendNetworkCommandStatus,
+        ErrorKind::RecvStatus,
+        |status| {
+            let status_json = json!({
+                "activated": status.activated,
+                "portal_active": status.portal_active,
+                "metrics": status.metrics.to_json(),
+            });
+
+            serialized_response(serializer, StatusCode::OK, &status_json)
+        }
+    )
+}
+
+fn metrics(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Status {
+            response_tx: response_tx,
+        },
+        Status,
+        ErrorKind::SendNetworkCommandStatus,
+        ErrorKind::RecvStatus,
+        |status| serialized_response(serializer, StatusCode::OK, &status.metrics.to_json())
+    )
 }
 
-impl typemap::Key for RequestSharedState {
-    type Value = RequestSharedState;
+fn capabilities(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    respond(serialized_response(
+        serializer,
+        StatusCode::OK,
+        &req.state.capabilities,
+    ))
 }
 
-#[derive(Debug)]
-struct StringError(String);
+/// Runs the Internet check probes and lists which succeeded. The request
+/// takes up to the probe timeout for every probe.
+fn internet(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
 
-impl fmt::Display for StringError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+    let (probes, timeout) = {
+        let config = req.state.config.lock().unwrap();
+
+        (config.internet_checks.clone(), config.internet_check_timeout)
+    };
+
+    if probes.is_empty() {
+        return respond(error_response(
+            StatusCode::NOT_FOUND,
+            "INTERNET_CHECK_NOT_CONFIGURED",
+            "No Internet check probes are configured",
+        ));
     }
+
+    run_blocking(move || {
+        let results = check_internet(&probes, timeout);
+
+        serialized_response(serializer, StatusCode::OK, &results_to_json(&results))
+    })
+}
+
+/// Has NetworkManager check the connectivity right away, e.g. after a link
+/// change, rather than reporting the result of its last periodic check
+fn recheck_connectivity(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    run_blocking(move || {
+        let started = Instant::now();
+
+        let connectivity = match check_connectivity() {
+            Ok(connectivity) => connectivity,
+            Err(e) => {
+                return error_response(
+                    StatusCode::BAD_GATEWAY,
+                    "CONNECTIVITY_CHECK_FAILED",
+                    &e.to_string(),
+                )
+            },
+        };
+
+        let elapsed = started.elapsed();
+        let duration_ms = elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_nanos()) / 1_000_000;
+
+        serialized_response(
+            serializer,
+            StatusCode::OK,
+            &json!({
+                "connectivity": format!("{:?}", connectivity),
+                "online": connectivity == Connectivity::Full,
+                "duration_ms": duration_ms,
+            }),
+        )
+    })
+}
+
+/// Describes the transports, features and portal power settings of this
+/// instance
+fn get_capabilities(config: &Config) -> Value {
+    let mut capabilities = config.summary();
+
+    capabilities["portal_power"] = json!({
+        "tx_power": config.portal_tx_power,
+        "power_save": config.portal_power_save,
+        "sleep_timeout": config.portal_sleep_timeout,
+    });
+
+    capabilities["portal_beacon"] = json!({
+        "supported": BEACON_SETTINGS_SUPPORTED,
+        "interval": config.portal_beacon_interval,
+        "dtim_period": config.portal_dtim_period,
+    });
+
+    // Enforced through DHCP, as NetworkManager has no station limit
+    capabilities["portal_max_clients"] = json!({
+        "limit": config.portal_max_clients,
+        "enforced_by": "dhcp",
+    });
+
+    capabilities
+}
+
+fn health(req: &Request) -> ResponseFuture {
+    health_response(req, false)
+}
+
+fn ready(req: &Request) -> ResponseFuture {
+    health_response(req, true)
+}
+
+/// Reports the components for supervisors. The service is healthy while
+/// NetworkManager is reachable and the network thread is alive, and ready
+/// once the network thread answers and DHCP and DNS serve the captive portal.
+fn health_response(req: &Request, readiness: bool) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let (response_tx, response_rx) = unbounded();
+
+    // The response channel closes right away if the network thread is gone
+    let _ = req.state.send(NetworkCommand::Health {
+        response_tx: response_tx,
+    });
+
+    let timeout = Delay::new(Instant::now() + Duration::from_secs(HEALTH_TIMEOUT));
+
+    // A busy network thread, e.g. while connecting, is alive but not ready
+    let health = response_rx.into_future().select2(timeout).then(|result| {
+        Ok::<_, hyper::Error>(match result {
+            Ok(Either::A(((Some(NetworkCommandResponse::Health(health)), _), _))) => {
+                ("running", Some(health))
+            },
+            Ok(Either::A(((Some(_), _), _))) => ("running", None),
+            Ok(Either::A(((None, _), _))) | Err(Either::A(_)) => ("stopped", None),
+            Ok(Either::B(_)) | Err(Either::B(_)) => ("busy", None),
+        })
+    });
+
+    Box::new(health.and_then(move |(network_thread, health)| {
+        run_blocking(move || {
+            let network_manager = is_network_manager_reachable();
+
+            let healthy = network_manager && network_thread != "stopped";
+
+            let ready = network_manager && match health {
+                Some(ref health) => health.dhcp_dns_running || !health.portal_active,
+                None => false,
+            };
+
+            let ok = if readiness { ready } else { healthy };
+
+            let body = json!({
+                "status": if ok { "ok" } else { "error" },
+                "checks": {
+                    "network_manager": network_manager,
+                    "network_thread": network_thread,
+                    "wifi_device": health.as_ref().map(|health| health.interface.clone()),
+                    "portal_active": health.as_ref().map(|health| health.portal_active),
+                    "dhcp_dns": health.as_ref().map(|health| health.dhcp_dns_running),
+                },
+            });
+
+            let status = if ok {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+
+            serialized_response(serializer, status, &body)
+        })
+    }))
 }
 
-impl StdError for StringError {
-    fn description(&self) -> &str {
-        &*self.0
+/// Deletes the saved profiles of a network and lists the remaining ones
+fn forget(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let ForgetRequest { ssid } = get_request_body!(req, ForgetRequest);
+
+    if let Err(reason) = validate_ssid(&ssid) {
+        return respond(error_response(
+            StatusCode::BAD_REQUEST,
+            "INVALID_SSID",
+            &format!("Invalid SSID: {}", reason),
+        ));
     }
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Forget {
+            ssid: ssid.clone(),
+            response_tx: response_tx,
+        },
+        Forgotten,
+        ErrorKind::SendNetworkCommandForget,
+        ErrorKind::RecvForgotten,
+        |forgotten| {
+            let forgotten_json = json!({
+                "ssid": ssid,
+                "deleted": forgotten.deleted,
+                "saved": saved_to_json(&forgotten.saved),
+            });
+
+            serialized_response(serializer, StatusCode::OK, &forgotten_json)
+        }
+    )
 }
 
-macro_rules! get_request_ref {
-    ($req:ident, $ty:ty, $err:expr) => (
-        match $req.get_ref::<$ty>() {
-            Ok(val) => val,
-            Err(err) => {
-                error!($err);
-                return Err(IronError::new(err, status::InternalServerError));
-            }
+/// Lists the saved WiFi networks, most recently used first, so that the UI
+/// can mark known networks
+fn saved(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::ListSaved {
+            response_tx: response_tx,
+        },
+        Saved,
+        ErrorKind::SendNetworkCommandListSaved,
+        ErrorKind::RecvSaved,
+        |profiles| serialized_response(serializer, StatusCode::OK, &saved_to_json(&profiles))
+    )
+}
+
+/// Reports the signal of the connected access point. It is read anew on every
+/// request, so the UI may poll it while the antenna is positioned.
+fn signal(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Signal {
+            response_tx: response_tx,
+        },
+        Signal,
+        ErrorKind::SendNetworkCommandSignal,
+        ErrorKind::RecvSignal,
+        |signal| {
+            let signal_json = match signal {
+                Some(signal) => json!({
+                    "connected": true,
+                    "ssid": signal.ssid,
+                    "strength": signal.strength,
+                    "rssi": signal.rssi,
+                }),
+                None => json!({ "connected": false }),
+            };
+
+            serialized_response(serializer, StatusCode::OK, &signal_json)
         }
     )
 }
 
-macro_rules! get_param {
-    ($params:ident, $param:expr, $ty:ty) => (
-        match $params.get($param) {
-            Some(value) => {
-                match <$ty as FromValue>::from_value(value) {
-                    Some(converted) => converted,
-                    None => {
-                        let err = format!("Unexpected type for '{}'", $param);
-                        error!("{}", err);
-                        return Err(IronError::new(StringError(err), status::InternalServerError));
-                    }
-                }
-            },
-            None => {
-                let err = format!("'{}' not found in request params: {:?}", $param, $params);
-                error!("{}", err);
-                return Err(IronError::new(StringError(err), status::InternalServerError));
-            }
+/// Reports the parameters of the captive portal access point, so that
+/// companion apps can show how to join it
+fn hotspot(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Hotspot {
+            response_tx: response_tx,
+        },
+        Hotspot,
+        ErrorKind::SendNetworkCommandHotspot,
+        ErrorKind::RecvHotspot,
+        |hotspot| {
+            serialized_response(serializer, StatusCode::OK, &hotspot_to_json(hotspot.as_ref()))
         }
     )
 }
 
-macro_rules! get_request_state {
-    ($req:ident) => (
-        get_request_ref!(
-            $req,
-            Write<RequestSharedState>,
-            "Getting reference to request shared state failed"
-        ).as_ref().lock().unwrap()
+fn hotspot_to_json(hotspot: Option<&Hotspot>) -> Value {
+    match hotspot {
+        Some(hotspot) => json!({
+            "active": true,
+            "ssid": hotspot.ssid,
+            "security": if hotspot.passphrase.is_some() { "wpa-psk" } else { "open" },
+            "passphrase": hotspot.passphrase,
+            "interface": hotspot.interface,
+            "gateway": hotspot.gateway.to_string(),
+            "channel": hotspot.channel,
+            "frequency": hotspot.frequency,
+            "band": hotspot
+                .frequency
+                .and_then(Band::from_frequency)
+                .map(|band| band.as_str()),
+        }),
+        None => json!({ "active": false }),
+    }
+}
+
+/// Recreates the access point and the DHCP and DNS servers of the captive
+/// portal, e.g. when it got into a bad RF state, with the SSID and channel
+/// of the current configuration. Clients have to join the portal again.
+fn restart_portal(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::RestartPortal {
+            response_tx: response_tx,
+        },
+        PortalRestarted,
+        ErrorKind::SendNetworkCommandRestartPortal,
+        ErrorKind::RecvPortalRestarted,
+        |restarted| match restarted {
+            Some(restarted) => serialized_response(
+                serializer,
+                StatusCode::OK,
+                &json!({
+                    "hotspot": hotspot_to_json(Some(&restarted.hotspot)),
+                    "duration_s": restarted.duration,
+                }),
+            ),
+            None => error_response(
+                StatusCode::CONFLICT,
+                "PORTAL_NOT_ACTIVE",
+                "The captive portal is not open",
+            ),
+        }
     )
 }
 
-fn exit_with_error<E>(state: &RequestSharedState, e: E, e_kind: ErrorKind) -> IronResult<Response>
-where
-    E: ::std::error::Error + Send + 'static,
-{
-    let description = e_kind.description().into();
-    let err = Err::<Response, E>(e).chain_err(|| e_kind);
-    exit(&state.exit_tx, err.unwrap_err());
-    Err(IronError::new(
-        StringError(description),
-        status::InternalServerError,
+/// The resolved configuration the device runs with, after reloads, with the
+/// source of every option and the secrets redacted. It is only served with
+/// credentials configured, as it tells much about the device.
+fn get_config(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let config = req.state.config.lock().unwrap().clone();
+
+    if config.auth_username.is_none() && config.auth_token.is_none() {
+        return respond(error_response(
+            StatusCode::FORBIDDEN,
+            "AUTH_NOT_CONFIGURED",
+            "Reading the configuration requires --auth-username or --auth-token",
+        ));
+    }
+
+    respond(serialized_response(
+        serializer,
+        StatusCode::OK,
+        &json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "options": config.to_json_with_sources(),
+        }),
     ))
 }
 
-struct RedirectMiddleware;
+/// Reads the configuration again, like `SIGHUP`, and lists the changed
+/// options which applied and those which need a restart
+fn reload_config(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
 
-impl AfterMiddleware for RedirectMiddleware {
-    fn catch(&self, req: &mut Request, err: IronError) -> IronResult<Response> {
-        let gateway = {
-            let request_state = get_request_state!(req);
-            format!("{}", request_state.gateway)
-        };
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::ReloadConfig {
+            response_tx: Some(response_tx),
+        },
+        ConfigReloaded,
+        ErrorKind::SendNetworkCommandReloadConfig,
+        ErrorKind::RecvConfigReloaded,
+        |reloaded| match reloaded {
+            Ok(reload) => serialized_response(
+                serializer,
+                StatusCode::OK,
+                &json!({
+                    "applied": reload.applied,
+                    "restart_required": reload.restart_required,
+                }),
+            ),
+            Err(message) => error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "INVALID_CONFIG",
+                &format!("The configuration is not valid: {}", message),
+            ),
+        }
+    )
+}
+
+/// Pixels per module of the PNG QR codes
+const QR_PNG_SCALE: usize = 8;
+
+/// Renders a QR code for joining the hotspot or opening the portal, for
+/// devices with a small display to show during onboarding
+fn qr(req: &Request) -> ResponseFuture {
+    let png = match get_query_param(req, "format") {
+        Some(ref format) if format == "png" => true,
+        Some(ref format) if format == "svg" => false,
+        None => false,
+        Some(format) => {
+            return respond(error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_REQUEST",
+                &format!("Invalid format: {} (expected `svg` or `png`)", format),
+            ))
+        },
+    };
+
+    match get_query_param(req, "type") {
+        Some(ref kind) if kind == "portal" => respond(qr_response(&req.state.portal_url, png)),
+        Some(ref kind) if kind != "hotspot" => respond(error_response(
+            StatusCode::BAD_REQUEST,
+            "INVALID_REQUEST",
+            &format!("Invalid type: {} (expected `hotspot` or `portal`)", kind),
+        )),
+        _ => request_network_command!(
+            req,
+            response_tx => NetworkCommand::Hotspot {
+                response_tx: response_tx,
+            },
+            Hotspot,
+            ErrorKind::SendNetworkCommandHotspot,
+            ErrorKind::RecvHotspot,
+            |hotspot| match hotspot {
+                Some(hotspot) => qr_response(
+                    &get_wifi_qr_data(&hotspot.ssid, hotspot.passphrase.as_ref()),
+                    png,
+                ),
+                None => error_response(
+                    StatusCode::NOT_FOUND,
+                    "HOTSPOT_NOT_ACTIVE",
+                    "The hotspot is not active",
+                ),
+            }
+        ),
+    }
+}
+
+fn qr_response(data: &str, png: bool) -> Response {
+    let code = match QrCode::encode(data.as_bytes()) {
+        Ok(code) => code,
+        Err(e) => {
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "QR_DATA_TOO_LONG",
+                &e,
+            )
+        },
+    };
+
+    if png {
+        typed_response(StatusCode::OK, "image/png", code.to_png(QR_PNG_SCALE))
+    } else {
+        typed_response(StatusCode::OK, "image/svg+xml", code.to_svg())
+    }
+}
 
-        if let Some(host) = req.headers.get::<headers::Host>() {
-            if host.hostname != gateway {
-                let url = Url::parse(&format!("http://{}/", gateway)).unwrap();
-                return Ok(Response::with((status::Found, Redirect(url))));
+/// Formats the de facto standard `WIFI:` URI understood by the camera apps of
+/// phones
+fn get_wifi_qr_data(ssid: &str, passphrase: Option<&String>) -> String {
+    let escape = |value: &str| {
+        let mut escaped = String::with_capacity(value.len());
+
+        for c in value.chars() {
+            if "\\;,:\"".contains(c) {
+                escaped.push('\\');
             }
+
+            escaped.push(c);
         }
 
-        Err(err)
+        escaped
+    };
+
+    match passphrase {
+        Some(passphrase) => format!(
+            "WIFI:T:WPA;S:{};P:{};;",
+            escape(ssid),
+            escape(passphrase)
+        ),
+        None => format!("WIFI:T:nopass;S:{};;", escape(ssid)),
     }
 }
 
-pub fn start_server(
-    gateway: Ipv4Addr,
-    server_rx: Receiver<NetworkCommandResponse>,
-    network_tx: Sender<NetworkCommand>,
-    exit_tx: Sender<ExitResult>,
-    ui_directory: &PathBuf,
-) {
-    let exit_tx_clone = exit_tx.clone();
-    let gateway_clone = gateway;
-    let request_state = RequestSharedState {
-        gateway: gateway,
-        server_rx: server_rx,
-        network_tx: network_tx,
-        exit_tx: exit_tx,
+/// Lists the WiFi devices, e.g. for choosing a USB dongle with a better
+/// antenna over the onboard chip
+fn interfaces(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Interfaces {
+            response_tx: response_tx,
+        },
+        Interfaces,
+        ErrorKind::SendNetworkCommandInterfaces,
+        ErrorKind::RecvInterfaces,
+        |interfaces| {
+            let devices = interfaces
+                .devices
+                .iter()
+                .map(|device| {
+                    json!({
+                        "interface": device.interface,
+                        "mac": device.mac,
+                        "driver": device.driver,
+                        "state": device.state,
+                        "capabilities": device.capabilities,
+                        "active": device.interface == interfaces.active,
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            let interfaces_json = json!({
+                "active": interfaces.active,
+                "interfaces": devices,
+            });
+
+            serialized_response(serializer, StatusCode::OK, &interfaces_json)
+        }
+    )
+}
+
+/// Moves the captive portal to another WiFi device. Clients associated with
+/// the portal are dropped and have to join it again on the new device.
+fn select_interface(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let InterfaceRequest { interface } = get_request_body!(req, InterfaceRequest);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::SelectInterface {
+            interface: interface.clone(),
+            response_tx: response_tx,
+        },
+        InterfaceSelected,
+        ErrorKind::SendNetworkCommandInterfaces,
+        ErrorKind::RecvInterfaces,
+        |selected| match selected {
+            Ok(active) => {
+                serialized_response(serializer, StatusCode::OK, &json!({ "active": active }))
+            },
+            Err(InterfaceError::NotFound) => error_response(
+                StatusCode::NOT_FOUND,
+                "INTERFACE_NOT_FOUND",
+                &format!("No device {}", interface),
+            ),
+            Err(InterfaceError::NotAWiFiDevice) => error_response(
+                StatusCode::BAD_REQUEST,
+                "NOT_A_WIFI_DEVICE",
+                &format!("{} is not a WiFi device", interface),
+            ),
+            Err(InterfaceError::Busy) => error_response(
+                StatusCode::CONFLICT,
+                "INTERFACE_BUSY",
+                "The interface can only be switched while the captive portal is open",
+            ),
+            Err(InterfaceError::Switch(message)) => error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "INTERFACE_SWITCH_FAILED",
+                &message,
+            ),
+        }
+    )
+}
+
+/// Tries the saved networks in range until one gets Internet connectivity.
+/// Clients of the captive portal lose the connection while the networks are
+/// tried, so the result mostly serves wired and already connected clients.
+fn connect_any(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::ConnectAny {
+            response_tx: response_tx,
+        },
+        ConnectedAny,
+        ErrorKind::SendNetworkCommandConnectAny,
+        ErrorKind::RecvConnectedAny,
+        |connected| match connected {
+            Ok(connected) => {
+                let attempts = connected
+                    .attempts
+                    .iter()
+                    .map(|&(ref ssid, result)| json!({ "ssid": ssid, "result": result }))
+                    .collect::<Vec<_>>();
+
+                serialized_response(
+                    serializer,
+                    StatusCode::OK,
+                    &json!({
+                        "connected": connected.ssid.is_some(),
+                        "ssid": connected.ssid,
+                        "attempts": attempts,
+                    }),
+                )
+            },
+            Err(ConnectAnyError::NotAWiFiDevice) => error_response(
+                StatusCode::CONFLICT,
+                "NOT_A_WIFI_DEVICE",
+                "Saved networks can only be tried on a WiFi device",
+            ),
+            Err(ConnectAnyError::Busy) => error_response(
+                StatusCode::CONFLICT,
+                "CONNECT_BUSY",
+                "Saved networks can only be tried while the captive portal is open",
+            ),
+            Err(ConnectAnyError::NoCandidates) => error_response(
+                StatusCode::NOT_FOUND,
+                "NO_SAVED_NETWORKS_IN_RANGE",
+                "None of the saved networks is in range",
+            ),
+        }
+    )
+}
+
+/// Restarts NetworkManager when it got wedged. Clients of the captive portal
+/// lose the connection while the portal is reopened.
+fn restart_network_manager(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::RestartNetworkManager {
+            response_tx: response_tx,
+        },
+        NetworkManagerRestarted,
+        ErrorKind::SendNetworkCommandRestartNetworkManager,
+        ErrorKind::RecvNetworkManagerRestarted,
+        |restarted| match restarted {
+            Ok(restarted) => serialized_response(
+                serializer,
+                StatusCode::OK,
+                &json!({
+                    "interface": restarted.interface,
+                    "access_points": restarted.access_points,
+                    "portal_active": restarted.portal_active,
+                    "duration_s": restarted.duration,
+                }),
+            ),
+            Err(message) => {
+                error_response(StatusCode::BAD_GATEWAY, "NM_RESTART_FAILED", &message)
+            },
+        }
+    )
+}
+
+fn saved_to_json(profiles: &[SavedProfile]) -> Value {
+    let profiles = profiles
+        .iter()
+        .map(|profile| {
+            json!({
+                "ssid": profile.ssid,
+                "security": profile.key_management.as_str(),
+                "autoconnect": profile.autoconnect,
+                "priority": profile.priority,
+                "last_used": if profile.timestamp == 0 {
+                    None
+                } else {
+                    Some(profile.timestamp)
+                },
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Value::Array(profiles)
+}
+
+fn connection(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Connection {
+            response_tx: response_tx,
+        },
+        Connection,
+        ErrorKind::SendNetworkCommandConnection,
+        ErrorKind::RecvConnection,
+        |details| {
+            let connection_json = match details {
+                Some(details) => json!({
+                    "connected": true,
+                    "ssid": details.ssid,
+                    "bssid": details.bssid,
+                    "signal": details.signal,
+                    "addresses": details
+                        .addresses
+                        .iter()
+                        .map(|&(address, prefix)| format!("{}/{}", address, prefix))
+                        .collect::<Vec<_>>(),
+                    "gateway": details.gateway.map(|gateway| gateway.to_string()),
+                    "dns": details
+                        .dns_servers
+                        .iter()
+                        .map(|server| server.to_string())
+                        .collect::<Vec<_>>(),
+                    "ipv6_addresses": details
+                        .ipv6_addresses
+                        .iter()
+                        .map(|&(address, prefix)| format!("{}/{}", address, prefix))
+                        .collect::<Vec<_>>(),
+                    "ipv6_gateway": details.ipv6_gateway.map(|gateway| gateway.to_string()),
+                    "uptime": details.uptime,
+                }),
+                None => json!({ "connected": false }),
+            };
+
+            serialized_response(serializer, StatusCode::OK, &connection_json)
+        }
+    )
+}
+
+fn ethernet(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Ethernet {
+            response_tx: response_tx,
+        },
+        Ethernet,
+        ErrorKind::SendNetworkCommandEthernet,
+        ErrorKind::RecvEthernet,
+        |status| ethernet_response(serializer, status)
+    )
+}
+
+fn configure_ethernet(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let request = get_request_body!(req, Ipv4Request);
+
+    let config = match parse_ipv4_request(&request) {
+        Ok(config) => config,
+        Err(message) => {
+            return respond(error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_REQUEST",
+                &message,
+            ))
+        },
     };
 
-    let mut router = Router::new();
-    router.get("/", Static::new(ui_directory), "index");
-    router.get("/ssid", ssid, "ssid");
-    router.post("/connect", connect, "connect");
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::ConfigureEthernet {
+            config: config,
+            response_tx: response_tx,
+        },
+        Ethernet,
+        ErrorKind::SendNetworkCommandEthernet,
+        ErrorKind::RecvEthernet,
+        |status| ethernet_response(serializer, status)
+    )
+}
 
-    let mut assets = Mount::new();
-    assets.mount("/", router);
-    assets.mount("/css", Static::new(&ui_directory.join("css")));
-    assets.mount("/img", Static::new(&ui_directory.join("img")));
-    assets.mount("/js", Static::new(&ui_directory.join("js")));
+fn parse_ipv4_request(
+    request: &Ipv4Request,
+) -> ::std::result::Result<Ipv4Settings, String> {
+    match request.method.as_str() {
+        "dhcp" => Ok(Ipv4Settings::Dhcp),
+        "static" => {
+            let address = match request.address {
+                Some(ref address) => address,
+                None => return Err("Static configuration requires an address".into()),
+            };
 
-    let mut chain = Chain::new(assets);
-    chain.link(Write::<RequestSharedState>::both(request_state));
-    chain.link_after(RedirectMiddleware);
+            let mut parts = address.splitn(2, '/');
 
-    let address = format!("{}:80", gateway_clone);
+            let ip = parts.next().unwrap();
+            let ip = ip.parse::<Ipv4Addr>()
+                .map_err(|e| format!("Invalid address {}: {}", ip, e))?;
 
-    info!("Starting HTTP server on {}", &address);
+            let prefix = match parts.next() {
+                Some(prefix) => match prefix.parse::<u8>() {
+                    Ok(prefix) if prefix <= 32 => prefix,
+                    _ => return Err(format!("Invalid prefix length: {}", prefix)),
+                },
+                None => 24,
+            };
 
-    if let Err(e) = Iron::new(chain).http(&address) {
-        exit(
-            &exit_tx_clone,
-            ErrorKind::StartHTTPServer(address, e.description().into()).into(),
-        );
+            let gateway = match request.gateway {
+                Some(ref gateway) if !gateway.is_empty() => Some(
+                    gateway
+                        .parse::<Ipv4Addr>()
+                        .map_err(|e| format!("Invalid gateway {}: {}", gateway, e))?,
+                ),
+                _ => None,
+            };
+
+            let dns_servers = match request.dns {
+                Some(ref dns) if !dns.is_empty() => {
+                    parse_ip_list(dns).map_err(|e| format!("Invalid DNS server address: {}", e))?
+                },
+                _ => vec![],
+            };
+
+            Ok(Ipv4Settings::Static {
+                address: ip,
+                prefix: prefix,
+                gateway: gateway,
+                dns_servers: dns_servers,
+            })
+        },
+        method => Err(format!(
+            "Invalid method: {} (expected `dhcp` or `static`)",
+            method
+        )),
     }
 }
 
-fn ssid(req: &mut Request) -> IronResult<Response> {
-    info!("User connected to the captive portal");
+fn parse_ipv6_request(
+    request: &Ipv6Request,
+) -> ::std::result::Result<Ipv6Settings, String> {
+    match request.method.as_str() {
+        "auto" => Ok(Ipv6Settings::Auto),
+        "static" => {
+            let address = match request.address {
+                Some(ref address) => address,
+                None => return Err("Static configuration requires an address".into()),
+            };
+
+            let mut parts = address.splitn(2, '/');
+
+            let ip = parts.next().unwrap();
+            let ip = ip.parse::<Ipv6Addr>()
+                .map_err(|e| format!("Invalid address {}: {}", ip, e))?;
+
+            let prefix = match parts.next() {
+                Some(prefix) => match prefix.parse::<u8>() {
+                    Ok(prefix) if prefix <= 128 => prefix,
+                    _ => return Err(format!("Invalid prefix length: {}", prefix)),
+                },
+                None => 64,
+            };
+
+            let gateway = match request.gateway {
+                Some(ref gateway) if !gateway.is_empty() => Some(
+                    gateway
+                        .parse::<Ipv6Addr>()
+                        .map_err(|e| format!("Invalid gateway {}: {}", gateway, e))?,
+                ),
+                _ => None,
+            };
+
+            Ok(Ipv6Settings::Static {
+                address: ip,
+                prefix: prefix,
+                gateway: gateway,
+            })
+        },
+        method => Err(format!(
+            "Invalid method: {} (expected `auto` or `static`)",
+            method
+        )),
+    }
+}
 
-    let request_state = get_request_state!(req);
+fn ethernet_response(
+    serializer: Serializer,
+    status: ::std::result::Result<EthernetStatus, EthernetError>,
+) -> Response {
+    match status {
+        Ok(status) => {
+            let status_json = json!({
+                "interface": status.interface,
+                "state": status.state,
+                "carrier": status.carrier,
+                "addresses": status
+                    .ipv4
+                    .addresses
+                    .iter()
+                    .map(|&(address, prefix)| format!("{}/{}", address, prefix))
+                    .collect::<Vec<_>>(),
+                "gateway": status.ipv4.gateway.map(|gateway| gateway.to_string()),
+                "dns": status
+                    .ipv4
+                    .dns_servers
+                    .iter()
+                    .map(|server| server.to_string())
+                    .collect::<Vec<_>>(),
+            });
 
-    if let Err(e) = request_state.network_tx.send(NetworkCommand::Activate) {
-        return exit_with_error(&request_state, e, ErrorKind::SendNetworkCommandActivate);
+            serialized_response(serializer, StatusCode::OK, &status_json)
+        },
+        Err(EthernetError::NotManaged) => error_response(
+            StatusCode::NOT_FOUND,
+            "ETHERNET_NOT_MANAGED",
+            "No Ethernet device is managed",
+        ),
+        Err(EthernetError::Configure(message)) => error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "ETHERNET_CONFIGURATION_FAILED",
+            &message,
+        ),
     }
+}
+
+
+fn advanced_ipv4(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
 
-    let access_points_ssids = match request_state.server_rx.recv() {
-        Ok(result) => match result {
-            NetworkCommandResponse::AccessPointsSsids(ssids) => ssids,
+    let request = get_request_body!(req, Ipv4Request);
+
+    let settings = match parse_ipv4_request(&request) {
+        Ok(settings) => settings,
+        Err(message) => {
+            return respond(error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_REQUEST",
+                &message,
+            ))
         },
-        Err(e) => return exit_with_error(&request_state, e, ErrorKind::RecvAccessPointSSIDs),
     };
 
-    let access_points_json = match serde_json::to_string(&access_points_ssids) {
-        Ok(json) => json,
-        Err(e) => return exit_with_error(&request_state, e, ErrorKind::SerializeAccessPointSSIDs),
+    apply_advanced(req, serializer, AdvancedChange::Ipv4(settings))
+}
+
+fn advanced_ipv6(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let request = get_request_body!(req, Ipv6Request);
+
+    let settings = match parse_ipv6_request(&request) {
+        Ok(settings) => settings,
+        Err(message) => {
+            return respond(error_response(
+                StatusCode::BAD_REQUEST,
+                "INVALID_REQUEST",
+                &message,
+            ))
+        },
     };
 
-    Ok(Response::with((status::Ok, access_points_json)))
+    apply_advanced(req, serializer, AdvancedChange::Ipv6(settings))
 }
 
-fn connect(req: &mut Request) -> IronResult<Response> {
-    let (ssid, passphrase) = {
-        let params = get_request_ref!(req, Params, "Getting request params failed");
-        let ssid = get_param!(params, "ssid", String);
-        let passphrase = get_param!(params, "passphrase", String);
-        (ssid, passphrase)
+fn advanced_dns(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let DnsRequest { dns } = get_request_body!(req, DnsRequest);
+
+    let dns_servers = if dns.is_empty() {
+        vec![]
+    } else {
+        match parse_ip_list(&dns) {
+            Ok(dns_servers) => dns_servers,
+            Err(e) => {
+                return respond(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "INVALID_REQUEST",
+                    &format!("Invalid DNS server address: {}", e),
+                ))
+            },
+        }
     };
 
-    debug!("Incoming `connect` to access point `{}` request", ssid);
+    apply_advanced(req, serializer, AdvancedChange::Dns(dns_servers))
+}
 
-    let request_state = get_request_state!(req);
+fn advanced_proxy(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
 
-    let command = NetworkCommand::Connect {
-        ssid: ssid,
-        passphrase: passphrase,
+    let ProxyRequest { pac_url } = get_request_body!(req, ProxyRequest);
+
+    let change = AdvancedChange::Proxy {
+        pac_url: pac_url.and_then(non_empty),
     };
 
-    if let Err(e) = request_state.network_tx.send(command) {
-        exit_with_error(&request_state, e, ErrorKind::SendNetworkCommandConnect)
+    apply_advanced(req, serializer, change)
+}
+
+/// Applies a change to the WiFi connection, which is rolled back unless it
+/// is confirmed with `POST /confirm` before the confirm timeout
+fn apply_advanced(req: &Request, serializer: Serializer, change: AdvancedChange) -> ResponseFuture {
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::ApplyAdvanced {
+            change: change,
+            response_tx: response_tx,
+        },
+        Advanced,
+        ErrorKind::SendNetworkCommandAdvanced,
+        ErrorKind::RecvAdvanced,
+        |result| match result {
+            Ok(confirm_timeout) => serialized_response(
+                serializer,
+                StatusCode::OK,
+                &json!({ "confirm_timeout": confirm_timeout }),
+            ),
+            Err(AdvancedError::NotConnected) => error_response(
+                StatusCode::CONFLICT,
+                "NOT_CONNECTED",
+                "No WiFi connection is active",
+            ),
+            Err(AdvancedError::Failed(message)) => error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "ADVANCED_SETTINGS_FAILED",
+                &message,
+            ),
+        }
+    )
+}
+
+fn confirm(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::Confirm {
+            response_tx: response_tx,
+        },
+        Confirmed,
+        ErrorKind::SendNetworkCommandAdvanced,
+        ErrorKind::RecvAdvanced,
+        |confirmed| {
+            serialized_response(serializer, StatusCode::OK, &json!({ "confirmed": confirmed }))
+        }
+    )
+}
+
+fn export_config(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let ExportRequest { encryption_key } = get_request_body!(req, ExportRequest);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::ExportProfiles {
+            key: encryption_key.and_then(non_empty),
+            response_tx: response_tx,
+        },
+        Backup,
+        ErrorKind::SendNetworkCommandBackup,
+        ErrorKind::RecvBackup,
+        |result| backup_response(serializer, result)
+    )
+}
+
+fn import_config(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let ImportRequest {
+        backup,
+        encryption_key,
+    } = get_request_body!(req, ImportRequest);
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::ImportProfiles {
+            backup: backup,
+            key: encryption_key.and_then(non_empty),
+            response_tx: response_tx,
+        },
+        Backup,
+        ErrorKind::SendNetworkCommandBackup,
+        ErrorKind::RecvBackup,
+        |result| backup_response(serializer, result)
+    )
+}
+
+fn non_empty(value: String) -> Option<String> {
+    if value.is_empty() {
+        None
     } else {
-        Ok(Response::with(status::Ok))
+        Some(value)
+    }
+}
+
+fn backup_response(
+    serializer: Serializer,
+    result: ::std::result::Result<Value, BackupError>,
+) -> Response {
+    match result {
+        Ok(value) => serialized_response(serializer, StatusCode::OK, &value),
+        Err(BackupError::Invalid(message)) => {
+            error_response(StatusCode::BAD_REQUEST, "INVALID_BACKUP", &message)
+        },
+        Err(BackupError::Failed(message)) => {
+            error_response(StatusCode::INTERNAL_SERVER_ERROR, "BACKUP_FAILED", &message)
+        },
+    }
+}
+
+fn bssid_blacklist(req: &Request) -> ResponseFuture {
+    let serializer = get_serializer(req);
+
+    let BlacklistBssidRequest { bssid } = get_request_body!(req, BlacklistBssidRequest);
+
+    let bssid = bssid.to_uppercase();
+
+    if !is_valid_bssid(&bssid) {
+        return respond(error_response(
+            StatusCode::BAD_REQUEST,
+            "INVALID_REQUEST",
+            &format!("Invalid BSSID: {}", bssid),
+        ));
     }
+
+    request_network_command!(
+        req,
+        response_tx => NetworkCommand::BlacklistBssid {
+            bssid: bssid,
+            response_tx: response_tx,
+        },
+        BssidBlacklist,
+        ErrorKind::SendNetworkCommandBlacklistBssid,
+        ErrorKind::RecvBssidBlacklist,
+        |blacklist| serialized_response(serializer, StatusCode::OK, &json!(blacklist))
+    )
+}
+
+fn is_valid_bssid(bssid: &str) -> bool {
+    let octets = bssid.split(':').collect::<Vec<_>>();
+
+    octets.len() == 6
+        && octets
+            .iter()
+            .all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_digit(16)))
+}
+
+fn unexpected_response(state: &RequestSharedState) -> Response {
+    let e_kind = ErrorKind::UnexpectedNetworkCommandResponse;
+    let api_error = ApiError::from_kind(&e_kind);
+    state.exit(e_kind.into());
+    api_error.into_response()
 }
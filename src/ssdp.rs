@@ -0,0 +1,267 @@
+use std::fs::File;
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::Config;
+use dns::is_timeout;
+use server::get_portal_url;
+use subnet;
+
+const SSDP_ADDRESS: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 250);
+const SSDP_PORT: u16 = 1900;
+
+/// Time the announcements are valid for (seconds)
+const MAX_AGE: u64 = 1800;
+
+/// Interval of the `ssdp:alive` announcements (seconds)
+const NOTIFY_INTERVAL: u64 = 60;
+
+/// Interval of retrying to listen while the portal network is down (seconds)
+const RETRY_INTERVAL: u64 = 5;
+
+const DEVICE_TYPE: &str = "urn:schemas-upnp-org:device:Basic:1";
+
+pub const DESCRIPTION_PATH: &str = "description.xml";
+
+const MACHINE_ID_PATH: &str = "/etc/machine-id";
+
+/// Announces the captive portal over SSDP on the portal network, so that it
+/// shows up in the network view of Windows and opens the portal on a double
+/// click. Answers searches and repeats the announcement while the portal
+/// network is up.
+pub fn spawn_ssdp(config: &Config) {
+    if !config.ssdp {
+        return;
+    }
+
+    let gateway = config.gateway;
+    let prefix = config.portal_prefix;
+    let location = format!("{}{}", get_portal_url(config), DESCRIPTION_PATH);
+    let uuid = get_device_uuid();
+
+    thread::spawn(move || loop {
+        match listen(gateway) {
+            Ok(socket) => {
+                info!("Announcing the captive portal over SSDP");
+                serve(&socket, gateway, prefix, &location, &uuid);
+            },
+            Err(e) => debug!("SSDP not listening: {}", e),
+        }
+
+        thread::sleep(Duration::from_secs(RETRY_INTERVAL));
+    });
+}
+
+/// Joining the group on the gateway address fails while the portal network
+/// is down
+fn listen(gateway: Ipv4Addr) -> ::std::io::Result<UdpSocket> {
+    let socket = UdpSocket::bind(SocketAddr::new(Ipv4Addr::new(0, 0, 0, 0).into(), SSDP_PORT))?;
+    socket.join_multicast_v4(&SSDP_ADDRESS, &gateway)?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    Ok(socket)
+}
+
+/// Serves until the portal network goes down
+fn serve(socket: &UdpSocket, gateway: Ipv4Addr, prefix: u8, location: &str, uuid: &str) {
+    let mut buffer = [0; 1024];
+    let mut last_notify: Option<Instant> = None;
+
+    loop {
+        let notify_due = last_notify.map_or(true, |last_notify| {
+            last_notify.elapsed() >= Duration::from_secs(NOTIFY_INTERVAL)
+        });
+
+        if notify_due {
+            if let Err(e) = notify(gateway, location, uuid) {
+                debug!("SSDP announcement failed: {}", e);
+                return;
+            }
+
+            last_notify = Some(Instant::now());
+        }
+
+        let (len, source) = match socket.recv_from(&mut buffer) {
+            Ok(received) => received,
+            Err(ref e) if is_timeout(e) => continue,
+            Err(e) => {
+                warn!("Receiving SSDP message failed: {}", e);
+                return;
+            },
+        };
+
+        let is_portal_client = match source {
+            SocketAddr::V4(source) => {
+                subnet::contains(gateway, prefix, *source.ip())
+            },
+            SocketAddr::V6(_) => false,
+        };
+
+        if !is_portal_client {
+            continue;
+        }
+
+        let message = String::from_utf8_lossy(&buffer[..len]);
+
+        if let Some(search_target) = get_search_target(&message) {
+            for target in get_targets(uuid) {
+                if search_target == "ssdp:all" || search_target == target {
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\n\
+                         CACHE-CONTROL: max-age={}\r\n\
+                         EXT:\r\n\
+                         LOCATION: {}\r\n\
+                         SERVER: {}\r\n\
+                         ST: {}\r\n\
+                         USN: {}\r\n\r\n",
+                        MAX_AGE,
+                        location,
+                        get_server(),
+                        target,
+                        get_usn(uuid, &target)
+                    );
+
+                    let _ = socket.send_to(response.as_bytes(), source);
+                }
+            }
+        }
+    }
+}
+
+fn notify(gateway: Ipv4Addr, location: &str, uuid: &str) -> ::std::io::Result<()> {
+    // Sending from the gateway address sends on the portal network
+    let socket = UdpSocket::bind(SocketAddr::new(gateway.into(), 0))?;
+    socket.set_multicast_ttl_v4(2)?;
+
+    for target in get_targets(uuid) {
+        let message = format!(
+            "NOTIFY * HTTP/1.1\r\n\
+             HOST: {}:{}\r\n\
+             CACHE-CONTROL: max-age={}\r\n\
+             LOCATION: {}\r\n\
+             NT: {}\r\n\
+             NTS: ssdp:alive\r\n\
+             SERVER: {}\r\n\
+             USN: {}\r\n\r\n",
+            SSDP_ADDRESS,
+            SSDP_PORT,
+            MAX_AGE,
+            location,
+            target,
+            get_server(),
+            get_usn(uuid, &target)
+        );
+
+        socket.send_to(message.as_bytes(), SocketAddr::new(SSDP_ADDRESS.into(), SSDP_PORT))?;
+    }
+
+    Ok(())
+}
+
+/// The search target of an `M-SEARCH` request, if the message is one
+fn get_search_target(message: &str) -> Option<String> {
+    let mut lines = message.lines();
+
+    if !lines.next()?.starts_with("M-SEARCH * HTTP/1.1") {
+        return None;
+    }
+
+    lines
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ':');
+
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if name.trim().eq_ignore_ascii_case("st") => {
+                    Some(value.trim().to_string())
+                },
+                _ => None,
+            }
+        })
+        .next()
+}
+
+fn get_targets(uuid: &str) -> Vec<String> {
+    vec![
+        "upnp:rootdevice".to_string(),
+        format!("uuid:{}", uuid),
+        DEVICE_TYPE.to_string(),
+    ]
+}
+
+fn get_usn(uuid: &str, target: &str) -> String {
+    if target.starts_with("uuid:") {
+        target.to_string()
+    } else {
+        format!("uuid:{}::{}", uuid, target)
+    }
+}
+
+fn get_server() -> String {
+    format!("Linux UPnP/1.0 wifi-connect/{}", env!("CARGO_PKG_VERSION"))
+}
+
+/// The UPnP device description the announcements point to
+pub fn get_description(config: &Config) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <root xmlns=\"urn:schemas-upnp-org:device-1-0\">\n\
+         <specVersion><major>1</major><minor>0</minor></specVersion>\n\
+         <device>\n\
+         <deviceType>{}</deviceType>\n\
+         <friendlyName>{}</friendlyName>\n\
+         <manufacturer>WiFi Connect</manufacturer>\n\
+         <modelName>WiFi Connect</modelName>\n\
+         <modelNumber>{}</modelNumber>\n\
+         <UDN>uuid:{}</UDN>\n\
+         <presentationURL>{}</presentationURL>\n\
+         </device>\n\
+         </root>\n",
+        DEVICE_TYPE,
+        escape_xml(&config.mdns_service_name),
+        env!("CARGO_PKG_VERSION"),
+        get_device_uuid(),
+        escape_xml(&get_portal_url(config))
+    )
+}
+
+/// A UUID which stays the same across restarts, derived from the machine ID
+/// without revealing it
+fn get_device_uuid() -> String {
+    let mut machine_id = String::new();
+
+    let _ = File::open(MACHINE_ID_PATH).and_then(|mut file| file.read_to_string(&mut machine_id));
+
+    // Two FNV-1a hashes with different seeds give the 128 bits
+    let hash = |seed: u64| {
+        format!("wifi-connect:{}", machine_id.trim())
+            .bytes()
+            .fold(seed, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            })
+    };
+
+    let high = hash(0xcbf2_9ce4_8422_2325);
+    let low = hash(0x8422_2325_cbf2_9ce4);
+
+    // Version 4 and RFC 4122 variant bits
+    let high = (high & !0xf000) | 0x4000;
+    let low = (low & !(0xc << 60)) | (0x8 << 60);
+
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0xffff,
+        low >> 48,
+        low & 0xffff_ffff_ffff
+    )
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,77 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde_json::{self, Value};
+
+use errors::*;
+
+/// Keeps small JSON documents in the state directory, so that data like
+/// counters survives process restarts and reboots
+#[derive(Clone)]
+pub struct StateStore {
+    directory: PathBuf,
+}
+
+impl StateStore {
+    pub fn new(directory: &Path) -> Self {
+        StateStore {
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    pub fn load(&self, name: &str) -> Option<Value> {
+        match self.load_impl(name) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!("Loading state document '{}' failed: {}", name, e);
+                None
+            },
+        }
+    }
+
+    fn load_impl(&self, name: &str) -> Result<Option<Value>> {
+        let path = self.document_path(name);
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let mut contents = String::new();
+        File::open(&path)?.read_to_string(&mut contents)?;
+
+        Ok(Some(serde_json::from_str(&contents)?))
+    }
+
+    pub fn save(&self, name: &str, value: &Value) -> Result<()> {
+        self.save_impl(name, value)
+            .chain_err(|| ErrorKind::SaveState(name.to_string()))
+    }
+
+    fn save_impl(&self, name: &str, value: &Value) -> Result<()> {
+        fs::create_dir_all(&self.directory)?;
+
+        write_json_atomically(&self.document_path(name), value)
+    }
+
+    fn document_path(&self, name: &str) -> PathBuf {
+        self.directory.join(format!("{}.json", name))
+    }
+}
+
+/// Writes the JSON document next to the path first, so that readers never
+/// see a partially written file
+pub fn write_json_atomically(path: &Path, value: &Value) -> Result<()> {
+    let temp_path = path.with_extension("json.tmp");
+
+    {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(value.to_string().as_bytes())?;
+        file.sync_all()?;
+    }
+
+    // Rename is atomic, so a crash will never leave a truncated document
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+
+/// Prefix length of the captive portal network unless given with the gateway
+pub const DEFAULT_PREFIX: u8 = 24;
+
+pub fn contains(network: Ipv4Addr, prefix: u8, address: Ipv4Addr) -> bool {
+    let mask = prefix_mask(prefix);
+    u32::from(network) & mask == u32::from(address) & mask
+}
+
+/// Whether two networks overlap, i.e. the shorter prefix contains the other
+pub fn overlaps(a: Ipv4Addr, a_prefix: u8, b: Ipv4Addr, b_prefix: u8) -> bool {
+    let prefix = if a_prefix < b_prefix { a_prefix } else { b_prefix };
+    contains(a, prefix, b)
+}
+
+/// Suggests a gateway for the portal network in the 192.168.0.0/16 range
+/// that does not overlap with the given network
+pub fn suggest_gateway(
+    network: Ipv4Addr,
+    prefix: u8,
+    current: Ipv4Addr,
+    portal_prefix: u8,
+) -> Option<Ipv4Addr> {
+    let start = current.octets()[2] as u16;
+
+    for offset in 1..256 {
+        let octet = ((start + offset) % 256) as u8;
+        let gateway = Ipv4Addr::new(192, 168, octet, 1);
+
+        if !overlaps(network, prefix, gateway, portal_prefix) {
+            return Some(gateway);
+        }
+    }
+
+    None
+}
+
+/// Parses a gateway address, optionally followed by the prefix length of the
+/// portal network, e.g. `10.0.0.1/16`
+pub fn parse_gateway(value: &str) -> Result<(Ipv4Addr, u8), String> {
+    let mut parts = value.splitn(2, '/');
+
+    let address = parts.next().unwrap_or("");
+    let gateway = Ipv4Addr::from_str(address).map_err(|e| format!("{}: {}", address, e))?;
+
+    let prefix = match parts.next() {
+        Some(prefix) => u8::from_str(prefix).map_err(|e| format!("{}: {}", prefix, e))?,
+        None => DEFAULT_PREFIX,
+    };
+
+    // Room for the gateway and at least one client
+    if prefix < 8 || prefix > 30 {
+        return Err(format!("Prefix length {} is not between 8 and 30", prefix));
+    }
+
+    if gateway == network(gateway, prefix) || gateway == broadcast(gateway, prefix) {
+        return Err(format!("{} is not a host address of {}/{}", gateway, gateway, prefix));
+    }
+
+    Ok((gateway, prefix))
+}
+
+/// A gateway in 10.0.0.0/8 unique to a device, taken from the last two
+/// octets of its MAC address, so that portals of several devices next to
+/// each other do not share a network
+pub fn derive_gateway(mac: [u8; 6]) -> Ipv4Addr {
+    Ipv4Addr::new(10, mac[4], mac[5], 1)
+}
+
+/// The addresses of the network of the gateway after it, or before it when
+/// the gateway is the last one
+pub fn default_dhcp_range(gateway: Ipv4Addr, prefix: u8) -> String {
+    let first = u32::from(network(gateway, prefix)) + 1;
+    let last = u32::from(broadcast(gateway, prefix)) - 1;
+    let gateway = u32::from(gateway);
+
+    let (start, end) = if gateway < last {
+        (gateway + 1, last)
+    } else {
+        (first, gateway - 1)
+    };
+
+    format!("{},{}", Ipv4Addr::from(start), Ipv4Addr::from(end))
+}
+
+/// The netmask of a prefix length, e.g. 255.255.255.0 for 24
+pub fn netmask(prefix: u8) -> Ipv4Addr {
+    Ipv4Addr::from(prefix_mask(prefix))
+}
+
+fn network(address: Ipv4Addr, prefix: u8) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(address) & prefix_mask(prefix))
+}
+
+/// The broadcast address of the network of an address
+pub fn broadcast(address: Ipv4Addr, prefix: u8) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(address) | !prefix_mask(prefix))
+}
+
+fn prefix_mask(prefix: u8) -> u32 {
+    if prefix == 0 {
+        0
+    } else {
+        !0u32 << (32 - u32::from(prefix.min(32)))
+    }
+}
@@ -0,0 +1,100 @@
+use std::env;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::str::FromStr;
+use std::time::Duration;
+
+use nix::sys::socket::{sendto, socket, AddressFamily, MsgFlags, SockAddr, SockFlag, SockType,
+                       UnixAddr};
+use nix::unistd::{close, getpid};
+
+/// The first file descriptor passed by socket activation
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sends a state change to systemd, e.g. `READY=1`, if it supervises the
+/// service with `Type=notify`. Without `$NOTIFY_SOCKET` this does nothing.
+pub fn notify(state: &str) {
+    let path = match env::var("NOTIFY_SOCKET") {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+
+    // Sockets in the abstract namespace are prefixed with `@`
+    let address = if path.starts_with('@') {
+        UnixAddr::new_abstract(path[1..].as_bytes())
+    } else {
+        UnixAddr::new(path.as_str())
+    };
+
+    let result = address.and_then(|address| {
+        let fd = socket(
+            AddressFamily::Unix,
+            SockType::Datagram,
+            SockFlag::empty(),
+            None,
+        )?;
+
+        let sent = sendto(
+            fd,
+            state.as_bytes(),
+            &SockAddr::Unix(address),
+            MsgFlags::empty(),
+        );
+
+        let _ = close(fd);
+
+        sent
+    });
+
+    if let Err(e) = result {
+        warn!("Notifying systemd of {} failed: {}", state, e);
+    }
+}
+
+/// Interval systemd expects `WATCHDOG=1` notifications in, if the unit sets
+/// `WatchdogSec`
+pub fn get_watchdog_interval() -> Option<Duration> {
+    if !is_for_this_process("WATCHDOG_PID") {
+        return None;
+    }
+
+    let usec = env::var("WATCHDOG_USEC")
+        .ok()
+        .and_then(|usec| u64::from_str(&usec).ok())?;
+
+    if usec == 0 {
+        return None;
+    }
+
+    Some(Duration::new(usec / 1_000_000, (usec % 1_000_000) as u32 * 1000))
+}
+
+/// Takes the listening sockets passed by systemd socket activation. The
+/// variables are removed, so that the sockets are taken only once and child
+/// processes do not mistake them for their own.
+pub fn take_listen_sockets() -> Vec<TcpListener> {
+    if !is_for_this_process("LISTEN_PID") {
+        return Vec::new();
+    }
+
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| RawFd::from_str(&count).ok())
+        .unwrap_or(0);
+
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+/// The variables of systemd are meant for the process it started, which a
+/// child would inherit otherwise. Without the PID they apply regardless.
+fn is_for_this_process(pid_variable: &str) -> bool {
+    env::var(pid_variable)
+        .map(|pid| pid == getpid().to_string())
+        .unwrap_or(true)
+}
@@ -0,0 +1,124 @@
+use std::fs::{self, OpenOptions};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use errors::*;
+use config::Config;
+
+/// Prepares a PKCS #12 identity for the HTTPS server from the configured
+/// certificate/key pair, or from a self-signed certificate generated on the
+/// first run and kept in the state directory. Only giving one of the pair is
+/// an error rather than a reason to fall back to the self-signed certificate.
+pub fn get_tls_identity(config: &Config) -> Result<PathBuf> {
+    get_tls_identity_impl(config).chain_err(|| ErrorKind::TlsIdentity)
+}
+
+fn get_tls_identity_impl(config: &Config) -> Result<PathBuf> {
+    let directory = config.state_directory.join("tls");
+
+    fs::create_dir_all(&directory)?;
+
+    let (certificate, key) = match (&config.tls_certificate, &config.tls_key) {
+        (&Some(ref certificate), &Some(ref key)) => (certificate.clone(), key.clone()),
+        (&None, &None) => generate_self_signed_certificate(&directory, config)?,
+        _ => bail!(ErrorKind::TlsKeyPair),
+    };
+
+    // The identity holds the private key without a password
+    let identity = directory.join("identity.p12");
+
+    create_private_file(&identity)?;
+
+    openssl(Command::new("openssl")
+        .arg("pkcs12")
+        .arg("-export")
+        .arg("-in")
+        .arg(&certificate)
+        .arg("-inkey")
+        .arg(&key)
+        .arg("-out")
+        .arg(&identity)
+        .arg("-passout")
+        .arg("pass:"))?;
+
+    Ok(identity)
+}
+
+/// Checks that the certificate has not expired and that the key can be read
+pub fn check_certificate(certificate: &Path, key: &Path) -> Result<()> {
+    openssl(Command::new("openssl")
+        .arg("x509")
+        .arg("-noout")
+        .arg("-checkend")
+        .arg("0")
+        .arg("-in")
+        .arg(certificate))?;
+
+    openssl(Command::new("openssl")
+        .arg("pkey")
+        .arg("-noout")
+        .arg("-in")
+        .arg(key))
+}
+
+fn generate_self_signed_certificate(
+    directory: &Path,
+    config: &Config,
+) -> Result<(PathBuf, PathBuf)> {
+    let certificate = directory.join("self-signed.crt");
+    let key = directory.join("self-signed.key");
+
+    if certificate.exists() && key.exists() {
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o600))?;
+        return Ok((certificate, key));
+    }
+
+    info!("Generating self-signed TLS certificate...");
+
+    create_private_file(&key)?;
+
+    openssl(Command::new("openssl")
+        .arg("req")
+        .arg("-x509")
+        .arg("-newkey")
+        .arg("rsa:2048")
+        .arg("-nodes")
+        .arg("-days")
+        .arg("3650")
+        .arg("-subj")
+        .arg(format!("/CN={}", config.gateway))
+        .arg("-keyout")
+        .arg(&key)
+        .arg("-out")
+        .arg(&certificate))?;
+
+    Ok((certificate, key))
+}
+
+/// Creates an empty file only readable by its owner, which openssl then
+/// writes to without changing the permissions
+fn create_private_file(path: &Path) -> Result<()> {
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+
+    // The mode only applies to new files
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+
+    Ok(())
+}
+
+fn openssl(command: &mut Command) -> Result<()> {
+    let output = command.output().chain_err(|| ErrorKind::Openssl)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        bail!(ErrorKind::OpensslStatus(stderr));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The default UI compiled into the binary, served unless a UI directory is
+/// given, so that minimal images need no UI files on disk
+const ASSETS: &[(&str, &[u8], &str)] = &[
+    (
+        "index.html",
+        include_bytes!("../ui/index.html"),
+        "text/html; charset=utf-8",
+    ),
+    (
+        "css/bootstrap.min.css",
+        include_bytes!("../ui/css/bootstrap.min.css"),
+        "text/css; charset=utf-8",
+    ),
+    ("img/favicon.png", include_bytes!("../ui/img/favicon.png"), "image/png"),
+    ("img/logo.svg", include_bytes!("../ui/img/logo.svg"), "image/svg+xml"),
+    (
+        "js/bootstrap.min.js",
+        include_bytes!("../ui/js/bootstrap.min.js"),
+        "application/javascript; charset=utf-8",
+    ),
+    (
+        "js/index.js",
+        include_bytes!("../ui/js/index.js"),
+        "application/javascript; charset=utf-8",
+    ),
+    (
+        "js/jquery.min.js",
+        include_bytes!("../ui/js/jquery.min.js"),
+        "application/javascript; charset=utf-8",
+    ),
+];
+
+/// Media types of the files of a UI directory by their extension
+const MEDIA_TYPES: &[(&str, &str)] = &[
+    ("css", "text/css; charset=utf-8"),
+    ("gif", "image/gif"),
+    ("html", "text/html; charset=utf-8"),
+    ("ico", "image/x-icon"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "application/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("png", "image/png"),
+    ("svg", "image/svg+xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+];
+
+/// Directories of the UI below the root, which hold its assets
+pub const ASSET_DIRECTORIES: &[&str] = &["css", "img", "js"];
+
+/// The UI served at the root and below the asset directories: the default
+/// one, unless a UI directory is given
+pub enum Ui {
+    Embedded,
+    Directory(PathBuf),
+}
+
+impl Ui {
+    /// The content and the media type of a file by its path below the UI,
+    /// e.g. `css/bootstrap.min.css`, with the index page at the root
+    pub fn get(&self, path: &str) -> Option<(Vec<u8>, &'static str)> {
+        let path = if path.is_empty() { "index.html" } else { path };
+
+        // Files outside of the UI directory are never served
+        if path.split('/').any(|segment| segment.is_empty() || segment.starts_with('.')) {
+            return None;
+        }
+
+        match *self {
+            Ui::Embedded => ASSETS
+                .iter()
+                .find(|&&(name, _, _)| name == path)
+                .map(|&(_, content, media_type)| (content.to_vec(), media_type)),
+            Ui::Directory(ref directory) => read_file(&directory.join(path)),
+        }
+    }
+}
+
+fn read_file(path: &Path) -> Option<(Vec<u8>, &'static str)> {
+    if !path.is_file() {
+        return None;
+    }
+
+    let mut content = Vec::new();
+
+    if let Err(e) = File::open(path).and_then(|mut file| file.read_to_end(&mut content)) {
+        warn!("Reading {} failed: {}", path.display(), e);
+        return None;
+    }
+
+    let extension = path.extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+
+    let media_type = MEDIA_TYPES
+        .iter()
+        .find(|&&(known, _)| known == extension)
+        .map_or("application/octet-stream", |&(_, media_type)| media_type);
+
+    Some((content, media_type))
+}
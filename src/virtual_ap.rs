@@ -0,0 +1,161 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::process::Command;
+
+use errors::*;
+use power::run_iw;
+
+/// Adds a virtual interface in access point mode to the radio of the WiFi
+/// interface, so that the captive portal stays up while the client interface
+/// tests the credentials. Only radios with an interface combination of an
+/// access point and a managed interface support this. Most of them share a
+/// single channel, so the access point follows the channel of the network
+/// connected to.
+pub fn create_virtual_ap(interface: &str, virtual_interface: &str) -> Result<()> {
+    let phy = get_phy(interface)
+        .chain_err(|| ErrorKind::VirtualApUnsupported(interface.to_string()))?;
+
+    if !supports_ap_and_client(&run_iw(&["phy", &phy, "info"])?) {
+        bail!(ErrorKind::VirtualApUnsupported(interface.to_string()));
+    }
+
+    // Left behind if the previous run was killed
+    if !Path::new("/sys/class/net").join(virtual_interface).exists() {
+        info!("Adding virtual interface {} to {}...", virtual_interface, phy);
+
+        run_iw(&[
+            "dev",
+            interface,
+            "interface",
+            "add",
+            virtual_interface,
+            "type",
+            "__ap",
+        ])?;
+    }
+
+    // Some drivers refuse two interfaces with the same address on a radio
+    if let Some(address) = get_virtual_address(interface) {
+        let status = Command::new("ip")
+            .args(&["link", "set", "dev", virtual_interface, "address", &address])
+            .status();
+
+        match status {
+            Ok(ref status) if status.success() => {},
+            Ok(status) => warn!("Setting the address of {} failed: {}", virtual_interface, status),
+            Err(e) => warn!("Setting the address of {} failed: {}", virtual_interface, e),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn remove_virtual_ap(virtual_interface: &str) {
+    info!("Removing virtual interface {}...", virtual_interface);
+
+    if let Err(e) = run_iw(&["dev", virtual_interface, "del"]) {
+        warn!("Removing virtual interface {} failed: {}", virtual_interface, e);
+    }
+}
+
+fn get_phy(interface: &str) -> ::std::io::Result<String> {
+    let mut phy = String::new();
+
+    File::open(format!("/sys/class/net/{}/phy80211/name", interface))?
+        .read_to_string(&mut phy)?;
+
+    Ok(phy.trim().to_string())
+}
+
+/// Whether one of the valid interface combinations listed by `iw phy info`
+/// has room for an access point next to a managed interface, e.g.
+/// `* #{ managed } <= 1, #{ AP } <= 1, #{ P2P-client } <= 1,` continued
+/// with `total <= 3, #channels <= 1`
+fn supports_ap_and_client(info: &str) -> bool {
+    let mut lines = info.lines();
+    let mut indent = None;
+
+    for line in lines.by_ref() {
+        if line.trim() == "valid interface combinations:" {
+            indent = Some(get_indent(line));
+            break;
+        }
+    }
+
+    let indent = match indent {
+        Some(indent) => indent,
+        None => return false,
+    };
+
+    // Each combination starts with `*` and may continue on the next lines,
+    // up to the next section
+    let mut combinations: Vec<String> = Vec::new();
+
+    for line in lines.take_while(|line| get_indent(line) > indent) {
+        let line = line.trim();
+
+        if line.starts_with('*') {
+            combinations.push(line[1..].to_string());
+        } else if let Some(combination) = combinations.last_mut() {
+            combination.push(' ');
+            combination.push_str(line);
+        }
+    }
+
+    combinations.iter().any(|combination| {
+        let modes = combination
+            .split("#{")
+            .skip(1)
+            .filter_map(|group| group.split('}').next())
+            .flat_map(|group| group.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>();
+
+        let total = combination
+            .split("total <=")
+            .nth(1)
+            .and_then(|total| total.split(',').next())
+            .and_then(|total| total.trim().parse::<u32>().ok())
+            .unwrap_or(0);
+
+        modes.contains(&"managed") && modes.contains(&"AP") && total >= 2
+    })
+}
+
+fn get_indent(line: &str) -> usize {
+    line.len() - line.trim_left().len()
+}
+
+/// A locally administered variant of the address of the client interface
+fn get_virtual_address(interface: &str) -> Option<String> {
+    let mut address = String::new();
+
+    File::open(format!("/sys/class/net/{}/address", interface))
+        .and_then(|mut file| file.read_to_string(&mut address))
+        .ok()?;
+
+    let mut octets = address
+        .trim()
+        .split(':')
+        .map(|octet| u8::from_str_radix(octet, 16).ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    if octets.len() != 6 {
+        return None;
+    }
+
+    octets[0] = if octets[0] & 0x02 == 0 {
+        octets[0] | 0x02
+    } else {
+        octets[0] ^ 0x04
+    };
+
+    Some(
+        octets
+            .iter()
+            .map(|octet| format!("{:02x}", octet))
+            .collect::<Vec<_>>()
+            .join(":"),
+    )
+}
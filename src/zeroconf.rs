@@ -0,0 +1,109 @@
+use dbus::{BusType, Connection, Message, Path};
+
+use errors::*;
+use config::{Config, Scheme};
+
+const AVAHI_DBUS_NAME: &str = "org.freedesktop.Avahi";
+const AVAHI_SERVER_INTERFACE: &str = "org.freedesktop.Avahi.Server";
+const AVAHI_ENTRY_GROUP_INTERFACE: &str = "org.freedesktop.Avahi.EntryGroup";
+
+/// `AVAHI_IF_UNSPEC` and `AVAHI_PROTO_UNSPEC`: all interfaces, IPv4 and IPv6
+const AVAHI_UNSPEC: i32 = -1;
+
+/// Time to wait for the Avahi daemon to answer (milliseconds)
+const CALL_TIMEOUT: i32 = 5_000;
+
+/// The captive portal HTTP service registered with the Avahi daemon. The
+/// daemon keeps the registration while the D-Bus connection of the entry
+/// group is open, and announces it on every interface, so that the portal
+/// stays discoverable on the network joined after the hotspot.
+pub struct MdnsService {
+    connection: Connection,
+    group: String,
+}
+
+impl MdnsService {
+    pub fn register(config: &Config) -> Result<Self> {
+        let service_type = match config.listening_scheme {
+            Scheme::Http => "_http._tcp",
+            Scheme::Https => "_https._tcp",
+        };
+
+        let connection = Connection::get_private(BusType::System)
+            .chain_err(|| ErrorKind::Avahi("EntryGroupNew".to_string()))?;
+
+        let reply = call(
+            &connection,
+            "/",
+            AVAHI_SERVER_INTERFACE,
+            "EntryGroupNew",
+            |message| message,
+        )?;
+
+        let group = reply
+            .get1::<Path>()
+            .ok_or_else(|| ErrorKind::Avahi("EntryGroupNew".to_string()))?
+            .to_string();
+
+        let service = MdnsService {
+            connection: connection,
+            group: group,
+        };
+
+        let txt = vec![b"path=/".to_vec()];
+
+        call(
+            &service.connection,
+            &service.group,
+            AVAHI_ENTRY_GROUP_INTERFACE,
+            "AddService",
+            |message| {
+                message
+                    .append3(AVAHI_UNSPEC, AVAHI_UNSPEC, 0u32)
+                    .append3(config.mdns_service_name.as_str(), service_type, "")
+                    .append3("", config.listening_port, txt)
+            },
+        )?;
+
+        call(
+            &service.connection,
+            &service.group,
+            AVAHI_ENTRY_GROUP_INTERFACE,
+            "Commit",
+            |message| message,
+        )?;
+
+        Ok(service)
+    }
+
+    /// Withdraws the announcement, which closing the connection does as well
+    pub fn unregister(self) {
+        if let Err(e) = call(
+            &self.connection,
+            &self.group,
+            AVAHI_ENTRY_GROUP_INTERFACE,
+            "Free",
+            |message| message,
+        ) {
+            debug!("Unregistering the mDNS service failed: {}", e);
+        }
+    }
+}
+
+fn call<F>(
+    connection: &Connection,
+    path: &str,
+    interface: &str,
+    method: &str,
+    append: F,
+) -> Result<Message>
+where
+    F: FnOnce(Message) -> Message,
+{
+    let message = Message::new_method_call(AVAHI_DBUS_NAME, path, interface, method)
+        .map_err(|e| Error::from(ErrorKind::Avahi(format!("{}: {}", method, e))))?;
+
+    connection
+        .send_with_reply_and_block(append(message), CALL_TIMEOUT)
+        .chain_err(|| ErrorKind::Avahi(method.to_string()))
+}
@@ -10,6 +10,14 @@ $(function(){
 		}
 	});
 
+	$.get("/capabilities", function(data){
+		var capabilities = typeof data === 'string' ? JSON.parse(data) : data;
+		var features = (capabilities.data || capabilities).features || [];
+		if(features.indexOf('enrollment-token') !== -1){
+			$('#enrollment-token-group').removeClass('hidden');
+		}
+	});
+
 	$('#connect-form').submit(function(ev){
 		$.post('/connect', $('#connect-form').serialize(), function(data){
 			$('.before-submit').hide();